@@ -15,11 +15,11 @@ struct Platformer {
     cam_x: f32,
     cam_y: f32,
 
-    tile_sprite: Sprite,
-    player_sprite: Sprite,
+    tile_sheet: SpriteSheet,
+    player_sheet: SpriteSheet,
 
-    dir_mod_x: i32,
-    dir_mod_y: i32,
+    jumping: bool,
+    facing_left: bool,
 }
 
 impl Platformer {
@@ -57,11 +57,11 @@ impl Platformer {
             cam_x: 0.0,
             cam_y: 0.0,
 
-            tile_sprite: Sprite::from_file("examples/sprites/level.spr").unwrap(),
-            player_sprite: Sprite::from_file("examples/sprites/jario.spr").unwrap(),
+            tile_sheet: SpriteSheet::from_file("examples/sprites/level.sprsheet").unwrap(),
+            player_sheet: SpriteSheet::from_file("examples/sprites/jario.sprsheet").unwrap(),
 
-            dir_mod_x: 0,
-            dir_mod_y: 0,
+            jumping: false,
+            facing_left: false,
         }
     }
 
@@ -105,15 +105,15 @@ impl ConsoleGame for Platformer {
             }
             if engine.key_held(LEFT) {
                 self.vel_x += (if self.on_ground { -25.0 } else { -15.0 }) * elapsed_time;
-                self.dir_mod_y = 1;
+                self.facing_left = true;
             }
             if engine.key_held(RIGHT) {
                 self.vel_x += (if self.on_ground { 25.0 } else { 15.0 }) * elapsed_time;
-                self.dir_mod_y = 0;
+                self.facing_left = false;
             }
             if engine.key_pressed(SPACE) && self.vel_y == 0.0 {
                 self.vel_y = -12.0;
-                self.dir_mod_x = 1;
+                self.jumping = true;
             }
         }
 
@@ -169,7 +169,7 @@ impl ConsoleGame for Platformer {
             new_player_y = new_player_y.floor();
             self.vel_y = 0.0;
             self.on_ground = true;
-            self.dir_mod_x = 0;
+            self.jumping = false;
         }
 
         self.player_x = new_player_x;
@@ -218,47 +218,35 @@ impl ConsoleGame for Platformer {
                         );
                     }
                     '#' => {
-                        engine.draw_partial_sprite(
+                        engine.draw_sheet_frame(
                             x * tile_width - tile_offset_x as i32,
                             y * tile_height - tile_offset_y as i32,
-                            &self.tile_sprite,
-                            (2 * tile_width) as usize,
-                            0,
-                            (tile_width) as usize,
-                            (tile_height) as usize,
+                            &self.tile_sheet,
+                            "brick",
                         );
                     }
                     'G' => {
-                        engine.draw_partial_sprite(
+                        engine.draw_sheet_frame(
                             x * tile_width - tile_offset_x as i32,
                             y * tile_height - tile_offset_y as i32,
-                            &self.tile_sprite,
-                            0,
-                            0,
-                            (tile_width) as usize,
-                            (tile_height) as usize,
+                            &self.tile_sheet,
+                            "ground",
                         );
                     }
                     'B' => {
-                        engine.draw_partial_sprite(
+                        engine.draw_sheet_frame(
                             x * tile_width - tile_offset_x as i32,
                             y * tile_height - tile_offset_y as i32,
-                            &self.tile_sprite,
-                            0,
-                            tile_height as usize,
-                            (tile_width) as usize,
-                            (tile_height) as usize,
+                            &self.tile_sheet,
+                            "block",
                         );
                     }
                     '?' => {
-                        engine.draw_partial_sprite(
+                        engine.draw_sheet_frame(
                             x * tile_width - tile_offset_x as i32,
                             y * tile_height - tile_offset_y as i32,
-                            &self.tile_sprite,
-                            tile_width as usize,
-                            tile_height as usize,
-                            (tile_width) as usize,
-                            (tile_height) as usize,
+                            &self.tile_sheet,
+                            "question",
                         );
                     }
                     'o' => {
@@ -270,14 +258,11 @@ impl ConsoleGame for Platformer {
                             SOLID,
                             FG_CYAN,
                         );
-                        engine.draw_partial_sprite(
+                        engine.draw_sheet_frame(
                             x * tile_width - tile_offset_x as i32,
                             y * tile_height - tile_offset_y as i32,
-                            &self.tile_sprite,
-                            (3 * tile_width) as usize,
-                            0,
-                            (tile_width) as usize,
-                            (tile_height) as usize,
+                            &self.tile_sheet,
+                            "coin",
                         );
                     }
                     _ => {
@@ -294,14 +279,18 @@ impl ConsoleGame for Platformer {
             }
         }
 
-        engine.draw_partial_sprite(
+        let player_frame = match (self.jumping, self.facing_left) {
+            (false, false) => "jario_stand_right",
+            (true, false) => "jario_jump_right",
+            (false, true) => "jario_stand_left",
+            (true, true) => "jario_jump_left",
+        };
+
+        engine.draw_sheet_frame(
             ((self.player_x - offset_x) * tile_width as f32) as i32,
             ((self.player_y - offset_y) * tile_height as f32) as i32,
-            &self.player_sprite,
-            (self.dir_mod_x * tile_width) as usize,
-            (self.dir_mod_y * tile_height) as usize,
-            tile_width as usize,
-            tile_height as usize,
+            &self.player_sheet,
+            player_frame,
         );
 
         true
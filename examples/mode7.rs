@@ -65,43 +65,23 @@ impl ConsoleGame for Mode7 {
             self.fov_half -= 0.1 * elapsed_time;
         }
 
-        let far_x1 = self.world_x + (self.world_a - self.fov_half).cos() * self.far;
-        let far_y1 = self.world_y + (self.world_a - self.fov_half).sin() * self.far;
-
-        let near_x1 = self.world_x + (self.world_a - self.fov_half).cos() * self.near;
-        let near_y1 = self.world_y + (self.world_a - self.fov_half).sin() * self.near;
-
-        let far_x2 = self.world_x + (self.world_a + self.fov_half).cos() * self.far;
-        let far_y2 = self.world_y + (self.world_a + self.fov_half).sin() * self.far;
-
-        let near_x2 = self.world_x + (self.world_a + self.fov_half).cos() * self.near;
-        let near_y2 = self.world_y + (self.world_a + self.fov_half).sin() * self.near;
-
-        for y in 0..(engine.screen_height() / 2) {
-            let sample_depth = y as f32 / (engine.screen_height() as f32 / 2.0);
-
-            let start_x = (far_x1 - near_x1) / sample_depth + near_x1;
-            let start_y = (far_y1 - near_y1) / sample_depth + near_y1;
-            let end_x = (far_x2 - near_x2) / sample_depth + near_x2;
-            let end_y = (far_y2 - near_y2) / sample_depth + near_y2;
-
-            for x in 0..engine.screen_width() {
-                let sample_width = x as f32 / engine.screen_width() as f32;
-                let sample_x = (end_x - start_x) * sample_width + start_x;
-                let sample_y = (end_y - start_y) * sample_width + start_y;
-
-                let sample_x = sample_x % 1.0;
-                let sample_y = sample_y % 1.0;
-
-                let glyph = self.ground_sprite.sample_glyph(sample_x, sample_y);
-                let color = self.ground_sprite.sample_color(sample_x, sample_y);
-                engine.draw_with(x, y + (engine.screen_height() / 2), glyph, color);
-
-                let glyph = self.sky_sprite.sample_glyph(sample_x, sample_y);
-                let color = self.sky_sprite.sample_color(sample_x, sample_y);
-                engine.draw_with(x, (engine.screen_height() / 2) - y, glyph, color);
-            }
-        }
+        let camera = PlaneCamera {
+            x: self.world_x,
+            y: self.world_y,
+            angle: self.world_a,
+        };
+        let horizon = engine.screen_height() / 2;
+        let fov = self.fov_half * 2.0;
+
+        engine.draw_affine_plane(
+            &self.ground_sprite,
+            camera,
+            horizon,
+            self.near,
+            self.far,
+            fov,
+        );
+        engine.draw_affine_ceiling(&self.sky_sprite, camera, horizon, self.near, self.far, fov);
 
         engine.draw_line_with(
             0,
@@ -193,27 +193,13 @@ impl ConsoleGame for PerlinNoise {
 
             self.perlin_noise_2d();
 
-            for x in 0..self.output_width {
-                for y in 0..self.output_height {
-                    let bw = (self.noise_2d[y * self.output_width + x] * 12.0) as usize;
-                    let (bg_col, fg_col, sym) = match bw {
-                        0 => (BG_BLACK, FG_BLACK, SOLID),
-                        1 => (BG_BLACK, FG_DARK_GREY, QUARTER),
-                        2 => (BG_BLACK, FG_DARK_GREY, HALF),
-                        3 => (BG_BLACK, FG_DARK_GREY, THREE_QUARTERS),
-                        4 => (BG_BLACK, FG_DARK_GREY, SOLID),
-                        5 => (BG_DARK_GREY, FG_GREY, QUARTER),
-                        6 => (BG_DARK_GREY, FG_GREY, HALF),
-                        7 => (BG_DARK_GREY, FG_GREY, THREE_QUARTERS),
-                        8 => (BG_DARK_GREY, FG_GREY, SOLID),
-                        9 => (BG_GREY, FG_WHITE, QUARTER),
-                        10 => (BG_GREY, FG_WHITE, HALF),
-                        11 => (BG_GREY, FG_WHITE, THREE_QUARTERS),
-                        _ => (BG_GREY, FG_WHITE, SOLID),
-                    };
-                    engine.draw_with(x as i32, y as i32, sym, fg_col | bg_col);
-                }
-            }
+            engine.draw_dithered(
+                0,
+                0,
+                self.output_width,
+                &self.noise_2d,
+                &[FG_BLACK, FG_DARK_GREY, FG_GREY, FG_WHITE],
+            );
         } else if self.mode == 3 {
             if engine.key_released(Z) {
                 for i in 0..self.output_width * self.output_height {
@@ -223,31 +209,13 @@ impl ConsoleGame for PerlinNoise {
 
             self.perlin_noise_2d();
 
-            for x in 0..self.output_width {
-                for y in 0..self.output_height {
-                    let bw = (self.noise_2d[y * self.output_width + x] * 16.0) as usize;
-                    let (bg_col, fg_col, sym) = match bw {
-                        0 => (BG_DARK_BLUE, FG_DARK_BLUE, SOLID),
-                        1 => (BG_DARK_BLUE, FG_BLUE, QUARTER),
-                        2 => (BG_DARK_BLUE, FG_BLUE, HALF),
-                        3 => (BG_DARK_BLUE, FG_BLUE, THREE_QUARTERS),
-                        4 => (BG_DARK_BLUE, FG_BLUE, SOLID),
-                        5 => (BG_BLUE, FG_GREEN, QUARTER),
-                        6 => (BG_BLUE, FG_GREEN, HALF),
-                        7 => (BG_BLUE, FG_GREEN, THREE_QUARTERS),
-                        8 => (BG_BLUE, FG_GREEN, SOLID),
-                        9 => (BG_GREEN, FG_DARK_GREY, QUARTER),
-                        10 => (BG_GREEN, FG_DARK_GREY, HALF),
-                        11 => (BG_GREEN, FG_DARK_GREY, THREE_QUARTERS),
-                        12 => (BG_GREEN, FG_DARK_GREY, SOLID),
-                        13 => (BG_DARK_GREY, FG_WHITE, QUARTER),
-                        14 => (BG_DARK_GREY, FG_WHITE, HALF),
-                        15 => (BG_DARK_GREY, FG_WHITE, THREE_QUARTERS),
-                        _ => (BG_DARK_GREY, FG_WHITE, SOLID),
-                    };
-                    engine.draw_with(x as i32, y as i32, sym, fg_col | bg_col);
-                }
-            }
+            engine.draw_dithered(
+                0,
+                0,
+                self.output_width,
+                &self.noise_2d,
+                &[FG_DARK_BLUE, FG_BLUE, FG_GREEN, FG_DARK_GREY, FG_WHITE],
+            );
         }
 
         true
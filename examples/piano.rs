@@ -59,11 +59,11 @@ impl ConsoleGame for Piano {
 
         for (&key, &freq) in &self.key_map {
             if engine.key_pressed(key) {
-                engine.audio.note_on(freq);
+                engine.audio.note_on("piano", freq);
             }
 
             if engine.key_released(key) {
-                engine.audio.note_off(freq);
+                engine.audio.note_off("piano", freq);
             }
         }
 
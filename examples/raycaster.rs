@@ -194,16 +194,17 @@ impl ConsoleGame for Raycaster {
             }
         }
 
-        engine.draw_string(
+        engine.draw_string_coded(
             0,
             0,
             &format!(
-                "X={:.2}, Y={:.2}, A={:.2}, FPS={:.2}",
+                "X=§e{:.2}§r, Y=§e{:.2}§r, A=§e{:.2}§r, FPS=§a{:.2}",
                 self.player_x,
                 self.player_y,
                 self.player_a,
                 1.0 / elapsed_time
             ),
+            FG_WHITE,
         );
 
         for nx in 0..self.map_width {
@@ -0,0 +1,117 @@
+use rusty_console_game_engine::color::*;
+use rusty_console_game_engine::prelude::*;
+use rusty_console_game_engine::procgen::Rng;
+use rusty_console_game_engine::snake::*;
+
+const WIDTH: i32 = 40;
+const HEIGHT: i32 = 30;
+
+struct SnakeGame {
+    snake: Snake,
+    food: (i32, i32),
+    rng: Rng,
+
+    step_timer: f32,
+    step_rate: f32,
+    score: u32,
+    game_over: bool,
+}
+
+impl SnakeGame {
+    fn new() -> Self {
+        let mut game = Self {
+            snake: Snake::new(WIDTH / 2, HEIGHT / 2, 4, Direction::East, WIDTH, HEIGHT - 2),
+            food: (0, 0),
+            rng: Rng::new(rand::random()),
+            step_timer: 0.0,
+            step_rate: 0.1,
+            score: 0,
+            game_over: false,
+        };
+        game.reset();
+        game
+    }
+
+    fn reset(&mut self) {
+        self.snake = Snake::new(WIDTH / 2, HEIGHT / 2, 4, Direction::East, WIDTH, HEIGHT - 2);
+        self.food = self.snake.random_free_cell(&mut self.rng).unwrap();
+        self.step_rate = 0.1;
+        self.score = 0;
+        self.game_over = false;
+    }
+}
+
+impl ConsoleGame for SnakeGame {
+    fn app_name(&self) -> &str {
+        "Snake"
+    }
+
+    fn create(&mut self, _engine: &mut ConsoleGameEngine<Self>) -> bool {
+        true
+    }
+
+    fn update(&mut self, engine: &mut ConsoleGameEngine<Self>, elapsed_time: f32) -> bool {
+        if self.game_over {
+            if engine.key_pressed(SPACE) {
+                self.reset();
+            }
+        } else {
+            if engine.key_pressed(ARROW_UP) {
+                self.snake.turn(Direction::North);
+            }
+            if engine.key_pressed(ARROW_DOWN) {
+                self.snake.turn(Direction::South);
+            }
+            if engine.key_pressed(ARROW_LEFT) {
+                self.snake.turn(Direction::West);
+            }
+            if engine.key_pressed(ARROW_RIGHT) {
+                self.snake.turn(Direction::East);
+            }
+
+            self.step_timer += elapsed_time;
+            while self.step_timer >= self.step_rate {
+                self.step_timer -= self.step_rate;
+
+                match self.snake.step() {
+                    SnakeStep::Moved if self.snake.head() == self.food => {
+                        self.snake.grow(3);
+                        self.score += 1;
+                        self.step_rate = (self.step_rate * 0.95).max(0.04);
+                        match self.snake.random_free_cell(&mut self.rng) {
+                            Some(cell) => self.food = cell,
+                            None => self.game_over = true,
+                        }
+                    }
+                    SnakeStep::Moved => {}
+                    SnakeStep::HitSelf | SnakeStep::HitWall => self.game_over = true,
+                }
+
+                if self.game_over {
+                    break;
+                }
+            }
+        }
+
+        engine.clear(FG_BLACK);
+
+        engine.draw_with(self.food.0, self.food.1 + 2, SOLID, FG_RED);
+        engine.draw_snake(0, 2, &self.snake, FG_GREEN);
+
+        engine.fill_rect_with(0, 1, WIDTH, 2, SOLID, FG_DARK_GREY);
+        engine.draw_string_with(1, 0, &format!("Score: {}", self.score), FG_WHITE);
+        if self.game_over {
+            engine.draw_string_with(14, HEIGHT / 2, "GAME OVER", FG_WHITE);
+            engine.draw_string_with(9, HEIGHT / 2 + 2, "Press SPACE to retry", FG_GREY);
+        }
+
+        true
+    }
+}
+
+fn main() {
+    let mut game = ConsoleGameEngine::new(SnakeGame::new());
+    game.construct_console(WIDTH as i16, HEIGHT as i16, 16, 16)
+        .expect("Console Construction Failed");
+    game.start();
+}
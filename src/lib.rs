@@ -5,25 +5,41 @@
 
 // region: Imports
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::f32::consts::PI;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering::*},
-    mpsc::{self, Sender},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering::*},
+    mpsc::{self, Receiver, Sender},
+    Arc, Condvar, Mutex,
 };
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use windows::core::{BOOL, HSTRING, PCWSTR, PSTR, PWSTR};
+use windows::core::{BOOL, HSTRING, PCWSTR, PSTR};
 use windows::Win32::{
-    Foundation::*, Graphics::Gdi::*, Media::Audio::*, Media::MMSYSERR_NOERROR, System::Console::*,
-    UI::Input::KeyboardAndMouse::GetAsyncKeyState, UI::WindowsAndMessaging::wsprintfW,
+    Foundation::*,
+    Graphics::Gdi::*,
+    Media::Audio::*,
+    Media::MMSYSERR_NOERROR,
+    System::Console::*,
+    System::Threading::{AttachThreadInput, GetCurrentThreadId},
+    UI::Input::KeyboardAndMouse::GetAsyncKeyState,
+    UI::WindowsAndMessaging::{
+        ClipCursor, GetCursorPos, GetWindowLongW, GetWindowRect, GetWindowThreadProcessId,
+        SetCursorPos, SetLayeredWindowAttributes, SetWindowLongW, SetWindowPos, ShowCursor,
+        GWL_EXSTYLE, GWL_STYLE, HWND_NOTOPMOST, HWND_TOPMOST, LWA_ALPHA, SWP_FRAMECHANGED,
+        SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SWP_NOZORDER, WS_CAPTION, WS_EX_LAYERED,
+        WS_THICKFRAME,
+    },
 };
 
+#[cfg(feature = "hot-reload")]
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+
 // endregion
 
 // region: Constants
@@ -108,6 +124,49 @@ pub mod color {
     pub const BG_YELLOW: u16 = 0x00E0;
     /// White background color.
     pub const BG_WHITE: u16 = 0x00F0;
+
+    /// Draws a line along the top edge of the cell.
+    pub const GRID_TOP: u16 = 0x0400;
+    /// Draws a line along the left edge of the cell.
+    pub const GRID_LEFT: u16 = 0x0800;
+    /// Draws a line along the right edge of the cell.
+    pub const GRID_RIGHT: u16 = 0x1000;
+    /// Swaps the foreground and background colors of the cell.
+    pub const REVERSE: u16 = 0x4000;
+    /// Underlines the cell.
+    pub const UNDERLINE: u16 = 0x8000;
+    /// Every attribute flag. The grid lines and underline are drawn in the foreground color.
+    pub const FLAGS: u16 = GRID_TOP | GRID_LEFT | GRID_RIGHT | REVERSE | UNDERLINE;
+
+    /// Returns `col` with `flags` added, e.g. `with_flags(FG_WHITE, UNDERLINE | GRID_LEFT)`.
+    pub const fn with_flags(col: u16, flags: u16) -> u16 {
+        col | (flags & FLAGS)
+    }
+
+    /// Returns `col` underlined.
+    pub const fn underline(col: u16) -> u16 {
+        col | UNDERLINE
+    }
+
+    /// Returns `col` in reverse video.
+    pub const fn reverse(col: u16) -> u16 {
+        col | REVERSE
+    }
+
+    /// Returns `col` with grid lines on the top, left and right edges of the cell.
+    pub const fn grid(col: u16) -> u16 {
+        col | GRID_TOP | GRID_LEFT | GRID_RIGHT
+    }
+
+    /// Returns the attribute flags set in `col`.
+    pub const fn flags(col: u16) -> u16 {
+        col & FLAGS
+    }
+
+    /// Returns `col` with every attribute flag removed.
+    pub const fn without_flags(col: u16) -> u16 {
+        col & !FLAGS
+    }
 }
 
 /// Provides convenient Unicode pixel constants for drawing.
@@ -137,6 +196,116 @@ pub mod pixel {
     pub const EMPTY: u16 = 0x20;
 }
 
+/// Provides named constants for extended Unicode glyphs.
+///
+/// Covers the box-drawing set, half blocks, arrows and card suits, so UI code can
+/// use names instead of hex literals. Like [`pixel`], they are used as the glyph
+/// argument of drawing functions.
+///
+/// # Examples
+///
+/// ```rust
+/// use rusty_console_game_engine::glyph::*;
+///
+/// engine.draw(10, 5, DOUBLE_TOP_LEFT, FG_WHITE);
+/// engine.draw(12, 5, HEART, FG_RED);
+/// ```
+pub mod glyph {
+    /// `─` single horizontal line.
+    pub const SINGLE_HORIZONTAL: u16 = 0x2500;
+    /// `│` single vertical line.
+    pub const SINGLE_VERTICAL: u16 = 0x2502;
+    /// `┌` single top-left corner.
+    pub const SINGLE_TOP_LEFT: u16 = 0x250C;
+    /// `┐` single top-right corner.
+    pub const SINGLE_TOP_RIGHT: u16 = 0x2510;
+    /// `└` single bottom-left corner.
+    pub const SINGLE_BOTTOM_LEFT: u16 = 0x2514;
+    /// `┘` single bottom-right corner.
+    pub const SINGLE_BOTTOM_RIGHT: u16 = 0x2518;
+    /// `├` single T-junction pointing right.
+    pub const SINGLE_T_RIGHT: u16 = 0x251C;
+    /// `┤` single T-junction pointing left.
+    pub const SINGLE_T_LEFT: u16 = 0x2524;
+    /// `┬` single T-junction pointing down.
+    pub const SINGLE_T_DOWN: u16 = 0x252C;
+    /// `┴` single T-junction pointing up.
+    pub const SINGLE_T_UP: u16 = 0x2534;
+    /// `┼` single cross.
+    pub const SINGLE_CROSS: u16 = 0x253C;
+
+    /// `═` double horizontal line.
+    pub const DOUBLE_HORIZONTAL: u16 = 0x2550;
+    /// `║` double vertical line.
+    pub const DOUBLE_VERTICAL: u16 = 0x2551;
+    /// `╔` double top-left corner.
+    pub const DOUBLE_TOP_LEFT: u16 = 0x2554;
+    /// `╗` double top-right corner.
+    pub const DOUBLE_TOP_RIGHT: u16 = 0x2557;
+    /// `╚` double bottom-left corner.
+    pub const DOUBLE_BOTTOM_LEFT: u16 = 0x255A;
+    /// `╝` double bottom-right corner.
+    pub const DOUBLE_BOTTOM_RIGHT: u16 = 0x255D;
+    /// `╠` double T-junction pointing right.
+    pub const DOUBLE_T_RIGHT: u16 = 0x2560;
+    /// `╣` double T-junction pointing left.
+    pub const DOUBLE_T_LEFT: u16 = 0x2563;
+    /// `╦` double T-junction pointing down.
+    pub const DOUBLE_T_DOWN: u16 = 0x2566;
+    /// `╩` double T-junction pointing up.
+    pub const DOUBLE_T_UP: u16 = 0x2569;
+    /// `╬` double cross.
+    pub const DOUBLE_CROSS: u16 = 0x256C;
+
+    /// `▀` upper half block.
+    pub const UPPER_HALF: u16 = 0x2580;
+    /// `▄` lower half block.
+    pub const LOWER_HALF: u16 = 0x2584;
+    /// `▌` left half block.
+    pub const LEFT_HALF: u16 = 0x258C;
+    /// `▐` right half block.
+    pub const RIGHT_HALF: u16 = 0x2590;
+    /// `░` light shade, the same glyph as [`crate::pixel::QUARTER`].
+    pub const LIGHT_SHADE: u16 = 0x2591;
+    /// `▒` medium shade, the same glyph as [`crate::pixel::HALF`].
+    pub const MEDIUM_SHADE: u16 = 0x2592;
+    /// `▓` dark shade, the same glyph as [`crate::pixel::THREE_QUARTERS`].
+    pub const DARK_SHADE: u16 = 0x2593;
+
+    /// `←` left arrow.
+    pub const ARROW_LEFT: u16 = 0x2190;
+    /// `↑` up arrow.
+    pub const ARROW_UP: u16 = 0x2191;
+    /// `→` right arrow.
+    pub const ARROW_RIGHT: u16 = 0x2192;
+    /// `↓` down arrow.
+    pub const ARROW_DOWN: u16 = 0x2193;
+    /// `▲` up-pointing triangle.
+    pub const TRIANGLE_UP: u16 = 0x25B2;
+    /// `►` right-pointing triangle.
+    pub const TRIANGLE_RIGHT: u16 = 0x25BA;
+    /// `▼` down-pointing triangle.
+    pub const TRIANGLE_DOWN: u16 = 0x25BC;
+    /// `◄` left-pointing triangle.
+    pub const TRIANGLE_LEFT: u16 = 0x25C4;
+
+    /// `♠` spade suit.
+    pub const SPADE: u16 = 0x2660;
+    /// `♣` club suit.
+    pub const CLUB: u16 = 0x2663;
+    /// `♥` heart suit.
+    pub const HEART: u16 = 0x2665;
+    /// `♦` diamond suit.
+    pub const DIAMOND: u16 = 0x2666;
+
+    /// `•` bullet.
+    pub const BULLET: u16 = 0x2022;
+    /// `☺` smiley face.
+    pub const SMILEY: u16 = 0x263A;
+    /// `♪` eighth note.
+    pub const NOTE: u16 = 0x266A;
+}
+
 /// Provides constants for mouse button input.
 ///
 /// These constants are used with input functions like
@@ -183,6 +352,10 @@ pub mod mouse_button {
 /// }
 /// ```
 pub mod key {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        GetKeyNameTextW, MapVirtualKeyW, MAPVK_VSC_TO_VK_EX,
+    };
+
     /// Space key. Used with `key_pressed`, `key_released`, or `key_held`.
     pub const SPACE: usize = 0x20;
     /// Enter key.
@@ -205,6 +378,18 @@ pub mod key {
     pub const NUMLOCK: usize = 0x90;
     /// Scroll Lock key.
     pub const SCROLL_LOCK: usize = 0x91;
+    /// Page Up key.
+    pub const PAGE_UP: usize = 0x21;
+    /// Page Down key.
+    pub const PAGE_DOWN: usize = 0x22;
+    /// End key.
+    pub const END: usize = 0x23;
+    /// Home key.
+    pub const HOME: usize = 0x24;
+    /// Insert key.
+    pub const INSERT: usize = 0x2D;
+    /// Delete key.
+    pub const DELETE: usize = 0x2E;
 
     /// Up arrow key.
     pub const ARROW_UP: usize = 0x26;
@@ -378,6 +563,372 @@ pub mod key {
     /// Apostrophe / Double Quote key.
     /// Only works with US ANSI Keyboards
     pub const APOSTROPHE: usize = 0xDE;
+
+    pub(crate) const NAMES: &[(&str, usize)] = &[
+        ("SPACE", SPACE),
+        ("ENTER", ENTER),
+        ("ESCAPE", ESCAPE),
+        ("BACKSPACE", BACKSPACE),
+        ("TAB", TAB),
+        ("SHIFT", SHIFT),
+        ("CONTROL", CONTROL),
+        ("ALT", ALT),
+        ("CAPSLOCK", CAPSLOCK),
+        ("NUMLOCK", NUMLOCK),
+        ("SCROLL_LOCK", SCROLL_LOCK),
+        ("PAGE_UP", PAGE_UP),
+        ("PAGE_DOWN", PAGE_DOWN),
+        ("END", END),
+        ("HOME", HOME),
+        ("INSERT", INSERT),
+        ("DELETE", DELETE),
+        ("ARROW_UP", ARROW_UP),
+        ("ARROW_DOWN", ARROW_DOWN),
+        ("ARROW_LEFT", ARROW_LEFT),
+        ("ARROW_RIGHT", ARROW_RIGHT),
+        ("F1", F1),
+        ("F2", F2),
+        ("F3", F3),
+        ("F4", F4),
+        ("F5", F5),
+        ("F6", F6),
+        ("F7", F7),
+        ("F8", F8),
+        ("F9", F9),
+        ("F10", F10),
+        ("F11", F11),
+        ("F12", F12),
+        ("ZERO", ZERO),
+        ("ONE", ONE),
+        ("TWO", TWO),
+        ("THREE", THREE),
+        ("FOUR", FOUR),
+        ("FIVE", FIVE),
+        ("SIX", SIX),
+        ("SEVEN", SEVEN),
+        ("EIGHT", EIGHT),
+        ("NINE", NINE),
+        ("A", A),
+        ("B", B),
+        ("C", C),
+        ("D", D),
+        ("E", E),
+        ("F", F),
+        ("G", G),
+        ("H", H),
+        ("I", I),
+        ("J", J),
+        ("K", K),
+        ("L", L),
+        ("M", M),
+        ("N", N),
+        ("O", O),
+        ("P", P),
+        ("Q", Q),
+        ("R", R),
+        ("S", S),
+        ("T", T),
+        ("U", U),
+        ("V", V),
+        ("W", W),
+        ("X", X),
+        ("Y", Y),
+        ("Z", Z),
+        ("NUMPAD_0", NUMPAD_0),
+        ("NUMPAD_1", NUMPAD_1),
+        ("NUMPAD_2", NUMPAD_2),
+        ("NUMPAD_3", NUMPAD_3),
+        ("NUMPAD_4", NUMPAD_4),
+        ("NUMPAD_5", NUMPAD_5),
+        ("NUMPAD_6", NUMPAD_6),
+        ("NUMPAD_7", NUMPAD_7),
+        ("NUMPAD_8", NUMPAD_8),
+        ("NUMPAD_9", NUMPAD_9),
+        ("NUMPAD_ADD", NUMPAD_ADD),
+        ("NUMPAD_SUBTRACT", NUMPAD_SUBTRACT),
+        ("NUMPAD_MULTIPLY", NUMPAD_MULTIPLY),
+        ("NUMPAD_DIVIDE", NUMPAD_DIVIDE),
+        ("SEMICOLON", SEMICOLON),
+        ("EQUAL", EQUAL),
+        ("COMMA", COMMA),
+        ("DASH", DASH),
+        ("PERIOD", PERIOD),
+        ("SLASH", SLASH),
+        ("BACKTICK", BACKTICK),
+        ("LEFT_BRACE", LEFT_BRACE),
+        ("BACKSLASH", BACKSLASH),
+        ("RIGHT_BRACE", RIGHT_BRACE),
+        ("APOSTROPHE", APOSTROPHE),
+    ];
+
+    /// Looks up a key constant by its name, e.g. `"SPACE"`, `"ARROW_UP"` or `"w"`.
+    ///
+    /// Names are the same as the constants in this module and are case-insensitive.
+    /// Hexadecimal virtual key codes such as `"0x20"` are accepted too.
+    pub fn from_name(name: &str) -> Option<usize> {
+        let name = name.trim();
+        if let Some(hex) = name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+            return usize::from_str_radix(hex, 16).ok().filter(|&k| k < 256);
+        }
+
+        NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, k)| k)
+    }
+
+    /// Returns the name of the constant for `key`, e.g. `"SPACE"` for `key::SPACE`.
+    ///
+    /// This is the inverse of `from_name`.
+    pub fn name(key: usize) -> Option<&'static str> {
+        NAMES.iter().find(|&&(_, k)| k == key).map(|&(n, _)| n)
+    }
+
+    /// A physical key, identified by its position on the keyboard (its scan code)
+    /// rather than by what is printed on it.
+    ///
+    /// Variants are named after what the key shows on a US layout, so `Key::W` is the key
+    /// below `2` even on an AZERTY keyboard, where it says `Z`. Use `vk` to get the virtual
+    /// key code for `key_held` and friends, and `key_name` to show the label the user sees.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[repr(u16)]
+    pub enum Key {
+        /// Escape.
+        Escape = 0x01,
+        /// `1` key in the number row.
+        Digit1 = 0x02,
+        /// `2` key in the number row.
+        Digit2 = 0x03,
+        /// `3` key in the number row.
+        Digit3 = 0x04,
+        /// `4` key in the number row.
+        Digit4 = 0x05,
+        /// `5` key in the number row.
+        Digit5 = 0x06,
+        /// `6` key in the number row.
+        Digit6 = 0x07,
+        /// `7` key in the number row.
+        Digit7 = 0x08,
+        /// `8` key in the number row.
+        Digit8 = 0x09,
+        /// `9` key in the number row.
+        Digit9 = 0x0A,
+        /// `0` key in the number row.
+        Digit0 = 0x0B,
+        /// Key right of `0` (`-` on US layouts).
+        Minus = 0x0C,
+        /// Key left of Backspace (`=` on US layouts).
+        Equal = 0x0D,
+        /// Backspace.
+        Backspace = 0x0E,
+        /// Tab.
+        Tab = 0x0F,
+        /// `Q` key on US layouts.
+        Q = 0x10,
+        /// `W` key on US layouts.
+        W = 0x11,
+        /// `E` key on US layouts.
+        E = 0x12,
+        /// `R` key on US layouts.
+        R = 0x13,
+        /// `T` key on US layouts.
+        T = 0x14,
+        /// `Y` key on US layouts.
+        Y = 0x15,
+        /// `U` key on US layouts.
+        U = 0x16,
+        /// `I` key on US layouts.
+        I = 0x17,
+        /// `O` key on US layouts.
+        O = 0x18,
+        /// `P` key on US layouts.
+        P = 0x19,
+        /// Key right of `P` (`[` on US layouts).
+        LeftBracket = 0x1A,
+        /// Second key right of `P` (`]` on US layouts).
+        RightBracket = 0x1B,
+        /// Enter.
+        Enter = 0x1C,
+        /// Left Control.
+        LeftControl = 0x1D,
+        /// `A` key on US layouts.
+        A = 0x1E,
+        /// `S` key on US layouts.
+        S = 0x1F,
+        /// `D` key on US layouts.
+        D = 0x20,
+        /// `F` key on US layouts.
+        F = 0x21,
+        /// `G` key on US layouts.
+        G = 0x22,
+        /// `H` key on US layouts.
+        H = 0x23,
+        /// `J` key on US layouts.
+        J = 0x24,
+        /// `K` key on US layouts.
+        K = 0x25,
+        /// `L` key on US layouts.
+        L = 0x26,
+        /// Key right of `L` (`;` on US layouts).
+        Semicolon = 0x27,
+        /// Second key right of `L` (`'` on US layouts).
+        Apostrophe = 0x28,
+        /// Key left of `1` (`` ` `` on US layouts).
+        Backtick = 0x29,
+        /// Left Shift.
+        LeftShift = 0x2A,
+        /// Key above Enter on US layouts, left of Enter on ISO layouts (`\` on US layouts).
+        Backslash = 0x2B,
+        /// `Z` key on US layouts.
+        Z = 0x2C,
+        /// `X` key on US layouts.
+        X = 0x2D,
+        /// `C` key on US layouts.
+        C = 0x2E,
+        /// `V` key on US layouts.
+        V = 0x2F,
+        /// `B` key on US layouts.
+        B = 0x30,
+        /// `N` key on US layouts.
+        N = 0x31,
+        /// `M` key on US layouts.
+        M = 0x32,
+        /// Key right of `M` (`,` on US layouts).
+        Comma = 0x33,
+        /// Second key right of `M` (`.` on US layouts).
+        Period = 0x34,
+        /// Third key right of `M` (`/` on US layouts).
+        Slash = 0x35,
+        /// Right Shift.
+        RightShift = 0x36,
+        /// Numpad `*`.
+        NumpadMultiply = 0x37,
+        /// Left Alt.
+        LeftAlt = 0x38,
+        /// Space bar.
+        Space = 0x39,
+        /// Caps Lock.
+        CapsLock = 0x3A,
+        /// F1.
+        F1 = 0x3B,
+        /// F2.
+        F2 = 0x3C,
+        /// F3.
+        F3 = 0x3D,
+        /// F4.
+        F4 = 0x3E,
+        /// F5.
+        F5 = 0x3F,
+        /// F6.
+        F6 = 0x40,
+        /// F7.
+        F7 = 0x41,
+        /// F8.
+        F8 = 0x42,
+        /// F9.
+        F9 = 0x43,
+        /// F10.
+        F10 = 0x44,
+        /// Num Lock.
+        NumLock = 0x45,
+        /// Scroll Lock.
+        ScrollLock = 0x46,
+        /// Numpad `7`.
+        Numpad7 = 0x47,
+        /// Numpad `8`.
+        Numpad8 = 0x48,
+        /// Numpad `9`.
+        Numpad9 = 0x49,
+        /// Numpad `-`.
+        NumpadSubtract = 0x4A,
+        /// Numpad `4`.
+        Numpad4 = 0x4B,
+        /// Numpad `5`.
+        Numpad5 = 0x4C,
+        /// Numpad `6`.
+        Numpad6 = 0x4D,
+        /// Numpad `+`.
+        NumpadAdd = 0x4E,
+        /// Numpad `1`.
+        Numpad1 = 0x4F,
+        /// Numpad `2`.
+        Numpad2 = 0x50,
+        /// Numpad `3`.
+        Numpad3 = 0x51,
+        /// Numpad `0`.
+        Numpad0 = 0x52,
+        /// Numpad `.`.
+        NumpadDecimal = 0x53,
+        /// Extra key left of `Z` on ISO layouts (`<` on German layouts).
+        IntlBackslash = 0x56,
+        /// F11.
+        F11 = 0x57,
+        /// F12.
+        F12 = 0x58,
+        /// Numpad Enter.
+        NumpadEnter = 0xE01C,
+        /// Right Control.
+        RightControl = 0xE01D,
+        /// Numpad `/`.
+        NumpadDivide = 0xE035,
+        /// Right Alt (AltGr on many layouts).
+        RightAlt = 0xE038,
+        /// Home.
+        Home = 0xE047,
+        /// Up arrow.
+        ArrowUp = 0xE048,
+        /// Page Up.
+        PageUp = 0xE049,
+        /// Left arrow.
+        ArrowLeft = 0xE04B,
+        /// Right arrow.
+        ArrowRight = 0xE04D,
+        /// End.
+        End = 0xE04F,
+        /// Down arrow.
+        ArrowDown = 0xE050,
+        /// Page Down.
+        PageDown = 0xE051,
+        /// Insert.
+        Insert = 0xE052,
+        /// Delete.
+        Delete = 0xE053,
+    }
+
+    impl Key {
+        /// Returns the scan code of the key. Extended keys have the `0xE0` prefix in the
+        /// high byte.
+        pub fn scancode(self) -> u16 {
+            self as u16
+        }
+
+        /// Returns the virtual key code this key produces with the active keyboard layout,
+        /// for use with `key_pressed`, `key_released` and `key_held`.
+        pub fn vk(self) -> usize {
+            let code = unsafe { MapVirtualKeyW(self.scancode() as u32, MAPVK_VSC_TO_VK_EX) };
+            code as usize & 0xFF
+        }
+    }
+
+    /// Returns the label of `key` in the active keyboard layout, e.g. `"Z"` for `Key::Y`
+    /// on a German keyboard or `"Ctrl"` / `"Strg"` for `Key::LeftControl`.
+    ///
+    /// Falls back to the US name of the variant if Windows has no name for the key.
+    pub fn key_name(key: Key) -> String {
+        let scancode = key.scancode() as i32;
+        let mut lparam = (scancode & 0xFF) << 16;
+        if scancode & 0xE000 != 0 {
+            lparam |= 1 << 24;
+        }
+
+        let mut buf = [0u16; 64];
+        let len = unsafe { GetKeyNameTextW(lparam, &mut buf) };
+        if len > 0 {
+            String::from_utf16_lossy(&buf[..len as usize])
+        } else {
+            format!("{:?}", key)
+        }
+    }
 }
 
 /// Provides named constants for musical note frequencies (in Hertz).
@@ -627,14 +1178,87 @@ pub mod note {
 ///
 /// The goal is that a simple game can be written with only the prelude.
 pub mod prelude {
+    pub use crate::Atmosphere;
+    pub use crate::AudioBackend;
+    pub use crate::BlendMode;
+    pub use crate::BoneAttachment;
+    pub use crate::BorderStyle;
+    pub use crate::BulletManager;
+    pub use crate::Bus;
+    pub use crate::ChunkedWorld;
+    pub use crate::ComboMeter;
     pub use crate::ConsoleGame;
     pub use crate::ConsoleGameEngine;
+    pub use crate::Dialogue;
+    pub use crate::DrawCommand;
+    pub use crate::Drum;
+    pub use crate::Easing;
+    pub use crate::Effect;
+    pub use crate::EventBus;
+    pub use crate::FallingSand;
+    pub use crate::FloatingNumbers;
+    pub use crate::Flock;
+    pub use crate::FlockParams;
+    pub use crate::GaugeKind;
+    pub use crate::GaugeStyle;
+    pub use crate::GlyphFallback;
+    pub use crate::GridRange;
+    pub use crate::Handle;
+    pub use crate::InputEvent;
+    pub use crate::Instrument;
+    pub use crate::Inventory;
+    pub use crate::InventoryItem;
+    pub use crate::InventoryLayout;
+    pub use crate::KeyEvent;
+    pub use crate::Material;
+    pub use crate::Menu;
+    pub use crate::MenuManager;
+    pub use crate::Modifiers;
+    pub use crate::MotionPath;
+    pub use crate::Oscillator;
+    pub use crate::Palette;
+    pub use crate::PanelStyle;
+    pub use crate::ParallaxBackground;
+    pub use crate::PathFollower;
+    pub use crate::PlaneCamera;
+    pub use crate::Pool;
+    pub use crate::PostFx;
+    pub use crate::PromptStatus;
+    pub use crate::RenderQueue;
+    pub use crate::RewindBuffer;
+    pub use crate::RollingCounter;
+    pub use crate::SfxGenerator;
+    pub use crate::SfxKind;
+    pub use crate::Skeleton;
+    pub use crate::SkeletonAnimation;
+    pub use crate::SoundId;
+    pub use crate::SpatialHash;
     pub use crate::Sprite;
+    pub use crate::SpriteAtlas;
+    pub use crate::SpriteCompression;
+    pub use crate::SpriteSheet;
+    pub use crate::SpriteTransform;
+    pub use crate::StateMachine;
+    pub use crate::SystemSound;
+    pub use crate::Terrain;
+    pub use crate::TileMap;
+    pub use crate::TopDownController;
+    pub use crate::Trail;
+    pub use crate::Transition;
+    pub use crate::TurnManager;
+    pub use crate::TurnScheduler;
+    pub use crate::WaveDirector;
+    pub use crate::Waveform;
+    pub use crate::Weather;
+    pub use crate::{sprites_overlap, sprites_overlap_with};
+
+    pub use crate::gfx3d::{FlyCamera, Mat4, Mesh, OrbitCamera, Vec3};
 
     pub use crate::color::{BG_BLACK, BG_WHITE, FG_BLACK, FG_BLUE, FG_GREEN, FG_RED, FG_WHITE};
 
     pub use crate::pixel::{EMPTY, HALF, QUARTER, SOLID, THREE_QUARTERS};
 
+    pub use crate::key::Key;
     pub use crate::key::{A, ARROW_DOWN, ARROW_LEFT, ARROW_RIGHT, ARROW_UP, D, S, SPACE, W};
 
     pub use crate::mouse_button::{LEFT, MIDDLE, RIGHT};
@@ -654,6 +1278,8 @@ struct ConsoleState {
     font_cfi: CONSOLE_FONT_INFOEX,
     cursor_info: CONSOLE_CURSOR_INFO,
     console_mode: CONSOLE_MODE,
+    output_mode: CONSOLE_MODE,
+    color_table: Option<[COLORREF; 16]>,
 }
 
 impl ConsoleState {
@@ -684,6 +1310,11 @@ impl ConsoleState {
             GetConsoleMode(input_handle, &mut mode).expect("Failed to get console mode");
         }
 
+        let mut output_mode = CONSOLE_MODE(0);
+        unsafe {
+            GetConsoleMode(output_handle, &mut output_mode).ok();
+        }
+
         Self {
             screen_width: csbi.dwSize.X,
             screen_height: csbi.dwSize.Y,
@@ -691,6 +1322,8 @@ impl ConsoleState {
             font_cfi,
             cursor_info,
             console_mode: mode,
+            output_mode,
+            color_table: color_table(output_handle).ok(),
         }
     }
 
@@ -717,8 +1350,96 @@ impl ConsoleState {
             SetCurrentConsoleFontEx(output_handle, false, &self.font_cfi).ok();
             SetConsoleCursorInfo(output_handle, &self.cursor_info).ok();
             SetConsoleMode(input_handle, self.console_mode).ok();
+            SetConsoleMode(output_handle, self.output_mode).ok();
+        }
+        if let Some(table) = self.color_table {
+            set_color_table(output_handle, table).ok();
+        }
+    }
+}
+
+/// The console state to put back if the game panics, with the handles it belongs to.
+struct CrashGuard {
+    output_handle: usize,
+    input_handle: usize,
+    state: ConsoleState,
+    /// The thread running the game loop. Panics on other threads (audio, workers) don't
+    /// bring the game down, so they leave the console alone.
+    thread: thread::ThreadId,
+}
+
+static CRASH_GUARD: Mutex<Option<CrashGuard>> = Mutex::new(None);
+static PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+/// Set while `set_mouse_cursor_visible(false)` has the cursor hidden.
+static CURSOR_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Makes sure the console is restored to `state` if the game panics, before the panic
+/// message is printed, so the message shows up in a usable console instead of being
+/// drawn into (or cleared along with) the game screen. This also works with
+/// `panic = "abort"`, where `Drop` never runs.
+fn install_crash_guard(output_handle: HANDLE, input_handle: HANDLE, state: ConsoleState) {
+    *CRASH_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = Some(CrashGuard {
+        output_handle: output_handle.0 as usize,
+        input_handle: input_handle.0 as usize,
+        state,
+        thread: thread::current().id(),
+    });
+
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_after_crash();
+            previous(info);
+        }));
+    });
+}
+
+/// Forgets the state saved by `install_crash_guard`, once the console has been restored
+/// normally.
+fn remove_crash_guard() {
+    CRASH_GUARD.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Most `ShowCursor` calls `step_cursor_count` makes.
+const MAX_CURSOR_STEPS: usize = 32;
+
+/// Calls `ShowCursor` until the cursor is shown or hidden. It keeps a display count that
+/// shows the cursor at 0 and above, which other code may have moved far, so this gives up
+/// after `MAX_CURSOR_STEPS` calls rather than spinning.
+fn step_cursor_count(show: bool) {
+    for _ in 0..MAX_CURSOR_STEPS {
+        if (unsafe { ShowCursor(show) } >= 0) == show {
+            break;
+        }
+    }
+}
+
+fn restore_after_crash() {
+    let guard = {
+        let mut slot = CRASH_GUARD.lock().unwrap_or_else(|e| e.into_inner());
+        if slot
+            .as_ref()
+            .is_some_and(|g| g.thread == thread::current().id())
+        {
+            slot.take()
+        } else {
+            None
         }
+    };
+    let Some(guard) = guard else {
+        return;
+    };
+    let output_handle = HANDLE(guard.output_handle as *mut _);
+    let input_handle = HANDLE(guard.input_handle as *mut _);
+    unsafe {
+        // The game may have switched to another screen, captured the mouse or hidden it.
+        SetConsoleActiveScreenBuffer(output_handle).ok();
+        ClipCursor(None).ok();
+    }
+    if CURSOR_HIDDEN.swap(false, SeqCst) {
+        step_cursor_count(true);
     }
+    guard.state.restore(output_handle, input_handle);
 }
 
 // endregion
@@ -752,11 +1473,21 @@ impl Sprite {
     }
 
     /// Loads a sprite from a file (by convention ending in `.spr`).
-    /// The file must contain width and height (u32 little-endian) followed by colors and glyphs.
+    ///
+    /// Both the original format (width and height as u32 little-endian followed by colors and
+    /// glyphs) and the v2 format written by `save_to_file_with` are accepted.
     pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let mut file = File::open(path)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
+        Self::from_bytes(&buf)
+    }
+
+    /// Decodes a sprite from the contents of a `.spr` file.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if buf.starts_with(SPRITE_MAGIC) {
+            return Self::from_bytes_v2(buf);
+        }
 
         if buf.len() < 8 {
             return Err("sprite file too small".into());
@@ -772,20 +1503,72 @@ impl Sprite {
             return Err("sprite file truncated".into());
         }
 
-        let mut offset = 8;
-        let mut colors = Vec::with_capacity(count);
-        for _ in 0..count {
-            let v = u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap());
-            offset += 2;
-            colors.push(v);
+        let colors = read_plane(&buf[8..], count);
+        let glyphs = read_plane(&buf[8 + count * 2..], count);
+
+        Ok(Self {
+            width,
+            height,
+            glyphs,
+            colors,
+        })
+    }
+
+    fn from_bytes_v2(buf: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if buf.len() < 14 {
+            return Err("sprite file too small".into());
         }
 
-        let mut glyphs = Vec::with_capacity(count);
-        for _ in 0..count {
-            let v = u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap());
-            offset += 2;
-            glyphs.push(v);
+        let version = buf[4];
+        if version != 2 {
+            return Err(format!("unsupported sprite version {}", version).into());
         }
+        let compression = SpriteCompression::from_byte(buf[5])?;
+
+        let width = u32::from_le_bytes(buf[6..10].try_into().unwrap()) as usize;
+        let height = u32::from_le_bytes(buf[10..14].try_into().unwrap()) as usize;
+        let count = width
+            .checked_mul(height)
+            .ok_or("sprite dimensions overflow")?;
+        let payload = &buf[14..];
+        // Both planes, at 2 bytes per cell each.
+        let plane_bytes = count.checked_mul(4).ok_or("sprite dimensions overflow")?;
+
+        let (colors, glyphs) = match compression {
+            SpriteCompression::None => {
+                if payload.len() < plane_bytes {
+                    return Err("sprite file truncated".into());
+                }
+                (
+                    read_plane(payload, count),
+                    read_plane(&payload[count * 2..], count),
+                )
+            }
+            SpriteCompression::Rle => {
+                // Each 4-byte run covers at most u16::MAX cells.
+                if count > (payload.len() / 4).saturating_mul(u16::MAX as usize) {
+                    return Err("sprite file truncated".into());
+                }
+                let (colors, used) = rle_decode(payload, count)?;
+                let (glyphs, _) = rle_decode(&payload[used..], count)?;
+                (colors, glyphs)
+            }
+            #[cfg(feature = "compression")]
+            SpriteCompression::Deflate => {
+                if plane_bytes > payload.len().saturating_mul(MAX_DEFLATE_RATIO) {
+                    return Err("sprite file truncated".into());
+                }
+                let raw = miniz_oxide::inflate::decompress_to_vec_with_limit(payload, plane_bytes)
+                    .map_err(|e| format!("failed to inflate sprite: {:?}", e))?;
+                if raw.len() < plane_bytes {
+                    return Err("sprite file truncated".into());
+                }
+                (
+                    read_plane(&raw, count),
+                    read_plane(&raw[count * 2..], count),
+                )
+            }
+        };
 
         Ok(Self {
             width,
@@ -795,7 +1578,7 @@ impl Sprite {
         })
     }
 
-    /// Saves the sprite to a `.spr` file in the same format as `from_file`.
+    /// Saves the sprite to a `.spr` file in the original, uncompressed format.
     pub fn save_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let mut file = File::create(path)?;
         file.write_all(&(self.width as u32).to_le_bytes())?;
@@ -811,6 +1594,48 @@ impl Sprite {
         Ok(())
     }
 
+    /// Saves the sprite to a `.spr` file in the v2 format, compressing the color and glyph
+    /// planes with `compression`. The file can be loaded again with `from_file`.
+    pub fn save_to_file_with(
+        &self,
+        path: &str,
+        compression: SpriteCompression,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.to_bytes_v2(compression))?;
+        Ok(())
+    }
+
+    /// Encodes the sprite in the v2 `.spr` format.
+    pub fn to_bytes_v2(&self, compression: SpriteCompression) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SPRITE_MAGIC);
+        buf.push(2);
+        buf.push(compression as u8);
+        buf.extend_from_slice(&(self.width as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.height as u32).to_le_bytes());
+
+        match compression {
+            SpriteCompression::None => {
+                write_plane(&mut buf, &self.colors);
+                write_plane(&mut buf, &self.glyphs);
+            }
+            SpriteCompression::Rle => {
+                rle_encode(&mut buf, &self.colors);
+                rle_encode(&mut buf, &self.glyphs);
+            }
+            #[cfg(feature = "compression")]
+            SpriteCompression::Deflate => {
+                let mut raw = Vec::with_capacity(self.colors.len() * 4);
+                write_plane(&mut raw, &self.colors);
+                write_plane(&mut raw, &self.glyphs);
+                buf.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(&raw, 9));
+            }
+        }
+
+        buf
+    }
+
     /// Sets the glyph at `(x, y)` to `c`.
     pub fn set_glyph(&mut self, x: usize, y: usize, g: u16) {
         if x < self.width && y < self.height {
@@ -866,897 +1691,18412 @@ impl Sprite {
         let (sx, sy) = self.wrapped_sample_index(x, y);
         self.get_color(sx, sy)
     }
-}
-
-// endregion
-
-// region: Audio
-
-const CHUNK_SIZE: usize = 512;
-static NOTE_COUNTER: AtomicU64 = AtomicU64::new(0);
-
-#[derive(Clone)]
-enum AudioCommand {
-    LoadSample(String),
-    PlaySample(String),
-    LoadSampleFromBuffer(String, Vec<i16>),
-    NoteOn(f32),
-    NoteOff(f32),
-    Quit,
-}
-
-struct PlayingSound {
-    data: Vec<i16>,
-    cursor: usize,
-}
 
-struct PlayingNote {
-    freq: f32,
-    phase: f32,
-    amplitude: f32,
-    target_amp: f32,
-    step: f32,
-    active: bool,
-}
-
-/// Audio engine used through  the `ConsoleGameEngine`.
-///
-/// Handles asynchronous playback of WAV files and synthesized notes.
-///
-/// Users can interact with it via the audio field in the `ConsoleGameEngine`:
-///
-/// ```rust
-/// engine.audio.load_sample("explosion.wav");
-/// engine.audio.play_sample("explosion.wav");
-/// engine.audio.play_note(A4, 500);
-/// engine.audio.play_notes(&[A4, C_SHARP5, E5], 1000);
-/// engine.audio.note_on(A4);
-/// engine.audio.note_off(A4);
-/// ```
-#[derive(Clone)]
-pub struct AudioEngine {
-    tx: Sender<AudioCommand>,
-}
-
-impl AudioEngine {
-    #[allow(clippy::new_without_default)]
-    fn new() -> Self {
-        let (tx, rx) = mpsc::channel::<AudioCommand>();
-
-        thread::spawn(move || {
-            let format = WAVEFORMATEX {
-                wFormatTag: WAVE_FORMAT_PCM as u16,
-                nChannels: 2,
-                nSamplesPerSec: 44100,
-                nAvgBytesPerSec: 44100 * 2 * 2,
-                nBlockAlign: 4,
-                wBitsPerSample: 16,
-                cbSize: 0,
-            };
-
-            let mut h_waveout = HWAVEOUT::default();
-            unsafe {
-                let res = waveOutOpen(
-                    Some(&mut h_waveout),
-                    WAVE_MAPPER,
-                    &format,
-                    None,
-                    Some(0),
-                    CALLBACK_NULL,
-                );
-
-                if res != MMSYSERR_NOERROR {
-                    eprintln!("Failed to open audio device: {}", res);
-                    return;
+    /// Converts an RGB image (3 bytes per pixel, row by row) into a sprite.
+    ///
+    /// Every pixel becomes the console color, or blend of two colors using a shade glyph,
+    /// closest to it. Ordered dithering is applied so gradients don't band.
+    pub fn from_rgb(width: usize, height: usize, rgb: &[u8]) -> Sprite {
+        let mut candidates = Vec::with_capacity(16 + 16 * 15 * 3);
+        for fg in 0..16u16 {
+            candidates.push((CONSOLE_RGB[fg as usize], SOLID, fg));
+            for bg in (0..16u16).filter(|&bg| bg != fg) {
+                for (glyph, cover) in [(QUARTER, 0.25), (HALF, 0.5), (THREE_QUARTERS, 0.75)] {
+                    let [fr, fg_, fb] = CONSOLE_RGB[fg as usize].map(f32::from);
+                    let [br, bg_, bb] = CONSOLE_RGB[bg as usize].map(f32::from);
+                    let mix = |f: f32, b: f32| (f * cover + b * (1.0 - cover)) as u8;
+                    candidates.push((
+                        [mix(fr, br), mix(fg_, bg_), mix(fb, bb)],
+                        glyph,
+                        fg | (bg << 4),
+                    ));
                 }
             }
+        }
 
-            let mut samples = HashMap::new();
-            let mut active_sounds = Vec::new();
-            let mut active_notes = Vec::new();
-
-            'audio_loop: loop {
-                while let Ok(cmd) = rx.try_recv() {
-                    match cmd {
-                        AudioCommand::LoadSample(path) => {
-                            if let Ok(data) = AudioEngine::load_wav(&path) {
-                                samples.insert(path, data);
-                            }
-                        }
-                        AudioCommand::LoadSampleFromBuffer(key, buffer) => {
-                            samples.insert(key, buffer);
-                        }
-                        AudioCommand::PlaySample(path) => {
-                            if let Some(data) = samples.get(&path) {
-                                active_sounds.push(PlayingSound {
-                                    data: data.clone(),
-                                    cursor: 0,
-                                });
-                            }
-                        }
-                        AudioCommand::NoteOn(freq) => {
-                            let sample_rate = 44100.0;
-                            let attack_samples = 100;
-                            let mut buffer = vec![0i16; attack_samples * 2];
-                            for i in 0..attack_samples {
-                                let t = i as f32 / sample_rate;
-                                let s = ((2.0 * PI * freq * t).sin() * i16::MAX as f32 * 0.1)
-                                    .clamp(i16::MIN as f32, i16::MAX as f32);
-                                buffer[i * 2] = s as i16;
-                                buffer[i * 2 + 1] = s as i16;
-                            }
-                            AudioEngine::play_buffer(h_waveout, buffer);
-
-                            let attack_ms = 50.0;
-                            let step = 1.0 / (44100.0 * (attack_ms / 1000.0));
-                            active_notes.push(PlayingNote {
-                                freq,
-                                phase: 0.0,
-                                amplitude: 0.0,
-                                target_amp: 1.0,
-                                step,
-                                active: true,
-                            });
-                        }
-                        AudioCommand::NoteOff(freq) => {
-                            let sample_rate = 44100.0;
-                            let release_samples = 100;
-                            let mut buffer = vec![0i16; release_samples * 2];
-
-                            for i in 0..release_samples {
-                                let t = i as f32 / sample_rate;
-                                let s = ((2.0 * PI * freq * t).sin() * i16::MAX as f32 * 0.05)
-                                    .clamp(i16::MIN as f32, i16::MAX as f32);
-                                buffer[i * 2] = s as i16;
-                                buffer[i * 2 + 1] = s as i16;
-                            }
-                            AudioEngine::play_buffer(h_waveout, buffer);
-
-                            for note in active_notes.iter_mut() {
-                                if (note.freq - freq).abs() < f32::EPSILON && note.active {
-                                    let release_ms = 50.0;
-                                    note.target_amp = 0.0;
-                                    note.step = -(1.0 / (44100.0 * (release_ms / 1000.0)));
-                                }
-                            }
-                        }
-                        AudioCommand::Quit => break 'audio_loop,
-                    }
-                }
-
-                let mut mix_buffer = vec![0i32; CHUNK_SIZE * 2];
-
-                for sound in active_sounds.iter_mut() {
-                    for i in 0..CHUNK_SIZE {
-                        let idx = i * 2;
-                        if sound.cursor + 1 < sound.data.len() {
-                            mix_buffer[idx] += sound.data[sound.cursor] as i32;
-                            mix_buffer[idx + 1] += sound.data[sound.cursor + 1] as i32;
-                            sound.cursor += 2;
-                        }
-                    }
-                }
-
-                let sample_rate = 44100.0;
-                let max_notes = active_notes.len().max(1) as f32;
-
-                for note in active_notes.iter_mut().filter(|n| n.active) {
-                    let step = 2.0 * PI * note.freq / sample_rate;
+        let mut sprite = Sprite::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * width + x) * 3;
+                let Some(px) = rgb.get(i..i + 3) else {
+                    continue;
+                };
+                let offset = (dither_threshold(x as i32, y as i32) - 0.5) * 32.0;
+                let target = [0, 1, 2].map(|c| (px[c] as f32 + offset).clamp(0.0, 255.0));
+
+                let (_, glyph, col) = candidates
+                    .iter()
+                    .min_by(|a, b| {
+                        let dist = |c: &[u8; 3]| {
+                            (0..3)
+                                .map(|k| (c[k] as f32 - target[k]).powi(2))
+                                .sum::<f32>()
+                        };
+                        dist(&a.0).total_cmp(&dist(&b.0))
+                    })
+                    .copied()
+                    .unwrap();
+
+                sprite.set_glyph(x, y, glyph);
+                sprite.set_color(x, y, col);
+            }
+        }
 
-                    for i in 0..CHUNK_SIZE {
-                        let idx = i * 2;
+        sprite
+    }
 
-                        if (note.step > 0.0 && note.amplitude < note.target_amp)
-                            || (note.step < 0.0 && note.amplitude > note.target_amp)
-                        {
-                            note.amplitude = (note.amplitude + note.step).clamp(0.0, 1.0);
-                        } else if note.target_amp == 0.0 {
-                            note.active = false;
-                        }
+    /// Draws a single cell at `(x, y)` with the specified glyph and color.
+    /// Cells outside the sprite are ignored.
+    pub fn draw_with(&mut self, x: i32, y: i32, c: u16, col: u16) {
+        if x >= 0 && y >= 0 {
+            self.set_glyph(x as usize, y as usize, c);
+            self.set_color(x as usize, y as usize, col);
+        }
+    }
 
-                        let s = (note.phase).sin() * note.amplitude * (0.3 / max_notes);
+    /// Fills the entire sprite with the given glyph and color.
+    pub fn clear(&mut self, c: u16, col: u16) {
+        self.glyphs.fill(c);
+        self.colors.fill(col);
+    }
 
-                        note.phase += step;
-                        if note.phase > PI * 2.0 {
-                            note.phase -= PI * 2.0;
-                        }
+    /// Draws a string starting at `(x, y)` with the specified color.
+    pub fn draw_string_with(&mut self, x: i32, y: i32, text: &str, col: u16) {
+        for (i, ch, flags) in layout_text(text) {
+            self.draw_with(x + i, y, ch, col | flags);
+        }
+    }
 
-                        let si = (s * i16::MAX as f32) as i16;
-                        mix_buffer[idx] += si as i32;
-                        mix_buffer[idx + 1] += si as i32;
-                    }
-                }
+    /// Draws a string at `(x, y)` ignoring spaces (transparent spaces), using the specified color.
+    pub fn draw_string_alpha_with(&mut self, x: i32, y: i32, text: &str, col: u16) {
+        for (i, ch, flags) in layout_text(text) {
+            if ch != ' ' as u16 {
+                self.draw_with(x + i, y, ch, col | flags);
+            }
+        }
+    }
 
-                let final_buffer: Vec<i16> = mix_buffer
-                    .into_iter()
-                    .map(|s| s.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
-                    .collect();
+    /// Draws a line from `(x1, y1)` to `(x2, y2)` with the specified glyph and color.
+    pub fn draw_line_with(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, c: u16, col: u16) {
+        plot_line(x1, y1, x2, y2, |x, y| self.draw_with(x, y, c, col));
+    }
 
-                AudioEngine::play_buffer(h_waveout, final_buffer);
+    /// Draws a rectangle at `(x, y)` with width `w` and height `h` using the specified glyph and color.
+    pub fn draw_rectangle_with(&mut self, x: i32, y: i32, w: i32, h: i32, c: u16, col: u16) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
 
-                active_sounds.retain(|s| s.cursor < s.data.len());
-                active_notes.retain(|n| n.active);
+        self.draw_line_with(x, y, x + w - 1, y, c, col);
+        self.draw_line_with(x, y + h - 1, x + w - 1, y + h - 1, c, col);
+        self.draw_line_with(x, y, x, y + h - 1, c, col);
+        self.draw_line_with(x + w - 1, y, x + w - 1, y + h - 1, c, col);
+    }
 
-                thread::sleep(std::time::Duration::from_millis(10));
+    /// Fills a rectangle from `(x1, y1)` to `(x2, y2)` with the specified glyph and color.
+    pub fn fill_rect_with(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, c: u16, col: u16) {
+        for x in x1.max(0)..x2.min(self.width as i32) {
+            for y in y1.max(0)..y2.min(self.height as i32) {
+                self.draw_with(x, y, c, col);
             }
-        });
-
-        Self { tx }
+        }
     }
 
-    /// Loads a WAV file asynchronously.
-    ///
-    /// The sample can later be played using `play_sample`.
-    /// The path is used as the key to identify the sample.
-    /// Normally used in the `create` function when implementing the `ConsoleGame` trait.
-    pub fn load_sample<P: AsRef<Path>>(&self, path: P) {
-        let _ = self.tx.send(AudioCommand::LoadSample(
-            path.as_ref().to_string_lossy().into(),
-        ));
+    /// Draws another sprite onto this one at position `(x, y)`, skipping `PIXEL_EMPTY` cells.
+    pub fn draw_sprite(&mut self, x: i32, y: i32, sprite: &Sprite) {
+        self.draw_partial_sprite(x, y, sprite, 0, 0, sprite.width, sprite.height);
     }
 
-    /// Plays a previously loaded sample asynchronously.
-    ///
-    /// Multiple instances of the same sample can play simultaneously.
-    pub fn play_sample<P: AsRef<Path>>(&self, path: P) {
-        let _ = self.tx.send(AudioCommand::PlaySample(
-            path.as_ref().to_string_lossy().into(),
-        ));
+    /// Draws another sprite onto this one at position `(x, y)` using the given blend mode.
+    pub fn draw_sprite_blend(&mut self, x: i32, y: i32, sprite: &Sprite, mode: BlendMode) {
+        self.draw_partial_sprite_blend(x, y, sprite, 0, 0, sprite.width, sprite.height, mode);
     }
 
-    /// Generates and plays a single note of the given frequency (Hz) and duration (ms).
-    ///
-    /// Useful for procedural audio or simple effects.
-    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
-    pub fn play_note(&self, frequency: f32, duration_ms: u32) {
-        let sample_rate = 44100;
-        let sample_count = ((duration_ms as f32 / 1000.0) * sample_rate as f32) as usize;
-        if sample_count == 0 {
-            return;
-        }
+    /// Draws a portion of another sprite onto this one at position `(x, y)`,
+    /// skipping `PIXEL_EMPTY` cells. See `ConsoleGameEngine::draw_partial_sprite`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_partial_sprite(
+        &mut self,
+        x: i32,
+        y: i32,
+        sprite: &Sprite,
+        ox: usize,
+        oy: usize,
+        w: usize,
+        h: usize,
+    ) {
+        self.draw_partial_sprite_blend(x, y, sprite, ox, oy, w, h, BlendMode::Mask);
+    }
 
-        let mut mono = vec![0f32; sample_count];
-        for (n, v) in mono.iter_mut().enumerate() {
-            let t = n as f32 / sample_rate as f32;
-            *v = (t * frequency * 2.0 * PI).sin();
+    /// Draws a portion of another sprite onto this one at position `(x, y)` using the given
+    /// blend mode.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_partial_sprite_blend(
+        &mut self,
+        x: i32,
+        y: i32,
+        sprite: &Sprite,
+        ox: usize,
+        oy: usize,
+        w: usize,
+        h: usize,
+        mode: BlendMode,
+    ) {
+        for i in 0..w {
+            for j in 0..h {
+                let (dx, dy) = (x + i as i32, y + j as i32);
+                if dx < 0 || dy < 0 {
+                    continue;
+                }
+                let glyph = sprite.get_glyph(i + ox, j + oy);
+                let color = sprite.get_color(i + ox, j + oy);
+                let dst_col = self.get_color(dx as usize, dy as usize);
+                if let Some((g, c)) = mode.blend(glyph, color, dst_col) {
+                    self.draw_with(dx, dy, g, c);
+                }
+            }
         }
+    }
 
-        Self::apply_attack_release(&mut mono, sample_rate, duration_ms);
-
-        let mut stereo = vec![0i16; sample_count * 2];
-        let scale = 0.95;
-        for (n, &v) in mono.iter().enumerate() {
-            let s = (v * scale * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
-            let si = s as i16;
-            stereo[n * 2] = si;
-            stereo[n * 2 + 1] = si;
+    /// Returns a copy of the `w`×`h` region starting at `(x, y)`.
+    /// Parts of the region outside the sprite are filled with `PIXEL_EMPTY` and `FG_BLACK`.
+    pub fn sub_sprite(&self, x: usize, y: usize, w: usize, h: usize) -> Sprite {
+        let mut out = Sprite::new(w, h);
+        for j in 0..h {
+            for i in 0..w {
+                out.set_glyph(i, j, self.get_glyph(x + i, y + j));
+                out.set_color(i, j, self.get_color(x + i, y + j));
+            }
         }
-
-        let key = Self::generate_unique_key();
-        let _ = self
-            .tx
-            .send(AudioCommand::LoadSampleFromBuffer(key.clone(), stereo));
-        let _ = self.tx.send(AudioCommand::PlaySample(key));
+        out
     }
 
-    /// Generates and plays multiple notes simultaneously (like a chord).
-    ///
-    /// Each frequency in `freqs` is mixed together, scaled to avoid clipping,
-    /// and played for the given duration (ms).
-    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
-    pub fn play_notes(&self, freqs: &[f32], duration_ms: u32) {
-        if freqs.is_empty() {
-            return;
+    /// Returns a copy of the sprite scaled to `w`×`h` using nearest-neighbour lookup.
+    pub fn resized(&self, w: usize, h: usize) -> Sprite {
+        let mut out = Sprite::new(w, h);
+        if self.width == 0 || self.height == 0 {
+            return out;
         }
-        let sample_rate = 44100u32;
-        let sample_count = ((duration_ms as f32 / 1000.0) * sample_rate as f32) as usize;
-        if sample_count == 0 {
-            return;
+        for j in 0..h {
+            let sy = j * self.height / h;
+            for i in 0..w {
+                let sx = i * self.width / w;
+                out.set_glyph(i, j, self.get_glyph(sx, sy));
+                out.set_color(i, j, self.get_color(sx, sy));
+            }
         }
+        out
+    }
 
-        let mut mono = vec![0f32; sample_count];
-        for &freq in freqs {
-            for (n, v) in mono.iter_mut().enumerate() {
-                let t = n as f32 / sample_rate as f32;
-                *v += (t * freq * 2.0 * PI).sin();
+    /// Returns a copy of the sprite scaled to `w`×`h` by sampling the centre of each
+    /// destination cell with `sample_glyph` and `sample_color`.
+    pub fn resized_sampled(&self, w: usize, h: usize) -> Sprite {
+        let mut out = Sprite::new(w, h);
+        if self.width == 0 || self.height == 0 {
+            return out;
+        }
+        for j in 0..h {
+            let v = (j as f32 + 0.5) / h as f32;
+            for i in 0..w {
+                let u = (i as f32 + 0.5) / w as f32;
+                out.set_glyph(i, j, self.sample_glyph(u, v));
+                out.set_color(i, j, self.sample_color(u, v));
             }
         }
+        out
+    }
 
-        let max_possible = freqs.len() as f32;
-        let scale = 0.9 / max_possible;
-        for v in mono.iter_mut() {
-            *v *= scale;
+    /// Returns a copy of the sprite rotated 90 degrees clockwise.
+    pub fn rotated_90(&self) -> Sprite {
+        let mut out = Sprite::new(self.height, self.width);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let nx = self.height - 1 - y;
+                out.set_glyph(nx, x, self.get_glyph(x, y));
+                out.set_color(nx, x, self.get_color(x, y));
+            }
         }
+        out
+    }
 
-        Self::apply_attack_release(&mut mono, sample_rate, duration_ms);
-
-        let mut stereo = vec![0i16; sample_count * 2];
-        for (n, &v) in mono.iter().enumerate() {
-            let s = (v * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
-            let si = s as i16;
-            stereo[n * 2] = si;
-            stereo[n * 2 + 1] = si;
+    /// Returns a copy of the sprite mirrored horizontally.
+    pub fn flipped(&self) -> Sprite {
+        let mut out = Sprite::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let nx = self.width - 1 - x;
+                out.set_glyph(nx, y, self.get_glyph(x, y));
+                out.set_color(nx, y, self.get_color(x, y));
+            }
         }
-
-        let key = Self::generate_unique_key();
-        let _ = self
-            .tx
-            .send(AudioCommand::LoadSampleFromBuffer(key.clone(), stereo));
-        let _ = self.tx.send(AudioCommand::PlaySample(key));
+        out
     }
 
-    /// Starts playing a note of the given frequency (Hz) immediately.
-    ///
-    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
-    pub fn note_on(&self, freq: f32) {
-        let _ = self.tx.send(AudioCommand::NoteOn(freq));
+    /// Returns a copy of the sprite mirrored vertically.
+    pub fn flipped_vertical(&self) -> Sprite {
+        let mut out = Sprite::new(self.width, self.height);
+        for y in 0..self.height {
+            let ny = self.height - 1 - y;
+            for x in 0..self.width {
+                out.set_glyph(x, ny, self.get_glyph(x, y));
+                out.set_color(x, ny, self.get_color(x, y));
+            }
+        }
+        out
     }
+}
 
-    /// Stops a previously started note of the given frequency (Hz).
-    ///
-    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
-    /// and with `note_on` to control sustained notes.
-    pub fn note_off(&self, freq: f32) {
-        let _ = self.tx.send(AudioCommand::NoteOff(freq));
-    }
+const SPRITE_MAGIC: &[u8; 4] = b"RSPR";
+
+/// Approximate RGB values of the 16 default console colors, indexed by color attribute.
+const CONSOLE_RGB: [[u8; 3]; 16] = [
+    [12, 12, 12],
+    [0, 55, 218],
+    [19, 161, 14],
+    [58, 150, 221],
+    [197, 15, 31],
+    [136, 23, 152],
+    [193, 156, 0],
+    [204, 204, 204],
+    [118, 118, 118],
+    [59, 120, 255],
+    [22, 198, 12],
+    [97, 214, 214],
+    [231, 72, 86],
+    [180, 0, 158],
+    [249, 241, 165],
+    [242, 242, 242],
+];
+
+/// The most deflate can compress data by, used to reject sprite headers whose size the
+/// payload couldn't possibly hold.
+#[cfg(feature = "compression")]
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// How the color and glyph planes of a v2 `.spr` file are stored.
+///
+/// `Deflate` only exists with the `compression` feature, so matches outside the crate need
+/// a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum SpriteCompression {
+    /// Planes are stored as-is.
+    None = 0,
+    /// Each plane is stored as runs of `(count: u16, value: u16)`.
+    Rle = 1,
+    /// Both planes are deflate-compressed together. Requires the `compression` feature.
+    #[cfg(feature = "compression")]
+    Deflate = 2,
+}
 
-    fn apply_attack_release(buffer: &mut [f32], sample_rate: u32, duration_ms: u32) {
-        let len = buffer.len();
-        if len == 0 {
-            return;
+impl SpriteCompression {
+    fn from_byte(b: u8) -> Result<Self, Box<dyn std::error::Error>> {
+        match b {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Rle),
+            #[cfg(feature = "compression")]
+            2 => Ok(Self::Deflate),
+            #[cfg(not(feature = "compression"))]
+            2 => Err("deflate-compressed sprites require the `compression` feature".into()),
+            _ => Err(format!("unknown sprite compression {}", b).into()),
         }
+    }
+}
 
-        let ramp_pct = 0.10;
-        let ramp_samps =
-            ((duration_ms as f32 / 1000.0) * sample_rate as f32 * ramp_pct).round() as usize;
-        let ramp_samps = ramp_samps.min(len / 2);
+fn read_plane(buf: &[u8], count: usize) -> Vec<u16> {
+    buf.chunks_exact(2)
+        .take(count)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
 
-        for (i, v) in buffer.iter_mut().take(ramp_samps).enumerate() {
-            *v *= i as f32 / ramp_samps.max(1) as f32;
-        }
+fn write_plane(buf: &mut Vec<u8>, plane: &[u16]) {
+    for &v in plane {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+}
 
-        for i in 0..ramp_samps {
-            let idx = len - ramp_samps + i;
-            buffer[idx] *= 1.0 - (i as f32 / ramp_samps as f32);
+fn rle_encode(buf: &mut Vec<u8>, plane: &[u16]) {
+    let mut i = 0;
+    while i < plane.len() {
+        let value = plane[i];
+        let mut run = 1;
+        while i + run < plane.len() && plane[i + run] == value && run < u16::MAX as usize {
+            run += 1;
         }
+        buf.extend_from_slice(&(run as u16).to_le_bytes());
+        buf.extend_from_slice(&value.to_le_bytes());
+        i += run;
     }
+}
 
-    fn generate_unique_key() -> String {
-        let id = NOTE_COUNTER.fetch_add(1, Relaxed);
-        format!("__temp_notes_{}", id)
+fn rle_decode(buf: &[u8], count: usize) -> Result<(Vec<u16>, usize), Box<dyn std::error::Error>> {
+    let mut plane = Vec::new();
+    let mut offset = 0;
+    while plane.len() < count {
+        let run = buf.get(offset..offset + 4).ok_or("sprite file truncated")?;
+        let len = u16::from_le_bytes([run[0], run[1]]) as usize;
+        let value = u16::from_le_bytes([run[2], run[3]]);
+        if len == 0 || plane.len() + len > count {
+            return Err("corrupt sprite run".into());
+        }
+        plane.resize(plane.len() + len, value);
+        offset += 4;
     }
+    Ok((plane, offset))
+}
 
-    fn load_wav(path: &str) -> std::io::Result<Vec<i16>> {
-        let mut file = File::open(path)?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-
-        let data_start = buf.windows(4).position(|w| w == b"data").unwrap() + 8;
-        let samples: Vec<i16> = buf[data_start..]
-            .chunks_exact(2)
-            .map(|b| i16::from_le_bytes([b[0], b[1]]))
-            .collect();
+/// How sprite cells are combined with what is already drawn underneath them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Skips `PIXEL_EMPTY` cells and copies everything else.
+    #[default]
+    Mask,
+    /// Copies every cell, including `PIXEL_EMPTY` ones.
+    Opaque,
+    /// Like `Mask`, but keeps the background color of the destination cell so only the
+    /// glyph and foreground color are replaced.
+    KeepBackground,
+    /// Skips cells whose glyph equals the given key and copies everything else.
+    ColorKey(u16),
+}
 
-        Ok(samples)
+impl BlendMode {
+    /// Combines a source cell with the destination cell underneath it.
+    /// Returns `None` if the destination should be left unchanged.
+    pub fn blend(self, glyph: u16, col: u16, dst_col: u16) -> Option<(u16, u16)> {
+        match self {
+            BlendMode::Mask => (glyph != EMPTY).then_some((glyph, col)),
+            BlendMode::Opaque => Some((glyph, col)),
+            BlendMode::KeepBackground => {
+                (glyph != EMPTY).then_some((glyph, (col & 0x000F) | (dst_col & 0x00F0)))
+            }
+            BlendMode::ColorKey(key) => (glyph != key).then_some((glyph, col)),
+        }
     }
+}
 
-    fn play_buffer(h_waveout: HWAVEOUT, data: Vec<i16>) {
-        let boxed_data = Box::new(data);
-        let raw_data = Box::into_raw(boxed_data);
+/// Calls `plot` for every point on the line from `(x1, y1)` to `(x2, y2)`.
+fn plot_line(x1: i32, y1: i32, x2: i32, y2: i32, mut plot: impl FnMut(i32, i32)) {
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let dx1 = dx.abs();
+    let dy1 = dy.abs();
+    let mut px = 2 * dy1 - dx1;
+    let mut py = 2 * dx1 - dy1;
 
-        let mut hdr = Box::new(WAVEHDR {
-            lpData: PSTR(unsafe { (*raw_data).as_ptr() as *mut u8 }),
-            dwBufferLength: (unsafe { (*raw_data).len() * 2 } as u32),
-            dwFlags: 0,
-            dwLoops: 0,
-            dwUser: raw_data as usize,
-            ..Default::default()
-        });
+    if dy1 <= dx1 {
+        let (mut x, mut y, xe) = if dx >= 0 { (x1, y1, x2) } else { (x2, y2, x1) };
+        plot(x, y);
 
-        unsafe {
-            waveOutPrepareHeader(h_waveout, &mut *hdr, std::mem::size_of::<WAVEHDR>() as u32);
-            waveOutWrite(h_waveout, &mut *hdr, std::mem::size_of::<WAVEHDR>() as u32);
+        while x < xe {
+            x += 1;
+            if px < 0 {
+                px += 2 * dy1;
+            } else {
+                if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
+                    y += 1;
+                } else {
+                    y -= 1;
+                }
+                px += 2 * (dy1 - dx1);
+            }
+            plot(x, y);
         }
+    } else {
+        let (mut x, mut y, ye) = if dy >= 0 { (x1, y1, y2) } else { (x2, y2, y1) };
+        plot(x, y);
 
-        let _ = Box::into_raw(hdr);
+        while y < ye {
+            y += 1;
+            if py <= 0 {
+                py += 2 * dx1;
+            } else {
+                if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
+                    x += 1;
+                } else {
+                    x -= 1;
+                }
+                py += 2 * (dx1 - dy1);
+            }
+            plot(x, y);
+        }
     }
 }
 
-impl Drop for AudioEngine {
-    fn drop(&mut self) {
-        let _ = self.tx.send(AudioCommand::Quit);
+/// How a sprite is flipped and scaled when testing for collisions with `sprites_overlap_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteTransform {
+    /// Mirror the sprite horizontally.
+    pub flip_x: bool,
+    /// Mirror the sprite vertically.
+    pub flip_y: bool,
+    /// Integer scale factor. Every sprite cell covers `scale`×`scale` screen cells.
+    pub scale: usize,
+}
+
+impl Default for SpriteTransform {
+    fn default() -> Self {
+        Self {
+            flip_x: false,
+            flip_y: false,
+            scale: 1,
+        }
     }
 }
 
-// endregion
+/// Returns `true` if sprite `a` drawn at `(ax, ay)` and sprite `b` drawn at `(bx, by)` have
+/// at least one non-`PIXEL_EMPTY` cell in the same place.
+pub fn sprites_overlap(a: &Sprite, ax: i32, ay: i32, b: &Sprite, bx: i32, by: i32) -> bool {
+    let t = SpriteTransform::default();
+    sprites_overlap_with(a, ax, ay, t, b, bx, by, t)
+}
 
-// region: Engine
+/// Like `sprites_overlap`, but with each sprite flipped and scaled by its transform first.
+#[allow(clippy::too_many_arguments)]
+pub fn sprites_overlap_with(
+    a: &Sprite,
+    ax: i32,
+    ay: i32,
+    ta: SpriteTransform,
+    b: &Sprite,
+    bx: i32,
+    by: i32,
+    tb: SpriteTransform,
+) -> bool {
+    let size = |s: &Sprite, t: SpriteTransform| {
+        let scale = t.scale.max(1) as i32;
+        (s.width as i32 * scale, s.height as i32 * scale)
+    };
+    let (aw, ah) = size(a, ta);
+    let (bw, bh) = size(b, tb);
+
+    let (x1, y1) = (ax.max(bx), ay.max(by));
+    let (x2, y2) = ((ax + aw).min(bx + bw), (ay + ah).min(by + bh));
+
+    let solid = |s: &Sprite, t: SpriteTransform, ox: i32, oy: i32, x: i32, y: i32| {
+        let scale = t.scale.max(1) as i32;
+        let mut lx = ((x - ox) / scale) as usize;
+        let mut ly = ((y - oy) / scale) as usize;
+        if t.flip_x {
+            lx = s.width - 1 - lx;
+        }
+        if t.flip_y {
+            ly = s.height - 1 - ly;
+        }
+        s.get_glyph(lx, ly) != EMPTY
+    };
 
-static RUNNING: AtomicBool = AtomicBool::new(true);
+    (y1..y2).any(|y| (x1..x2).any(|x| solid(a, ta, ax, ay, x, y) && solid(b, tb, bx, by, x, y)))
+}
 
-unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
-    if ctrl_type == CTRL_CLOSE_EVENT {
-        RUNNING.store(false, SeqCst);
+/// A rectangular region of a sprite, in characters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// Left edge of the region.
+    pub x: usize,
+    /// Top edge of the region.
+    pub y: usize,
+    /// Width of the region.
+    pub w: usize,
+    /// Height of the region.
+    pub h: usize,
+}
+
+impl Rect {
+    /// Creates a new rectangle.
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> Self {
+        Self { x, y, w, h }
     }
-    BOOL(1)
 }
 
-/// Trait that defines the behavior of a game to be run by the `ConsoleGameEngine`.
+/// A named sequence of frames in a [`SpriteSheet`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    /// The frames of the animation, in order.
+    pub frames: Vec<Rect>,
+    /// Playback speed in frames per second.
+    pub fps: f32,
+}
+
+impl Animation {
+    /// Returns the frame shown `time` seconds after the animation started, looping forever.
+    pub fn frame_at(&self, time: f32) -> Option<Rect> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let index = (time.max(0.0) * self.fps) as usize % self.frames.len();
+        Some(self.frames[index])
+    }
+}
+
+/// A sprite together with named frames and animations inside it.
 ///
-/// To create a game, define a struct containing your game state and implement this trait
-/// for it. The engine will call the provided methods during the game loop.
-pub trait ConsoleGame: Sized {
-    /// Sets the name of the game or application.
-    /// The default name is "Default"
-    ///
-    /// Override this method to set a custom title for your game.
-    fn app_name(&self) -> &str {
-        "Default"
+/// Sprite sheets are loaded from a `.sprsheet` file sitting next to the `.spr` it describes:
+///
+/// ```toml
+/// sprite = "jario.spr"
+///
+/// [frames]
+/// jario_run_1 = [0, 0, 16, 16]   # x, y, width, height
+/// jario_run_2 = [16, 0, 16, 16]
+///
+/// [animations]
+/// jario_run = ["jario_run_1", "jario_run_2"]
+///
+/// [fps]
+/// jario_run = 8
+/// ```
+///
+/// Animations play at 10 frames per second unless listed in the `[fps]` section.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let sheet = SpriteSheet::from_file("examples/sprites/jario.sprsheet")?;
+/// engine.draw_sheet_frame(10, 10, &sheet, "jario_stand_right");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpriteSheet {
+    /// The sprite all frames are taken from.
+    pub sprite: Sprite,
+    frames: HashMap<String, Rect>,
+    animations: HashMap<String, Animation>,
+}
+
+impl SpriteSheet {
+    /// Creates a sprite sheet for `sprite` without any frames or animations.
+    pub fn new(sprite: Sprite) -> Self {
+        Self {
+            sprite,
+            ..Default::default()
+        }
     }
 
-    /// Called once when the game starts.
-    ///
-    /// Use this method to initialize your game state, load sprites, set variables, etc.
-    ///
-    /// # Parameters
-    /// * `engine` - A mutable reference to the running `ConsoleGameEngine`. You can use
-    ///   this to query the screen, input, or draw anything immediately if needed.
-    ///
-    /// # Returns
-    /// Return `true` to continue running the game, or `false` to immediately exit.
-    fn create(&mut self, engine: &mut ConsoleGameEngine<Self>) -> bool;
+    /// Loads a `.sprsheet` file and the sprite it refers to.
+    /// The sprite path is relative to the directory of the `.sprsheet` file.
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
 
-    /// Called once per frame to update the game state and render.
-    ///
-    /// This is where the main game logic should live: moving objects, handling input,
-    /// checking collisions, drawing, etc.
-    ///
-    /// # Parameters
-    /// * `engine` - A mutable reference to the `ConsoleGameEngine`. Use it to draw shapes,
-    ///   sprites, and query input.
-    /// * `elapsed_time` - Time (in seconds) since the last frame. Useful for smooth movement
-    ///   and animations.
+        let mut sprite_path = None;
+        let mut frames = HashMap::new();
+        let mut animation_frames: Vec<(String, Vec<String>)> = Vec::new();
+        let mut fps: HashMap<String, f32> = HashMap::new();
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = Self::strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `name = value`", n + 1))?;
+            let (name, value) = (name.trim().to_string(), value.trim());
+            let bad_value = || format!("line {}: invalid value for `{}`", n + 1, name);
+
+            match section.as_str() {
+                "" if name == "sprite" => {
+                    sprite_path = Some(Self::parse_string(value).ok_or_else(bad_value)?);
+                }
+                "frames" => {
+                    let values = Self::parse_list(value).ok_or_else(bad_value)?;
+                    let nums: Vec<usize> = values
+                        .iter()
+                        .map(|v| v.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| bad_value())?;
+                    if nums.len() != 4 {
+                        return Err(bad_value().into());
+                    }
+                    frames.insert(name, Rect::new(nums[0], nums[1], nums[2], nums[3]));
+                }
+                "animations" => {
+                    let values = Self::parse_list(value).ok_or_else(bad_value)?;
+                    let names = values
+                        .iter()
+                        .map(|v| Self::parse_string(v))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(bad_value)?;
+                    animation_frames.push((name, names));
+                }
+                "fps" => {
+                    let value = value.parse().map_err(|_| bad_value())?;
+                    fps.insert(name, value);
+                }
+                _ => {}
+            }
+        }
+
+        let sprite_path = sprite_path.ok_or("sprite sheet doesn't name a sprite")?;
+        let sprite_path = Path::new(path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(sprite_path);
+        let sprite = Sprite::from_file(&sprite_path.to_string_lossy())?;
+
+        let mut animations = HashMap::new();
+        for (name, names) in animation_frames {
+            let rects = names
+                .iter()
+                .map(|f| {
+                    frames
+                        .get(f)
+                        .copied()
+                        .ok_or_else(|| format!("animation `{}` uses unknown frame `{}`", name, f))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let fps = fps.get(&name).copied().unwrap_or(10.0);
+            animations.insert(name, Animation { frames: rects, fps });
+        }
+
+        Ok(Self {
+            sprite,
+            frames,
+            animations,
+        })
+    }
+
+    /// Cuts `line` at the first `#` that isn't inside a quoted string.
+    fn strip_comment(line: &str) -> &str {
+        let mut quoted = false;
+        for (i, ch) in line.char_indices() {
+            match ch {
+                '"' => quoted = !quoted,
+                '#' if !quoted => return &line[..i],
+                _ => {}
+            }
+        }
+        line
+    }
+
+    fn parse_string(value: &str) -> Option<String> {
+        value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .map(|v| v.to_string())
+    }
+
+    fn parse_list(value: &str) -> Option<Vec<String>> {
+        let inner = value.strip_prefix('[')?.strip_suffix(']')?;
+        Some(
+            inner
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Returns the region of the frame called `name`.
+    pub fn frame(&self, name: &str) -> Option<Rect> {
+        self.frames.get(name).copied()
+    }
+
+    /// Returns the animation called `name`.
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    /// Adds (or replaces) a frame called `name`.
+    pub fn add_frame(&mut self, name: &str, rect: Rect) {
+        self.frames.insert(name.to_string(), rect);
+    }
+
+    /// Adds (or replaces) an animation called `name`.
+    pub fn add_animation(&mut self, name: &str, animation: Animation) {
+        self.animations.insert(name.to_string(), animation);
+    }
+
+    /// Returns the names of all frames in the sheet.
+    pub fn frame_names(&self) -> impl Iterator<Item = &str> {
+        self.frames.keys().map(|k| k.as_str())
+    }
+}
+
+/// Packs many small sprites into a single sprite.
+///
+/// Drawing everything from one atlas keeps a game's assets in one `.spr` file, and the
+/// returned rectangles can be drawn with `draw_partial_sprite` or turned into a
+/// [`SpriteSheet`] with `pack_sheet`.
+pub struct SpriteAtlas;
+
+impl SpriteAtlas {
+    /// Packs `sprites` into one sprite and returns it with the region of every input
+    /// sprite, in the same order as `sprites`.
     ///
-    /// # Returns
-    /// Return `true` to continue running the game, or `false` to exit.
-    fn update(&mut self, engine: &mut ConsoleGameEngine<Self>, elapsed_time: f32) -> bool;
+    /// Sprites are placed on shelves, tallest first, in an atlas roughly as wide as it is
+    /// tall. Unused cells are `PIXEL_EMPTY`.
+    pub fn pack(sprites: &[Sprite]) -> (Sprite, Vec<Rect>) {
+        let area: usize = sprites.iter().map(|s| s.width * s.height).sum();
+        let widest = sprites.iter().map(|s| s.width).max().unwrap_or(0);
+        let atlas_width = widest.max((area as f64).sqrt().ceil() as usize);
+
+        let mut order: Vec<usize> = (0..sprites.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse((sprites[i].height, sprites[i].width)));
+
+        let mut rects = vec![Rect::default(); sprites.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let sprite = &sprites[i];
+            if x + sprite.width > atlas_width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            rects[i] = Rect::new(x, y, sprite.width, sprite.height);
+            x += sprite.width;
+            shelf_height = shelf_height.max(sprite.height);
+        }
+
+        let mut atlas = Sprite::new(atlas_width, y + shelf_height);
+        for (sprite, rect) in sprites.iter().zip(&rects) {
+            atlas.draw_sprite_blend(rect.x as i32, rect.y as i32, sprite, BlendMode::Opaque);
+        }
+
+        (atlas, rects)
+    }
+
+    /// Packs named sprites into a [`SpriteSheet`] with one frame per sprite.
+    pub fn pack_sheet(sprites: &[(&str, Sprite)]) -> SpriteSheet {
+        let only_sprites: Vec<Sprite> = sprites.iter().map(|(_, s)| s.clone()).collect();
+        let (atlas, rects) = Self::pack(&only_sprites);
+
+        let mut sheet = SpriteSheet::new(atlas);
+        for ((name, _), rect) in sprites.iter().zip(rects) {
+            sheet.add_frame(name, rect);
+        }
+        sheet
+    }
+}
+
+// endregion
+
+// region: Tile Map
+
+/// A grid of tile ids where every cell can also be marked as solid.
+///
+/// Tile ids are free for the game to interpret (e.g. characters of a level string or
+/// indices into a sprite sheet). Solidity is used by the collision and lighting helpers.
+/// Cells outside the map are treated as solid.
+///
+/// # Examples
+///
+/// ```rust
+/// use rusty_console_game_engine::*;
+///
+/// let map = TileMap::from_text(
+///     "########\n\
+///      #......#\n\
+///      #..##..#\n\
+///      ########",
+///     "#",
+/// );
+/// assert!(map.is_solid(0, 0));
+/// assert_eq!(map.get(1, 1), '.' as u16);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TileMap {
+    /// Width of the map in tiles.
+    pub width: usize,
+    /// Height of the map in tiles.
+    pub height: usize,
+    tiles: Vec<u16>,
+    solid: Vec<bool>,
+}
+
+impl TileMap {
+    /// Creates a new map of the given size, filled with tile `0` and no solid cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![0; width * height],
+            solid: vec![false; width * height],
+        }
+    }
+
+    /// Creates a map from lines of text, one tile per character.
+    ///
+    /// Each tile id is the character's UTF-16 value, and every tile whose character appears
+    /// in `solid` is marked as solid. Short lines are padded with spaces.
+    pub fn from_text(text: &str, solid: &str) -> Self {
+        let lines: Vec<Vec<char>> = text.lines().map(|l| l.trim().chars().collect()).collect();
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let mut map = Self::new(width, lines.len());
+
+        for (y, line) in lines.iter().enumerate() {
+            for x in 0..width {
+                let ch = line.get(x).copied().unwrap_or(' ');
+                map.set(x as i32, y as i32, ch as u16);
+                map.set_solid(x as i32, y as i32, solid.contains(ch));
+            }
+        }
+
+        map
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if `(x, y)` is inside the map.
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        self.index(x, y).is_some()
+    }
+
+    /// Returns the tile at `(x, y)`, or `0` if out of bounds.
+    pub fn get(&self, x: i32, y: i32) -> u16 {
+        self.index(x, y).map_or(0, |i| self.tiles[i])
+    }
+
+    /// Sets the tile at `(x, y)`. Does nothing if out of bounds.
+    pub fn set(&mut self, x: i32, y: i32, tile: u16) {
+        if let Some(i) = self.index(x, y) {
+            self.tiles[i] = tile;
+        }
+    }
+
+    /// Returns `true` if the cell at `(x, y)` is solid. Cells outside the map are solid.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        self.index(x, y).is_none_or(|i| self.solid[i])
+    }
+
+    /// Marks the cell at `(x, y)` as solid or open. Does nothing if out of bounds.
+    pub fn set_solid(&mut self, x: i32, y: i32, solid: bool) {
+        if let Some(i) = self.index(x, y) {
+            self.solid[i] = solid;
+        }
+    }
+}
+
+// endregion
+
+// region: Chunked World
+
+type ChunkGenerator = Box<dyn FnMut(i32, i32, &mut TileMap)>;
+type ChunkEvicted = Box<dyn FnMut(i32, i32, &TileMap)>;
+
+struct Chunk {
+    map: TileMap,
+    last_used: u64,
+    modified: bool,
+}
+
+/// An endless tile world split into square chunks that are generated on demand.
+///
+/// Chunks are created the first time a tile in them is touched, by a generator called with
+/// the chunk coordinates and an empty `TileMap` to fill in. The generator can build the
+/// chunk procedurally from the coordinates or load it from disk. Only `capacity` chunks
+/// are kept in memory: when more are needed, the least recently used ones are evicted,
+/// and regenerated if they are visited again. Chunks changed with `set` or `set_solid` are
+/// passed to the `on_evict` callback first, so they can be saved. Call `save_all` before
+/// quitting to save the modified chunks that are still in memory.
+///
+/// Tile coordinates can be any `i32`, including negative ones.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut world = ChunkedWorld::new(32, 64, |cx, cy, chunk| {
+///     let mut rng = procgen::Rng::new(((cx as u64) << 32) ^ cy as u32 as u64);
+///     for y in 0..chunk.height as i32 {
+///         for x in 0..chunk.width as i32 {
+///             let star = rng.chance(0.02);
+///             chunk.set(x, y, if star { '*' as u16 } else { ' ' as u16 });
+///         }
+///     }
+/// });
+///
+/// engine.draw_chunked_world(&mut world, camera_x, camera_y, |tile, _| (tile, FG_WHITE));
+/// ```
+pub struct ChunkedWorld {
+    chunk_size: usize,
+    capacity: usize,
+    generator: ChunkGenerator,
+    on_evict: Option<ChunkEvicted>,
+    chunks: HashMap<(i32, i32), Chunk>,
+    clock: u64,
+}
+
+impl ChunkedWorld {
+    /// Creates a world of `chunk_size` × `chunk_size` chunks, keeping at most `capacity`
+    /// of them in memory (at least 1).
+    pub fn new<F>(chunk_size: usize, capacity: usize, generator: F) -> Self
+    where
+        F: FnMut(i32, i32, &mut TileMap) + 'static,
+    {
+        Self {
+            chunk_size: chunk_size.max(1),
+            capacity: capacity.max(1),
+            generator: Box::new(generator),
+            on_evict: None,
+            chunks: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Sets a callback for modified chunks that are about to be evicted, e.g. to save them.
+    /// It gets the chunk coordinates and the chunk.
+    pub fn on_evict<F>(&mut self, callback: F)
+    where
+        F: FnMut(i32, i32, &TileMap) + 'static,
+    {
+        self.on_evict = Some(Box::new(callback));
+    }
+
+    /// Passes every modified chunk in memory to the `on_evict` callback, without evicting
+    /// it, and marks it as saved. Call this before quitting, or the changes to chunks that
+    /// were never evicted are lost.
+    pub fn save_all(&mut self) {
+        let Some(on_evict) = &mut self.on_evict else {
+            return;
+        };
+        for (&(cx, cy), chunk) in self.chunks.iter_mut().filter(|(_, c)| c.modified) {
+            on_evict(cx, cy, &chunk.map);
+            chunk.modified = false;
+        }
+    }
+
+    /// Returns the width and height of a chunk in tiles.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Returns the coordinates of the chunk containing tile `(x, y)`.
+    pub fn chunk_of(&self, x: i32, y: i32) -> (i32, i32) {
+        let size = self.chunk_size as i32;
+        (x.div_euclid(size), y.div_euclid(size))
+    }
+
+    /// Returns the number of chunks in memory.
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Returns `true` if chunk `(cx, cy)` is in memory.
+    pub fn is_loaded(&self, cx: i32, cy: i32) -> bool {
+        self.chunks.contains_key(&(cx, cy))
+    }
+
+    /// Returns chunk `(cx, cy)`, generating it if needed.
+    pub fn chunk(&mut self, cx: i32, cy: i32) -> &TileMap {
+        &self.load(cx, cy).map
+    }
+
+    /// Returns the tile at `(x, y)`, generating its chunk if needed.
+    pub fn get(&mut self, x: i32, y: i32) -> u16 {
+        let (cx, cy, lx, ly) = self.locate(x, y);
+        self.load(cx, cy).map.get(lx, ly)
+    }
+
+    /// Sets the tile at `(x, y)`, generating its chunk if needed.
+    pub fn set(&mut self, x: i32, y: i32, tile: u16) {
+        let (cx, cy, lx, ly) = self.locate(x, y);
+        let chunk = self.load(cx, cy);
+        chunk.map.set(lx, ly, tile);
+        chunk.modified = true;
+    }
+
+    /// Returns `true` if the cell at `(x, y)` is solid, generating its chunk if needed.
+    pub fn is_solid(&mut self, x: i32, y: i32) -> bool {
+        let (cx, cy, lx, ly) = self.locate(x, y);
+        self.load(cx, cy).map.is_solid(lx, ly)
+    }
+
+    /// Marks the cell at `(x, y)` as solid or open, generating its chunk if needed.
+    pub fn set_solid(&mut self, x: i32, y: i32, solid: bool) {
+        let (cx, cy, lx, ly) = self.locate(x, y);
+        let chunk = self.load(cx, cy);
+        chunk.map.set_solid(lx, ly, solid);
+        chunk.modified = true;
+    }
+
+    /// Makes sure every chunk overlapping the `w` × `h` tile area at `(x, y)` is in memory,
+    /// e.g. the area around the camera plus a margin, so they are ready before they scroll
+    /// into view.
+    pub fn load_area(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        let (cx1, cy1) = self.chunk_of(x, y);
+        let (cx2, cy2) = self.chunk_of(x + w.max(1) - 1, y + h.max(1) - 1);
+        for cy in cy1..=cy2 {
+            for cx in cx1..=cx2 {
+                self.load(cx, cy);
+            }
+        }
+    }
+
+    fn locate(&self, x: i32, y: i32) -> (i32, i32, i32, i32) {
+        let size = self.chunk_size as i32;
+        let (cx, cy) = self.chunk_of(x, y);
+        (cx, cy, x - cx * size, y - cy * size)
+    }
+
+    fn load(&mut self, cx: i32, cy: i32) -> &mut Chunk {
+        self.clock += 1;
+        if !self.chunks.contains_key(&(cx, cy)) {
+            if self.chunks.len() >= self.capacity {
+                self.evict_oldest();
+            }
+            let mut map = TileMap::new(self.chunk_size, self.chunk_size);
+            (self.generator)(cx, cy, &mut map);
+            self.chunks.insert(
+                (cx, cy),
+                Chunk {
+                    map,
+                    last_used: 0,
+                    modified: false,
+                },
+            );
+        }
+
+        let chunk = self.chunks.get_mut(&(cx, cy)).unwrap();
+        chunk.last_used = self.clock;
+        chunk
+    }
+
+    fn evict_oldest(&mut self) {
+        let Some(key) = self
+            .chunks
+            .iter()
+            .min_by_key(|(_, chunk)| chunk.last_used)
+            .map(|(&key, _)| key)
+        else {
+            return;
+        };
+        let chunk = self.chunks.remove(&key).unwrap();
+        if chunk.modified {
+            if let Some(on_evict) = &mut self.on_evict {
+                on_evict(key.0, key.1, &chunk.map);
+            }
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the part of `world` seen by a camera whose top-left corner is at tile
+    /// `(camera_x, camera_y)`, filling the whole screen. Chunks are generated as they come
+    /// into view.
+    ///
+    /// `style` turns a tile id and whether it is solid into the glyph and color to draw.
+    pub fn draw_chunked_world<F>(
+        &mut self,
+        world: &mut ChunkedWorld,
+        camera_x: i32,
+        camera_y: i32,
+        style: F,
+    ) where
+        F: Fn(u16, bool) -> (u16, u16),
+    {
+        let (w, h) = (self.screen_width(), self.screen_height());
+        let size = world.chunk_size() as i32;
+        let (cx1, cy1) = world.chunk_of(camera_x, camera_y);
+        let (cx2, cy2) = world.chunk_of(camera_x + w - 1, camera_y + h - 1);
+
+        // Draw chunk by chunk, so each one is only looked up once even if the cache is
+        // too small to hold the whole screen.
+        for cy in cy1..=cy2 {
+            for cx in cx1..=cx2 {
+                let chunk = world.chunk(cx, cy);
+                for ly in 0..size {
+                    for lx in 0..size {
+                        let x = cx * size + lx - camera_x;
+                        let y = cy * size + ly - camera_y;
+                        if x < 0 || y < 0 || x >= w || y >= h {
+                            continue;
+                        }
+                        let (glyph, col) = style(chunk.get(lx, ly), chunk.is_solid(lx, ly));
+                        self.draw_with(x, y, glyph, col);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Lighting
+
+/// A wall segment that blocks light, in tile units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    /// Start x.
+    pub sx: f32,
+    /// Start y.
+    pub sy: f32,
+    /// End x.
+    pub ex: f32,
+    /// End y.
+    pub ey: f32,
+}
+
+/// The area visible from a point, as a fan of triangles around `origin`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VisibilityPolygon {
+    /// The point the polygon was cast from.
+    pub origin: (f32, f32),
+    /// Outline of the visible area, sorted by angle around `origin`.
+    pub points: Vec<(f32, f32)>,
+}
+
+impl VisibilityPolygon {
+    /// Casts rays from `(ox, oy)` towards the ends of every edge and returns the visible area.
+    ///
+    /// Light stops after `radius` tiles. Pass `f32::INFINITY` for an unlimited range, in which
+    /// case the edges must enclose the origin.
+    pub fn cast(edges: &[Edge], ox: f32, oy: f32, radius: f32) -> Self {
+        let mut angles = Vec::with_capacity(edges.len() * 6 + 32);
+        for e in edges {
+            for (px, py) in [(e.sx, e.sy), (e.ex, e.ey)] {
+                let base = (py - oy).atan2(px - ox);
+                angles.extend([base - 0.0001, base, base + 0.0001]);
+            }
+        }
+        if radius.is_finite() {
+            angles.extend((0..32).map(|i| i as f32 * std::f32::consts::TAU / 32.0));
+        }
+
+        let mut hits = Vec::with_capacity(angles.len());
+        for angle in angles {
+            let (rdx, rdy) = (angle.cos(), angle.sin());
+            let mut best = radius;
+
+            for e in edges {
+                let (sdx, sdy) = (e.ex - e.sx, e.ey - e.sy);
+                let denom = sdx * rdy - sdy * rdx;
+                if denom.abs() < 1e-9 {
+                    continue;
+                }
+
+                let t2 = (rdx * (e.sy - oy) + rdy * (ox - e.sx)) / denom;
+                let t1 = if rdx.abs() > rdy.abs() {
+                    (e.sx + sdx * t2 - ox) / rdx
+                } else {
+                    (e.sy + sdy * t2 - oy) / rdy
+                };
+
+                if t1 > 0.0 && (0.0..=1.0).contains(&t2) && t1 < best {
+                    best = t1;
+                }
+            }
+
+            if best.is_finite() {
+                hits.push((angle, ox + rdx * best, oy + rdy * best));
+            }
+        }
+
+        hits.sort_by(|a, b| {
+            a.0.rem_euclid(std::f32::consts::TAU)
+                .total_cmp(&b.0.rem_euclid(std::f32::consts::TAU))
+        });
+        hits.dedup_by(|a, b| (a.1 - b.1).abs() < 0.01 && (a.2 - b.2).abs() < 0.01);
+
+        Self {
+            origin: (ox, oy),
+            points: hits.into_iter().map(|(_, x, y)| (x, y)).collect(),
+        }
+    }
+
+    /// Returns `true` if `(x, y)` lies inside the visible area.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let n = self.points.len();
+        if n < 2 {
+            return false;
+        }
+
+        let (ox, oy) = self.origin;
+        let side = |ax: f32, ay: f32, bx: f32, by: f32| (bx - ax) * (y - ay) - (by - ay) * (x - ax);
+
+        (0..n).any(|i| {
+            let (ax, ay) = self.points[i];
+            let (bx, by) = self.points[(i + 1) % n];
+            let d1 = side(ox, oy, ax, ay);
+            let d2 = side(ax, ay, bx, by);
+            let d3 = side(bx, by, ox, oy);
+            let neg = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+            let pos = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+            !(neg && pos)
+        })
+    }
+}
+
+/// Which tiles of a [`TileMap`] are lit, as produced by [`TileMap::light_mask`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightMask {
+    /// Width of the mask in tiles.
+    pub width: usize,
+    /// Height of the mask in tiles.
+    pub height: usize,
+    lit: Vec<bool>,
+}
+
+impl LightMask {
+    /// Returns `true` if the tile at `(x, y)` is lit. Tiles outside the mask are unlit.
+    pub fn is_lit(&self, x: i32, y: i32) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.lit[y as usize * self.width + x as usize]
+    }
+}
+
+impl TileMap {
+    /// Returns the outline of all solid cells as wall edges, merging neighbouring faces
+    /// into single edges, plus the border of the map.
+    pub fn edges(&self) -> Vec<Edge> {
+        const N: usize = 0;
+        const S: usize = 1;
+        const E: usize = 2;
+        const W: usize = 3;
+
+        let mut edges = Vec::new();
+        let mut ids: Vec<[Option<usize>; 4]> = vec![[None; 4]; self.width * self.height];
+
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                if !self.is_solid(x, y) {
+                    continue;
+                }
+                let i = y as usize * self.width + x as usize;
+                let (fx, fy) = (x as f32, y as f32);
+
+                // Vertical faces extend the edge of the cell above, horizontal faces the cell to the left.
+                let faces = [
+                    (W, x - 1, y, x, y - 1, (fx, fy, fx, fy + 1.0)),
+                    (E, x + 1, y, x, y - 1, (fx + 1.0, fy, fx + 1.0, fy + 1.0)),
+                    (N, x, y - 1, x - 1, y, (fx, fy, fx + 1.0, fy)),
+                    (S, x, y + 1, x - 1, y, (fx, fy + 1.0, fx + 1.0, fy + 1.0)),
+                ];
+
+                for (face, nx, ny, px, py, (sx, sy, ex, ey)) in faces {
+                    if !self.in_bounds(nx, ny) || self.is_solid(nx, ny) {
+                        continue;
+                    }
+
+                    let prev = self.index(px, py).and_then(|p| ids[p][face]);
+                    match prev {
+                        Some(id) => {
+                            edges[id] = Edge {
+                                ex,
+                                ey,
+                                ..edges[id]
+                            };
+                            ids[i][face] = Some(id);
+                        }
+                        None => {
+                            ids[i][face] = Some(edges.len());
+                            edges.push(Edge { sx, sy, ex, ey });
+                        }
+                    }
+                }
+            }
+        }
+
+        let (w, h) = (self.width as f32, self.height as f32);
+        edges.extend([
+            Edge {
+                sx: 0.0,
+                sy: 0.0,
+                ex: w,
+                ey: 0.0,
+            },
+            Edge {
+                sx: w,
+                sy: 0.0,
+                ex: w,
+                ey: h,
+            },
+            Edge {
+                sx: w,
+                sy: h,
+                ex: 0.0,
+                ey: h,
+            },
+            Edge {
+                sx: 0.0,
+                sy: h,
+                ex: 0.0,
+                ey: 0.0,
+            },
+        ]);
+
+        edges
+    }
+
+    /// Returns the area visible from `(x, y)` (in tile units), up to `radius` tiles away.
+    pub fn visibility(&self, x: f32, y: f32, radius: f32) -> VisibilityPolygon {
+        VisibilityPolygon::cast(&self.edges(), x, y, radius)
+    }
+
+    /// Returns which tiles can be seen from `(x, y)` (in tile units), up to `radius` tiles away.
+    ///
+    /// Open tiles are lit if their centre is visible. Solid tiles are lit if one of their
+    /// faces is visible, so the walls around the viewer show up.
+    pub fn light_mask(&self, x: f32, y: f32, radius: f32) -> LightMask {
+        let poly = self.visibility(x, y, radius);
+        let mut lit = vec![false; self.width * self.height];
+
+        for ty in 0..self.height as i32 {
+            for tx in 0..self.width as i32 {
+                let (cx, cy) = (tx as f32 + 0.5, ty as f32 + 0.5);
+                let visible = if self.is_solid(tx, ty) {
+                    [(0, -1), (0, 1), (-1, 0), (1, 0)].iter().any(|&(dx, dy)| {
+                        !self.is_solid(tx + dx, ty + dy)
+                            && poly.contains(cx + dx as f32 * 0.51, cy + dy as f32 * 0.51)
+                    })
+                } else {
+                    poly.contains(cx, cy)
+                };
+                lit[ty as usize * self.width + tx as usize] = visible;
+            }
+        }
+
+        LightMask {
+            width: self.width,
+            height: self.height,
+            lit,
+        }
+    }
+
+    /// Returns which tiles can be seen from tile `(x, y)` within `radius` tiles, using
+    /// recursive shadowcasting.
+    ///
+    /// Unlike `light_mask`, this works on whole tiles, as traditional roguelikes do: walls
+    /// bounding the visible area are included, and it is cheap enough to run for every
+    /// monster each turn.
+    ///
+    /// Visibility is not always symmetric: around pillars and corners, a tile seen from
+    /// `(x, y)` may not see `(x, y)` back. Check the field of view of the one doing the
+    /// looking rather than the one being looked at.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let fov = self.map.field_of_view(self.player.x, self.player.y, 8);
+    /// if fov.is_lit(monster.x, monster.y) {
+    ///     // the player can see the monster
+    /// }
+    /// ```
+    pub fn field_of_view(&self, x: i32, y: i32, radius: i32) -> LightMask {
+        let mut mask = LightMask {
+            width: self.width,
+            height: self.height,
+            lit: vec![false; self.width * self.height],
+        };
+        if let Some(i) = self.index(x, y) {
+            mask.lit[i] = true;
+        }
+
+        // Each octant maps its own (column, row) onto the map with these multipliers.
+        const OCTANTS: [(i32, i32, i32, i32); 8] = [
+            (1, 0, 0, 1),
+            (0, 1, 1, 0),
+            (0, -1, 1, 0),
+            (-1, 0, 0, 1),
+            (-1, 0, 0, -1),
+            (0, -1, -1, 0),
+            (0, 1, -1, 0),
+            (1, 0, 0, -1),
+        ];
+        for octant in OCTANTS {
+            self.cast_shadows(&mut mask, (x, y), radius, 1, 1.0, 0.0, octant);
+        }
+
+        mask
+    }
+
+    /// Scans one octant outwards from `row`, between the slopes `start` and `end`, recursing
+    /// around each run of solid tiles.
+    #[allow(clippy::too_many_arguments)]
+    fn cast_shadows(
+        &self,
+        mask: &mut LightMask,
+        origin: (i32, i32),
+        radius: i32,
+        row: i32,
+        mut start: f32,
+        end: f32,
+        (xx, xy, yx, yy): (i32, i32, i32, i32),
+    ) {
+        if start < end {
+            return;
+        }
+
+        let mut next_start = start;
+        for distance in row..=radius {
+            let dy = -distance;
+            let mut blocked = false;
+            for dx in -distance..=0 {
+                let left = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right {
+                    continue;
+                }
+                if end > left {
+                    break;
+                }
+
+                let x = origin.0 + dx * xx + dy * xy;
+                let y = origin.1 + dx * yx + dy * yy;
+                if dx * dx + dy * dy <= radius * radius {
+                    if let Some(i) = self.index(x, y) {
+                        mask.lit[i] = true;
+                    }
+                }
+
+                let solid = self.is_solid(x, y);
+                if blocked {
+                    if solid {
+                        next_start = right;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if solid && distance < radius {
+                    blocked = true;
+                    self.cast_shadows(
+                        mask,
+                        origin,
+                        radius,
+                        distance + 1,
+                        start,
+                        left,
+                        (xx, xy, yx, yy),
+                    );
+                    next_start = right;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Procgen
+
+/// Procedural level generators.
+///
+/// Every generator takes a seed, so the same seed always gives the same level. Generated
+/// [`TileMap`]s use [`WALL`](procgen::WALL) for solid tiles and [`FLOOR`](procgen::FLOOR)
+/// for open ones, so they can be drawn as text right away or mapped onto a tileset.
+pub mod procgen {
+    use crate::color::*;
+    use crate::{Rect, TileMap};
+
+    /// Tile id of solid cells in generated maps.
+    pub const WALL: u16 = '#' as u16;
+    /// Tile id of open cells in generated maps.
+    pub const FLOOR: u16 = '.' as u16;
+
+    /// A small, fast, seedable random number generator (xorshift64*).
+    ///
+    /// Not suitable for cryptography, but plenty for levels, loot and particles.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Rng {
+        state: u64,
+    }
+
+    impl Rng {
+        pub fn new(seed: u64) -> Self {
+            const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+            // A zero state would only ever produce zeros, so the one seed that leads to it
+            // starts where seed 0 does instead.
+            let state = seed ^ MIX;
+            Self {
+                state: if state == 0 { MIX } else { state },
+            }
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            self.state ^= self.state >> 12;
+            self.state ^= self.state << 25;
+            self.state ^= self.state >> 27;
+            self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        /// Returns a float in `0.0..1.0`.
+        pub fn next_f32(&mut self) -> f32 {
+            (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        /// Returns an integer in `min..max`, or `min` if the range is empty.
+        pub fn range(&mut self, min: i32, max: i32) -> i32 {
+            if max <= min {
+                return min;
+            }
+            min + (self.next_u64() % (max - min) as u64) as i32
+        }
+
+        /// Returns `true` with probability `p`.
+        pub fn chance(&mut self, p: f32) -> bool {
+            self.next_f32() < p
+        }
+
+        /// Shuffles `items` in place.
+        pub fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                let j = (self.next_u64() % (i as u64 + 1)) as usize;
+                items.swap(i, j);
+            }
+        }
+    }
+
+    /// A generated map together with the rooms carved into it.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Dungeon {
+        pub map: TileMap,
+        /// Every room, in tiles. Corridors are not included.
+        pub rooms: Vec<Rect>,
+    }
+
+    /// Settings for [`bsp`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BspParams {
+        /// Areas are only split if both halves are at least this many tiles across.
+        pub min_leaf: usize,
+        /// Smallest room width or height, in tiles.
+        pub min_room: usize,
+    }
+
+    impl Default for BspParams {
+        fn default() -> Self {
+            Self {
+                min_leaf: 10,
+                min_room: 4,
+            }
+        }
+    }
+
+    /// Settings for [`cave`].
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct CaveParams {
+        /// Fraction of tiles that start as walls.
+        pub fill: f32,
+        /// Smoothing passes. Each pass turns a tile into wall if more than four of its eight
+        /// neighbours are walls, and into floor if fewer than four are.
+        pub steps: usize,
+        /// Fill in every cave except the largest, so the whole map is reachable.
+        pub keep_largest: bool,
+    }
+
+    impl Default for CaveParams {
+        fn default() -> Self {
+            Self {
+                fill: 0.45,
+                steps: 5,
+                keep_largest: true,
+            }
+        }
+    }
+
+    /// Settings for [`drunkard_walk`].
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct WalkParams {
+        /// Stop once this fraction of the map's interior is floor.
+        pub coverage: f32,
+        /// Stop after this many steps even if `coverage` hasn't been reached.
+        pub max_steps: usize,
+    }
+
+    impl Default for WalkParams {
+        fn default() -> Self {
+            Self {
+                coverage: 0.4,
+                max_steps: 100_000,
+            }
+        }
+    }
+
+    /// A map of the given size with every tile a wall.
+    fn solid_map(width: usize, height: usize) -> TileMap {
+        let mut map = TileMap::new(width, height);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                set_wall(&mut map, x, y, true);
+            }
+        }
+        map
+    }
+
+    fn set_wall(map: &mut TileMap, x: i32, y: i32, wall: bool) {
+        map.set(x, y, if wall { WALL } else { FLOOR });
+        map.set_solid(x, y, wall);
+    }
+
+    /// Rooms and corridors, by binary space partitioning.
+    ///
+    /// The map is split in two again and again until the pieces are too small, then one room
+    /// is placed in each piece and sibling pieces are joined with L-shaped corridors, so
+    /// every room is reachable.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let dungeon = procgen::bsp(80, 50, &BspParams::default(), seed);
+    /// let start = dungeon.rooms[0];
+    /// ```
+    pub fn bsp(width: usize, height: usize, params: &BspParams, seed: u64) -> Dungeon {
+        let mut rng = Rng::new(seed);
+        let mut dungeon = Dungeon {
+            map: solid_map(width, height),
+            rooms: Vec::new(),
+        };
+        let params = BspParams {
+            min_room: params.min_room.max(1),
+            min_leaf: params.min_leaf.max(params.min_room.max(1) + 2),
+        };
+        if width >= params.min_room + 2 && height >= params.min_room + 2 {
+            split_leaf(
+                &mut dungeon,
+                &mut rng,
+                &params,
+                Rect::new(0, 0, width, height),
+            );
+        }
+        dungeon
+    }
+
+    /// Splits `leaf` or places a room in it, returning a point inside the room reached.
+    fn split_leaf(
+        dungeon: &mut Dungeon,
+        rng: &mut Rng,
+        params: &BspParams,
+        leaf: Rect,
+    ) -> (i32, i32) {
+        let can_split_x = leaf.w >= params.min_leaf * 2;
+        let can_split_y = leaf.h >= params.min_leaf * 2;
+
+        if !can_split_x && !can_split_y {
+            let max_w = leaf.w - 2;
+            let max_h = leaf.h - 2;
+            let w = rng.range(params.min_room.min(max_w) as i32, max_w as i32 + 1) as usize;
+            let h = rng.range(params.min_room.min(max_h) as i32, max_h as i32 + 1) as usize;
+            let x = leaf.x + 1 + rng.range(0, (max_w - w) as i32 + 1) as usize;
+            let y = leaf.y + 1 + rng.range(0, (max_h - h) as i32 + 1) as usize;
+
+            for ty in y..y + h {
+                for tx in x..x + w {
+                    set_wall(&mut dungeon.map, tx as i32, ty as i32, false);
+                }
+            }
+            dungeon.rooms.push(Rect::new(x, y, w, h));
+            return (
+                rng.range(x as i32, (x + w) as i32),
+                rng.range(y as i32, (y + h) as i32),
+            );
+        }
+
+        // Prefer splitting across the longer side so rooms don't end up as long strips.
+        let split_x = if can_split_x && can_split_y {
+            leaf.w >= leaf.h
+        } else {
+            can_split_x
+        };
+        let (a, b) = if split_x {
+            let at = rng.range(
+                params.min_leaf as i32,
+                (leaf.w - params.min_leaf) as i32 + 1,
+            ) as usize;
+            (
+                Rect::new(leaf.x, leaf.y, at, leaf.h),
+                Rect::new(leaf.x + at, leaf.y, leaf.w - at, leaf.h),
+            )
+        } else {
+            let at = rng.range(
+                params.min_leaf as i32,
+                (leaf.h - params.min_leaf) as i32 + 1,
+            ) as usize;
+            (
+                Rect::new(leaf.x, leaf.y, leaf.w, at),
+                Rect::new(leaf.x, leaf.y + at, leaf.w, leaf.h - at),
+            )
+        };
+
+        let (ax, ay) = split_leaf(dungeon, rng, params, a);
+        let (bx, by) = split_leaf(dungeon, rng, params, b);
+        let corner = if rng.chance(0.5) { (bx, ay) } else { (ax, by) };
+        carve_line(&mut dungeon.map, (ax, ay), corner);
+        carve_line(&mut dungeon.map, corner, (bx, by));
+        (ax, ay)
+    }
+
+    /// Carves a straight horizontal or vertical corridor.
+    fn carve_line(map: &mut TileMap, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) {
+        for x in x1.min(x2)..=x1.max(x2) {
+            for y in y1.min(y2)..=y1.max(y2) {
+                set_wall(map, x, y, false);
+            }
+        }
+    }
+
+    /// Organic caves, by cellular automata.
+    ///
+    /// The map starts as random noise and is smoothed until walls clump together into
+    /// caverns. The border is always wall.
+    pub fn cave(width: usize, height: usize, params: &CaveParams, seed: u64) -> TileMap {
+        let mut rng = Rng::new(seed);
+        let (w, h) = (width as i32, height as i32);
+        let border = |x: i32, y: i32| x == 0 || y == 0 || x == w - 1 || y == h - 1;
+
+        let mut walls: Vec<bool> = (0..w * h)
+            .map(|i| border(i % w, i / w) || rng.chance(params.fill))
+            .collect();
+
+        for _ in 0..params.steps {
+            let next = (0..w * h)
+                .map(|i| {
+                    let (x, y) = (i % w, i / w);
+                    if border(x, y) {
+                        return true;
+                    }
+                    let mut count = 0;
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            if (dx, dy) != (0, 0) && walls[((y + dy) * w + x + dx) as usize] {
+                                count += 1;
+                            }
+                        }
+                    }
+                    match count {
+                        5.. => true,
+                        0..=3 => false,
+                        _ => walls[i as usize],
+                    }
+                })
+                .collect();
+            walls = next;
+        }
+
+        let mut map = solid_map(width, height);
+        for (i, &wall) in walls.iter().enumerate() {
+            set_wall(&mut map, i as i32 % w, i as i32 / w, wall);
+        }
+        if params.keep_largest {
+            keep_largest_region(&mut map);
+        }
+        map
+    }
+
+    /// Fills in every open region except the largest.
+    fn keep_largest_region(map: &mut TileMap) {
+        let (w, h) = (map.width as i32, map.height as i32);
+        let mut region = vec![usize::MAX; map.width * map.height];
+        let mut sizes = Vec::new();
+
+        for start in 0..w * h {
+            let (sx, sy) = (start % w, start / w);
+            if map.is_solid(sx, sy) || region[start as usize] != usize::MAX {
+                continue;
+            }
+            let id = sizes.len();
+            let mut size = 0;
+            let mut stack = vec![(sx, sy)];
+            region[start as usize] = id;
+            while let Some((x, y)) = stack.pop() {
+                size += 1;
+                for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                    if !map.is_solid(nx, ny) && region[(ny * w + nx) as usize] == usize::MAX {
+                        region[(ny * w + nx) as usize] = id;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            sizes.push(size);
+        }
+
+        let largest = (0..sizes.len()).max_by_key(|&i| sizes[i]);
+        for (i, &r) in region.iter().enumerate() {
+            if r != usize::MAX && Some(r) != largest {
+                set_wall(map, i as i32 % w, i as i32 / w, true);
+            }
+        }
+    }
+
+    /// Winding tunnels, by a drunkard's walk.
+    ///
+    /// A walker starts in the middle of the map and stumbles in random directions, carving
+    /// floor as it goes, until enough of the map is open. Everything carved is connected.
+    /// The border is always wall.
+    pub fn drunkard_walk(width: usize, height: usize, params: &WalkParams, seed: u64) -> TileMap {
+        let mut rng = Rng::new(seed);
+        let mut map = solid_map(width, height);
+        let (w, h) = (width as i32, height as i32);
+        if w < 3 || h < 3 {
+            return map;
+        }
+
+        let interior = ((w - 2) * (h - 2)) as f32;
+        let target = (interior * params.coverage.clamp(0.0, 1.0)).ceil() as usize;
+        let (mut x, mut y) = (w / 2, h / 2);
+        let mut carved = 0;
+
+        for _ in 0..params.max_steps {
+            if map.is_solid(x, y) {
+                set_wall(&mut map, x, y, false);
+                carved += 1;
+            }
+            if carved >= target {
+                break;
+            }
+            let (dx, dy) = [(1, 0), (-1, 0), (0, 1), (0, -1)][rng.range(0, 4) as usize];
+            x = (x + dx).clamp(1, w - 2);
+            y = (y + dy).clamp(1, h - 2);
+        }
+
+        map
+    }
+
+    /// One of the four ways out of a maze cell.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Direction {
+        North,
+        East,
+        South,
+        West,
+    }
+
+    impl Direction {
+        pub const ALL: [Direction; 4] = [
+            Direction::North,
+            Direction::East,
+            Direction::South,
+            Direction::West,
+        ];
+
+        /// The `(x, y)` step this direction takes. North is up the screen.
+        pub fn offset(self) -> (i32, i32) {
+            match self {
+                Direction::North => (0, -1),
+                Direction::East => (1, 0),
+                Direction::South => (0, 1),
+                Direction::West => (-1, 0),
+            }
+        }
+
+        pub fn opposite(self) -> Direction {
+            match self {
+                Direction::North => Direction::South,
+                Direction::East => Direction::West,
+                Direction::South => Direction::North,
+                Direction::West => Direction::East,
+            }
+        }
+
+        fn bit(self) -> u8 {
+            1 << self as u8
+        }
+    }
+
+    /// A perfect maze: every cell can be reached from every other by exactly one path.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct Maze {
+        /// Width in cells.
+        pub width: usize,
+        /// Height in cells.
+        pub height: usize,
+        /// Bit `d` of each cell is set if there is a passage in direction `d`.
+        passages: Vec<u8>,
+    }
+
+    impl Maze {
+        fn index(&self, x: i32, y: i32) -> Option<usize> {
+            if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+                Some(y as usize * self.width + x as usize)
+            } else {
+                None
+            }
+        }
+
+        /// Returns `true` if the cell at `(x, y)` has a passage towards `dir`. Cells outside
+        /// the maze have none.
+        pub fn is_open(&self, x: i32, y: i32, dir: Direction) -> bool {
+            self.index(x, y)
+                .is_some_and(|i| self.passages[i] & dir.bit() != 0)
+        }
+
+        /// Returns `true` if a wall blocks the cell at `(x, y)` towards `dir`.
+        pub fn has_wall(&self, x: i32, y: i32, dir: Direction) -> bool {
+            !self.is_open(x, y, dir)
+        }
+
+        /// Returns the cells reachable in one step from `(x, y)`.
+        pub fn neighbors(&self, x: i32, y: i32) -> Vec<(i32, i32)> {
+            Direction::ALL
+                .iter()
+                .filter(|&&d| self.is_open(x, y, d))
+                .map(|d| (x + d.offset().0, y + d.offset().1))
+                .collect()
+        }
+
+        /// Returns the path of cells from `from` to `to`, both included, or `None` if either
+        /// is outside the maze.
+        pub fn solve(&self, from: (i32, i32), to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+            let start = self.index(from.0, from.1)?;
+            let goal = self.index(to.0, to.1)?;
+
+            let mut came_from = vec![usize::MAX; self.passages.len()];
+            came_from[start] = start;
+            let mut queue = std::collections::VecDeque::from([from]);
+            while let Some((x, y)) = queue.pop_front() {
+                if (x, y) == to {
+                    break;
+                }
+                let here = y as usize * self.width + x as usize;
+                for (nx, ny) in self.neighbors(x, y) {
+                    let next = ny as usize * self.width + nx as usize;
+                    if came_from[next] == usize::MAX {
+                        came_from[next] = here;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+
+            if came_from[goal] == usize::MAX {
+                return None;
+            }
+            let mut path = vec![to];
+            let mut at = goal;
+            while at != start {
+                at = came_from[at];
+                path.push(((at % self.width) as i32, (at / self.width) as i32));
+            }
+            path.reverse();
+            Some(path)
+        }
+
+        /// Converts the maze into a tile map, with each cell `path_width` tiles across and
+        /// one tile of wall between cells, as drawn by the mazes example.
+        ///
+        /// Cell `(x, y)` covers tiles from `(x * (path_width + 1) + 1, y * (path_width + 1) + 1)`.
+        pub fn to_tile_map(&self, path_width: usize) -> TileMap {
+            let path_width = path_width.max(1);
+            let step = path_width as i32 + 1;
+            let mut map = solid_map(
+                self.width * (path_width + 1) + 1,
+                self.height * (path_width + 1) + 1,
+            );
+
+            for cy in 0..self.height as i32 {
+                for cx in 0..self.width as i32 {
+                    let (ox, oy) = (cx * step + 1, cy * step + 1);
+                    for p in 0..path_width as i32 {
+                        for q in 0..path_width as i32 {
+                            set_wall(&mut map, ox + p, oy + q, false);
+                        }
+                        if self.is_open(cx, cy, Direction::East) {
+                            set_wall(&mut map, ox + path_width as i32, oy + p, false);
+                        }
+                        if self.is_open(cx, cy, Direction::South) {
+                            set_wall(&mut map, ox + p, oy + path_width as i32, false);
+                        }
+                    }
+                }
+            }
+
+            map
+        }
+    }
+
+    /// Generates a perfect maze with the recursive backtracker, as animated by the mazes
+    /// example.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let maze = procgen::maze(40, 25, seed);
+    /// let exit = (39, 24);
+    /// let route = maze.solve((0, 0), exit).unwrap();
+    /// ```
+    pub fn maze(width: usize, height: usize, seed: u64) -> Maze {
+        let mut rng = Rng::new(seed);
+        let mut maze = Maze {
+            width,
+            height,
+            passages: vec![0; width * height],
+        };
+        if width == 0 || height == 0 {
+            return maze;
+        }
+
+        let mut visited = vec![false; width * height];
+        let start = (rng.range(0, width as i32), rng.range(0, height as i32));
+        visited[start.1 as usize * width + start.0 as usize] = true;
+        let mut stack = vec![start];
+
+        while let Some(&(x, y)) = stack.last() {
+            let options: Vec<Direction> = Direction::ALL
+                .into_iter()
+                .filter(|d| {
+                    let (dx, dy) = d.offset();
+                    maze.index(x + dx, y + dy).is_some_and(|i| !visited[i])
+                })
+                .collect();
+            if options.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let dir = options[rng.range(0, options.len() as i32) as usize];
+            let (nx, ny) = (x + dir.offset().0, y + dir.offset().1);
+            let here = y as usize * width + x as usize;
+            let next = ny as usize * width + nx as usize;
+            maze.passages[here] |= dir.bit();
+            maze.passages[next] |= dir.opposite().bit();
+            visited[next] = true;
+            stack.push((nx, ny));
+        }
+
+        maze
+    }
+
+    /// Which tiles may sit next to each other, for [`wave_function_collapse`].
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct WfcRules {
+        tiles: Vec<u16>,
+        weights: Vec<f32>,
+        solid: Vec<bool>,
+        /// `allowed[t][d]` lists the tiles that may sit in direction `d` of tile `t`.
+        allowed: Vec<[Vec<usize>; 4]>,
+    }
+
+    impl WfcRules {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Learns the tiles, how often each appears and which pairs touch from `sample`.
+        ///
+        /// Generated maps only place tiles side by side the way they appear somewhere in the
+        /// sample, and tiles show up about as often as they do there.
+        pub fn learn(sample: &TileMap) -> Self {
+            let mut rules = Self::new();
+            for y in 0..sample.height as i32 {
+                for x in 0..sample.width as i32 {
+                    let t = rules.add_tile(sample.get(x, y), 0.0, sample.is_solid(x, y));
+                    rules.weights[t] += 1.0;
+                    for dir in Direction::ALL {
+                        let (nx, ny) = (x + dir.offset().0, y + dir.offset().1);
+                        if sample.in_bounds(nx, ny) {
+                            let n =
+                                rules.add_tile(sample.get(nx, ny), 0.0, sample.is_solid(nx, ny));
+                            rules.allow(t, dir, n);
+                        }
+                    }
+                }
+            }
+            rules
+        }
+
+        /// Adds `tile` with a relative `weight`, or returns its index if it has already been
+        /// added. `solid` is copied onto the generated map.
+        pub fn add_tile(&mut self, tile: u16, weight: f32, solid: bool) -> usize {
+            if let Some(i) = self.tiles.iter().position(|&t| t == tile) {
+                return i;
+            }
+            self.tiles.push(tile);
+            self.weights.push(weight);
+            self.solid.push(solid);
+            self.allowed.push(Default::default());
+            self.tiles.len() - 1
+        }
+
+        /// Allows tile index `b` in direction `dir` of tile index `a`, and `a` in the opposite
+        /// direction of `b`.
+        pub fn allow(&mut self, a: usize, dir: Direction, b: usize) {
+            for (from, dir, to) in [(a, dir, b), (b, dir.opposite(), a)] {
+                let list = &mut self.allowed[from][dir as usize];
+                if !list.contains(&to) {
+                    list.push(to);
+                }
+            }
+        }
+
+        /// Returns the tile ids, in index order.
+        pub fn tiles(&self) -> &[u16] {
+            &self.tiles
+        }
+    }
+
+    /// How many times generation starts over after running into a contradiction.
+    const WFC_ATTEMPTS: u64 = 10;
+
+    /// Generates a map of the given size that follows `rules`, using wave function collapse.
+    ///
+    /// Every cell starts able to be any tile. The cell with the fewest options left is fixed
+    /// to a random one of them, the choice is propagated to its neighbours, and so on until
+    /// every cell is decided. If a cell runs out of options the attempt starts again; `None`
+    /// is returned if several attempts in a row fail, which usually means the rules are too
+    /// strict for the size.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let rules = WfcRules::learn(&TileMap::from_text(SAMPLE, "#"));
+    /// let level = procgen::wave_function_collapse(&rules, 80, 40, seed).expect("no solution");
+    /// ```
+    pub fn wave_function_collapse(
+        rules: &WfcRules,
+        width: usize,
+        height: usize,
+        seed: u64,
+    ) -> Option<TileMap> {
+        if rules.tiles.is_empty() {
+            return None;
+        }
+        (0..WFC_ATTEMPTS).find_map(|attempt| {
+            let mut rng = Rng::new(seed.wrapping_add(attempt));
+            collapse(rules, width, height, &mut rng)
+        })
+    }
+
+    fn collapse(rules: &WfcRules, width: usize, height: usize, rng: &mut Rng) -> Option<TileMap> {
+        let n = rules.tiles.len();
+        let weight = |t: usize| rules.weights[t].max(f32::EPSILON);
+        let mut options = vec![vec![true; n]; width * height];
+        let mut remaining = vec![n; width * height];
+
+        loop {
+            // Pick the undecided cell with the lowest entropy, breaking ties randomly.
+            let mut best: Option<(usize, f32)> = None;
+            for (i, cell) in options.iter().enumerate() {
+                if remaining[i] <= 1 {
+                    continue;
+                }
+                let (sum, sum_log) = (0..n).filter(|&t| cell[t]).fold((0.0, 0.0), |(s, l), t| {
+                    (s + weight(t), l + weight(t) * weight(t).ln())
+                });
+                let entropy = sum.ln() - sum_log / sum + rng.next_f32() * 1e-4;
+                if best.is_none_or(|(_, e)| entropy < e) {
+                    best = Some((i, entropy));
+                }
+            }
+            let Some((cell, _)) = best else {
+                break;
+            };
+
+            let total: f32 = (0..n).filter(|&t| options[cell][t]).map(weight).sum();
+            let mut pick = rng.next_f32() * total;
+            let choice = (0..n)
+                .filter(|&t| options[cell][t])
+                .find(|&t| {
+                    pick -= weight(t);
+                    pick <= 0.0
+                })
+                .or_else(|| (0..n).rev().find(|&t| options[cell][t]))?;
+            options[cell] = (0..n).map(|t| t == choice).collect();
+            remaining[cell] = 1;
+
+            let mut stack = vec![cell];
+            while let Some(i) = stack.pop() {
+                let (x, y) = ((i % width) as i32, (i / width) as i32);
+                for dir in Direction::ALL {
+                    let (nx, ny) = (x + dir.offset().0, y + dir.offset().1);
+                    if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                        continue;
+                    }
+                    let j = ny as usize * width + nx as usize;
+                    let mut supported = vec![false; n];
+                    for t in (0..n).filter(|&t| options[i][t]) {
+                        for &a in &rules.allowed[t][dir as usize] {
+                            supported[a] = true;
+                        }
+                    }
+                    let mut changed = false;
+                    for t in 0..n {
+                        if options[j][t] && !supported[t] {
+                            options[j][t] = false;
+                            remaining[j] -= 1;
+                            changed = true;
+                        }
+                    }
+                    if remaining[j] == 0 {
+                        return None;
+                    }
+                    if changed {
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+
+        let mut map = TileMap::new(width, height);
+        for (i, cell) in options.iter().enumerate() {
+            let t = cell.iter().position(|&o| o)?;
+            let (x, y) = ((i % width) as i32, (i / width) as i32);
+            map.set(x, y, rules.tiles[t]);
+            map.set_solid(x, y, rules.solid[t]);
+        }
+        Some(map)
+    }
+
+    /// A planet orbiting a [`StarSystem`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Planet {
+        /// Distance from the star, in arbitrary units that grow outwards.
+        pub distance: f32,
+        /// Diameter, from `4.0` to `20.0`.
+        pub diameter: f32,
+        /// Surface temperature in degrees Celsius.
+        pub temperature: f32,
+        /// Population, `0` for uninhabited planets.
+        pub population: u64,
+        pub ring: bool,
+        /// Diameters of the planet's moons.
+        pub moons: Vec<f32>,
+    }
+
+    /// A star and its planets, generated from its position in a [`Galaxy`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct StarSystem {
+        pub x: i32,
+        pub y: i32,
+        pub name: String,
+        /// Diameter of the star, from `10.0` to `40.0`.
+        pub diameter: f32,
+        /// Foreground color of the star.
+        pub color: u16,
+        /// Planets, nearest first.
+        pub planets: Vec<Planet>,
+    }
+
+    /// An endless, seeded universe of star systems.
+    ///
+    /// Nothing is stored: every sector's contents are derived from its coordinates and the
+    /// seed by hashing, so the same sector always holds the same star system, however far
+    /// away it is and in whatever order sectors are visited.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rusty_console_game_engine::*;
+    ///
+    /// let galaxy = procgen::Galaxy::new(1234);
+    /// if let Some(system) = galaxy.system(10, -3) {
+    ///     assert_eq!(galaxy.system(10, -3), Some(system));
+    /// }
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Galaxy {
+        pub seed: u64,
+        /// Chance of a sector containing a star system. Defaults to `0.05`.
+        pub density: f32,
+    }
+
+    const STAR_COLORS: [u16; 8] = [
+        FG_WHITE,
+        FG_YELLOW,
+        FG_DARK_YELLOW,
+        FG_RED,
+        FG_DARK_RED,
+        FG_CYAN,
+        FG_BLUE,
+        FG_GREY,
+    ];
+
+    const SYLLABLES: [&str; 24] = [
+        "al", "be", "cor", "da", "el", "fa", "ga", "hy", "ion", "ka", "lo", "ma", "nex", "or",
+        "pra", "qu", "ri", "sol", "ta", "ul", "ve", "xa", "yr", "zen",
+    ];
+
+    impl Galaxy {
+        pub fn new(seed: u64) -> Self {
+            Self {
+                seed,
+                density: 0.05,
+            }
+        }
+
+        /// Returns a random number generator seeded from sector `(x, y)`.
+        pub fn sector_rng(&self, x: i32, y: i32) -> Rng {
+            let mut h = self.seed ^ ((x as u32 as u64) << 32 | y as u32 as u64);
+            // splitmix64, so neighbouring sectors get unrelated seeds.
+            h = h.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            Rng::new(h ^ (h >> 31))
+        }
+
+        /// Returns `true` if sector `(x, y)` contains a star system. Cheaper than `system`.
+        pub fn has_star(&self, x: i32, y: i32) -> bool {
+            self.sector_rng(x, y).chance(self.density)
+        }
+
+        /// Returns the star of sector `(x, y)` as `(diameter, color)` without generating its
+        /// planets, or `None` if the sector is empty. Cheap enough to call for every sector
+        /// on screen.
+        pub fn star(&self, x: i32, y: i32) -> Option<(f32, u16)> {
+            let mut rng = self.sector_rng(x, y);
+            if !rng.chance(self.density) {
+                return None;
+            }
+            let diameter = 10.0 + rng.next_f32() * 30.0;
+            let color = STAR_COLORS[rng.range(0, STAR_COLORS.len() as i32) as usize];
+            Some((diameter, color))
+        }
+
+        /// Generates the star system of sector `(x, y)`, or `None` if the sector is empty.
+        pub fn system(&self, x: i32, y: i32) -> Option<StarSystem> {
+            let (diameter, color) = self.star(x, y)?;
+            // Planets use their own stream, so `star` doesn't have to generate them.
+            let mut rng = self.sector_rng(x, y);
+            rng.next_u64();
+            let mut rng = Rng::new(rng.next_u64());
+
+            let syllables = rng.range(2, 4);
+            let mut name: String = (0..syllables)
+                .map(|_| SYLLABLES[rng.range(0, SYLLABLES.len() as i32) as usize])
+                .collect();
+            name[..1].make_ascii_uppercase();
+
+            let mut planets = Vec::new();
+            let mut distance = rng.range(60, 200) as f32;
+            for _ in 0..rng.range(0, 10) {
+                distance += rng.range(20, 200) as f32;
+                let temperature = 400.0 - distance * 0.6 + rng.range(-40, 40) as f32;
+                let habitable = (-30.0..60.0).contains(&temperature);
+                let population = if habitable && rng.chance(0.5) {
+                    rng.next_u64() % 10_000_000_000
+                } else {
+                    0
+                };
+                let moons = (0..rng.range(-5, 5).max(0))
+                    .map(|_| 1.0 + rng.next_f32() * 4.0)
+                    .collect();
+                planets.push(Planet {
+                    distance,
+                    diameter: 4.0 + rng.next_f32() * 16.0,
+                    temperature,
+                    population,
+                    ring: rng.chance(0.1),
+                    moons,
+                });
+            }
+
+            Some(StarSystem {
+                x,
+                y,
+                name,
+                diameter,
+                color,
+                planets,
+            })
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the stars of `galaxy` as a map filling the screen, with each sector
+    /// `sector_size` cells across and sector `(offset_x, offset_y)` in the top-left corner.
+    /// Bigger stars are drawn as bigger circles.
+    pub fn draw_galaxy(
+        &mut self,
+        galaxy: &procgen::Galaxy,
+        offset_x: i32,
+        offset_y: i32,
+        sector_size: i32,
+    ) {
+        let sector_size = sector_size.max(1);
+        let sectors_x = (self.screen_width() + sector_size - 1) / sector_size;
+        let sectors_y = (self.screen_height() + sector_size - 1) / sector_size;
+        for sy in 0..sectors_y {
+            for sx in 0..sectors_x {
+                let Some((diameter, col)) = galaxy.star(offset_x + sx, offset_y + sy) else {
+                    continue;
+                };
+                let cx = sx * sector_size + sector_size / 2;
+                let cy = sy * sector_size + sector_size / 2;
+                let r = (diameter / 40.0 * sector_size as f32 / 2.0) as i32;
+                if r == 0 {
+                    self.draw_with(cx, cy, '*' as u16, col);
+                } else {
+                    self.fill_circle_with(cx, cy, r, SOLID, col);
+                }
+            }
+        }
+    }
+
+    /// Draws `system` side-on in the `w` × `h` area at `(x, y)`: the star on the left with
+    /// its planets to the right, in order, each with its moons below it. Sizes are scaled
+    /// down to fit the area's height.
+    pub fn draw_star_system(
+        &mut self,
+        system: &procgen::StarSystem,
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+    ) {
+        let mid = y + h / 2;
+        let scale = h as f32 / 2.0 / 40.0;
+
+        let star_r = ((system.diameter * scale) as i32).max(1);
+        self.fill_circle_with(x + star_r, mid, star_r, SOLID, system.color);
+
+        let mut px = x + star_r * 2 + 2;
+        for planet in &system.planets {
+            let r = ((planet.diameter * scale) as i32).max(1);
+            if px + r * 2 >= x + w {
+                break;
+            }
+            let col = if planet.population > 0 {
+                FG_GREEN
+            } else if planet.temperature > 60.0 {
+                FG_DARK_RED
+            } else if planet.temperature < -30.0 {
+                FG_CYAN
+            } else {
+                FG_DARK_YELLOW
+            };
+            self.fill_circle_with(px + r, mid, r, SOLID, col);
+            if planet.ring {
+                self.draw_line_with(px - 1, mid, px + r * 2 + 1, mid, '-' as u16, FG_GREY);
+            }
+
+            let mut my = mid + r + 2;
+            for moon in &planet.moons {
+                let mr = ((moon * scale) as i32).max(1);
+                if my + mr * 2 >= y + h {
+                    break;
+                }
+                self.fill_circle_with(px + r, my + mr, mr, SOLID, FG_GREY);
+                my += mr * 2 + 2;
+            }
+            px += r * 2 + 2;
+        }
+    }
+}
+
+// endregion
+
+// region: Parallax
+
+/// One layer of a `ParallaxBackground`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParallaxLayer {
+    /// The image, repeated horizontally. `EMPTY` cells let the layers behind show through.
+    pub sprite: Sprite,
+    /// How far the layer moves for every cell the camera moves, e.g. `0.1` for distant
+    /// mountains, `0.5` for trees and `1.0` for something moving with the level.
+    pub factor_x: f32,
+    pub factor_y: f32,
+    /// Screen row of the layer's top edge when the camera is at `y = 0`.
+    pub y: i32,
+    /// Whether the layer is also repeated vertically.
+    pub wrap_y: bool,
+    /// Scrolling on its own in cells per second, e.g. for drifting clouds.
+    pub velocity: (f32, f32),
+    offset: (f32, f32),
+}
+
+/// A side-scroller background made of layers that scroll at different speeds as the
+/// camera moves, giving a sense of depth.
+///
+/// Layers are drawn back to front in the order they were added, and every layer wraps
+/// around horizontally so it covers the screen however far the camera goes.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut background = ParallaxBackground::new();
+/// background.add_layer(Sprite::from_file("sky.spr")?, 0.0, 0.0);
+/// background.add_layer(Sprite::from_file("mountains.spr")?, 0.2, 0.0).y = 20;
+/// background.add_layer(Sprite::from_file("clouds.spr")?, 0.4, 0.0).velocity = (-2.0, 0.0);
+///
+/// // In update:
+/// background.update(elapsed_time);
+/// engine.draw_parallax(&background, camera_x, camera_y);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParallaxBackground {
+    pub layers: Vec<ParallaxLayer>,
+}
+
+impl ParallaxBackground {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer in front of the existing ones, scrolling by `factor_x` and `factor_y`
+    /// of the camera's movement. Returns it so its other settings can be changed.
+    pub fn add_layer(
+        &mut self,
+        sprite: Sprite,
+        factor_x: f32,
+        factor_y: f32,
+    ) -> &mut ParallaxLayer {
+        self.layers.push(ParallaxLayer {
+            sprite,
+            factor_x,
+            factor_y,
+            y: 0,
+            wrap_y: false,
+            velocity: (0.0, 0.0),
+            offset: (0.0, 0.0),
+        });
+        self.layers.last_mut().unwrap()
+    }
+
+    /// Scrolls layers with a velocity by `elapsed` seconds.
+    pub fn update(&mut self, elapsed: f32) {
+        for layer in &mut self.layers {
+            let (w, h) = (layer.sprite.width as f32, layer.sprite.height as f32);
+            // Keep the offset small so it doesn't lose precision over a long session.
+            layer.offset.0 = (layer.offset.0 + layer.velocity.0 * elapsed).rem_euclid(w.max(1.0));
+            layer.offset.1 = (layer.offset.1 + layer.velocity.1 * elapsed).rem_euclid(h.max(1.0));
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws every layer of `background` as seen from a camera whose top-left corner is at
+    /// `(camera_x, camera_y)` in the world.
+    pub fn draw_parallax(&mut self, background: &ParallaxBackground, camera_x: f32, camera_y: f32) {
+        let (screen_w, screen_h) = (self.screen_width(), self.screen_height());
+        for layer in &background.layers {
+            let (w, h) = (layer.sprite.width as i32, layer.sprite.height as i32);
+            if w == 0 || h == 0 {
+                continue;
+            }
+
+            let scroll_x = (camera_x * layer.factor_x - layer.offset.0).floor() as i32;
+            let scroll_y = (camera_y * layer.factor_y - layer.offset.1).floor() as i32;
+            let start_x = -scroll_x.rem_euclid(w);
+            let (start_y, end_y) = if layer.wrap_y {
+                (-(scroll_y - layer.y).rem_euclid(h), screen_h)
+            } else {
+                let top = layer.y - scroll_y;
+                (top, top + 1)
+            };
+
+            let mut y = start_y;
+            while y < end_y {
+                let mut x = start_x;
+                while x < screen_w {
+                    self.draw_sprite(x, y, &layer.sprite);
+                    x += w;
+                }
+                y += h;
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Trails
+
+/// The recent positions of a moving entity, drawn as a streak that fades out behind it,
+/// for racers, snakes, comets and projectiles.
+///
+/// Points are connected with lines when drawn, so fast movement still leaves an unbroken
+/// streak. The newest part is drawn with the dark shade glyph and older parts with
+/// progressively lighter ones.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut trail = Trail::new(12, 0.4);
+///
+/// // In update:
+/// trail.push(ship.x, ship.y);
+/// trail.update(elapsed_time);
+/// engine.draw_trail(&trail, FG_CYAN);
+/// engine.draw(ship.x as i32, ship.y as i32, '>' as u16);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trail {
+    /// Most points kept. The oldest are dropped first.
+    pub max_points: usize,
+    /// Seconds a point lasts before it fades away.
+    pub lifetime: f32,
+    /// Smallest distance in cells from the last point for a new one to be recorded.
+    /// Defaults to `0.5`.
+    pub spacing: f32,
+    points: VecDeque<(f32, f32, f32)>,
+}
+
+impl Trail {
+    /// Creates an empty trail of at most `max_points` points, each lasting `lifetime`
+    /// seconds.
+    pub fn new(max_points: usize, lifetime: f32) -> Self {
+        Self {
+            max_points,
+            lifetime,
+            spacing: 0.5,
+            points: VecDeque::with_capacity(max_points),
+        }
+    }
+
+    /// Records the entity at `(x, y)`, unless it hasn't moved `spacing` since the last
+    /// point.
+    pub fn push(&mut self, x: f32, y: f32) {
+        if let Some(&(lx, ly, _)) = self.points.front() {
+            let (dx, dy) = (x - lx, y - ly);
+            if dx * dx + dy * dy < self.spacing * self.spacing {
+                return;
+            }
+        }
+        self.points.push_front((x, y, 0.0));
+        self.points.truncate(self.max_points);
+    }
+
+    /// Ages every point by `elapsed` seconds and drops the ones that have faded.
+    pub fn update(&mut self, elapsed: f32) {
+        for point in &mut self.points {
+            point.2 += elapsed;
+        }
+        while self.points.back().is_some_and(|p| p.2 >= self.lifetime) {
+            self.points.pop_back();
+        }
+    }
+
+    /// Returns the points, newest first.
+    pub fn points(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.points.iter().map(|&(x, y, _)| (x, y))
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Removes every point, e.g. when the entity teleports.
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    /// Returns how faded the point at `index` is, from `0.0` to `1.0`, going by both its
+    /// age and how far back in the trail it is.
+    fn fade(&self, index: usize) -> f32 {
+        let age = self.points[index].2 / self.lifetime.max(f32::EPSILON);
+        let place = index as f32 / self.max_points.max(1) as f32;
+        age.max(place).min(1.0)
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws `trail` in `col`, fading from the dark shade glyph at its newest point to the
+    /// light shade glyph at its oldest.
+    pub fn draw_trail(&mut self, trail: &Trail, col: u16) {
+        let shade = |fade: f32| match fade {
+            f if f < 1.0 / 3.0 => glyph::DARK_SHADE,
+            f if f < 2.0 / 3.0 => glyph::MEDIUM_SHADE,
+            _ => glyph::LIGHT_SHADE,
+        };
+        // Draw oldest first, so newer segments end up on top where they overlap.
+        for i in (0..trail.len()).rev() {
+            let (x1, y1, _) = trail.points[i];
+            let glyph = shade(trail.fade(i));
+            match i.checked_sub(1).map(|j| trail.points[j]) {
+                Some((x2, y2, _)) => plot_line(
+                    x1.floor() as i32,
+                    y1.floor() as i32,
+                    x2.floor() as i32,
+                    y2.floor() as i32,
+                    |x, y| self.draw_with(x, y, glyph, col),
+                ),
+                None => self.draw_with(x1.floor() as i32, y1.floor() as i32, glyph, col),
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Affine Plane
+
+/// Position and heading of the viewer for `draw_affine_plane` and `draw_affine_ceiling`.
+///
+/// Coordinates are in texture units: the sprite repeats every `1.0` in both directions.
+/// An `angle` of 0 looks along the positive X axis.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlaneCamera {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws `sprite` as an endless textured floor below the screen row `horizon`, the
+    /// "Mode 7" pseudo-3D effect.
+    ///
+    /// `near` and `far` set up the frustum as in the classic effect: a row at depth `d`
+    /// (0 at the horizon, 1 at the bottom of the screen) samples the plane at distance
+    /// `near + (far - near) / d` from the camera. `fov` is the full horizontal field of view
+    /// in radians. The texture wraps, so the camera can go anywhere, including negative
+    /// coordinates.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let camera = PlaneCamera { x: 1000.0, y: 1000.0, angle: 0.1 };
+    /// let horizon = engine.screen_height() / 2;
+    /// engine.draw_affine_plane(&ground, camera, horizon, 0.005, 0.03, PI / 2.0);
+    /// engine.draw_affine_ceiling(&sky, camera, horizon, 0.005, 0.03, PI / 2.0);
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_affine_plane(
+        &mut self,
+        sprite: &Sprite,
+        camera: PlaneCamera,
+        horizon: i32,
+        near: f32,
+        far: f32,
+        fov: f32,
+    ) {
+        self.draw_affine(sprite, camera, horizon, near, far, fov, false);
+    }
+
+    /// Like `draw_affine_plane`, but draws the plane above `horizon`, mirrored, as a sky or
+    /// ceiling.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_affine_ceiling(
+        &mut self,
+        sprite: &Sprite,
+        camera: PlaneCamera,
+        horizon: i32,
+        near: f32,
+        far: f32,
+        fov: f32,
+    ) {
+        self.draw_affine(sprite, camera, horizon, near, far, fov, true);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_affine(
+        &mut self,
+        sprite: &Sprite,
+        camera: PlaneCamera,
+        horizon: i32,
+        near: f32,
+        far: f32,
+        fov: f32,
+        above: bool,
+    ) {
+        self.draw_calls += 1;
+
+        let (width, height) = (self.screen_width(), self.screen_height());
+        let horizon = horizon.clamp(0, height);
+        let rows = if above { horizon } else { height - horizon };
+        if rows <= 0 || width <= 0 {
+            return;
+        }
+
+        // Corners of the view frustum on the plane, left edge then right edge.
+        let corner =
+            |angle: f32, dist: f32| (camera.x + angle.cos() * dist, camera.y + angle.sin() * dist);
+        let left = camera.angle - fov / 2.0;
+        let right = camera.angle + fov / 2.0;
+        let (far_x1, far_y1) = corner(left, far);
+        let (near_x1, near_y1) = corner(left, near);
+        let (far_x2, far_y2) = corner(right, far);
+        let (near_x2, near_y2) = corner(right, near);
+
+        let (y1, y2) = if above {
+            (0, horizon)
+        } else {
+            (horizon, height)
+        };
+        let cells = (rows * width) as usize;
+        self.raster_rows(y1, y2, cells, |y, row| {
+            let r = if above { horizon - 1 - y } else { y - horizon };
+            // Sample the middle of the row so the row at the horizon isn't at infinity.
+            let depth = (r as f32 + 0.5) / rows as f32;
+
+            let start_x = (far_x1 - near_x1) / depth + near_x1;
+            let start_y = (far_y1 - near_y1) / depth + near_y1;
+            let end_x = (far_x2 - near_x2) / depth + near_x2;
+            let end_y = (far_y2 - near_y2) / depth + near_y2;
+
+            for (x, cell) in row.iter_mut().enumerate() {
+                let t = x as f32 / width as f32;
+                let sx = (end_x - start_x) * t + start_x;
+                let sy = (end_y - start_y) * t + start_y;
+                cell.Char.UnicodeChar = sprite.sample_glyph(sx, sy);
+                cell.Attributes = sprite.sample_color(sx, sy);
+            }
+        });
+    }
+}
+
+// endregion
+
+// region: 3D
+
+/// Vector and matrix math for 3D rendering, plus camera controllers.
+///
+/// The conventions follow the classic console 3D engine: a left-handed coordinate system with
+/// `+x` to the right, `+y` up and `+z` into the screen, and row vectors multiplied on the left
+/// of a matrix (`v * M`), so transforms chain left to right: `world * view * projection`.
+pub mod gfx3d {
+    use crate::{key, mouse_button, ConsoleGame, ConsoleGameEngine, InputEvent, Sprite};
+    use std::collections::HashMap;
+    use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+    /// A point or direction in 3D space.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Vec3 {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+
+    impl Vec3 {
+        /// The world up direction, `+y`.
+        pub const UP: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+
+        pub const fn new(x: f32, y: f32, z: f32) -> Self {
+            Self { x, y, z }
+        }
+
+        pub fn dot(self, other: Vec3) -> f32 {
+            self.x * other.x + self.y * other.y + self.z * other.z
+        }
+
+        pub fn cross(self, other: Vec3) -> Vec3 {
+            Vec3::new(
+                self.y * other.z - self.z * other.y,
+                self.z * other.x - self.x * other.z,
+                self.x * other.y - self.y * other.x,
+            )
+        }
+
+        pub fn length(self) -> f32 {
+            self.dot(self).sqrt()
+        }
+
+        /// Returns the vector scaled to length 1, or the zero vector if it has no length.
+        pub fn normalize(self) -> Vec3 {
+            let length = self.length();
+            if length > 0.0 {
+                self / length
+            } else {
+                self
+            }
+        }
+
+        pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+            self + (other - self) * t
+        }
+    }
+
+    impl Add for Vec3 {
+        type Output = Vec3;
+
+        fn add(self, rhs: Vec3) -> Vec3 {
+            Vec3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+        }
+    }
+
+    impl AddAssign for Vec3 {
+        fn add_assign(&mut self, rhs: Vec3) {
+            *self = *self + rhs;
+        }
+    }
+
+    impl Sub for Vec3 {
+        type Output = Vec3;
+
+        fn sub(self, rhs: Vec3) -> Vec3 {
+            Vec3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+        }
+    }
+
+    impl SubAssign for Vec3 {
+        fn sub_assign(&mut self, rhs: Vec3) {
+            *self = *self - rhs;
+        }
+    }
+
+    impl Mul<f32> for Vec3 {
+        type Output = Vec3;
+
+        fn mul(self, rhs: f32) -> Vec3 {
+            Vec3::new(self.x * rhs, self.y * rhs, self.z * rhs)
+        }
+    }
+
+    impl Div<f32> for Vec3 {
+        type Output = Vec3;
+
+        fn div(self, rhs: f32) -> Vec3 {
+            Vec3::new(self.x / rhs, self.y / rhs, self.z / rhs)
+        }
+    }
+
+    impl Neg for Vec3 {
+        type Output = Vec3;
+
+        fn neg(self) -> Vec3 {
+            Vec3::new(-self.x, -self.y, -self.z)
+        }
+    }
+
+    /// A 4x4 transform matrix, indexed `m[row][column]`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Mat4 {
+        pub m: [[f32; 4]; 4],
+    }
+
+    impl Default for Mat4 {
+        fn default() -> Self {
+            Self::identity()
+        }
+    }
+
+    impl Mat4 {
+        pub const fn identity() -> Self {
+            Self {
+                m: [
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            }
+        }
+
+        pub fn translation(offset: Vec3) -> Self {
+            let mut mat = Self::identity();
+            mat.m[3] = [offset.x, offset.y, offset.z, 1.0];
+            mat
+        }
+
+        pub fn scale(scale: Vec3) -> Self {
+            let mut mat = Self::identity();
+            mat.m[0][0] = scale.x;
+            mat.m[1][1] = scale.y;
+            mat.m[2][2] = scale.z;
+            mat
+        }
+
+        pub fn rotation_x(angle: f32) -> Self {
+            let (sin, cos) = angle.sin_cos();
+            let mut mat = Self::identity();
+            mat.m[1][1] = cos;
+            mat.m[1][2] = sin;
+            mat.m[2][1] = -sin;
+            mat.m[2][2] = cos;
+            mat
+        }
+
+        pub fn rotation_y(angle: f32) -> Self {
+            let (sin, cos) = angle.sin_cos();
+            let mut mat = Self::identity();
+            mat.m[0][0] = cos;
+            mat.m[0][2] = -sin;
+            mat.m[2][0] = sin;
+            mat.m[2][2] = cos;
+            mat
+        }
+
+        pub fn rotation_z(angle: f32) -> Self {
+            let (sin, cos) = angle.sin_cos();
+            let mut mat = Self::identity();
+            mat.m[0][0] = cos;
+            mat.m[0][1] = sin;
+            mat.m[1][0] = -sin;
+            mat.m[1][1] = cos;
+            mat
+        }
+
+        /// Perspective projection. `fov` is the vertical field of view in radians and `aspect`
+        /// is the screen's height divided by its width, in pixels.
+        ///
+        /// Visible points end up with `x` and `y` in `-1..=1` and `z` in `0..=1` after the
+        /// divide by `w` that `transform_point` performs.
+        pub fn projection(fov: f32, aspect: f32, near: f32, far: f32) -> Self {
+            let f = 1.0 / (fov * 0.5).tan();
+            let mut mat = Self { m: [[0.0; 4]; 4] };
+            mat.m[0][0] = aspect * f;
+            mat.m[1][1] = f;
+            mat.m[2][2] = far / (far - near);
+            mat.m[3][2] = (-far * near) / (far - near);
+            mat.m[2][3] = 1.0;
+            mat
+        }
+
+        /// View matrix for a camera at `eye` looking towards `target`.
+        pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+            let forward = (target - eye).normalize();
+            let right = up.cross(forward).normalize();
+            let up = forward.cross(right);
+            Self {
+                m: [
+                    [right.x, up.x, forward.x, 0.0],
+                    [right.y, up.y, forward.y, 0.0],
+                    [right.z, up.z, forward.z, 0.0],
+                    [-eye.dot(right), -eye.dot(up), -eye.dot(forward), 1.0],
+                ],
+            }
+        }
+
+        /// Transforms a point, including translation and the perspective divide.
+        pub fn transform_point(&self, v: Vec3) -> Vec3 {
+            let m = &self.m;
+            let x = v.x * m[0][0] + v.y * m[1][0] + v.z * m[2][0] + m[3][0];
+            let y = v.x * m[0][1] + v.y * m[1][1] + v.z * m[2][1] + m[3][1];
+            let z = v.x * m[0][2] + v.y * m[1][2] + v.z * m[2][2] + m[3][2];
+            let w = v.x * m[0][3] + v.y * m[1][3] + v.z * m[2][3] + m[3][3];
+            if w != 0.0 && w != 1.0 {
+                Vec3::new(x / w, y / w, z / w)
+            } else {
+                Vec3::new(x, y, z)
+            }
+        }
+
+        /// Transforms a direction, ignoring translation.
+        pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+            let m = &self.m;
+            Vec3::new(
+                v.x * m[0][0] + v.y * m[1][0] + v.z * m[2][0],
+                v.x * m[0][1] + v.y * m[1][1] + v.z * m[2][1],
+                v.x * m[0][2] + v.y * m[1][2] + v.z * m[2][2],
+            )
+        }
+    }
+
+    impl Mul for Mat4 {
+        type Output = Mat4;
+
+        fn mul(self, rhs: Mat4) -> Mat4 {
+            let mut out = Mat4 { m: [[0.0; 4]; 4] };
+            for r in 0..4 {
+                for c in 0..4 {
+                    out.m[r][c] = (0..4).map(|k| self.m[r][k] * rhs.m[k][c]).sum();
+                }
+            }
+            out
+        }
+    }
+
+    /// A single triangle of a mesh. Front faces wind clockwise as seen by the camera.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Triangle {
+        pub p: [Vec3; 3],
+    }
+
+    impl Triangle {
+        pub fn new(a: Vec3, b: Vec3, c: Vec3) -> Self {
+            Self { p: [a, b, c] }
+        }
+
+        /// Unit normal of the front face.
+        pub fn normal(&self) -> Vec3 {
+            (self.p[1] - self.p[0])
+                .cross(self.p[2] - self.p[0])
+                .normalize()
+        }
+    }
+
+    /// An axis-aligned bounding box.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Aabb {
+        pub min: Vec3,
+        pub max: Vec3,
+    }
+
+    impl Aabb {
+        /// The smallest box containing all of `points`, or an empty box at the origin.
+        pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+            let mut points = points.into_iter();
+            let Some(first) = points.next() else {
+                return Self::default();
+            };
+            points.fold(
+                Self {
+                    min: first,
+                    max: first,
+                },
+                |b, p| Self {
+                    min: Vec3::new(b.min.x.min(p.x), b.min.y.min(p.y), b.min.z.min(p.z)),
+                    max: Vec3::new(b.max.x.max(p.x), b.max.y.max(p.y), b.max.z.max(p.z)),
+                },
+            )
+        }
+
+        pub fn center(&self) -> Vec3 {
+            (self.min + self.max) * 0.5
+        }
+
+        pub fn corners(&self) -> [Vec3; 8] {
+            let (a, b) = (self.min, self.max);
+            [
+                Vec3::new(a.x, a.y, a.z),
+                Vec3::new(b.x, a.y, a.z),
+                Vec3::new(a.x, b.y, a.z),
+                Vec3::new(b.x, b.y, a.z),
+                Vec3::new(a.x, a.y, b.z),
+                Vec3::new(b.x, a.y, b.z),
+                Vec3::new(a.x, b.y, b.z),
+                Vec3::new(b.x, b.y, b.z),
+            ]
+        }
+
+        /// The box around this one after it is moved by `transform`.
+        pub fn transformed(&self, transform: &Mat4) -> Self {
+            Self::from_points(self.corners().map(|c| transform.transform_point(c)))
+        }
+    }
+
+    /// A bounding sphere.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Sphere {
+        pub center: Vec3,
+        pub radius: f32,
+    }
+
+    impl Sphere {
+        /// The sphere after it is moved by `transform`. The radius grows with the largest scale
+        /// in the transform, so the result still contains everything the original did.
+        pub fn transformed(&self, transform: &Mat4) -> Self {
+            let scale = (0..3)
+                .map(|r| Vec3::new(transform.m[r][0], transform.m[r][1], transform.m[r][2]))
+                .map(Vec3::length)
+                .fold(0.0, f32::max);
+            Self {
+                center: transform.transform_point(self.center),
+                radius: self.radius * scale,
+            }
+        }
+    }
+
+    /// A triangle mesh with precomputed bounds for culling.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Mesh {
+        tris: Vec<Triangle>,
+        normals: Vec<[Vec3; 3]>,
+        aabb: Aabb,
+        sphere: Sphere,
+    }
+
+    impl Mesh {
+        pub fn new(tris: Vec<Triangle>) -> Self {
+            let aabb = Aabb::from_points(tris.iter().flat_map(|t| t.p));
+            let center = aabb.center();
+            let radius = tris
+                .iter()
+                .flat_map(|t| t.p)
+                .map(|p| (p - center).length())
+                .fold(0.0, f32::max);
+
+            // Vertices at the same position share the sum of their faces' normals, weighted by
+            // area since the cross product's length is twice the face's area.
+            let key = |v: Vec3| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+            let mut sums: HashMap<[u32; 3], Vec3> = HashMap::new();
+            for t in &tris {
+                let n = (t.p[1] - t.p[0]).cross(t.p[2] - t.p[0]);
+                for v in t.p {
+                    *sums.entry(key(v)).or_default() += n;
+                }
+            }
+            let normals = tris
+                .iter()
+                .map(|t| t.p.map(|v| sums[&key(v)].normalize()))
+                .collect();
+
+            Self {
+                tris,
+                normals,
+                aabb,
+                sphere: Sphere { center, radius },
+            }
+        }
+
+        /// Loads a mesh from a Wavefront OBJ file.
+        ///
+        /// Only vertex positions and faces are read. Faces with more than three vertices are
+        /// split into a fan of triangles, and texture and normal indices are ignored.
+        pub fn from_obj(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            Self::parse_obj(&std::fs::read_to_string(path)?)
+        }
+
+        /// Parses a mesh from the text of a Wavefront OBJ file. See [`Mesh::from_obj`].
+        pub fn parse_obj(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut verts = Vec::new();
+            let mut tris = Vec::new();
+
+            for (n, line) in text.lines().enumerate() {
+                let mut parts = line.split_whitespace();
+                match parts.next() {
+                    Some("v") => {
+                        let mut coord = || -> Result<f32, Box<dyn std::error::Error>> {
+                            let part = parts
+                                .next()
+                                .ok_or(format!("line {}: missing coordinate", n + 1))?;
+                            Ok(part.parse()?)
+                        };
+                        verts.push(Vec3::new(coord()?, coord()?, coord()?));
+                    }
+                    Some("f") => {
+                        let face = parts
+                            .map(|part| {
+                                let index: i64 = part.split('/').next().unwrap_or("").parse()?;
+                                let index = if index < 0 {
+                                    verts.len() as i64 + index
+                                } else {
+                                    index - 1
+                                };
+                                verts.get(index as usize).copied().ok_or_else(|| {
+                                    format!("line {}: bad vertex index", n + 1).into()
+                                })
+                            })
+                            .collect::<Result<Vec<Vec3>, Box<dyn std::error::Error>>>()?;
+                        for i in 1..face.len().saturating_sub(1) {
+                            tris.push(Triangle::new(face[0], face[i], face[i + 1]));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            Ok(Self::new(tris))
+        }
+
+        pub fn tris(&self) -> &[Triangle] {
+            &self.tris
+        }
+
+        /// Smoothed normals of the three vertices of triangle `index`, used for Gouraud
+        /// shading.
+        pub fn vertex_normals(&self, index: usize) -> [Vec3; 3] {
+            self.normals[index]
+        }
+
+        /// Bounding box of the mesh in model space.
+        pub fn aabb(&self) -> Aabb {
+            self.aabb
+        }
+
+        /// Bounding sphere of the mesh in model space.
+        pub fn bounding_sphere(&self) -> Sphere {
+            self.sphere
+        }
+    }
+
+    /// A sprite that always faces the camera, for `draw_billboards`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Billboard<'a> {
+        pub sprite: &'a Sprite,
+        /// World position of the sprite's centre.
+        pub position: Vec3,
+        /// Height of the sprite in world units. The width follows the sprite's aspect ratio.
+        pub size: f32,
+    }
+
+    /// A light source for `draw_mesh_lit`, in world space.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Light {
+        /// Light arriving from infinitely far away, like the sun. `direction` is the way the
+        /// light travels.
+        Directional { direction: Vec3, intensity: f32 },
+        /// Light spreading out from `position`, fading to nothing at `range`.
+        Point {
+            position: Vec3,
+            intensity: f32,
+            range: f32,
+        },
+    }
+
+    impl Light {
+        /// How much of this light reaches a surface at `point` facing along `normal`.
+        pub fn illuminate(&self, point: Vec3, normal: Vec3) -> f32 {
+            match *self {
+                Light::Directional {
+                    direction,
+                    intensity,
+                } => intensity * normal.dot(-direction.normalize()).max(0.0),
+                Light::Point {
+                    position,
+                    intensity,
+                    range,
+                } => {
+                    let to_light = position - point;
+                    let distance = to_light.length();
+                    if distance >= range || distance == 0.0 {
+                        return 0.0;
+                    }
+                    let falloff = 1.0 - distance / range;
+                    intensity * falloff * normal.dot(to_light / distance).max(0.0)
+                }
+            }
+        }
+    }
+
+    /// How lighting is spread across each triangle.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum Shading {
+        /// One brightness per triangle, from its face normal.
+        #[default]
+        Flat,
+        /// Brightness lit at each vertex from the smoothed vertex normals and blended across
+        /// the triangle.
+        Gouraud,
+    }
+
+    /// The lights in a scene and how they are applied.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Lighting {
+        pub lights: Vec<Light>,
+        /// Brightness every surface gets regardless of the lights, from 0 to 1.
+        pub ambient: f32,
+        pub shading: Shading,
+    }
+
+    impl Default for Lighting {
+        fn default() -> Self {
+            Self {
+                lights: Vec::new(),
+                ambient: 0.1,
+                shading: Shading::Flat,
+            }
+        }
+    }
+
+    impl Lighting {
+        /// Total brightness, from 0 to 1, of a surface at `point` facing along `normal`.
+        pub fn brightness(&self, point: Vec3, normal: Vec3) -> f32 {
+            let lit: f32 = self
+                .lights
+                .iter()
+                .map(|l| l.illuminate(point, normal))
+                .sum();
+            (self.ambient + lit).clamp(0.0, 1.0)
+        }
+    }
+
+    /// Picks the glyph and attributes that best show `col` at `brightness` (0 to 1).
+    ///
+    /// The four shade glyphs blend from black to the dark version of the color, then from
+    /// the dark to the bright version. Greys ramp through dark grey and grey up to white.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let (glyph, col) = gfx3d::shade(FG_RED, 0.5);
+    /// engine.draw_with(x, y, glyph, col);
+    /// ```
+    pub fn shade(col: u16, brightness: f32) -> (u16, u16) {
+        use crate::color::*;
+        use crate::pixel::*;
+
+        let hue = col & 0x7;
+        let ramp: &[u16] = if hue == 0 || hue == 7 {
+            &[FG_BLACK, FG_DARK_GREY, FG_GREY, FG_WHITE]
+        } else {
+            &[FG_BLACK, hue, hue | 0x8]
+        };
+        let levels = 1 + 4 * (ramp.len() - 1);
+        let level = (brightness.clamp(0.0, 1.0) * (levels - 1) as f32).round() as usize;
+        if level == 0 {
+            return (SOLID, FG_BLACK | BG_BLACK);
+        }
+
+        let segment = (level - 1) / 4;
+        let glyph = [QUARTER, HALF, THREE_QUARTERS, SOLID][(level - 1) % 4];
+        (glyph, ramp[segment + 1] | ramp[segment] << 4)
+    }
+
+    /// A plane `normal · p + d = 0`. Points with a positive distance are in front of it.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Plane {
+        pub normal: Vec3,
+        pub d: f32,
+    }
+
+    impl Plane {
+        /// Signed distance from the plane to `point`.
+        pub fn distance(&self, point: Vec3) -> f32 {
+            self.normal.dot(point) + self.d
+        }
+    }
+
+    /// The six planes bounding what a camera can see, facing inwards.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Frustum {
+        pub planes: [Plane; 6],
+    }
+
+    impl Frustum {
+        /// Extracts the frustum from a combined `view * projection` matrix, giving planes in
+        /// world space. Including a model's world matrix (`world * view * projection`) gives
+        /// planes in that model's space instead, so its bounds can be tested untransformed.
+        pub fn from_matrix(matrix: &Mat4) -> Self {
+            let m = &matrix.m;
+            let column = |c: usize| [m[0][c], m[1][c], m[2][c], m[3][c]];
+            let (x, y, z, w) = (column(0), column(1), column(2), column(3));
+            let plane = |a: [f32; 4], sign: f32, b: [f32; 4]| {
+                let normal = Vec3::new(a[0] + sign * b[0], a[1] + sign * b[1], a[2] + sign * b[2]);
+                let length = normal.length().max(f32::EPSILON);
+                Plane {
+                    normal: normal / length,
+                    d: (a[3] + sign * b[3]) / length,
+                }
+            };
+            Self {
+                planes: [
+                    plane(w, 1.0, x),
+                    plane(w, -1.0, x),
+                    plane(w, 1.0, y),
+                    plane(w, -1.0, y),
+                    plane(z, 0.0, z),
+                    plane(w, -1.0, z),
+                ],
+            }
+        }
+
+        pub fn contains_point(&self, point: Vec3) -> bool {
+            self.planes.iter().all(|p| p.distance(point) >= 0.0)
+        }
+
+        /// Returns `false` only if the sphere is entirely outside the frustum.
+        pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+            self.planes
+                .iter()
+                .all(|p| p.distance(sphere.center) >= -sphere.radius)
+        }
+
+        /// Returns `false` only if the box is entirely outside one of the planes.
+        pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+            self.planes.iter().all(|p| {
+                // The corner furthest along the plane's normal.
+                let corner = Vec3::new(
+                    if p.normal.x >= 0.0 {
+                        aabb.max.x
+                    } else {
+                        aabb.min.x
+                    },
+                    if p.normal.y >= 0.0 {
+                        aabb.max.y
+                    } else {
+                        aabb.min.y
+                    },
+                    if p.normal.z >= 0.0 {
+                        aabb.max.z
+                    } else {
+                        aabb.min.z
+                    },
+                );
+                p.distance(corner) >= 0.0
+            })
+        }
+
+        /// Tests the mesh's bounding sphere, then its bounding box.
+        pub fn intersects_mesh(&self, mesh: &Mesh) -> bool {
+            self.intersects_sphere(&mesh.bounding_sphere()) && self.intersects_aabb(&mesh.aabb())
+        }
+    }
+
+    /// Pitch is kept just short of straight up or down so the view never flips.
+    const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+    /// Unit vector a camera with the given yaw and pitch looks along. Yaw 0 looks down `+z`
+    /// and positive yaw turns right.
+    fn direction(yaw: f32, pitch: f32) -> Vec3 {
+        Vec3::new(
+            yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            yaw.cos() * pitch.cos(),
+        )
+    }
+
+    /// A free-flying first person camera.
+    ///
+    /// `update` drives it from the keyboard and mouse: W/S and A/D move, E/Q rise and sink,
+    /// the arrow keys turn, and the mouse looks around while it is captured with
+    /// `set_mouse_captured`. Other input sources, like a gamepad, can call `turn` and
+    /// `move_local` directly.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// self.camera.update(engine, elapsed_time);
+    /// let view = self.camera.view_matrix();
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct FlyCamera {
+        pub position: Vec3,
+        /// Rotation about the Y axis in radians.
+        pub yaw: f32,
+        /// Rotation above (positive) or below the horizon in radians.
+        pub pitch: f32,
+        /// Movement speed in world units per second.
+        pub speed: f32,
+        /// Keyboard turn speed in radians per second.
+        pub turn_speed: f32,
+        /// Mouse look speed in radians per pixel.
+        pub sensitivity: f32,
+    }
+
+    impl Default for FlyCamera {
+        fn default() -> Self {
+            Self::new(Vec3::default())
+        }
+    }
+
+    impl FlyCamera {
+        pub fn new(position: Vec3) -> Self {
+            Self {
+                position,
+                yaw: 0.0,
+                pitch: 0.0,
+                speed: 8.0,
+                turn_speed: 2.0,
+                sensitivity: 0.003,
+            }
+        }
+
+        /// Unit vector the camera is looking along.
+        pub fn forward(&self) -> Vec3 {
+            direction(self.yaw, self.pitch)
+        }
+
+        /// Unit vector to the camera's right, level with the horizon.
+        pub fn right(&self) -> Vec3 {
+            Vec3::UP.cross(direction(self.yaw, 0.0)).normalize()
+        }
+
+        /// Turns the camera by the given angles in radians.
+        pub fn turn(&mut self, yaw: f32, pitch: f32) {
+            self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+            self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        /// Moves the camera relative to where it is looking. Forward follows the pitch, so
+        /// looking up and moving forward climbs.
+        pub fn move_local(&mut self, forward: f32, right: f32, up: f32) {
+            self.position += self.forward() * forward + self.right() * right + Vec3::UP * up;
+        }
+
+        /// Applies one frame of keyboard and mouse input.
+        pub fn update<G: ConsoleGame>(&mut self, engine: &ConsoleGameEngine<G>, elapsed: f32) {
+            let axis = |positive: &[usize], negative: &[usize]| {
+                let held = |keys: &[usize]| keys.iter().any(|&k| engine.key_held(k));
+                held(positive) as i32 as f32 - held(negative) as i32 as f32
+            };
+
+            let yaw = axis(&[key::ARROW_RIGHT], &[key::ARROW_LEFT]);
+            let pitch = axis(&[key::ARROW_UP], &[key::ARROW_DOWN]);
+            self.turn(
+                yaw * self.turn_speed * elapsed,
+                pitch * self.turn_speed * elapsed,
+            );
+
+            let (dx, dy) = engine.mouse_delta();
+            self.turn(dx as f32 * self.sensitivity, -dy as f32 * self.sensitivity);
+
+            let step = self.speed * elapsed;
+            self.move_local(
+                axis(&[key::W], &[key::S]) * step,
+                axis(&[key::D], &[key::A]) * step,
+                axis(&[key::E], &[key::Q]) * step,
+            );
+        }
+
+        pub fn view_matrix(&self) -> Mat4 {
+            Mat4::look_at(self.position, self.position + self.forward(), Vec3::UP)
+        }
+    }
+
+    /// A camera circling a target point, as used by model viewers.
+    ///
+    /// `update` drives it from the keyboard and mouse: dragging with the left mouse button or
+    /// holding the arrow keys orbits, and Page Up/Page Down zoom, as does the mouse wheel when
+    /// the event queue is enabled. Other input sources can call `orbit` and `zoom` directly.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct OrbitCamera {
+        pub target: Vec3,
+        pub distance: f32,
+        /// Rotation about the target's Y axis in radians.
+        pub yaw: f32,
+        /// Height above (positive) or below the target as an angle in radians.
+        pub pitch: f32,
+        pub min_distance: f32,
+        pub max_distance: f32,
+        /// Keyboard orbit speed in radians per second.
+        pub turn_speed: f32,
+        /// Mouse drag speed in radians per console cell.
+        pub sensitivity: f32,
+        /// Zoom speed as a fraction of the distance per second or per wheel notch.
+        pub zoom_speed: f32,
+        last_mouse: Option<(i32, i32)>,
+    }
+
+    impl Default for OrbitCamera {
+        fn default() -> Self {
+            Self::new(Vec3::default(), 5.0)
+        }
+    }
+
+    impl OrbitCamera {
+        pub fn new(target: Vec3, distance: f32) -> Self {
+            Self {
+                target,
+                distance,
+                yaw: 0.0,
+                pitch: 0.3,
+                min_distance: 0.1,
+                max_distance: 1000.0,
+                turn_speed: 2.0,
+                sensitivity: 0.05,
+                zoom_speed: 1.0,
+                last_mouse: None,
+            }
+        }
+
+        /// World position of the camera.
+        pub fn position(&self) -> Vec3 {
+            self.target - direction(self.yaw, self.pitch) * self.distance
+        }
+
+        /// Moves the camera around the target by the given angles in radians.
+        pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+            self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+            self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        /// Scales the distance to the target by `factor`, within `min_distance` and
+        /// `max_distance`. Factors below 1 move closer.
+        pub fn zoom(&mut self, factor: f32) {
+            self.distance = (self.distance * factor).clamp(self.min_distance, self.max_distance);
+        }
+
+        /// Applies one frame of keyboard and mouse input.
+        pub fn update<G: ConsoleGame>(&mut self, engine: &ConsoleGameEngine<G>, elapsed: f32) {
+            let axis = |positive: usize, negative: usize| {
+                engine.key_held(positive) as i32 as f32 - engine.key_held(negative) as i32 as f32
+            };
+
+            let step = self.turn_speed * elapsed;
+            self.orbit(
+                axis(key::ARROW_LEFT, key::ARROW_RIGHT) * step,
+                axis(key::ARROW_UP, key::ARROW_DOWN) * step,
+            );
+
+            let mouse = engine.mouse_pos();
+            if engine.mouse_held(mouse_button::LEFT) {
+                if let Some((x, y)) = self.last_mouse {
+                    self.orbit(
+                        (x - mouse.0) as f32 * self.sensitivity,
+                        (mouse.1 - y) as f32 * self.sensitivity,
+                    );
+                }
+                self.last_mouse = Some(mouse);
+            } else {
+                self.last_mouse = None;
+            }
+
+            let zoom = axis(key::PAGE_DOWN, key::PAGE_UP) * self.zoom_speed * elapsed;
+            // Kept fractional, so high-resolution wheels and touchpads that send deltas
+            // smaller than a notch still zoom.
+            let notches: f32 = engine
+                .events()
+                .iter()
+                .map(|event| match event {
+                    InputEvent::MouseWheel { delta } => *delta as f32 / 120.0,
+                    _ => 0.0,
+                })
+                .sum();
+            self.zoom((1.0 + zoom) * (1.0 + self.zoom_speed * 0.1).powf(-notches));
+        }
+
+        pub fn view_matrix(&self) -> Mat4 {
+            Mat4::look_at(self.position(), self.target, Vec3::UP)
+        }
+    }
+}
+
+/// Nothing closer to the camera than this is drawn when the projection's near plane can't be
+/// recovered from the matrix.
+const DEFAULT_NEAR_CLIP: f32 = 0.1;
+
+/// Distance to the near plane of a projection made by `Mat4::projection`.
+fn projection_near(projection: &gfx3d::Mat4) -> f32 {
+    let m = &projection.m;
+    if m[2][2] != 0.0 && m[3][2] != 0.0 {
+        -m[3][2] / m[2][2]
+    } else {
+        DEFAULT_NEAR_CLIP
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Resets the depth buffer so the next `draw_mesh` calls start from an empty scene. Call
+    /// this once a frame, alongside `clear`.
+    pub fn clear_depth(&mut self) {
+        self.depth_buffer.fill(0.0);
+    }
+
+    /// Draws `mesh` placed by `world` as seen through `view` and `projection`, filling its
+    /// visible faces with `glyph` and `col`.
+    ///
+    /// Meshes whose bounding sphere or box lies entirely outside the view frustum are skipped
+    /// without touching their triangles, and `false` is returned. Faces pointing away from the
+    /// camera are culled, and the depth buffer keeps nearer faces in front regardless of draw
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.clear(FG_BLACK);
+    /// engine.clear_depth();
+    /// let view = self.camera.view_matrix();
+    /// for (mesh, world) in &self.scene {
+    ///     engine.draw_mesh(mesh, world, &view, &self.projection, SOLID, FG_WHITE);
+    /// }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_mesh(
+        &mut self,
+        mesh: &gfx3d::Mesh,
+        world: &gfx3d::Mat4,
+        view: &gfx3d::Mat4,
+        projection: &gfx3d::Mat4,
+        glyph: u16,
+        col: u16,
+    ) -> bool {
+        self.render_mesh(mesh, world, view, projection, |_| move |_| (glyph, col))
+    }
+
+    /// Draws `mesh` like `draw_mesh`, with each face shaded by `lighting` and drawn in
+    /// shades of `col` picked by [`gfx3d::shade`].
+    ///
+    /// With [`gfx3d::Shading::Flat`] every face gets a single shade from its normal. With
+    /// [`gfx3d::Shading::Gouraud`] the lights are evaluated at each vertex and blended across
+    /// the face, which smooths out curved surfaces.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let lighting = gfx3d::Lighting {
+    ///     lights: vec![gfx3d::Light::Directional {
+    ///         direction: Vec3::new(0.0, -1.0, 1.0),
+    ///         intensity: 0.9,
+    ///     }],
+    ///     ..Default::default()
+    /// };
+    /// engine.draw_mesh_lit(&self.teapot, &world, &view, &self.projection, &lighting, FG_CYAN);
+    /// ```
+    pub fn draw_mesh_lit(
+        &mut self,
+        mesh: &gfx3d::Mesh,
+        world: &gfx3d::Mat4,
+        view: &gfx3d::Mat4,
+        projection: &gfx3d::Mat4,
+        lighting: &gfx3d::Lighting,
+        col: u16,
+    ) -> bool {
+        self.render_mesh(mesh, world, view, projection, |i| {
+            let tri = &mesh.tris()[i];
+            let p = tri.p.map(|v| world.transform_point(v));
+            let levels = match lighting.shading {
+                gfx3d::Shading::Flat => {
+                    let normal = world.transform_vector(tri.normal()).normalize();
+                    [lighting.brightness((p[0] + p[1] + p[2]) / 3.0, normal); 3]
+                }
+                gfx3d::Shading::Gouraud => {
+                    let normals = mesh.vertex_normals(i);
+                    [0, 1, 2].map(|k| {
+                        let normal = world.transform_vector(normals[k]).normalize();
+                        lighting.brightness(p[k], normal)
+                    })
+                }
+            };
+            move |w: [f32; 3]| {
+                gfx3d::shade(col, w[0] * levels[0] + w[1] * levels[1] + w[2] * levels[2])
+            }
+        })
+    }
+
+    /// Culls `mesh` against the view frustum, then draws its front faces. `shader` is called
+    /// with the index of each visible triangle and returns the closure that colors its cells.
+    fn render_mesh<F, S>(
+        &mut self,
+        mesh: &gfx3d::Mesh,
+        world: &gfx3d::Mat4,
+        view: &gfx3d::Mat4,
+        projection: &gfx3d::Mat4,
+        mut shader: F,
+    ) -> bool
+    where
+        F: FnMut(usize) -> S,
+        S: Fn([f32; 3]) -> (u16, u16),
+    {
+        let world_view = *world * *view;
+        if !gfx3d::Frustum::from_matrix(&(world_view * *projection)).intersects_mesh(mesh) {
+            return false;
+        }
+        self.draw_calls += 1;
+
+        for (i, tri) in mesh.tris().iter().enumerate() {
+            let p = tri.p.map(|v| world_view.transform_point(v));
+            let tri = gfx3d::Triangle { p };
+            // In view space the camera sits at the origin.
+            if tri.normal().dot(p[0]) >= 0.0 {
+                continue;
+            }
+            self.draw_view_triangle(&tri, projection, shader(i));
+        }
+
+        true
+    }
+
+    /// Records that the surface at cell `(x, y)` is `distance` in front of the camera, so later
+    /// billboards and meshes behind it are hidden.
+    ///
+    /// This lets scenes drawn without `draw_mesh`, like a raycaster's walls, share the depth
+    /// buffer: write each column's wall distance and billboards will be clipped by the walls.
+    pub fn write_depth(&mut self, x: i32, y: i32, distance: f32) {
+        self.ensure_depth_buffer();
+        if x >= 0 && x < self.screen_width() && y >= 0 && y < self.screen_height() && distance > 0.0
+        {
+            let idx = (y * self.screen_width() + x) as usize;
+            self.depth_buffer[idx] = 1.0 / distance;
+        }
+    }
+
+    /// Returns the distance of the nearest surface drawn at cell `(x, y)` since the last
+    /// `clear_depth`, or infinity if nothing has been drawn there.
+    pub fn depth_at(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || x >= self.screen_width() || y < 0 || y >= self.screen_height() {
+            return f32::INFINITY;
+        }
+        match self
+            .depth_buffer
+            .get((y * self.screen_width() + x) as usize)
+        {
+            Some(&inv_z) if inv_z > 0.0 => 1.0 / inv_z,
+            _ => f32::INFINITY,
+        }
+    }
+
+    /// Draws `sprite` facing the camera, centred on `position` and `size` world units tall.
+    ///
+    /// The sprite shrinks with distance, empty glyphs are transparent, and cells behind
+    /// anything already in the depth buffer are hidden. Returns `false` if the sprite is
+    /// behind the camera or off screen.
+    pub fn draw_billboard(
+        &mut self,
+        sprite: &Sprite,
+        position: gfx3d::Vec3,
+        size: f32,
+        view: &gfx3d::Mat4,
+        projection: &gfx3d::Mat4,
+    ) -> bool {
+        let center = view.transform_point(position);
+        let near = projection_near(projection);
+        if center.z < near || sprite.width == 0 || sprite.height == 0 {
+            return false;
+        }
+
+        let (width, height) = (self.screen_width() as f32, self.screen_height() as f32);
+        let ndc = projection.transform_point(center);
+        let world_width = size * sprite.width as f32 / sprite.height as f32;
+        let half_w = projection.m[0][0] * world_width * 0.5 / center.z * 0.5 * width;
+        let half_h = projection.m[1][1] * size * 0.5 / center.z * 0.5 * height;
+        let cx = (ndc.x + 1.0) * 0.5 * width;
+        let cy = (1.0 - ndc.y) * 0.5 * height;
+
+        let (left, top) = (cx - half_w, cy - half_h);
+        let x1 = (left.floor() as i32).max(0);
+        let y1 = (top.floor() as i32).max(0);
+        let x2 = ((cx + half_w).ceil() as i32).min(self.screen_width());
+        let y2 = ((cy + half_h).ceil() as i32).min(self.screen_height());
+        if x1 >= x2 || y1 >= y2 {
+            return false;
+        }
+        self.draw_calls += 1;
+        self.ensure_depth_buffer();
+
+        let inv_z = 1.0 / center.z;
+        let screen_width = self.screen_width();
+        for y in y1..y2 {
+            let v = (y as f32 + 0.5 - top) / (half_h * 2.0);
+            if !(0.0..1.0).contains(&v) {
+                continue;
+            }
+            for x in x1..x2 {
+                let u = (x as f32 + 0.5 - left) / (half_w * 2.0);
+                if !(0.0..1.0).contains(&u) {
+                    continue;
+                }
+                let idx = (y * screen_width + x) as usize;
+                if inv_z <= self.depth_buffer[idx] {
+                    continue;
+                }
+                let glyph = sprite.sample_glyph(u, v);
+                if glyph == EMPTY {
+                    continue;
+                }
+                self.depth_buffer[idx] = inv_z;
+                self.window_buffer[idx].Char.UnicodeChar = glyph;
+                self.window_buffer[idx].Attributes = sprite.sample_color(u, v);
+            }
+        }
+
+        true
+    }
+
+    /// Draws every billboard with `draw_billboard`, furthest first.
+    ///
+    /// Sorting keeps overlapping sprites at the same depth from fighting, and means
+    /// transparent cells in near sprites show the far sprites behind them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let billboards: Vec<_> = self.enemies.iter().map(|e| gfx3d::Billboard {
+    ///     sprite: &self.enemy_sprite,
+    ///     position: e.position,
+    ///     size: 1.0,
+    /// }).collect();
+    /// engine.draw_billboards(&billboards, &view, &self.projection);
+    /// ```
+    pub fn draw_billboards(
+        &mut self,
+        billboards: &[gfx3d::Billboard],
+        view: &gfx3d::Mat4,
+        projection: &gfx3d::Mat4,
+    ) {
+        let mut order: Vec<(f32, &gfx3d::Billboard)> = billboards
+            .iter()
+            .map(|b| (view.transform_point(b.position).z, b))
+            .collect();
+        order.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, b) in order {
+            self.draw_billboard(b.sprite, b.position, b.size, view, projection);
+        }
+    }
+
+    fn ensure_depth_buffer(&mut self) {
+        let cells = (self.screen_width() * self.screen_height()) as usize;
+        if self.depth_buffer.len() != cells {
+            self.depth_buffer = vec![0.0; cells];
+        }
+    }
+
+    /// Clips a view space triangle against the near plane, projects it and rasterizes it with
+    /// depth testing. `shade` receives the barycentric weights of each cell's centre and picks
+    /// its glyph and color.
+    fn draw_view_triangle<F>(&mut self, tri: &gfx3d::Triangle, projection: &gfx3d::Mat4, shade: F)
+    where
+        F: Fn([f32; 3]) -> (u16, u16),
+    {
+        let near = projection_near(projection);
+
+        // Clip to z >= near, tracking each new vertex's weights of the original three.
+        let corners = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let mut poly: Vec<(gfx3d::Vec3, [f32; 3])> = Vec::with_capacity(4);
+        for i in 0..3 {
+            let (a, wa) = (tri.p[i], corners[i]);
+            let (b, wb) = (tri.p[(i + 1) % 3], corners[(i + 1) % 3]);
+            if a.z >= near {
+                poly.push((a, wa));
+            }
+            if (a.z >= near) != (b.z >= near) {
+                let t = (near - a.z) / (b.z - a.z);
+                let w = [0, 1, 2].map(|k| wa[k] + (wb[k] - wa[k]) * t);
+                poly.push((a.lerp(b, t), w));
+            }
+        }
+        if poly.len() < 3 {
+            return;
+        }
+
+        let (width, height) = (self.screen_width() as f32, self.screen_height() as f32);
+        let screen: Vec<(f32, f32, f32, [f32; 3])> = poly
+            .iter()
+            .map(|&(v, w)| {
+                let ndc = projection.transform_point(v);
+                let x = (ndc.x + 1.0) * 0.5 * width;
+                let y = (1.0 - ndc.y) * 0.5 * height;
+                (x, y, 1.0 / v.z, w)
+            })
+            .collect();
+
+        for i in 1..screen.len() - 1 {
+            self.raster_depth_triangle([screen[0], screen[i], screen[i + 1]], &shade);
+        }
+    }
+
+    /// Fills a screen space triangle whose vertices carry `1 / z` and barycentric weights,
+    /// keeping only cells nearer than what the depth buffer already holds.
+    fn raster_depth_triangle<F>(&mut self, v: [(f32, f32, f32, [f32; 3]); 3], shade: &F)
+    where
+        F: Fn([f32; 3]) -> (u16, u16),
+    {
+        self.ensure_depth_buffer();
+        let (width, height) = (self.screen_width(), self.screen_height());
+
+        let edge = |a: (f32, f32), b: (f32, f32), x: f32, y: f32| {
+            (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0)
+        };
+        let (p0, p1, p2) = ((v[0].0, v[0].1), (v[1].0, v[1].1), (v[2].0, v[2].1));
+        let area = edge(p0, p1, p2.0, p2.1);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+
+        let min_x = (p0.0.min(p1.0).min(p2.0).floor() as i32).max(0);
+        let max_x = (p0.0.max(p1.0).max(p2.0).ceil() as i32).min(width - 1);
+        let min_y = (p0.1.min(p1.1).min(p2.1).floor() as i32).max(0);
+        let max_y = (p0.1.max(p1.1).max(p2.1).ceil() as i32).min(height - 1);
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                let b0 = edge(p1, p2, cx, cy) / area;
+                let b1 = edge(p2, p0, cx, cy) / area;
+                let b2 = 1.0 - b0 - b1;
+                if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+                    continue;
+                }
+
+                let idx = (y * width + x) as usize;
+                let inv_z = b0 * v[0].2 + b1 * v[1].2 + b2 * v[2].2;
+                if inv_z <= self.depth_buffer[idx] {
+                    continue;
+                }
+                self.depth_buffer[idx] = inv_z;
+
+                // Perspective-correct weights of the original triangle's corners.
+                let weights = [0, 1, 2].map(|k| {
+                    (b0 * v[0].3[k] * v[0].2 + b1 * v[1].3[k] * v[1].2 + b2 * v[2].3[k] * v[2].2)
+                        / inv_z
+                });
+                let (glyph, col) = shade(weights);
+                self.window_buffer[idx].Char.UnicodeChar = glyph;
+                self.window_buffer[idx].Attributes = col;
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Skeleton
+
+/// What is drawn along a bone by `draw_skeleton`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BoneAttachment {
+    /// Nothing; the bone only positions its children.
+    #[default]
+    None,
+    /// A line from the joint to the bone's tip.
+    Segment { glyph: u16, col: u16 },
+    /// A sprite centred on the middle of the bone. Sprites are not rotated.
+    Sprite(Sprite),
+}
+
+/// One bone of a [`Skeleton`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bone {
+    pub name: String,
+    parent: Option<usize>,
+    /// Length in screen cells.
+    pub length: f32,
+    /// Rotation in radians relative to the parent bone, or to the X axis for root bones.
+    pub angle: f32,
+    pub attachment: BoneAttachment,
+}
+
+impl Bone {
+    /// Index of the bone this one hangs from, or `None` for bones attached to the root.
+    /// Set by `Skeleton::add_bone` and fixed after that, so parents always come first.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+}
+
+/// Where a bone ended up after its parents' rotations, from `Skeleton::world_bones`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BonePosition {
+    /// Screen position of the joint the bone hangs from.
+    pub start: (f32, f32),
+    /// Screen position of the bone's tip.
+    pub end: (f32, f32),
+    /// Absolute angle in radians.
+    pub angle: f32,
+}
+
+/// A hierarchy of bones for procedurally animated characters, like stick figures.
+///
+/// Each bone is rotated relative to its parent, so bending an upper arm carries the forearm
+/// with it. Angles can be set directly or driven by a [`SkeletonAnimation`].
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut body = Skeleton::new(40.0, 30.0);
+/// let spine = body.add_bone("spine", None, 6.0, -FRAC_PI_2);
+/// let arm = body.add_bone("arm", Some(spine), 4.0, 2.4);
+/// body.attach(spine, BoneAttachment::Segment { glyph: SOLID, col: FG_WHITE });
+/// body.attach(arm, BoneAttachment::Segment { glyph: SOLID, col: FG_WHITE });
+///
+/// body.set_angle(arm, 2.4 + (self.time * 4.0).sin() * 0.5);
+/// engine.draw_skeleton(&body);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Skeleton {
+    /// Screen position of the root joint.
+    pub x: f32,
+    pub y: f32,
+    bones: Vec<Bone>,
+}
+
+impl Skeleton {
+    /// Creates an empty skeleton rooted at `(x, y)`.
+    pub fn new(x: f32, y: f32) -> Self {
+        Self {
+            x,
+            y,
+            bones: Vec::new(),
+        }
+    }
+
+    /// Adds a bone and returns its index. Parents must be added before their children.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not the index of an existing bone.
+    pub fn add_bone(
+        &mut self,
+        name: &str,
+        parent: Option<usize>,
+        length: f32,
+        angle: f32,
+    ) -> usize {
+        if let Some(parent) = parent {
+            assert!(
+                parent < self.bones.len(),
+                "parent bone {} does not exist",
+                parent
+            );
+        }
+        self.bones.push(Bone {
+            name: name.to_string(),
+            parent,
+            length,
+            angle,
+            attachment: BoneAttachment::None,
+        });
+        self.bones.len() - 1
+    }
+
+    /// Returns the index of the bone called `name`.
+    pub fn bone_index(&self, name: &str) -> Option<usize> {
+        self.bones.iter().position(|b| b.name == name)
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.bones
+    }
+
+    pub fn bone_mut(&mut self, index: usize) -> Option<&mut Bone> {
+        self.bones.get_mut(index)
+    }
+
+    /// Sets the rotation of bone `index` relative to its parent. Unknown bones are ignored.
+    pub fn set_angle(&mut self, index: usize, angle: f32) {
+        if let Some(bone) = self.bones.get_mut(index) {
+            bone.angle = angle;
+        }
+    }
+
+    /// Sets what is drawn along bone `index`. Unknown bones are ignored.
+    pub fn attach(&mut self, index: usize, attachment: BoneAttachment) {
+        if let Some(bone) = self.bones.get_mut(index) {
+            bone.attachment = attachment;
+        }
+    }
+
+    /// Returns the current relative angle of every bone, in bone order.
+    pub fn pose(&self) -> Vec<f32> {
+        self.bones.iter().map(|b| b.angle).collect()
+    }
+
+    /// Sets bone angles from `pose`, in bone order. Extra angles are ignored.
+    pub fn set_pose(&mut self, pose: &[f32]) {
+        for (bone, &angle) in self.bones.iter_mut().zip(pose) {
+            bone.angle = angle;
+        }
+    }
+
+    /// Poses the skeleton as `animation` is at `time` seconds.
+    pub fn apply(&mut self, animation: &SkeletonAnimation, time: f32) {
+        self.set_pose(&animation.sample(time));
+    }
+
+    /// Returns where every bone is on screen, in bone order.
+    pub fn world_bones(&self) -> Vec<BonePosition> {
+        let mut out: Vec<BonePosition> = Vec::with_capacity(self.bones.len());
+        for bone in &self.bones {
+            let (start, base) = match bone.parent {
+                Some(p) => (out[p].end, out[p].angle),
+                None => ((self.x, self.y), 0.0),
+            };
+            let angle = base + bone.angle;
+            let end = (
+                start.0 + angle.cos() * bone.length,
+                start.1 + angle.sin() * bone.length,
+            );
+            out.push(BonePosition { start, end, angle });
+        }
+        out
+    }
+}
+
+/// Keyframed bone angles for a [`Skeleton`].
+///
+/// Each keyframe is a full pose, one angle per bone in bone order. Between keyframes the
+/// angles are blended along the shorter way round.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkeletonAnimation {
+    keyframes: Vec<(f32, Vec<f32>)>,
+    /// Whether the animation repeats after its last keyframe.
+    pub looping: bool,
+}
+
+impl SkeletonAnimation {
+    pub fn new(looping: bool) -> Self {
+        Self {
+            keyframes: Vec::new(),
+            looping,
+        }
+    }
+
+    /// Adds a pose at `time` seconds. Keyframes can be added in any order.
+    pub fn add_keyframe(&mut self, time: f32, pose: Vec<f32>) {
+        let index = self.keyframes.partition_point(|(t, _)| *t <= time);
+        self.keyframes.insert(index, (time, pose));
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |(t, _)| *t)
+    }
+
+    /// Returns the pose at `time` seconds.
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let (Some(first), Some(last)) = (self.keyframes.first(), self.keyframes.last()) else {
+            return Vec::new();
+        };
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+        if time <= first.0 {
+            return first.1.clone();
+        }
+        if time >= last.0 {
+            return last.1.clone();
+        }
+
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        let (t0, a) = &self.keyframes[next - 1];
+        let (t1, b) = &self.keyframes[next];
+        let k = (time - t0) / (t1 - t0);
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| {
+                let delta = (b - a + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU)
+                    - std::f32::consts::PI;
+                a + delta * k
+            })
+            .collect()
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws every bone's attachment, parents before children.
+    pub fn draw_skeleton(&mut self, skeleton: &Skeleton) {
+        for (bone, pos) in skeleton.bones.iter().zip(skeleton.world_bones()) {
+            let (start, end) = (pos.start, pos.end);
+            match &bone.attachment {
+                BoneAttachment::None => {}
+                BoneAttachment::Segment { glyph, col } => self.draw_line_with(
+                    start.0.round() as i32,
+                    start.1.round() as i32,
+                    end.0.round() as i32,
+                    end.1.round() as i32,
+                    *glyph,
+                    *col,
+                ),
+                BoneAttachment::Sprite(sprite) => {
+                    let mx = (start.0 + end.0) / 2.0 - sprite.width as f32 / 2.0;
+                    let my = (start.1 + end.1) / 2.0 - sprite.height as f32 / 2.0;
+                    self.draw_sprite(mx.round() as i32, my.round() as i32, sprite);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Pool
+
+/// Refers to a value in a `Pool`.
+///
+/// Handles stay valid until their value is despawned. After that they refer to nothing,
+/// even once the slot is reused for a new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl Handle {
+    /// Returns the slot the value is stored in. Slots are reused, so this is only unique
+    /// among living values.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PoolSlot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Stores values in reusable slots and hands out generational handles to them, so games
+/// can spawn and despawn entities without shifting a `Vec` or sharing them through
+/// `Rc<RefCell<_>>`.
+///
+/// Despawned slots are reused by later spawns, so once the pool has grown to its busiest
+/// size it never allocates again.
+///
+/// # Examples
+///
+/// ```rust
+/// use rusty_console_game_engine::*;
+///
+/// let mut enemies = Pool::new();
+/// let grunt = enemies.spawn("grunt");
+/// let tank = enemies.spawn("tank");
+///
+/// enemies.despawn(grunt);
+/// assert_eq!(enemies.get(grunt), None);
+/// assert_eq!(enemies.get(tank), Some(&"tank"));
+///
+/// // The slot is reused, but the old handle still refers to nothing.
+/// let boss = enemies.spawn("boss");
+/// assert_eq!(boss.index(), grunt.index());
+/// assert_eq!(enemies.get(grunt), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pool<T> {
+    slots: Vec<PoolSlot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Creates a pool with room for `capacity` values before it has to allocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::with_capacity(capacity),
+            len: 0,
+        }
+    }
+
+    /// Stores `value` and returns its handle.
+    pub fn spawn(&mut self, value: T) -> Handle {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Handle {
+                index,
+                generation: slot.generation,
+            };
+        }
+        self.slots.push(PoolSlot {
+            generation: 0,
+            value: Some(value),
+        });
+        Handle {
+            index: self.slots.len() as u32 - 1,
+            generation: 0,
+        }
+    }
+
+    /// Removes the value `handle` refers to and returns it.
+    pub fn despawn(&mut self, handle: Handle) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Returns the value `handle` refers to.
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    /// Returns the value `handle` refers to mutably.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    /// Returns `true` if `handle` still refers to a value.
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Returns the number of values stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no values are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Despawns every value. Existing handles stop referring to anything.
+    pub fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.value.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+        self.len = 0;
+    }
+
+    /// Despawns every value for which `keep` returns `false`.
+    pub fn retain(&mut self, mut keep: impl FnMut(Handle, &mut T) -> bool) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let handle = Handle {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            if slot
+                .value
+                .as_mut()
+                .is_some_and(|value| !keep(handle, value))
+            {
+                slot.value = None;
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+                self.len -= 1;
+            }
+        }
+    }
+
+    /// Iterates over the handles and values of everything stored.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let handle = Handle {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.value.as_ref().map(|value| (handle, value))
+        })
+    }
+
+    /// Iterates over the handles and values of everything stored, mutably.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let handle = Handle {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                slot.value.as_mut().map(|value| (handle, value))
+            })
+    }
+
+    /// Iterates over the values of everything stored.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.value.as_ref())
+    }
+
+    /// Iterates over the values of everything stored, mutably.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().filter_map(|slot| slot.value.as_mut())
+    }
+}
+
+// endregion
+
+// region: Spatial Hash
+
+/// Stores values with axis-aligned bounding boxes in a grid of buckets,
+/// so nearby values can be found without checking every one.
+///
+/// Every inserted value gets an id, which stays valid until the value is removed.
+/// Pick a `cell_size` around the size of a typical entity.
+///
+/// # Examples
+///
+/// ```rust
+/// use rusty_console_game_engine::*;
+///
+/// let mut hash = SpatialHash::new(8.0);
+/// let ship = hash.insert(10.0, 10.0, 3.0, 3.0, "ship");
+/// let rock = hash.insert(50.0, 12.0, 4.0, 4.0, "rock");
+///
+/// assert_eq!(hash.query_circle(11.0, 11.0, 5.0), vec![ship]);
+/// hash.update(rock, 12.0, 12.0, 4.0, 4.0);
+/// assert_eq!(hash.query_rect(9.0, 9.0, 4.0, 4.0).len(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    entries: Vec<Option<SpatialEntry<T>>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct SpatialEntry<T> {
+    value: T,
+    bounds: (f32, f32, f32, f32),
+}
+
+impl<T> SpatialHash<T> {
+    /// Creates an empty spatial hash with square buckets of `cell_size` units.
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn cell_range(&self, x: f32, y: f32, w: f32, h: f32) -> (i32, i32, i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (y / self.cell_size).floor() as i32,
+            ((x + w) / self.cell_size).floor() as i32,
+            ((y + h) / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn link(&mut self, id: usize, (x, y, w, h): (f32, f32, f32, f32)) {
+        let (cx1, cy1, cx2, cy2) = self.cell_range(x, y, w, h);
+        for cy in cy1..=cy2 {
+            for cx in cx1..=cx2 {
+                self.cells.entry((cx, cy)).or_default().push(id);
+            }
+        }
+    }
+
+    fn unlink(&mut self, id: usize, (x, y, w, h): (f32, f32, f32, f32)) {
+        let (cx1, cy1, cx2, cy2) = self.cell_range(x, y, w, h);
+        for cy in cy1..=cy2 {
+            for cx in cx1..=cx2 {
+                if let Some(bucket) = self.cells.get_mut(&(cx, cy)) {
+                    bucket.retain(|&other| other != id);
+                    if bucket.is_empty() {
+                        self.cells.remove(&(cx, cy));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Inserts `value` with the bounding box at `(x, y)` of size `w`×`h` and returns its id.
+    pub fn insert(&mut self, x: f32, y: f32, w: f32, h: f32, value: T) -> usize {
+        let bounds = (x, y, w, h);
+        let entry = Some(SpatialEntry { value, bounds });
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.entries[id] = entry;
+                id
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.link(id, bounds);
+        self.len += 1;
+        id
+    }
+
+    /// Removes the value with the given id and returns it.
+    pub fn remove(&mut self, id: usize) -> Option<T> {
+        let entry = self.entries.get_mut(id)?.take()?;
+        self.unlink(id, entry.bounds);
+        self.free.push(id);
+        self.len -= 1;
+        Some(entry.value)
+    }
+
+    /// Moves the value with the given id to a new bounding box.
+    /// Returns `false` if there is no value with that id.
+    pub fn update(&mut self, id: usize, x: f32, y: f32, w: f32, h: f32) -> bool {
+        let Some(old) = self
+            .entries
+            .get(id)
+            .and_then(|e| e.as_ref())
+            .map(|e| e.bounds)
+        else {
+            return false;
+        };
+
+        let new = (x, y, w, h);
+        if self.cell_range(old.0, old.1, old.2, old.3) != self.cell_range(x, y, w, h) {
+            self.unlink(id, old);
+            self.link(id, new);
+        }
+        if let Some(entry) = &mut self.entries[id] {
+            entry.bounds = new;
+        }
+        true
+    }
+
+    /// Returns the value with the given id.
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.entries.get(id)?.as_ref().map(|e| &e.value)
+    }
+
+    /// Returns the value with the given id mutably.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut T> {
+        self.entries.get_mut(id)?.as_mut().map(|e| &mut e.value)
+    }
+
+    /// Returns the bounding box `(x, y, w, h)` of the value with the given id.
+    pub fn bounds(&self, id: usize) -> Option<(f32, f32, f32, f32)> {
+        self.entries.get(id)?.as_ref().map(|e| e.bounds)
+    }
+
+    /// Returns the number of values stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no values are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.len = 0;
+    }
+
+    /// Iterates over the ids and values of everything stored.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(id, e)| e.as_ref().map(|e| (id, &e.value)))
+    }
+
+    fn candidates(&self, x: f32, y: f32, w: f32, h: f32) -> Vec<usize> {
+        let (cx1, cy1, cx2, cy2) = self.cell_range(x, y, w, h);
+        let mut ids = Vec::new();
+        for cy in cy1..=cy2 {
+            for cx in cx1..=cx2 {
+                if let Some(bucket) = self.cells.get(&(cx, cy)) {
+                    ids.extend_from_slice(bucket);
+                }
+            }
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Returns the ids of all values whose bounding box overlaps the rectangle at `(x, y)`
+    /// of size `w`×`h`.
+    pub fn query_rect(&self, x: f32, y: f32, w: f32, h: f32) -> Vec<usize> {
+        let mut ids = self.candidates(x, y, w, h);
+        ids.retain(|&id| {
+            let (bx, by, bw, bh) = self.bounds(id).unwrap();
+            bx <= x + w && x <= bx + bw && by <= y + h && y <= by + bh
+        });
+        ids
+    }
+
+    /// Returns the ids of all values whose bounding box overlaps the circle at `(cx, cy)`
+    /// with radius `r`.
+    pub fn query_circle(&self, cx: f32, cy: f32, r: f32) -> Vec<usize> {
+        let mut ids = self.candidates(cx - r, cy - r, r * 2.0, r * 2.0);
+        ids.retain(|&id| {
+            let (bx, by, bw, bh) = self.bounds(id).unwrap();
+            let dx = cx - cx.clamp(bx, bx + bw);
+            let dy = cy - cy.clamp(by, by + bh);
+            dx * dx + dy * dy <= r * r
+        });
+        ids
+    }
+}
+
+// endregion
+
+// region: Flock
+
+/// A single member of a `Flock`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Boid {
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+}
+
+/// Settings for a `Flock`. Distances are in cells, speeds in cells per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlockParams {
+    /// How far a boid can see its neighbors.
+    pub view_radius: f32,
+    /// Neighbors closer than this are steered away from.
+    pub separation_radius: f32,
+    /// Strength of steering away from close neighbors.
+    pub separation: f32,
+    /// Strength of matching the neighbors' heading.
+    pub alignment: f32,
+    /// Strength of steering towards the neighbors' center.
+    pub cohesion: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Largest change in velocity per second.
+    pub max_force: f32,
+    /// The width and height of a world boids wrap around in, or `None` to let them roam.
+    /// Neighbors are not seen across the wrapped edges.
+    pub wrap: Option<(f32, f32)>,
+}
+
+impl Default for FlockParams {
+    fn default() -> Self {
+        Self {
+            view_radius: 6.0,
+            separation_radius: 2.0,
+            separation: 1.5,
+            alignment: 1.0,
+            cohesion: 1.0,
+            min_speed: 4.0,
+            max_speed: 12.0,
+            max_force: 20.0,
+            wrap: None,
+        }
+    }
+}
+
+/// A flocking simulation (boids): every boid steers away from neighbors that are too
+/// close, towards the heading of its neighbors and towards their center.
+///
+/// Neighbors are found through a `SpatialHash`, so thousands of boids stay fast as long as
+/// each one only sees a handful of others.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut flock = Flock::new(FlockParams {
+///     wrap: Some((engine.screen_width() as f32, engine.screen_height() as f32)),
+///     ..Default::default()
+/// });
+/// for _ in 0..500 {
+///     flock.add(rng.random_range(0.0..160.0), rng.random_range(0.0..100.0), 1.0, 0.0);
+/// }
+///
+/// // In update:
+/// flock.update(elapsed_time);
+/// engine.draw_flock(&flock, FG_CYAN);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Flock {
+    pub boids: Vec<Boid>,
+    pub params: FlockParams,
+    hash: SpatialHash<usize>,
+    steering: Vec<(f32, f32)>,
+}
+
+impl Flock {
+    pub fn new(params: FlockParams) -> Self {
+        Self {
+            boids: Vec::new(),
+            hash: SpatialHash::new(params.view_radius),
+            params,
+            steering: Vec::new(),
+        }
+    }
+
+    /// Adds a boid at `(x, y)` moving with velocity `(vx, vy)` and returns its index.
+    pub fn add(&mut self, x: f32, y: f32, vx: f32, vy: f32) -> usize {
+        self.boids.push(Boid { x, y, vx, vy });
+        self.boids.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.boids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boids.is_empty()
+    }
+
+    /// Returns the indices of the boids within `radius` of `(x, y)`, as of the last update.
+    pub fn query(&self, x: f32, y: f32, radius: f32) -> Vec<usize> {
+        self.hash
+            .query_circle(x, y, radius)
+            .into_iter()
+            .filter_map(|id| self.hash.get(id).copied())
+            .collect()
+    }
+
+    /// Steers and moves every boid by `elapsed` seconds.
+    pub fn update(&mut self, elapsed: f32) {
+        let p = self.params;
+
+        self.hash = SpatialHash::new(p.view_radius);
+        for (i, b) in self.boids.iter().enumerate() {
+            self.hash.insert(b.x, b.y, 0.0, 0.0, i);
+        }
+
+        self.steering.clear();
+        for (i, b) in self.boids.iter().enumerate() {
+            let mut separation = (0.0, 0.0);
+            let mut heading = (0.0, 0.0);
+            let mut center = (0.0, 0.0);
+            let mut count = 0.0;
+
+            for id in self.hash.query_circle(b.x, b.y, p.view_radius) {
+                let j = self.hash.get(id).copied().unwrap_or(i);
+                if j == i {
+                    continue;
+                }
+                let other = &self.boids[j];
+                let (dx, dy) = (b.x - other.x, b.y - other.y);
+                let dist_sq = dx * dx + dy * dy;
+                if dist_sq < p.separation_radius * p.separation_radius && dist_sq > 0.0 {
+                    // Push harder the closer the neighbor is.
+                    separation.0 += dx / dist_sq;
+                    separation.1 += dy / dist_sq;
+                }
+                heading.0 += other.vx;
+                heading.1 += other.vy;
+                center.0 += other.x;
+                center.1 += other.y;
+                count += 1.0;
+            }
+
+            let mut force = (
+                separation.0 * p.separation * p.max_speed,
+                separation.1 * p.separation * p.max_speed,
+            );
+            if count > 0.0 {
+                force.0 += (heading.0 / count - b.vx) * p.alignment;
+                force.1 += (heading.1 / count - b.vy) * p.alignment;
+                force.0 += (center.0 / count - b.x) * p.cohesion;
+                force.1 += (center.1 / count - b.y) * p.cohesion;
+            }
+
+            let len = (force.0 * force.0 + force.1 * force.1).sqrt();
+            if len > p.max_force {
+                force = (force.0 / len * p.max_force, force.1 / len * p.max_force);
+            }
+            self.steering.push(force);
+        }
+
+        for (b, &(fx, fy)) in self.boids.iter_mut().zip(&self.steering) {
+            b.vx += fx * elapsed;
+            b.vy += fy * elapsed;
+
+            let speed = (b.vx * b.vx + b.vy * b.vy).sqrt();
+            if speed > 0.0 {
+                let clamped = speed.clamp(p.min_speed, p.max_speed.max(p.min_speed));
+                b.vx *= clamped / speed;
+                b.vy *= clamped / speed;
+            }
+
+            b.x += b.vx * elapsed;
+            b.y += b.vy * elapsed;
+            if let Some((w, h)) = p.wrap {
+                b.x = b.x.rem_euclid(w);
+                b.y = b.y.rem_euclid(h);
+            }
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws every boid in `flock` as an arrow pointing where it is heading.
+    pub fn draw_flock(&mut self, flock: &Flock, col: u16) {
+        for b in &flock.boids {
+            let glyph = if b.vx.abs() > b.vy.abs() * 2.0 {
+                if b.vx > 0.0 {
+                    glyph::ARROW_RIGHT
+                } else {
+                    glyph::ARROW_LEFT
+                }
+            } else if b.vy.abs() > b.vx.abs() * 2.0 {
+                if b.vy > 0.0 {
+                    glyph::ARROW_DOWN
+                } else {
+                    glyph::ARROW_UP
+                }
+            } else if (b.vx > 0.0) == (b.vy > 0.0) {
+                '\\' as u16
+            } else {
+                '/' as u16
+            };
+            self.draw_with(b.x.floor() as i32, b.y.floor() as i32, glyph, col);
+        }
+    }
+}
+
+// endregion
+
+// region: Falling Sand
+
+/// A material in a `FallingSand` grid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Material {
+    #[default]
+    Empty,
+    /// Falls and piles up in slopes.
+    Sand,
+    /// Falls and spreads out sideways to find its level.
+    Water,
+    /// Rises and drifts sideways.
+    Gas,
+    /// Never moves.
+    Solid,
+}
+
+impl Material {
+    /// Returns how heavy the material is. Heavier materials sink through lighter movable
+    /// ones, e.g. sand through water, and lighter ones rise through heavier ones.
+    pub fn density(self) -> i32 {
+        match self {
+            Material::Gas => 0,
+            Material::Empty => 1,
+            Material::Water => 2,
+            Material::Sand => 3,
+            Material::Solid => i32::MAX,
+        }
+    }
+
+    /// Returns the glyph and color the material is drawn with.
+    pub fn style(self) -> (u16, u16) {
+        match self {
+            Material::Empty => (EMPTY, FG_BLACK),
+            Material::Sand => (SOLID, FG_YELLOW),
+            Material::Water => (SOLID, FG_BLUE),
+            Material::Gas => (glyph::MEDIUM_SHADE, FG_GREY),
+            Material::Solid => (SOLID, FG_DARK_GREY),
+        }
+    }
+}
+
+/// A falling-sand cellular automaton: a grid of `Material`s that fall, flow and rise by
+/// simple local rules.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut sand = FallingSand::new(160, 100);
+/// sand.fill_rect(0, 99, 160, 1, Material::Solid);
+///
+/// // In update:
+/// if engine.mouse_held(LEFT) {
+///     sand.set(engine.mouse_x(), engine.mouse_y(), Material::Sand);
+/// }
+/// sand.update();
+/// engine.draw_falling_sand(&sand, 0, 0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallingSand {
+    pub width: usize,
+    pub height: usize,
+    cells: Vec<Material>,
+    moved: Vec<bool>,
+    frame: u32,
+}
+
+impl FallingSand {
+    /// Creates an empty grid.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Material::Empty; width * height],
+            moved: vec![false; width * height],
+            frame: 0,
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the material at `(x, y)`. Cells outside the grid are `Material::Solid`.
+    pub fn get(&self, x: i32, y: i32) -> Material {
+        self.index(x, y).map_or(Material::Solid, |i| self.cells[i])
+    }
+
+    /// Sets the material at `(x, y)`. Does nothing if out of bounds.
+    pub fn set(&mut self, x: i32, y: i32, material: Material) {
+        if let Some(i) = self.index(x, y) {
+            self.cells[i] = material;
+        }
+    }
+
+    /// Fills the `w` × `h` rectangle at `(x, y)` with `material`.
+    pub fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, material: Material) {
+        for cy in y..y + h {
+            for cx in x..x + w {
+                self.set(cx, cy, material);
+            }
+        }
+    }
+
+    /// Fills a circle of radius `r` around `(x, y)` with `material`, e.g. a brush.
+    pub fn fill_circle(&mut self, x: i32, y: i32, r: i32, material: Material) {
+        for cy in y - r..=y + r {
+            for cx in x - r..=x + r {
+                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r * r {
+                    self.set(cx, cy, material);
+                }
+            }
+        }
+    }
+
+    /// Returns how many cells hold `material`.
+    pub fn count(&self, material: Material) -> usize {
+        self.cells.iter().filter(|&&m| m == material).count()
+    }
+
+    /// Advances the simulation by one step. Every cell moves at most once per step.
+    pub fn update(&mut self) {
+        self.moved.fill(false);
+        self.frame = self.frame.wrapping_add(1);
+
+        // Falling materials are processed bottom-up and rising ones top-down, so a column
+        // moves together instead of one cell per step. Alternating the horizontal
+        // direction every step keeps piles from leaning to one side.
+        let flip = self.frame % 2 == 0;
+        for row in 0..self.height as i32 {
+            for col in 0..self.width as i32 {
+                let x = if flip {
+                    self.width as i32 - 1 - col
+                } else {
+                    col
+                };
+                let down = self.height as i32 - 1 - row;
+                self.step_cell(x, down, true);
+                self.step_cell(x, row, false);
+            }
+        }
+    }
+
+    fn step_cell(&mut self, x: i32, y: i32, falling: bool) {
+        let Some(i) = self.index(x, y) else {
+            return;
+        };
+        let material = self.cells[i];
+        if self.moved[i] || (material == Material::Gas) == falling {
+            return;
+        }
+        let side = if self.frame.wrapping_add((x + y) as u32) % 2 == 0 {
+            1
+        } else {
+            -1
+        };
+
+        let moves: &[(i32, i32)] = match material {
+            Material::Sand => &[(0, 1), (side, 1), (-side, 1)],
+            Material::Water => &[(0, 1), (side, 1), (-side, 1), (side, 0), (-side, 0)],
+            Material::Gas => &[(0, -1), (side, -1), (-side, -1), (side, 0), (-side, 0)],
+            Material::Empty | Material::Solid => return,
+        };
+
+        for &(dx, dy) in moves {
+            let (nx, ny) = (x + dx, y + dy);
+            let Some(j) = self.index(nx, ny) else {
+                continue;
+            };
+            if self.moved[j] {
+                continue;
+            }
+            let other = self.cells[j];
+            let swaps = match dy {
+                1 => other != Material::Solid && other.density() < material.density(),
+                -1 => other != Material::Solid && other.density() > material.density(),
+                _ => other == Material::Empty,
+            };
+            if swaps {
+                self.cells.swap(i, j);
+                self.moved[j] = true;
+                if other != Material::Empty {
+                    self.moved[i] = true;
+                }
+                return;
+            }
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws `sand` with its top-left corner at `(x, y)`, using `Material::style` for every
+    /// cell. Empty cells are skipped, so the grid can be drawn over a background.
+    pub fn draw_falling_sand(&mut self, sand: &FallingSand, x: i32, y: i32) {
+        for cy in 0..sand.height as i32 {
+            for cx in 0..sand.width as i32 {
+                let material = sand.get(cx, cy);
+                if material != Material::Empty {
+                    let (glyph, col) = material.style();
+                    self.draw_with(x + cx, y + cy, glyph, col);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Terrain
+
+/// Destructible terrain for Worms or Scorched Earth style games: a `Sprite` with the look
+/// of every cell, plus a bitfield of which cells are solid.
+///
+/// Cells outside the terrain are open, except below the bottom edge, which counts as solid
+/// ground so nothing falls out of the world.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let heights: Vec<i32> = (0..160).map(|x| 60 + ((x as f32 * 0.05).sin() * 10.0) as i32).collect();
+/// let mut terrain = Terrain::from_heights(160, 100, &heights, SOLID, FG_GREEN);
+///
+/// // When a shell lands:
+/// terrain.destroy_circle(shell_x, shell_y, 6);
+///
+/// // Every frame, let loose chunks drop:
+/// terrain.settle_step();
+/// engine.draw_terrain(&terrain, 0, 0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Terrain {
+    pub width: usize,
+    pub height: usize,
+    solid: Vec<u64>,
+    sprite: Sprite,
+}
+
+impl Terrain {
+    /// Creates terrain of the given size with no solid cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            solid: vec![0; (width * height).div_ceil(64)],
+            sprite: Sprite::new(width, height),
+        }
+    }
+
+    /// Creates terrain from `sprite`, where every cell with a glyph other than `EMPTY` is
+    /// solid.
+    pub fn from_sprite(sprite: Sprite) -> Self {
+        let mut terrain = Self::new(sprite.width, sprite.height);
+        for y in 0..sprite.height {
+            for x in 0..sprite.width {
+                terrain.set_solid(x as i32, y as i32, sprite.get_glyph(x, y) != EMPTY);
+            }
+        }
+        terrain.sprite = sprite;
+        terrain
+    }
+
+    /// Creates terrain where column `x` is filled from row `heights[x]` down to the
+    /// bottom, e.g. from a noise function for rolling hills.
+    pub fn from_heights(
+        width: usize,
+        height: usize,
+        heights: &[i32],
+        glyph: u16,
+        col: u16,
+    ) -> Self {
+        let mut terrain = Self::new(width, height);
+        for (x, &top) in heights.iter().enumerate().take(width) {
+            for y in top.max(0)..height as i32 {
+                terrain.add(x as i32, y, glyph, col);
+            }
+        }
+        terrain
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the sprite with the look of the terrain. Open cells are `EMPTY`.
+    pub fn sprite(&self) -> &Sprite {
+        &self.sprite
+    }
+
+    /// Returns `true` if the cell at `(x, y)` is solid.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        match self.index(x, y) {
+            Some(i) => self.solid[i / 64] & (1 << (i % 64)) != 0,
+            None => y >= self.height as i32 && x >= 0 && x < self.width as i32,
+        }
+    }
+
+    /// Marks the cell at `(x, y)` as solid or open without changing its look.
+    pub fn set_solid(&mut self, x: i32, y: i32, solid: bool) {
+        if let Some(i) = self.index(x, y) {
+            if solid {
+                self.solid[i / 64] |= 1 << (i % 64);
+            } else {
+                self.solid[i / 64] &= !(1 << (i % 64));
+            }
+        }
+    }
+
+    /// Makes the cell at `(x, y)` solid and draws it with `glyph` and `col`.
+    pub fn add(&mut self, x: i32, y: i32, glyph: u16, col: u16) {
+        if self.index(x, y).is_some() {
+            self.set_solid(x, y, true);
+            self.sprite.set_glyph(x as usize, y as usize, glyph);
+            self.sprite.set_color(x as usize, y as usize, col);
+        }
+    }
+
+    /// Clears the cell at `(x, y)`, returning `true` if it was solid.
+    pub fn remove(&mut self, x: i32, y: i32) -> bool {
+        if !self.index(x, y).is_some_and(|_| self.is_solid(x, y)) {
+            return false;
+        }
+        self.set_solid(x, y, false);
+        self.sprite.set_glyph(x as usize, y as usize, EMPTY);
+        self.sprite.set_color(x as usize, y as usize, 0);
+        true
+    }
+
+    /// Carves a crater of radius `r` around `(x, y)` and returns how many solid cells were
+    /// destroyed.
+    pub fn destroy_circle(&mut self, x: i32, y: i32, r: i32) -> usize {
+        let mut destroyed = 0;
+        for cy in y - r..=y + r {
+            for cx in x - r..=x + r {
+                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r * r && self.remove(cx, cy) {
+                    destroyed += 1;
+                }
+            }
+        }
+        destroyed
+    }
+
+    /// Fills a circle of radius `r` around `(x, y)` with solid cells, e.g. a dirt bomb.
+    pub fn add_circle(&mut self, x: i32, y: i32, r: i32, glyph: u16, col: u16) {
+        for cy in y - r..=y + r {
+            for cx in x - r..=x + r {
+                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r * r {
+                    self.add(cx, cy, glyph, col);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if any cell of the `w` × `h` rectangle at `(x, y)` is solid.
+    pub fn collides_rect(&self, x: i32, y: i32, w: i32, h: i32) -> bool {
+        (y..y + h).any(|cy| (x..x + w).any(|cx| self.is_solid(cx, cy)))
+    }
+
+    /// Returns `true` if any cell within `r` of `(x, y)` is solid.
+    pub fn collides_circle(&self, x: f32, y: f32, r: f32) -> bool {
+        let (x1, y1) = ((x - r).floor() as i32, (y - r).floor() as i32);
+        let (x2, y2) = ((x + r).ceil() as i32, (y + r).ceil() as i32);
+        (y1..=y2).any(|cy| {
+            (x1..=x2).any(|cx| {
+                let dx = cx as f32 + 0.5 - x;
+                let dy = cy as f32 + 0.5 - y;
+                dx * dx + dy * dy <= r * r && self.is_solid(cx, cy)
+            })
+        })
+    }
+
+    /// Returns the first solid row at or below `y` in column `x`, e.g. to drop a player
+    /// onto the ground. Returns `height` if there is nothing but the bottom edge below.
+    pub fn ground_below(&self, x: i32, y: i32) -> i32 {
+        (y.max(0)..self.height as i32)
+            .find(|&cy| self.is_solid(x, cy))
+            .unwrap_or(self.height as i32)
+    }
+
+    /// Returns the direction pointing away from the solid cells within `r` of `(x, y)`,
+    /// normalized, or `(0.0, 0.0)` if there are none. Useful for bouncing and sliding.
+    pub fn surface_normal(&self, x: f32, y: f32, r: i32) -> (f32, f32) {
+        let (cx, cy) = (x.floor() as i32, y.floor() as i32);
+        let (mut nx, mut ny) = (0.0, 0.0);
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if dx * dx + dy * dy <= r * r && self.is_solid(cx + dx, cy + dy) {
+                    nx -= dx as f32;
+                    ny -= dy as f32;
+                }
+            }
+        }
+        let len = (nx * nx + ny * ny).sqrt();
+        if len > 0.0 {
+            (nx / len, ny / len)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// Moves every solid cell that isn't connected to the bottom edge down by one row, so
+    /// chunks cut loose by craters fall. Call it once per frame to animate the fall, or
+    /// use `settle` to drop everything at once. Returns `true` if anything moved.
+    pub fn settle_step(&mut self) -> bool {
+        let supported = self.supported_cells();
+        let mut moved = false;
+        for y in (0..self.height as i32).rev() {
+            for x in 0..self.width as i32 {
+                let i = y as usize * self.width + x as usize;
+                if !self.is_solid(x, y) || supported[i] || self.is_solid(x, y + 1) {
+                    continue;
+                }
+                if y + 1 < self.height as i32 {
+                    let (glyph, col) = (
+                        self.sprite.get_glyph(x as usize, y as usize),
+                        self.sprite.get_color(x as usize, y as usize),
+                    );
+                    self.add(x, y + 1, glyph, col);
+                }
+                self.remove(x, y);
+                moved = true;
+            }
+        }
+        moved
+    }
+
+    /// Drops every loose chunk until the terrain is stable.
+    pub fn settle(&mut self) {
+        while self.settle_step() {}
+    }
+
+    /// Flood fills from the solid cells on the bottom row, through solid neighbors.
+    fn supported_cells(&self) -> Vec<bool> {
+        let mut supported = vec![false; self.width * self.height];
+        if self.height == 0 {
+            return supported;
+        }
+        let bottom = self.height as i32 - 1;
+        let mut stack: Vec<(i32, i32)> = (0..self.width as i32)
+            .filter(|&x| self.is_solid(x, bottom))
+            .map(|x| (x, bottom))
+            .collect();
+        while let Some((x, y)) = stack.pop() {
+            let Some(i) = self.index(x, y) else {
+                continue;
+            };
+            if supported[i] || !self.is_solid(x, y) {
+                continue;
+            }
+            supported[i] = true;
+            stack.extend([(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]);
+        }
+        supported
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the solid cells of `terrain` with its top-left corner at `(x, y)`.
+    pub fn draw_terrain(&mut self, terrain: &Terrain, x: i32, y: i32) {
+        for ty in 0..terrain.height {
+            for tx in 0..terrain.width {
+                if terrain.is_solid(tx as i32, ty as i32) {
+                    let glyph = terrain.sprite.get_glyph(tx, ty);
+                    let col = terrain.sprite.get_color(tx, ty);
+                    self.draw_with(x + tx as i32, y + ty as i32, glyph, col);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Top-Down Controller
+
+/// Moves a box through a `TileMap` for top-down games: 8-direction movement, sliding along
+/// walls and knockback.
+///
+/// Positions and sizes are in tiles, so with one tile per cell they are screen cells too.
+/// Movement is resolved one axis at a time, so pushing diagonally into a wall slides along
+/// it instead of stopping. Large moves are split into small steps, so fast movement and
+/// strong knockback can't tunnel through thin walls.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut player = TopDownController::new(4.0, 4.0, 1.0, 1.0, 8.0);
+///
+/// // In update:
+/// player.update(engine, &self.map, elapsed_time);
+/// if hit_by_enemy {
+///     player.knockback(enemy_dx * 20.0, enemy_dy * 20.0);
+/// }
+/// engine.draw(player.x as i32, player.y as i32, '@' as u16);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopDownController {
+    /// Left edge of the box.
+    pub x: f32,
+    /// Top edge of the box.
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Walking speed in tiles per second.
+    pub speed: f32,
+    /// How quickly knockback wears off, as the fraction lost per second. Defaults to `8.0`.
+    pub knockback_decay: f32,
+    knockback: (f32, f32),
+    facing: (i32, i32),
+    moving: bool,
+}
+
+/// Longest distance moved in one collision step, in tiles.
+const CONTROLLER_STEP: f32 = 0.25;
+
+impl TopDownController {
+    pub fn new(x: f32, y: f32, width: f32, height: f32, speed: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            speed,
+            knockback_decay: 8.0,
+            knockback: (0.0, 0.0),
+            facing: (0, 1),
+            moving: false,
+        }
+    }
+
+    /// Moves with WASD or the arrow keys. See `move_with_input`.
+    pub fn update<G: ConsoleGame>(
+        &mut self,
+        engine: &ConsoleGameEngine<G>,
+        map: &TileMap,
+        elapsed: f32,
+    ) {
+        let held = |keys: [usize; 2]| keys.iter().any(|&k| engine.key_held(k));
+        let axis = |neg, pos| held(pos) as i32 - held(neg) as i32;
+        let dx = axis([key::A, key::ARROW_LEFT], [key::D, key::ARROW_RIGHT]);
+        let dy = axis([key::W, key::ARROW_UP], [key::S, key::ARROW_DOWN]);
+        self.move_with_input(map, dx, dy, elapsed);
+    }
+
+    /// Moves in direction `(dx, dy)`, each `-1`, `0` or `1`, for `elapsed` seconds, plus any
+    /// knockback. Diagonal movement is as fast as straight movement.
+    pub fn move_with_input(&mut self, map: &TileMap, dx: i32, dy: i32, elapsed: f32) {
+        let (dx, dy) = (dx.signum(), dy.signum());
+        self.moving = dx != 0 || dy != 0;
+        if self.moving {
+            self.facing = (dx, dy);
+        }
+
+        let scale = if dx != 0 && dy != 0 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        let vx = dx as f32 * self.speed * scale + self.knockback.0;
+        let vy = dy as f32 * self.speed * scale + self.knockback.1;
+
+        let (hit_x, hit_y) = self.move_by(map, vx * elapsed, vy * elapsed);
+        if hit_x {
+            self.knockback.0 = 0.0;
+        }
+        if hit_y {
+            self.knockback.1 = 0.0;
+        }
+
+        let decay = (1.0 - self.knockback_decay * elapsed).max(0.0);
+        self.knockback.0 *= decay;
+        self.knockback.1 *= decay;
+    }
+
+    /// Moves by `(dx, dy)` tiles, stopping at walls. Returns whether the move was blocked
+    /// horizontally and vertically.
+    pub fn move_by(&mut self, map: &TileMap, dx: f32, dy: f32) -> (bool, bool) {
+        let steps = (dx.abs().max(dy.abs()) / CONTROLLER_STEP).ceil().max(1.0) as usize;
+        let (step_x, step_y) = (dx / steps as f32, dy / steps as f32);
+        let (mut hit_x, mut hit_y) = (false, false);
+
+        for _ in 0..steps {
+            if !hit_x && step_x != 0.0 {
+                let old = self.x;
+                self.x += step_x;
+                if self.collides(map) {
+                    // Snap flush against the wall.
+                    self.x = if step_x > 0.0 {
+                        (self.x + self.width).floor() - self.width
+                    } else {
+                        self.x.ceil()
+                    };
+                    if self.collides(map) {
+                        self.x = old;
+                    }
+                    hit_x = true;
+                }
+            }
+            if !hit_y && step_y != 0.0 {
+                let old = self.y;
+                self.y += step_y;
+                if self.collides(map) {
+                    self.y = if step_y > 0.0 {
+                        (self.y + self.height).floor() - self.height
+                    } else {
+                        self.y.ceil()
+                    };
+                    if self.collides(map) {
+                        self.y = old;
+                    }
+                    hit_y = true;
+                }
+            }
+        }
+        (hit_x, hit_y)
+    }
+
+    /// Pushes the controller with velocity `(vx, vy)` in tiles per second, which wears off
+    /// over time. Knockback adds to walking, and stops at walls.
+    pub fn knockback(&mut self, vx: f32, vy: f32) {
+        self.knockback.0 += vx;
+        self.knockback.1 += vy;
+    }
+
+    /// Returns `true` while knockback is still moving the controller noticeably.
+    pub fn is_knocked_back(&self) -> bool {
+        self.knockback.0.abs() + self.knockback.1.abs() > 0.1
+    }
+
+    /// Returns the last direction moved in, as `(dx, dy)` with each `-1`, `0` or `1`.
+    /// Starts facing down.
+    pub fn facing(&self) -> (i32, i32) {
+        self.facing
+    }
+
+    /// Returns `true` if the controller was given a direction on the last move.
+    pub fn is_moving(&self) -> bool {
+        self.moving
+    }
+
+    /// Returns the center of the box.
+    pub fn center(&self) -> (f32, f32) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+
+    /// Returns `true` if the box overlaps a solid tile.
+    pub fn collides(&self, map: &TileMap) -> bool {
+        // Shrink slightly, so a box exactly flush with a wall doesn't count as inside it.
+        const EPSILON: f32 = 1e-4;
+        let x1 = (self.x + EPSILON).floor() as i32;
+        let y1 = (self.y + EPSILON).floor() as i32;
+        let x2 = (self.x + self.width - EPSILON).floor() as i32;
+        let y2 = (self.y + self.height - EPSILON).floor() as i32;
+        (y1..=y2).any(|ty| (x1..=x2).any(|tx| map.is_solid(tx, ty)))
+    }
+}
+
+// endregion
+
+// region: Bullets
+
+/// A single projectile in a `BulletManager`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bullet {
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+    /// Seconds since it was spawned.
+    pub age: f32,
+    /// Seconds it lives for before disappearing.
+    pub lifetime: f32,
+    pub glyph: u16,
+    pub col: u16,
+    /// Game-defined value, e.g. who fired it or how much damage it does.
+    pub tag: u32,
+    last: (f32, f32),
+}
+
+/// Moves, expires and collides large numbers of bullets, for shmups and twin-stick games.
+///
+/// Bullets live in a `Pool`, so once it has grown to the busiest frame's bullet count,
+/// spawning and updating never allocate. Handles from `spawn` let the game steer or
+/// despawn a particular bullet later, e.g. a homing missile.
+/// Collision callbacks return `true` to destroy the bullet, or `false` to let it carry on,
+/// e.g. after bouncing it or for piercing shots.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut bullets = BulletManager::with_capacity(4096);
+/// bullets.set_bounds(0.0, 0.0, 160.0, 100.0);
+///
+/// // In update:
+/// if engine.key_held(SPACE) {
+///     let shot = bullets.spawn(ship.x, ship.y, 0.0, -60.0, 2.0, '|' as u16, FG_YELLOW);
+///     bullets.get_mut(shot).unwrap().tag = PLAYER;
+/// }
+/// bullets.update(elapsed_time);
+/// bullets.collide_tiles(&self.map, |_, _, _| true);
+/// bullets.collide_entities(&self.enemies, |bullet, id, _| {
+///     hits.push(id);
+///     bullet.tag == PLAYER
+/// });
+/// engine.draw_bullets(&bullets);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BulletManager {
+    bullets: Pool<Bullet>,
+    bounds: Option<(f32, f32, f32, f32)>,
+}
+
+impl BulletManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a manager with room for `capacity` bullets before it has to allocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bullets: Pool::with_capacity(capacity),
+            bounds: None,
+        }
+    }
+
+    /// Removes bullets that leave the `w` × `h` rectangle at `(x, y)` on every update,
+    /// usually the screen. Without bounds, bullets only disappear when their lifetime runs
+    /// out.
+    pub fn set_bounds(&mut self, x: f32, y: f32, w: f32, h: f32) {
+        self.bounds = Some((x, y, w, h));
+    }
+
+    /// Stops culling bullets that leave the bounds.
+    pub fn clear_bounds(&mut self) {
+        self.bounds = None;
+    }
+
+    /// Spawns a bullet at `(x, y)` moving with velocity `(vx, vy)` in cells per second,
+    /// which disappears after `lifetime` seconds, and returns its handle.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        &mut self,
+        x: f32,
+        y: f32,
+        vx: f32,
+        vy: f32,
+        lifetime: f32,
+        glyph: u16,
+        col: u16,
+    ) -> Handle {
+        self.bullets.spawn(Bullet {
+            x,
+            y,
+            vx,
+            vy,
+            age: 0.0,
+            lifetime,
+            glyph,
+            col,
+            tag: 0,
+            last: (x, y),
+        })
+    }
+
+    /// Removes the bullet `handle` refers to and returns it.
+    pub fn despawn(&mut self, handle: Handle) -> Option<Bullet> {
+        self.bullets.despawn(handle)
+    }
+
+    /// Returns the bullet `handle` refers to, or `None` if it is gone.
+    pub fn get(&self, handle: Handle) -> Option<&Bullet> {
+        self.bullets.get(handle)
+    }
+
+    /// Returns the bullet `handle` refers to mutably, or `None` if it is gone.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut Bullet> {
+        self.bullets.get_mut(handle)
+    }
+
+    /// Moves every bullet by `elapsed` seconds and removes the ones that have expired or
+    /// left the bounds.
+    pub fn update(&mut self, elapsed: f32) {
+        let bounds = self.bounds;
+        self.bullets.retain(|_, b| {
+            b.last = (b.x, b.y);
+            b.x += b.vx * elapsed;
+            b.y += b.vy * elapsed;
+            b.age += elapsed;
+            let inside = bounds
+                .is_none_or(|(x, y, w, h)| b.x >= x && b.y >= y && b.x < x + w && b.y < y + h);
+            b.age < b.lifetime && inside
+        });
+    }
+
+    /// Calls `on_hit` with the bullet and the tile for every bullet that hit a solid tile
+    /// in `map` during the last update. The whole path since the last update is checked,
+    /// so fast bullets can't pass through thin walls.
+    pub fn collide_tiles(
+        &mut self,
+        map: &TileMap,
+        mut on_hit: impl FnMut(&mut Bullet, i32, i32) -> bool,
+    ) {
+        self.bullets.retain(|_, b| {
+            let (dx, dy) = (b.x - b.last.0, b.y - b.last.1);
+            // Sample every half cell along the path.
+            let steps = (dx.abs().max(dy.abs()) * 2.0).ceil().max(1.0) as usize;
+            for i in 1..=steps {
+                let t = i as f32 / steps as f32;
+                let tx = (b.last.0 + dx * t).floor() as i32;
+                let ty = (b.last.1 + dy * t).floor() as i32;
+                if map.is_solid(tx, ty) {
+                    return !on_hit(b, tx, ty);
+                }
+            }
+            true
+        });
+    }
+
+    /// Calls `on_hit` with the bullet, id and value of every entity in `entities` whose
+    /// bounding box a bullet is inside. A bullet that isn't destroyed goes on to check the
+    /// remaining entities.
+    pub fn collide_entities<T>(
+        &mut self,
+        entities: &SpatialHash<T>,
+        mut on_hit: impl FnMut(&mut Bullet, usize, &T) -> bool,
+    ) {
+        self.bullets.retain(|_, b| {
+            // A point only ever falls into one bucket.
+            let (cx, cy, _, _) = entities.cell_range(b.x, b.y, 0.0, 0.0);
+            let Some(bucket) = entities.cells.get(&(cx, cy)) else {
+                return true;
+            };
+            for &id in bucket {
+                let Some(entry) = &entities.entries[id] else {
+                    continue;
+                };
+                let (x, y, w, h) = entry.bounds;
+                if b.x >= x
+                    && b.y >= y
+                    && b.x <= x + w
+                    && b.y <= y + h
+                    && on_hit(b, id, &entry.value)
+                {
+                    return false;
+                }
+            }
+            true
+        });
+    }
+
+    /// Calls `on_hit` for every bullet inside the `w` × `h` rectangle at `(x, y)`, e.g. the
+    /// player's ship.
+    pub fn collide_rect(
+        &mut self,
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        mut on_hit: impl FnMut(&mut Bullet) -> bool,
+    ) {
+        self.bullets.retain(|_, b| {
+            let inside = b.x >= x && b.y >= y && b.x <= x + w && b.y <= y + h;
+            !(inside && on_hit(b))
+        });
+    }
+
+    /// Removes every bullet for which `keep` returns `false`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Bullet) -> bool) {
+        self.bullets.retain(|_, b| keep(b));
+    }
+
+    pub fn len(&self) -> usize {
+        self.bullets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bullets.is_empty()
+    }
+
+    /// Removes all bullets, keeping the allocated room for them.
+    pub fn clear(&mut self) {
+        self.bullets.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &Bullet)> {
+        self.bullets.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, &mut Bullet)> {
+        self.bullets.iter_mut()
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws every bullet in `bullets` with its glyph and color.
+    pub fn draw_bullets(&mut self, bullets: &BulletManager) {
+        for (_, b) in bullets.iter() {
+            self.draw_with(b.x.floor() as i32, b.y.floor() as i32, b.glyph, b.col);
+        }
+    }
+}
+
+// endregion
+
+// region: State Machine
+
+type EnterFn<C> = Box<dyn FnMut(&mut C)>;
+type UpdateFn<C, E> = Box<dyn FnMut(&mut C, f32) -> Option<E>>;
+
+/// A finite state machine for game objects, like enemy AI or player control.
+///
+/// `S` is the state type and `E` the events that move between states, usually small `Copy`
+/// enums. `C` is the context the callbacks work on, such as the enemy itself; it defaults to
+/// `()` for machines that only track state.
+///
+/// Transitions are looked up in a table: an event that has no transition from the current
+/// state is ignored. When a transition fires, the old state's exit callback runs, then the
+/// new state's enter callback. Update callbacks run every frame for the current state and
+/// may return an event to fire.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// enum Ai { Patrol, Chase }
+/// #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// enum Seen { Player, Nothing }
+///
+/// let mut ai = StateMachine::<Ai, Seen, Enemy>::builder(Ai::Patrol)
+///     .on_update(Ai::Patrol, |enemy, dt| {
+///         enemy.walk(dt);
+///         enemy.sees_player().then_some(Seen::Player)
+///     })
+///     .on_enter(Ai::Chase, |enemy| enemy.alert())
+///     .transition(Ai::Patrol, Seen::Player, Ai::Chase)
+///     .transition(Ai::Chase, Seen::Nothing, Ai::Patrol)
+///     .build();
+///
+/// ai.update(&mut self.enemy, elapsed_time);
+/// ```
+pub struct StateMachine<S, E, C = ()> {
+    state: S,
+    previous: Option<S>,
+    entered: bool,
+    time_in_state: f32,
+    transitions: HashMap<(S, E), S>,
+    any_transitions: HashMap<E, S>,
+    on_enter: HashMap<S, EnterFn<C>>,
+    on_exit: HashMap<S, EnterFn<C>>,
+    on_update: HashMap<S, UpdateFn<C, E>>,
+}
+
+impl<S, E, C> StateMachine<S, E, C>
+where
+    S: Copy + Eq + std::hash::Hash,
+    E: Copy + Eq + std::hash::Hash,
+{
+    /// Creates a machine in `initial` with no transitions or callbacks.
+    pub fn new(initial: S) -> Self {
+        Self {
+            state: initial,
+            previous: None,
+            entered: false,
+            time_in_state: 0.0,
+            transitions: HashMap::new(),
+            any_transitions: HashMap::new(),
+            on_enter: HashMap::new(),
+            on_exit: HashMap::new(),
+            on_update: HashMap::new(),
+        }
+    }
+
+    /// Starts building a machine in `initial`.
+    pub fn builder(initial: S) -> StateMachineBuilder<S, E, C> {
+        StateMachineBuilder {
+            machine: Self::new(initial),
+        }
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+    /// Returns the state before the last transition, if there has been one.
+    pub fn previous(&self) -> Option<S> {
+        self.previous
+    }
+
+    /// Returns how many seconds of `update` have passed since the current state was entered.
+    pub fn time_in_state(&self) -> f32 {
+        self.time_in_state
+    }
+
+    /// Returns `true` if the machine is in `state`.
+    pub fn is(&self, state: S) -> bool {
+        self.state == state
+    }
+
+    /// Adds a transition from `from` to `to` when `event` fires.
+    pub fn add_transition(&mut self, from: S, event: E, to: S) {
+        self.transitions.insert((from, event), to);
+    }
+
+    /// Adds a transition to `to` when `event` fires in any state. Transitions added with
+    /// `add_transition` take priority.
+    pub fn add_any_transition(&mut self, event: E, to: S) {
+        self.any_transitions.insert(event, to);
+    }
+
+    /// Fires `event`, moving to a new state if there is a transition for it. Returns `true`
+    /// if the state changed.
+    pub fn handle(&mut self, ctx: &mut C, event: E) -> bool {
+        self.ensure_entered(ctx);
+        let to = self
+            .transitions
+            .get(&(self.state, event))
+            .or_else(|| self.any_transitions.get(&event))
+            .copied();
+        match to {
+            Some(to) => {
+                self.change(ctx, to);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves to `state` directly, ignoring the transition table. Exit and enter callbacks
+    /// still run, even when `state` is the current state.
+    pub fn set_state(&mut self, ctx: &mut C, state: S) {
+        self.ensure_entered(ctx);
+        self.change(ctx, state);
+    }
+
+    /// Runs the current state's update callback, firing the event it returns.
+    ///
+    /// The initial state's enter callback runs on the first call to `update`, `handle` or
+    /// `set_state`.
+    pub fn update(&mut self, ctx: &mut C, elapsed: f32) {
+        self.ensure_entered(ctx);
+        self.time_in_state += elapsed;
+        let event = self
+            .on_update
+            .get_mut(&self.state)
+            .and_then(|f| f(ctx, elapsed));
+        if let Some(event) = event {
+            self.handle(ctx, event);
+        }
+    }
+
+    fn ensure_entered(&mut self, ctx: &mut C) {
+        if !self.entered {
+            self.entered = true;
+            if let Some(f) = self.on_enter.get_mut(&self.state) {
+                f(ctx);
+            }
+        }
+    }
+
+    fn change(&mut self, ctx: &mut C, to: S) {
+        if let Some(f) = self.on_exit.get_mut(&self.state) {
+            f(ctx);
+        }
+        self.previous = Some(self.state);
+        self.state = to;
+        self.time_in_state = 0.0;
+        if let Some(f) = self.on_enter.get_mut(&to) {
+            f(ctx);
+        }
+    }
+}
+
+/// Builds a [`StateMachine`] one callback and transition at a time.
+pub struct StateMachineBuilder<S, E, C = ()> {
+    machine: StateMachine<S, E, C>,
+}
+
+impl<S, E, C> StateMachineBuilder<S, E, C>
+where
+    S: Copy + Eq + std::hash::Hash,
+    E: Copy + Eq + std::hash::Hash,
+{
+    /// Runs `f` whenever `state` is entered.
+    pub fn on_enter(mut self, state: S, f: impl FnMut(&mut C) + 'static) -> Self {
+        self.machine.on_enter.insert(state, Box::new(f));
+        self
+    }
+
+    /// Runs `f` whenever `state` is left.
+    pub fn on_exit(mut self, state: S, f: impl FnMut(&mut C) + 'static) -> Self {
+        self.machine.on_exit.insert(state, Box::new(f));
+        self
+    }
+
+    /// Runs `f` on every `update` while in `state`. Returning an event fires it.
+    pub fn on_update(
+        mut self,
+        state: S,
+        f: impl FnMut(&mut C, f32) -> Option<E> + 'static,
+    ) -> Self {
+        self.machine.on_update.insert(state, Box::new(f));
+        self
+    }
+
+    /// Moves from `from` to `to` when `event` fires.
+    pub fn transition(mut self, from: S, event: E, to: S) -> Self {
+        self.machine.add_transition(from, event, to);
+        self
+    }
+
+    /// Moves to `to` when `event` fires in any state without its own transition for it.
+    pub fn any_transition(mut self, event: E, to: S) -> Self {
+        self.machine.add_any_transition(event, to);
+        self
+    }
+
+    pub fn build(self) -> StateMachine<S, E, C> {
+        self.machine
+    }
+}
+
+// endregion
+
+// region: Events
+
+type EventHandler<C> = Box<dyn FnMut(&mut C, &dyn std::any::Any)>;
+
+/// A queue of typed game messages, so systems can react to each other without knowing
+/// about each other, e.g. audio playing a sound on every collision, or the HUD updating
+/// when the score changes.
+///
+/// Any `'static` type can be an event. Events are queued by `emit` and delivered in the
+/// order they were emitted when `dispatch` runs, usually once per frame in `update`. Every
+/// handler subscribed to an event's type is called with it, and events nobody subscribed
+/// to are dropped. `C` is the context the handlers work on, like for [`StateMachine`]; it
+/// defaults to `()`.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// struct PlayerDied;
+/// struct Scored(u32);
+///
+/// let mut events = EventBus::<Hud>::new();
+/// events.subscribe(|hud, scored: &Scored| hud.score += scored.0);
+/// events.subscribe(|hud, _: &PlayerDied| hud.show_game_over());
+///
+/// // Anywhere during the frame:
+/// events.emit(Scored(100));
+/// events.emit(PlayerDied);
+///
+/// // At the end of update:
+/// events.dispatch(&mut self.hud);
+/// ```
+pub struct EventBus<C = ()> {
+    queue: VecDeque<Box<dyn std::any::Any>>,
+    handlers: HashMap<std::any::TypeId, Vec<EventHandler<C>>>,
+}
+
+impl<C> Default for EventBus<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> EventBus<C> {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Calls `f` with every event of type `E` on `dispatch`. Handlers for the same type run
+    /// in the order they were subscribed.
+    pub fn subscribe<E: 'static>(&mut self, mut f: impl FnMut(&mut C, &E) + 'static) {
+        self.handlers
+            .entry(std::any::TypeId::of::<E>())
+            .or_default()
+            .push(Box::new(move |ctx, event| {
+                if let Some(event) = event.downcast_ref::<E>() {
+                    f(ctx, event);
+                }
+            }));
+    }
+
+    /// Removes every handler for events of type `E`.
+    pub fn unsubscribe_all<E: 'static>(&mut self) {
+        self.handlers.remove(&std::any::TypeId::of::<E>());
+    }
+
+    /// Returns `true` if any handler is subscribed to events of type `E`.
+    pub fn has_subscribers<E: 'static>(&self) -> bool {
+        self.handlers.contains_key(&std::any::TypeId::of::<E>())
+    }
+
+    /// Queues `event` until the next `dispatch`.
+    pub fn emit<E: 'static>(&mut self, event: E) {
+        self.queue.push_back(Box::new(event));
+    }
+
+    /// Delivers every queued event to its handlers, in the order they were emitted, and
+    /// empties the queue. Returns how many events were delivered.
+    pub fn dispatch(&mut self, ctx: &mut C) -> usize {
+        let mut delivered = 0;
+        while let Some(event) = self.queue.pop_front() {
+            let Some(handlers) = self.handlers.get_mut(&(*event).type_id()) else {
+                continue;
+            };
+            for handler in handlers {
+                handler(ctx, &*event);
+            }
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Removes the queued events of type `E` and returns them in the order they were
+    /// emitted, for systems that poll instead of subscribing.
+    pub fn drain<E: 'static>(&mut self) -> Vec<E> {
+        let mut drained = Vec::new();
+        let mut rest = VecDeque::with_capacity(self.queue.len());
+        for event in self.queue.drain(..) {
+            match event.downcast::<E>() {
+                Ok(event) => drained.push(*event),
+                Err(event) => rest.push_back(event),
+            }
+        }
+        self.queue = rest;
+        drained
+    }
+
+    /// Returns the number of queued events.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if no events are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Drops every queued event without delivering it.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
+
+// endregion
+
+// region: Paths
+
+/// Points sampled per segment when a `MotionPath` is a spline.
+const SPLINE_STEPS: usize = 8;
+
+/// A route through a list of points, as straight lines or a smooth curve, for enemies,
+/// camera rails and cutscenes to move along.
+///
+/// Positions along the path are given as distances in cells from its start. Splines are
+/// Catmull-Rom curves that pass through every point.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let path = MotionPath::spline(&[(0.0, 10.0), (40.0, 2.0), (80.0, 10.0), (120.0, 2.0)]);
+/// let mut follower = PathFollower::new(20.0);
+/// follower.easing = Easing::InOut;
+///
+/// // In update:
+/// let (x, y) = follower.update(&path, elapsed_time);
+/// let (ax, ay) = follower.lookahead(&path, 3.0);
+/// enemy.aim_at(ax, ay);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotionPath {
+    points: Vec<(f32, f32)>,
+    looped: bool,
+    spline: bool,
+    /// The path as straight segments, with the distance from the start at every point.
+    samples: Vec<(f32, f32, f32)>,
+}
+
+impl MotionPath {
+    /// Creates a path of straight lines through `points`.
+    pub fn polyline(points: &[(f32, f32)]) -> Self {
+        Self::build(points, false, false)
+    }
+
+    /// Creates a smooth curve through `points`.
+    pub fn spline(points: &[(f32, f32)]) -> Self {
+        Self::build(points, true, false)
+    }
+
+    /// Returns the path with its end joined back to its start, so followers go round
+    /// forever.
+    pub fn looped(self) -> Self {
+        Self::build(&self.points, self.spline, true)
+    }
+
+    fn build(points: &[(f32, f32)], spline: bool, looped: bool) -> Self {
+        let mut route = points.to_vec();
+        if looped && points.len() > 1 {
+            route.push(points[0]);
+        }
+
+        let mut sampled = Vec::new();
+        if spline && route.len() > 2 {
+            let n = route.len();
+            let at = |i: isize| -> (f32, f32) {
+                if looped {
+                    // The last point repeats the first, so wrap over the others.
+                    points[i.rem_euclid(points.len() as isize) as usize]
+                } else {
+                    route[i.clamp(0, n as isize - 1) as usize]
+                }
+            };
+            for i in 0..n - 1 {
+                let i = i as isize;
+                let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+                for step in 0..SPLINE_STEPS {
+                    let t = step as f32 / SPLINE_STEPS as f32;
+                    sampled.push(catmull_rom(p0, p1, p2, p3, t));
+                }
+            }
+            sampled.push(route[n - 1]);
+        } else {
+            sampled = route;
+        }
+
+        let mut samples = Vec::with_capacity(sampled.len());
+        let mut distance = 0.0;
+        for (i, &(x, y)) in sampled.iter().enumerate() {
+            if i > 0 {
+                let (px, py) = sampled[i - 1];
+                distance += ((x - px) * (x - px) + (y - py) * (y - py)).sqrt();
+            }
+            samples.push((x, y, distance));
+        }
+
+        Self {
+            points: points.to_vec(),
+            looped,
+            spline,
+            samples,
+        }
+    }
+
+    /// Returns the points the path was made from.
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points
+    }
+
+    /// Returns `true` if the path joins back to its start.
+    pub fn is_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Returns the length of the path in cells.
+    pub fn length(&self) -> f32 {
+        self.samples.last().map_or(0.0, |s| s.2)
+    }
+
+    /// Brings `distance` onto the path: wrapped around for looped paths, clamped to the
+    /// ends otherwise.
+    fn wrap(&self, distance: f32) -> f32 {
+        let length = self.length();
+        if self.looped && length > 0.0 {
+            distance.rem_euclid(length)
+        } else {
+            distance.clamp(0.0, length)
+        }
+    }
+
+    /// Returns the point `distance` cells along the path.
+    pub fn point_at(&self, distance: f32) -> (f32, f32) {
+        let Some(&(x, y, _)) = self.samples.first() else {
+            return (0.0, 0.0);
+        };
+        let distance = self.wrap(distance);
+        let i = self.samples.partition_point(|s| s.2 < distance);
+        if i == 0 {
+            return (x, y);
+        }
+        let Some(&(x2, y2, d2)) = self.samples.get(i) else {
+            let (x, y, _) = self.samples[self.samples.len() - 1];
+            return (x, y);
+        };
+        let (x1, y1, d1) = self.samples[i - 1];
+        let t = if d2 > d1 {
+            (distance - d1) / (d2 - d1)
+        } else {
+            0.0
+        };
+        (x1 + (x2 - x1) * t, y1 + (y2 - y1) * t)
+    }
+
+    /// Returns the normalized direction of the path `distance` cells along it, or
+    /// `(0.0, 0.0)` if it has no length.
+    pub fn direction_at(&self, distance: f32) -> (f32, f32) {
+        let length = self.length();
+        if length <= 0.0 {
+            return (0.0, 0.0);
+        }
+        // Look a little either side, staying on the path at the ends.
+        let step = (length / 1000.0).max(0.01);
+        let (d1, d2) = if self.looped {
+            (distance - step, distance + step)
+        } else {
+            let d = self.wrap(distance);
+            ((d - step).max(0.0), (d + step).min(length))
+        };
+        let ((x1, y1), (x2, y2)) = (self.point_at(d1), self.point_at(d2));
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len > 0.0 {
+            (dx / len, dy / len)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+}
+
+/// Returns the point `t` of the way from `p1` to `p2` on a Catmull-Rom curve.
+fn catmull_rom(
+    p0: (f32, f32),
+    p1: (f32, f32),
+    p2: (f32, f32),
+    p3: (f32, f32),
+    t: f32,
+) -> (f32, f32) {
+    let (t2, t3) = (t * t, t * t * t);
+    let axis = |a: f32, b: f32, c: f32, d: f32| {
+        0.5 * (2.0 * b
+            + (c - a) * t
+            + (2.0 * a - 5.0 * b + 4.0 * c - d) * t2
+            + (3.0 * b - a - 3.0 * c + d) * t3)
+    };
+    (axis(p0.0, p1.0, p2.0, p3.0), axis(p0.1, p1.1, p2.1, p3.1))
+}
+
+/// Which ends of a path a `PathFollower` slows down at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Easing {
+    /// Full speed the whole way.
+    #[default]
+    Linear,
+    /// Speeds up from the start.
+    In,
+    /// Slows down into the end.
+    Out,
+    /// Speeds up from the start and slows down into the end.
+    InOut,
+}
+
+/// Slowest an eased follower goes, as a fraction of its speed, so it never stalls.
+const EASING_MIN_SPEED: f32 = 0.1;
+
+/// Moves along a `MotionPath` at a set speed, keeping track of how far it has come.
+///
+/// The follower only stores its distance, so one path can be shared by any number of
+/// followers. Easing only applies to paths that aren't looped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathFollower {
+    /// Distance travelled along the path, in cells.
+    pub distance: f32,
+    /// Cells per second. Negative speeds go backwards.
+    pub speed: f32,
+    pub easing: Easing,
+    /// How many cells from the ends easing takes to reach full speed. Defaults to `8.0`.
+    pub ease_distance: f32,
+}
+
+impl PathFollower {
+    /// Creates a follower at the start of a path, moving at `speed` cells per second.
+    pub fn new(speed: f32) -> Self {
+        Self {
+            distance: 0.0,
+            speed,
+            easing: Easing::Linear,
+            ease_distance: 8.0,
+        }
+    }
+
+    /// Moves `dist` cells along `path` and returns the new position.
+    pub fn advance(&mut self, path: &MotionPath, dist: f32) -> (f32, f32) {
+        self.distance = path.wrap(self.distance + dist);
+        self.position(path)
+    }
+
+    /// Moves along `path` at `speed`, eased near the ends, for `elapsed` seconds and
+    /// returns the new position.
+    pub fn update(&mut self, path: &MotionPath, elapsed: f32) -> (f32, f32) {
+        let speed = self.speed * self.speed_factor(path);
+        self.advance(path, speed * elapsed)
+    }
+
+    fn speed_factor(&self, path: &MotionPath) -> f32 {
+        if path.looped || self.ease_distance <= 0.0 {
+            return 1.0;
+        }
+        // Square roots give constant acceleration away from the end.
+        let ramp = |d: f32| (d / self.ease_distance).sqrt().clamp(EASING_MIN_SPEED, 1.0);
+        let from_start = ramp(self.distance);
+        let to_end = ramp(path.length() - self.distance);
+        match self.easing {
+            Easing::Linear => 1.0,
+            Easing::In => from_start,
+            Easing::Out => to_end,
+            Easing::InOut => from_start.min(to_end),
+        }
+    }
+
+    /// Returns the current position on `path`.
+    pub fn position(&self, path: &MotionPath) -> (f32, f32) {
+        path.point_at(self.distance)
+    }
+
+    /// Returns the point `ahead` cells further along `path`, e.g. for aiming or for a
+    /// camera to look towards.
+    pub fn lookahead(&self, path: &MotionPath, ahead: f32) -> (f32, f32) {
+        path.point_at(self.distance + ahead)
+    }
+
+    /// Returns the normalized direction of travel along `path`.
+    pub fn heading(&self, path: &MotionPath) -> (f32, f32) {
+        let (dx, dy) = path.direction_at(self.distance);
+        if self.speed < 0.0 {
+            (-dx, -dy)
+        } else {
+            (dx, dy)
+        }
+    }
+
+    /// Returns how much of `path` has been covered, from `0.0` to `1.0`.
+    pub fn progress(&self, path: &MotionPath) -> f32 {
+        let length = path.length();
+        if length > 0.0 {
+            self.distance / length
+        } else {
+            1.0
+        }
+    }
+
+    /// Returns `true` once the follower has reached the end of a path that isn't looped,
+    /// or the start when going backwards.
+    pub fn is_finished(&self, path: &MotionPath) -> bool {
+        if path.looped {
+            return false;
+        }
+        if self.speed < 0.0 {
+            self.distance <= 0.0
+        } else {
+            self.distance >= path.length()
+        }
+    }
+
+    /// Moves back to the start of the path.
+    pub fn restart(&mut self) {
+        self.distance = 0.0;
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws `path` with `glyph` in `col`, e.g. to check a route while building a level.
+    pub fn draw_path(&mut self, path: &MotionPath, glyph: u16, col: u16) {
+        for pair in path.samples.windows(2) {
+            let ((x1, y1, _), (x2, y2, _)) = (pair[0], pair[1]);
+            self.draw_line_with(
+                x1.floor() as i32,
+                y1.floor() as i32,
+                x2.floor() as i32,
+                y2.floor() as i32,
+                glyph,
+                col,
+            );
+        }
+    }
+}
+
+// endregion
+
+// region: Waves
+
+/// One batch of identical enemies within a [`Wave`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnGroup {
+    /// Game-defined enemy type, passed through to the spawn events.
+    pub kind: String,
+    /// How many to spawn.
+    pub count: u32,
+    /// Seconds between each spawn.
+    pub interval: f32,
+    /// Name of the path they follow, registered with `WaveDirector::add_path`.
+    pub path: Option<String>,
+    /// Seconds after the wave starts before the first spawn.
+    pub delay: f32,
+}
+
+/// A set of spawn groups that run together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wave {
+    /// Seconds to wait after the previous wave finishes spawning, or after the start for
+    /// the first wave.
+    pub delay: f32,
+    pub groups: Vec<SpawnGroup>,
+}
+
+/// An enemy the game should spawn this frame, from `WaveDirector::update`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnEvent {
+    /// Index of the wave it belongs to.
+    pub wave: usize,
+    pub kind: String,
+    pub path: Option<String>,
+    /// Which of its group this is, counting from 0.
+    pub index: u32,
+}
+
+/// Runs a list of enemy waves, for shmups and tower defense games.
+///
+/// Waves can be built in code or loaded from JSON with the `serde` feature:
+///
+/// ```json
+/// {
+///     "paths": { "top": [[0, 5], [60, 5], [60, 40]] },
+///     "waves": [
+///         {
+///             "delay": 2.0,
+///             "groups": [
+///                 { "type": "grunt", "count": 8, "interval": 0.5, "path": "top" },
+///                 { "type": "tank", "count": 1, "delay": 4.0, "path": "top" }
+///             ]
+///         }
+///     ]
+/// }
+/// ```
+///
+/// `count` defaults to 1 and `interval`, `delay` and `path` are optional.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut waves = WaveDirector::load("levels/1.json")?;
+///
+/// for spawn in waves.update(elapsed_time) {
+///     let path = spawn.path.as_deref().and_then(|p| waves.path(p));
+///     self.enemies.push(Enemy::new(&spawn.kind, path));
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WaveDirector {
+    waves: Vec<Wave>,
+    paths: HashMap<String, Vec<(f32, f32)>>,
+    wave: usize,
+    wave_time: f32,
+    spawned: Vec<u32>,
+    started: bool,
+}
+
+impl WaveDirector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a wave after the existing ones.
+    pub fn add_wave(&mut self, wave: Wave) {
+        self.waves.push(wave);
+    }
+
+    /// Registers a path that spawn groups can refer to by `name`.
+    pub fn add_path(&mut self, name: &str, points: Vec<(f32, f32)>) {
+        self.paths.insert(name.to_string(), points);
+    }
+
+    /// Returns the points of the path called `name`.
+    pub fn path(&self, name: &str) -> Option<&[(f32, f32)]> {
+        self.paths.get(name).map(Vec::as_slice)
+    }
+
+    pub fn waves(&self) -> &[Wave] {
+        &self.waves
+    }
+
+    /// Returns the index of the wave currently spawning or waiting to, or `None` once every
+    /// wave has finished.
+    pub fn current_wave(&self) -> Option<usize> {
+        (self.wave < self.waves.len()).then_some(self.wave)
+    }
+
+    /// Returns `true` once every wave has finished spawning.
+    pub fn is_finished(&self) -> bool {
+        self.wave >= self.waves.len()
+    }
+
+    /// Returns the seconds until the current wave starts, or 0 if it already has.
+    pub fn time_until_wave(&self) -> f32 {
+        (-self.wave_time).max(0.0)
+    }
+
+    /// Skips the rest of the current wave's delay so it starts on the next `update`.
+    pub fn start_wave_now(&mut self) {
+        self.wave_time = self.wave_time.max(0.0);
+    }
+
+    /// Starts again from the first wave.
+    pub fn reset(&mut self) {
+        self.wave = 0;
+        self.wave_time = 0.0;
+        self.spawned.clear();
+        self.started = false;
+    }
+
+    /// Advances time by `elapsed` seconds and returns everything due to spawn.
+    pub fn update(&mut self, elapsed: f32) -> Vec<SpawnEvent> {
+        let mut events = Vec::new();
+        if !self.started {
+            self.started = true;
+            self.begin_wave(0);
+        }
+
+        self.wave_time += elapsed;
+        while let Some(wave) = self.waves.get(self.wave) {
+            // Waves added after the director ran out of them start with nothing spawned.
+            self.spawned.resize(wave.groups.len(), 0);
+            let mut done = true;
+            for (group, spawned) in wave.groups.iter().zip(self.spawned.iter_mut()) {
+                while *spawned < group.count
+                    && group.delay + *spawned as f32 * group.interval <= self.wave_time
+                {
+                    events.push(SpawnEvent {
+                        wave: self.wave,
+                        kind: group.kind.clone(),
+                        path: group.path.clone(),
+                        index: *spawned,
+                    });
+                    *spawned += 1;
+                }
+                done &= *spawned >= group.count;
+            }
+            if !done {
+                break;
+            }
+
+            // Carry the time left over into the next wave's delay.
+            let finished_at = wave
+                .groups
+                .iter()
+                .map(|g| g.delay + g.count.saturating_sub(1) as f32 * g.interval)
+                .fold(0.0, f32::max);
+            let overflow = (self.wave_time - finished_at).max(0.0);
+            self.begin_wave(self.wave + 1);
+            self.wave_time += overflow;
+        }
+
+        events
+    }
+
+    fn begin_wave(&mut self, index: usize) {
+        self.wave = index;
+        self.wave_time = -self.waves.get(index).map_or(0.0, |w| w.delay);
+        self.spawned = vec![0; self.waves.get(index).map_or(0, |w| w.groups.len())];
+    }
+
+    /// Loads waves and paths from a JSON file. See [`WaveDirector`] for the format.
+    #[cfg(feature = "serde")]
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses waves and paths from JSON text. See [`WaveDirector`] for the format.
+    #[cfg(feature = "serde")]
+    pub fn from_json(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        use serde_json::Value;
+
+        let root: Value = serde_json::from_str(text)?;
+        let number = |v: &Value, key: &str, default: f64| -> Result<f64, String> {
+            match v.get(key) {
+                None | Some(Value::Null) => Ok(default),
+                Some(n) => n.as_f64().ok_or(format!("`{}` must be a number", key)),
+            }
+        };
+
+        let mut director = Self::new();
+        if let Some(paths) = root.get("paths").and_then(Value::as_object) {
+            for (name, points) in paths {
+                let points = points
+                    .as_array()
+                    .ok_or(format!("path `{}` must be a list of points", name))?
+                    .iter()
+                    .map(|p| match p.as_array().map(Vec::as_slice) {
+                        Some([x, y]) => Some((x.as_f64()? as f32, y.as_f64()? as f32)),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(format!("path `{}` has a point that isn't [x, y]", name))?;
+                director.add_path(name, points);
+            }
+        }
+
+        let waves = root
+            .get("waves")
+            .and_then(Value::as_array)
+            .ok_or("missing `waves` list")?;
+        for wave in waves {
+            let mut groups = Vec::new();
+            for group in wave
+                .get("groups")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                let kind = group
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .ok_or("spawn group is missing its `type`")?;
+                let path = match group.get("path") {
+                    None | Some(Value::Null) => None,
+                    Some(Value::String(p)) if director.paths.contains_key(p) => Some(p.clone()),
+                    Some(p) => return Err(format!("unknown path {}", p).into()),
+                };
+                groups.push(SpawnGroup {
+                    kind: kind.to_string(),
+                    count: number(group, "count", 1.0)? as u32,
+                    interval: number(group, "interval", 0.0)? as f32,
+                    path,
+                    delay: number(group, "delay", 0.0)? as f32,
+                });
+            }
+            director.add_wave(Wave {
+                delay: number(wave, "delay", 0.0)? as f32,
+                groups,
+            });
+        }
+
+        Ok(director)
+    }
+}
+
+// endregion
+
+// region: Inventory
+
+/// Something that can be stored in an [`Inventory`].
+///
+/// Items that compare equal stack together, up to `max_stack`. The other methods describe
+/// the item to the default renderer and to the game's UI.
+pub trait InventoryItem: Clone + PartialEq {
+    /// How many of this item fit in one slot.
+    fn max_stack(&self) -> u32 {
+        1
+    }
+
+    /// Icon drawn in the item's slot by `draw_inventory`.
+    fn icon(&self) -> Option<&Sprite> {
+        None
+    }
+
+    /// Display name, for tooltips and menus.
+    fn name(&self) -> &str {
+        ""
+    }
+}
+
+/// A number of identical items in one inventory slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemStack<T> {
+    pub item: T,
+    pub count: u32,
+}
+
+/// Where an inventory is drawn on screen and how, for `draw_inventory` and
+/// `Inventory::handle_mouse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InventoryLayout {
+    /// Top left corner of the grid.
+    pub x: i32,
+    pub y: i32,
+    /// Size of each slot including its border, in cells.
+    pub slot_width: i32,
+    pub slot_height: i32,
+    pub border_color: u16,
+    /// Border color of the slot under the mouse.
+    pub hover_color: u16,
+    /// Color of the stack counts.
+    pub count_color: u16,
+}
+
+impl Default for InventoryLayout {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            slot_width: 6,
+            slot_height: 5,
+            border_color: FG_DARK_GREY,
+            hover_color: FG_WHITE,
+            count_color: FG_WHITE,
+        }
+    }
+}
+
+impl InventoryLayout {
+    /// Returns the index of the slot under screen cell `(x, y)` in an inventory `width` slots
+    /// wide and `height` tall.
+    pub fn slot_at(&self, width: usize, height: usize, x: i32, y: i32) -> Option<usize> {
+        if self.slot_width <= 0 || self.slot_height <= 0 || x < self.x || y < self.y {
+            return None;
+        }
+        let sx = ((x - self.x) / self.slot_width) as usize;
+        let sy = ((y - self.y) / self.slot_height) as usize;
+        (sx < width && sy < height).then_some(sy * width + sx)
+    }
+}
+
+/// A grid of item slots with stacking and mouse drag and drop, as found in roguelikes and
+/// RPGs.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// #[derive(Clone, PartialEq)]
+/// enum Loot { Potion, Sword }
+///
+/// impl InventoryItem for Loot {
+///     fn max_stack(&self) -> u32 {
+///         match self { Loot::Potion => 10, Loot::Sword => 1 }
+///     }
+/// }
+///
+/// let mut bag = Inventory::new(8, 4);
+/// bag.add(Loot::Potion, 3);
+///
+/// // Each frame:
+/// bag.handle_mouse(engine, &self.layout);
+/// engine.draw_inventory(&bag, &self.layout);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inventory<T> {
+    /// Width of the grid in slots.
+    pub width: usize,
+    /// Height of the grid in slots.
+    pub height: usize,
+    slots: Vec<Option<ItemStack<T>>>,
+    held: Option<(ItemStack<T>, usize)>,
+}
+
+impl<T: InventoryItem> Inventory<T> {
+    /// Creates an empty inventory `width` slots wide and `height` slots tall.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            slots: vec![None; width * height],
+            held: None,
+        }
+    }
+
+    /// Returns the stack in slot `index`, counting across then down.
+    pub fn slot(&self, index: usize) -> Option<&ItemStack<T>> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    /// Returns the stack in the slot at column `x`, row `y`.
+    pub fn slot_at(&self, x: usize, y: usize) -> Option<&ItemStack<T>> {
+        if x < self.width {
+            self.slot(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over every slot in order.
+    pub fn slots(&self) -> impl Iterator<Item = Option<&ItemStack<T>>> {
+        self.slots.iter().map(Option::as_ref)
+    }
+
+    /// Puts `stack` in slot `index` and returns what was there.
+    pub fn set_slot(&mut self, index: usize, stack: Option<ItemStack<T>>) -> Option<ItemStack<T>> {
+        let slot = self.slots.get_mut(index)?;
+        std::mem::replace(slot, stack.filter(|s| s.count > 0))
+    }
+
+    /// Empties slot `index` and returns what was there.
+    pub fn take(&mut self, index: usize) -> Option<ItemStack<T>> {
+        self.set_slot(index, None)
+    }
+
+    /// Adds `count` of `item`, topping up existing stacks before filling empty slots. Returns
+    /// how many didn't fit.
+    pub fn add(&mut self, item: T, mut count: u32) -> u32 {
+        let max = item.max_stack().max(1);
+        for stack in self.slots.iter_mut().flatten() {
+            if count == 0 {
+                break;
+            }
+            if stack.item == item && stack.count < max {
+                let moved = count.min(max - stack.count);
+                stack.count += moved;
+                count -= moved;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|s| s.is_none()) {
+            if count == 0 {
+                break;
+            }
+            let moved = count.min(max);
+            *slot = Some(ItemStack {
+                item: item.clone(),
+                count: moved,
+            });
+            count -= moved;
+        }
+        count
+    }
+
+    /// Removes up to `count` of `item`, emptying the last slots first. Returns how many
+    /// were removed.
+    pub fn remove(&mut self, item: &T, count: u32) -> u32 {
+        let mut removed = 0;
+        for slot in self.slots.iter_mut().rev() {
+            if removed == count {
+                break;
+            }
+            if let Some(stack) = slot.as_mut().filter(|s| s.item == *item) {
+                let taken = stack.count.min(count - removed);
+                stack.count -= taken;
+                removed += taken;
+                if stack.count == 0 {
+                    *slot = None;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Returns how many of `item` are stored across all slots.
+    pub fn count(&self, item: &T) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|s| s.item == *item)
+            .map(|s| s.count)
+            .sum()
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.count(item) > 0
+    }
+
+    /// Moves the stack in slot `from` onto slot `to`. Matching items merge as far as the
+    /// stack limit allows, leaving the rest behind; anything else swaps places.
+    pub fn move_slot(&mut self, from: usize, to: usize) {
+        if from == to || from >= self.slots.len() || to >= self.slots.len() {
+            return;
+        }
+        if let Some(stack) = self.take(from) {
+            let rest = self.place(stack, to);
+            self.slots[from] = rest;
+        }
+    }
+
+    /// Returns the stack being dragged with the mouse, if any.
+    pub fn held(&self) -> Option<&ItemStack<T>> {
+        self.held.as_ref().map(|(stack, _)| stack)
+    }
+
+    /// Drives drag and drop from the mouse, for an inventory drawn with `layout`.
+    ///
+    /// Pressing the left button on a slot picks up its stack and the right button picks up
+    /// half of it. Releasing over a slot drops it there, merging or swapping as
+    /// `move_slot` does; releasing anywhere else puts it back. If the game has filled the
+    /// inventory in the meantime and part of the stack has nowhere to go, that part stays
+    /// held. Returns `true` if the contents changed this frame.
+    pub fn handle_mouse<G: ConsoleGame>(
+        &mut self,
+        engine: &ConsoleGameEngine<G>,
+        layout: &InventoryLayout,
+    ) -> bool {
+        let (mx, my) = engine.mouse_pos();
+        let hovered = layout.slot_at(self.width, self.height, mx, my);
+
+        if self.held.is_none() {
+            let Some(index) = hovered else {
+                return false;
+            };
+            let half = engine.mouse_pressed(mouse_button::RIGHT);
+            if !half && !engine.mouse_pressed(mouse_button::LEFT) {
+                return false;
+            }
+            let Some(stack) = self.slots[index].as_mut() else {
+                return false;
+            };
+            let count = if half {
+                stack.count.div_ceil(2)
+            } else {
+                stack.count
+            };
+            stack.count -= count;
+            let picked = ItemStack {
+                item: stack.item.clone(),
+                count,
+            };
+            if stack.count == 0 {
+                self.slots[index] = None;
+            }
+            self.held = Some((picked, index));
+            return false;
+        }
+
+        if engine.mouse_held(mouse_button::LEFT) || engine.mouse_held(mouse_button::RIGHT) {
+            return false;
+        }
+        let Some((stack, origin)) = self.held.take() else {
+            return false;
+        };
+        match hovered {
+            Some(index) if index != origin => {
+                if let Some(rest) = self.place(stack, index) {
+                    self.held = self.put_back(rest, origin).map(|rest| (rest, origin));
+                }
+                true
+            }
+            _ => {
+                self.held = self.put_back(stack, origin).map(|rest| (rest, origin));
+                false
+            }
+        }
+    }
+
+    /// Drops any stack held by the mouse back where it came from. Returns whatever doesn't
+    /// fit back into the inventory.
+    pub fn cancel_drag(&mut self) -> Option<ItemStack<T>> {
+        let (stack, origin) = self.held.take()?;
+        self.put_back(stack, origin)
+    }
+
+    /// Puts `stack` into slot `index`, returning what has to go somewhere else: either the
+    /// part that didn't fit on a matching stack, or the different stack that was there.
+    fn place(&mut self, stack: ItemStack<T>, index: usize) -> Option<ItemStack<T>> {
+        match self.slots[index].as_mut() {
+            Some(target) if target.item == stack.item => {
+                let max = stack.item.max_stack().max(1);
+                let moved = stack.count.min(max.saturating_sub(target.count));
+                target.count += moved;
+                let rest = stack.count - moved;
+                (rest > 0).then_some(ItemStack {
+                    item: stack.item,
+                    count: rest,
+                })
+            }
+            _ => self.slots[index].replace(stack),
+        }
+    }
+
+    /// Returns a stack to `origin`, falling back to `add` if that slot has been filled.
+    /// Returns whatever still doesn't fit.
+    fn put_back(&mut self, stack: ItemStack<T>, origin: usize) -> Option<ItemStack<T>> {
+        let rest = self.place(stack, origin)?;
+        let count = self.add(rest.item.clone(), rest.count);
+        (count > 0).then_some(ItemStack {
+            item: rest.item,
+            count,
+        })
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws `inventory` as a grid of bordered slots, each showing its item's icon and, for
+    /// stacks of more than one, the count in the bottom right corner. A stack being dragged
+    /// is drawn under the mouse.
+    pub fn draw_inventory<T: InventoryItem>(
+        &mut self,
+        inventory: &Inventory<T>,
+        layout: &InventoryLayout,
+    ) {
+        let (mx, my) = self.mouse_pos();
+        let hovered = layout.slot_at(inventory.width, inventory.height, mx, my);
+        let (w, h) = (layout.slot_width, layout.slot_height);
+
+        for (i, slot) in inventory.slots().enumerate() {
+            let x = layout.x + (i % inventory.width.max(1)) as i32 * w;
+            let y = layout.y + (i / inventory.width.max(1)) as i32 * h;
+            let style = PanelStyle {
+                border_color: if hovered == Some(i) {
+                    layout.hover_color
+                } else {
+                    layout.border_color
+                },
+                ..Default::default()
+            };
+            self.draw_panel(x, y, w, h, &style);
+            if let Some(stack) = slot {
+                self.draw_item_stack(stack, x, y, layout);
+            }
+        }
+
+        if let Some(stack) = inventory.held() {
+            self.draw_item_stack(stack, mx - w / 2, my - h / 2, layout);
+        }
+    }
+
+    fn draw_item_stack<T: InventoryItem>(
+        &mut self,
+        stack: &ItemStack<T>,
+        x: i32,
+        y: i32,
+        layout: &InventoryLayout,
+    ) {
+        let (w, h) = (layout.slot_width, layout.slot_height);
+        if let Some(icon) = stack.item.icon() {
+            let ix = x + (w - icon.width as i32) / 2;
+            let iy = y + (h - icon.height as i32) / 2;
+            self.draw_sprite(ix, iy, icon);
+        }
+        if stack.count > 1 {
+            let text = stack.count.to_string();
+            let tx = x + w - 1 - text.len() as i32;
+            self.draw_string_with(tx, y + h - 1, &text, layout.count_color);
+        }
+    }
+}
+
+// endregion
+
+// region: Turn Scheduler
+
+/// Energy an actor spends on an ordinary turn, and needs before it can act.
+pub const TURN_ENERGY: i32 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ScheduledActor<T> {
+    id: T,
+    speed: i32,
+    energy: i32,
+}
+
+/// Decides who acts next in a roguelike, using energy.
+///
+/// Every tick each actor gains energy equal to its speed, and any actor with at least
+/// [`TURN_ENERGY`] gets a turn. An actor with speed 200 acts twice as often as one with
+/// speed 100. Actions that should take longer or shorter than a normal turn can `spend`
+/// more or less energy.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut turns = TurnScheduler::new();
+/// turns.add(PLAYER, 100);
+/// turns.add(BAT, 150);
+///
+/// while let Some(actor) = turns.next_turn() {
+///     if actor == PLAYER {
+///         break; // wait for input
+///     }
+///     self.act(actor);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnScheduler<T> {
+    actors: Vec<ScheduledActor<T>>,
+    ticks: u64,
+}
+
+impl<T> Default for TurnScheduler<T> {
+    fn default() -> Self {
+        Self {
+            actors: Vec::new(),
+            ticks: 0,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> TurnScheduler<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an actor with the given speed. It starts with no energy, so it acts after those
+    /// already waiting.
+    pub fn add(&mut self, id: T, speed: i32) {
+        self.actors.push(ScheduledActor {
+            id,
+            speed,
+            energy: 0,
+        });
+    }
+
+    /// Removes an actor, for example when it dies. Returns `false` if it wasn't scheduled.
+    pub fn remove(&mut self, id: &T) -> bool {
+        let len = self.actors.len();
+        self.actors.retain(|a| a.id != *id);
+        self.actors.len() != len
+    }
+
+    pub fn contains(&self, id: &T) -> bool {
+        self.actors.iter().any(|a| a.id == *id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+
+    /// Returns the speed of an actor.
+    pub fn speed(&self, id: &T) -> Option<i32> {
+        self.actors.iter().find(|a| a.id == *id).map(|a| a.speed)
+    }
+
+    /// Changes the speed of an actor, for example when it is hasted or slowed.
+    pub fn set_speed(&mut self, id: &T, speed: i32) {
+        if let Some(actor) = self.actors.iter_mut().find(|a| a.id == *id) {
+            actor.speed = speed;
+        }
+    }
+
+    /// Takes `energy` from an actor on top of the normal turn cost. Negative amounts give
+    /// energy back, so a quick action can cost less than a full turn.
+    pub fn spend(&mut self, id: &T, energy: i32) {
+        if let Some(actor) = self.actors.iter_mut().find(|a| a.id == *id) {
+            actor.energy -= energy;
+        }
+    }
+
+    /// Returns the number of ticks that have passed.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the actor whose turn it is and charges it [`TURN_ENERGY`], advancing time
+    /// until someone has enough energy. The actor with the most energy goes first, with
+    /// ties going to whoever was added first.
+    ///
+    /// Returns `None` if no actor can ever act, because there are none or none has a
+    /// positive speed.
+    pub fn next_turn(&mut self) -> Option<T> {
+        if !self
+            .actors
+            .iter()
+            .any(|a| a.speed > 0 || a.energy >= TURN_ENERGY)
+        {
+            return None;
+        }
+
+        loop {
+            let ready = self
+                .actors
+                .iter_mut()
+                .filter(|a| a.energy >= TURN_ENERGY)
+                .reduce(|best, a| if a.energy > best.energy { a } else { best });
+            if let Some(actor) = ready {
+                actor.energy -= TURN_ENERGY;
+                return Some(actor.id.clone());
+            }
+
+            // Skip straight to the tick where the next actor is ready.
+            let wait = self
+                .actors
+                .iter()
+                .filter(|a| a.speed > 0)
+                .map(|a| (TURN_ENERGY - a.energy + a.speed - 1) / a.speed)
+                .min()?
+                .max(1);
+            for actor in &mut self.actors {
+                actor.energy += actor.speed.max(0) * wait;
+            }
+            self.ticks += wait as u64;
+        }
+    }
+}
+
+// endregion
+
+// region: Turn Manager
+
+#[derive(Debug, Clone, PartialEq)]
+struct TurnUnit<T> {
+    id: T,
+    initiative: i32,
+    max_points: i32,
+    points: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TurnRecord<A> {
+    cost: i32,
+    action: A,
+}
+
+/// Runs rounds of a tactics or board game: every unit takes a turn in initiative order,
+/// spending action points, and actions taken this turn can be undone.
+///
+/// `T` identifies units and `A` is whatever the game needs to undo an action, like the
+/// square a unit moved from. A unit's action points refill at the start of its turn.
+/// Ending the turn commits its actions, so only the current turn can be undone.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut turns = TurnManager::new();
+/// turns.add(KNIGHT, 12, 4);
+/// turns.add(GOBLIN, 8, 3);
+///
+/// // When the player moves the current unit:
+/// let range = move_range(&self.map, unit.x, unit.y, turns.points() as u32, |x, y| self.occupied(x, y));
+/// if let Some(cost) = range.cost(target_x, target_y) {
+///     if turns.perform(cost as i32, (unit.x, unit.y)) {
+///         unit.move_to(target_x, target_y);
+///     }
+/// }
+/// if engine.key_pressed(key::Z) {
+///     if let Some((x, y)) = turns.undo() {
+///         unit.move_to(x, y);
+///     }
+/// }
+/// if engine.key_pressed(key::ENTER) {
+///     turns.end_turn();
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnManager<T, A = ()> {
+    units: Vec<TurnUnit<T>>,
+    current: usize,
+    round: u32,
+    history: Vec<TurnRecord<A>>,
+    started: bool,
+}
+
+impl<T, A> Default for TurnManager<T, A> {
+    fn default() -> Self {
+        Self {
+            units: Vec::new(),
+            current: 0,
+            round: 1,
+            history: Vec::new(),
+            started: false,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq, A> TurnManager<T, A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a unit with `action_points` to spend every turn. Higher initiative goes first,
+    /// with ties going to whoever was added first. Units added once the first turn is
+    /// under way join in their place in the order without interrupting it.
+    pub fn add(&mut self, id: T, initiative: i32, action_points: i32) {
+        let at = self.units.partition_point(|u| u.initiative >= initiative);
+        self.units.insert(
+            at,
+            TurnUnit {
+                id,
+                initiative,
+                max_points: action_points,
+                points: action_points,
+            },
+        );
+        if self.started && at <= self.current && self.units.len() > 1 {
+            self.current += 1;
+        }
+    }
+
+    /// Removes a unit, for example when it dies. If it was its turn, the next unit's turn
+    /// starts. Returns `false` if it wasn't in the order.
+    pub fn remove(&mut self, id: &T) -> bool {
+        let Some(i) = self.units.iter().position(|u| u.id == *id) else {
+            return false;
+        };
+        self.units.remove(i);
+        if i < self.current {
+            self.current -= 1;
+        } else if i == self.current {
+            self.history.clear();
+            if self.current >= self.units.len() {
+                self.current = 0;
+                self.round += 1;
+            }
+            if let Some(unit) = self.units.get_mut(self.current) {
+                unit.points = unit.max_points;
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, id: &T) -> bool {
+        self.units.iter().any(|u| u.id == *id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Returns the unit whose turn it is.
+    pub fn current(&self) -> Option<&T> {
+        self.units.get(self.current).map(|u| &u.id)
+    }
+
+    /// Returns the round number, starting at 1.
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    /// Returns every unit in initiative order.
+    pub fn order(&self) -> impl Iterator<Item = &T> {
+        self.units.iter().map(|u| &u.id)
+    }
+
+    /// Returns the action points the current unit has left.
+    pub fn points(&self) -> i32 {
+        self.units.get(self.current).map_or(0, |u| u.points)
+    }
+
+    /// Returns the action points a unit has left.
+    pub fn points_of(&self, id: &T) -> Option<i32> {
+        self.units.iter().find(|u| u.id == *id).map(|u| u.points)
+    }
+
+    /// Changes how many action points a unit gets every turn, e.g. when it is slowed.
+    pub fn set_action_points(&mut self, id: &T, action_points: i32) {
+        if let Some(unit) = self.units.iter_mut().find(|u| u.id == *id) {
+            unit.max_points = action_points;
+        }
+    }
+
+    /// Returns `true` if the current unit has at least `cost` action points left.
+    pub fn can_afford(&self, cost: i32) -> bool {
+        self.units
+            .get(self.current)
+            .is_some_and(|u| u.points >= cost)
+    }
+
+    /// Spends `cost` of the current unit's action points on an action, remembering `action`
+    /// so it can be undone. Returns `false` and does nothing if it can't afford it.
+    pub fn perform(&mut self, cost: i32, action: A) -> bool {
+        let Some(unit) = self.units.get_mut(self.current) else {
+            return false;
+        };
+        if unit.points < cost {
+            return false;
+        }
+        unit.points -= cost;
+        self.started = true;
+        self.history.push(TurnRecord { cost, action });
+        true
+    }
+
+    /// Takes back the last action of this turn, refunding its action points, and returns
+    /// it so the game can revert it.
+    pub fn undo(&mut self) -> Option<A> {
+        let record = self.history.pop()?;
+        if let Some(unit) = self.units.get_mut(self.current) {
+            unit.points += record.cost;
+        }
+        Some(record.action)
+    }
+
+    /// Returns `true` if there is an action this turn to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    /// Returns the actions taken this turn, oldest first.
+    pub fn actions(&self) -> impl Iterator<Item = &A> {
+        self.history.iter().map(|r| &r.action)
+    }
+
+    /// Ends the current unit's turn, committing its actions, and returns the unit whose
+    /// turn it is now. A new round starts after the last unit.
+    pub fn end_turn(&mut self) -> Option<&T> {
+        if self.units.is_empty() {
+            return None;
+        }
+        self.started = true;
+        self.history.clear();
+        self.current += 1;
+        if self.current >= self.units.len() {
+            self.current = 0;
+            self.round += 1;
+        }
+        let unit = &mut self.units[self.current];
+        unit.points = unit.max_points;
+        Some(&unit.id)
+    }
+}
+
+/// The cells a unit can reach, found by `move_range`, with the cost of getting to each.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GridRange {
+    origin: (i32, i32),
+    cells: HashMap<(i32, i32), (u32, (i32, i32))>,
+}
+
+impl GridRange {
+    /// Returns `true` if `(x, y)` can be reached.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.cells.contains_key(&(x, y))
+    }
+
+    /// Returns the number of steps needed to reach `(x, y)`.
+    pub fn cost(&self, x: i32, y: i32) -> Option<u32> {
+        self.cells.get(&(x, y)).map(|&(cost, _)| cost)
+    }
+
+    /// Returns the shortest route to `(x, y)`, from the first step to the target. Empty if
+    /// it can't be reached or is the starting cell.
+    pub fn path_to(&self, x: i32, y: i32) -> Vec<(i32, i32)> {
+        let mut path = Vec::new();
+        let mut at = (x, y);
+        while at != self.origin {
+            let Some(&(_, from)) = self.cells.get(&at) else {
+                return Vec::new();
+            };
+            path.push(at);
+            at = from;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Iterates over every reachable cell, including the starting one.
+    pub fn cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.cells.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
+
+/// Finds every cell within `range` orthogonal steps of `(x, y)` on `map`, walking around
+/// solid tiles and any cell for which `blocked` returns `true`, such as ones with other
+/// units in them.
+pub fn move_range(
+    map: &TileMap,
+    x: i32,
+    y: i32,
+    range: u32,
+    mut blocked: impl FnMut(i32, i32) -> bool,
+) -> GridRange {
+    let mut cells = HashMap::new();
+    cells.insert((x, y), (0, (x, y)));
+    let mut queue = VecDeque::from([(x, y)]);
+    while let Some((cx, cy)) = queue.pop_front() {
+        let cost = cells[&(cx, cy)].0;
+        if cost >= range {
+            continue;
+        }
+        for (nx, ny) in [(cx + 1, cy), (cx - 1, cy), (cx, cy + 1), (cx, cy - 1)] {
+            if cells.contains_key(&(nx, ny)) || map.is_solid(nx, ny) || blocked(nx, ny) {
+                continue;
+            }
+            cells.insert((nx, ny), (cost + 1, (cx, cy)));
+            queue.push_back((nx, ny));
+        }
+    }
+    GridRange {
+        origin: (x, y),
+        cells,
+    }
+}
+
+/// Returns every cell between `min` and `max` orthogonal steps from `(x, y)`, ignoring
+/// walls, e.g. the squares a spear (`1..=2`) or a bow (`2..=6`) can hit.
+pub fn attack_range(x: i32, y: i32, min: u32, max: u32) -> Vec<(i32, i32)> {
+    let max = max as i32;
+    let mut cells = Vec::new();
+    for dy in -max..=max {
+        for dx in -max..=max {
+            let dist = dx.unsigned_abs() + dy.unsigned_abs();
+            if dist >= min && dist <= max as u32 {
+                cells.push((x + dx, y + dy));
+            }
+        }
+    }
+    cells
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Changes the background of every cell in `cells` to `bg`, keeping what is drawn in
+    /// them, e.g. to show a unit's move or attack range. `(x, y)` is where the grid's
+    /// origin is on screen.
+    pub fn highlight_cells(
+        &mut self,
+        x: i32,
+        y: i32,
+        cells: impl IntoIterator<Item = (i32, i32)>,
+        bg: u16,
+    ) {
+        let (w, h) = (self.screen_width(), self.screen_height());
+        for (cx, cy) in cells {
+            let (sx, sy) = (x + cx, y + cy);
+            if sx >= 0 && sy >= 0 && sx < w && sy < h {
+                let cell = &mut self.window_buffer[(sy * w + sx) as usize];
+                cell.Attributes = (cell.Attributes & !0x00F0) | (bg & 0x00F0);
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Board
+
+/// Chess and other 8×8 board games: FEN parsing, pluggable move rules and drawing.
+///
+/// [`StandardRules`](board::StandardRules) implements the moves of standard chess. Variants
+/// implement [`MoveRules`](board::MoveRules) themselves, usually by calling into
+/// `StandardRules` and adding or removing moves.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use rusty_console_game_engine::board::*;
+///
+/// let mut board = Board::from_fen(START_FEN)?;
+/// let moves = StandardRules.legal_moves(&board);
+/// board.make_move(Move::parse("e2e4")?);
+///
+/// engine.draw_board(2, 1, &board, &BoardStyle::default());
+/// ```
+pub mod board {
+    use crate::color::*;
+    use crate::pixel::EMPTY;
+    use crate::{ConsoleGame, ConsoleGameEngine, Sprite};
+
+    /// The FEN of the standard chess starting position.
+    pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Side {
+        White,
+        Black,
+    }
+
+    impl Side {
+        pub fn opponent(self) -> Side {
+            match self {
+                Side::White => Side::Black,
+                Side::Black => Side::White,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum PieceKind {
+        King,
+        Queen,
+        Rook,
+        Bishop,
+        Knight,
+        Pawn,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Piece {
+        pub kind: PieceKind,
+        pub side: Side,
+    }
+
+    impl Piece {
+        pub fn new(kind: PieceKind, side: Side) -> Self {
+            Self { kind, side }
+        }
+
+        /// Returns the piece for a FEN letter, uppercase for white.
+        pub fn from_fen_char(ch: char) -> Option<Self> {
+            let kind = match ch.to_ascii_lowercase() {
+                'k' => PieceKind::King,
+                'q' => PieceKind::Queen,
+                'r' => PieceKind::Rook,
+                'b' => PieceKind::Bishop,
+                'n' => PieceKind::Knight,
+                'p' => PieceKind::Pawn,
+                _ => return None,
+            };
+            let side = if ch.is_ascii_uppercase() {
+                Side::White
+            } else {
+                Side::Black
+            };
+            Some(Self { kind, side })
+        }
+
+        /// Returns the FEN letter of the piece, uppercase for white.
+        pub fn fen_char(self) -> char {
+            let ch = match self.kind {
+                PieceKind::King => 'k',
+                PieceKind::Queen => 'q',
+                PieceKind::Rook => 'r',
+                PieceKind::Bishop => 'b',
+                PieceKind::Knight => 'n',
+                PieceKind::Pawn => 'p',
+            };
+            match self.side {
+                Side::White => ch.to_ascii_uppercase(),
+                Side::Black => ch,
+            }
+        }
+
+        /// Returns the Unicode chess symbol of the piece, e.g. `♔` for the white king.
+        pub fn unicode(self) -> u16 {
+            let base = match self.side {
+                Side::White => 0x2654,
+                Side::Black => 0x265A,
+            };
+            base + self.index() as u16 % 6
+        }
+
+        /// Returns a number from 0 to 11 for the piece: white king, queen, rook, bishop,
+        /// knight and pawn, then the same for black. Used to index piece sprites.
+        pub fn index(self) -> usize {
+            let kind = match self.kind {
+                PieceKind::King => 0,
+                PieceKind::Queen => 1,
+                PieceKind::Rook => 2,
+                PieceKind::Bishop => 3,
+                PieceKind::Knight => 4,
+                PieceKind::Pawn => 5,
+            };
+            match self.side {
+                Side::White => kind,
+                Side::Black => kind + 6,
+            }
+        }
+    }
+
+    /// A square of the board. Files and ranks count from 0, so `a1` is `(0, 0)` and `h8`
+    /// is `(7, 7)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Square {
+        file: u8,
+        rank: u8,
+    }
+
+    impl Square {
+        /// Returns the square at `file` and `rank`, or `None` if either is 8 or more.
+        pub fn new(file: u8, rank: u8) -> Option<Self> {
+            (file < 8 && rank < 8).then(|| Self::at(file, rank))
+        }
+
+        /// Builds a square the caller already knows is on the board.
+        fn at(file: u8, rank: u8) -> Self {
+            debug_assert!(file < 8 && rank < 8);
+            Self { file, rank }
+        }
+
+        /// The file, from 0 for the a-file to 7 for the h-file.
+        pub fn file(self) -> u8 {
+            self.file
+        }
+
+        /// The rank, from 0 for the first rank to 7 for the eighth.
+        pub fn rank(self) -> u8 {
+            self.rank
+        }
+
+        /// Parses a square name like `e4`.
+        pub fn parse(name: &str) -> Option<Self> {
+            let mut chars = name.chars();
+            let file = chars.next()?;
+            let rank = chars.next()?;
+            if chars.next().is_some()
+                || !('a'..='h').contains(&file)
+                || !('1'..='8').contains(&rank)
+            {
+                return None;
+            }
+            Some(Self::at(file as u8 - b'a', rank as u8 - b'1'))
+        }
+
+        /// Returns the square's name, like `e4`.
+        pub fn name(self) -> String {
+            format!("{}{}", (b'a' + self.file) as char, self.rank + 1)
+        }
+
+        /// Returns the square `(df, dr)` files and ranks away, if it is on the board.
+        pub fn offset(self, df: i32, dr: i32) -> Option<Self> {
+            let (file, rank) = (self.file as i32 + df, self.rank as i32 + dr);
+            ((0..8).contains(&file) && (0..8).contains(&rank))
+                .then(|| Self::at(file as u8, rank as u8))
+        }
+
+        fn index(self) -> usize {
+            self.rank as usize * 8 + self.file as usize
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Move {
+        pub from: Square,
+        pub to: Square,
+        /// What a pawn reaching the last rank becomes.
+        pub promotion: Option<PieceKind>,
+    }
+
+    impl Move {
+        pub fn new(from: Square, to: Square) -> Self {
+            Self {
+                from,
+                to,
+                promotion: None,
+            }
+        }
+
+        /// Parses a move in UCI notation, like `e2e4` or `e7e8q`.
+        pub fn parse(uci: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let bad = || format!("invalid move {:?}", uci);
+            let from = uci.get(0..2).and_then(Square::parse).ok_or_else(bad)?;
+            let to = uci.get(2..4).and_then(Square::parse).ok_or_else(bad)?;
+            let promotion = match uci.get(4..) {
+                None | Some("") => None,
+                Some(p) => {
+                    let piece = p
+                        .chars()
+                        .next()
+                        .filter(|_| p.len() == 1)
+                        .and_then(Piece::from_fen_char)
+                        .ok_or_else(bad)?;
+                    Some(piece.kind)
+                }
+            };
+            Ok(Self {
+                from,
+                to,
+                promotion,
+            })
+        }
+
+        /// Returns the move in UCI notation, like `e2e4` or `e7e8q`.
+        pub fn uci(self) -> String {
+            let mut uci = format!("{}{}", self.from.name(), self.to.name());
+            if let Some(kind) = self.promotion {
+                uci.push(Piece::new(kind, Side::Black).fen_char());
+            }
+            uci
+        }
+    }
+
+    /// Which castling moves are still allowed.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct CastlingRights {
+        pub white_king_side: bool,
+        pub white_queen_side: bool,
+        pub black_king_side: bool,
+        pub black_queen_side: bool,
+    }
+
+    /// A position: the pieces on the squares plus whose move it is and the other state a
+    /// FEN records.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct Board {
+        squares: [Option<Piece>; 64],
+        pub side_to_move: Side,
+        pub castling: CastlingRights,
+        /// The square a pawn can be captured on en passant, if the last move allows it.
+        pub en_passant: Option<Square>,
+        /// Moves since the last capture or pawn move, for the fifty-move rule.
+        pub halfmove_clock: u32,
+        /// Starts at 1 and goes up after every black move.
+        pub fullmove_number: u32,
+    }
+
+    impl Default for Board {
+        fn default() -> Self {
+            Self {
+                squares: [None; 64],
+                side_to_move: Side::White,
+                castling: CastlingRights::default(),
+                en_passant: None,
+                halfmove_clock: 0,
+                fullmove_number: 1,
+            }
+        }
+    }
+
+    impl Board {
+        /// Creates an empty board with white to move.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Parses a position in Forsyth-Edwards Notation. Only the piece placement is
+        /// required; missing fields default to white to move, no castling, no en passant
+        /// and move 1.
+        pub fn from_fen(fen: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut fields = fen.split_whitespace();
+            let mut board = Self::new();
+
+            let placement = fields.next().ok_or("empty FEN")?;
+            let ranks: Vec<&str> = placement.split('/').collect();
+            if ranks.len() != 8 {
+                return Err(format!("expected 8 ranks, found {}", ranks.len()).into());
+            }
+            for (i, rank_text) in ranks.iter().enumerate() {
+                let rank = 7 - i as u8;
+                let mut file = 0u8;
+                for ch in rank_text.chars() {
+                    if let Some(skip) = ch.to_digit(10) {
+                        file += skip as u8;
+                    } else {
+                        let piece = Piece::from_fen_char(ch)
+                            .ok_or_else(|| format!("unknown piece {:?}", ch))?;
+                        if file < 8 {
+                            board.set(Square::at(file, rank), Some(piece));
+                        }
+                        file += 1;
+                    }
+                    if file > 8 {
+                        return Err(format!("rank {} is longer than 8 squares", rank + 1).into());
+                    }
+                }
+                if file != 8 {
+                    return Err(format!("rank {} is shorter than 8 squares", rank + 1).into());
+                }
+            }
+
+            board.side_to_move = match fields.next() {
+                None | Some("w") => Side::White,
+                Some("b") => Side::Black,
+                Some(other) => return Err(format!("invalid side to move {:?}", other).into()),
+            };
+
+            if let Some(castling) = fields.next().filter(|&c| c != "-") {
+                for ch in castling.chars() {
+                    match ch {
+                        'K' => board.castling.white_king_side = true,
+                        'Q' => board.castling.white_queen_side = true,
+                        'k' => board.castling.black_king_side = true,
+                        'q' => board.castling.black_queen_side = true,
+                        _ => return Err(format!("invalid castling rights {:?}", castling).into()),
+                    }
+                }
+            }
+
+            if let Some(ep) = fields.next().filter(|&e| e != "-") {
+                board.en_passant =
+                    Some(Square::parse(ep).ok_or_else(|| format!("invalid square {:?}", ep))?);
+            }
+            if let Some(clock) = fields.next() {
+                board.halfmove_clock = clock
+                    .parse()
+                    .map_err(|_| format!("invalid halfmove clock {:?}", clock))?;
+            }
+            if let Some(number) = fields.next() {
+                board.fullmove_number = number
+                    .parse()
+                    .map_err(|_| format!("invalid move number {:?}", number))?;
+            }
+
+            Ok(board)
+        }
+
+        /// Returns the position in Forsyth-Edwards Notation.
+        pub fn to_fen(&self) -> String {
+            let mut fen = String::new();
+            for rank in (0..8).rev() {
+                let mut empty = 0;
+                for file in 0..8 {
+                    match self.get(Square::at(file, rank)) {
+                        Some(piece) => {
+                            if empty > 0 {
+                                fen.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            fen.push(piece.fen_char());
+                        }
+                        None => empty += 1,
+                    }
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                }
+                if rank > 0 {
+                    fen.push('/');
+                }
+            }
+
+            fen.push_str(match self.side_to_move {
+                Side::White => " w ",
+                Side::Black => " b ",
+            });
+            let c = self.castling;
+            let rights: String = [
+                (c.white_king_side, 'K'),
+                (c.white_queen_side, 'Q'),
+                (c.black_king_side, 'k'),
+                (c.black_queen_side, 'q'),
+            ]
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .map(|&(_, ch)| ch)
+            .collect();
+            fen.push_str(if rights.is_empty() { "-" } else { &rights });
+            fen.push(' ');
+            match self.en_passant {
+                Some(sq) => fen.push_str(&sq.name()),
+                None => fen.push('-'),
+            }
+            fen.push_str(&format!(
+                " {} {}",
+                self.halfmove_clock, self.fullmove_number
+            ));
+            fen
+        }
+
+        /// Returns the piece on `sq`.
+        pub fn get(&self, sq: Square) -> Option<Piece> {
+            self.squares[sq.index()]
+        }
+
+        /// Puts `piece` on `sq`, or empties it with `None`.
+        pub fn set(&mut self, sq: Square, piece: Option<Piece>) {
+            self.squares[sq.index()] = piece;
+        }
+
+        /// Iterates over every piece and the square it is on.
+        pub fn pieces(&self) -> impl Iterator<Item = (Square, Piece)> + '_ {
+            self.squares
+                .iter()
+                .enumerate()
+                .filter_map(|(i, piece)| piece.map(|p| (Square::at(i as u8 % 8, i as u8 / 8), p)))
+        }
+
+        /// Returns the square of `side`'s king, if it has one.
+        pub fn king(&self, side: Side) -> Option<Square> {
+            self.pieces()
+                .find(|(_, p)| p.kind == PieceKind::King && p.side == side)
+                .map(|(sq, _)| sq)
+        }
+
+        /// Plays `mv` without checking it is legal, handling captures, castling, en passant
+        /// and promotion, and hands the move to the other side. Returns the captured piece.
+        pub fn make_move(&mut self, mv: Move) -> Option<Piece> {
+            let piece = self.get(mv.from)?;
+            let mut captured = self.get(mv.to);
+            self.set(mv.from, None);
+
+            if piece.kind == PieceKind::Pawn && Some(mv.to) == self.en_passant && captured.is_none()
+            {
+                let taken = Square::at(mv.to.file, mv.from.rank);
+                captured = self.get(taken);
+                self.set(taken, None);
+            }
+            if piece.kind == PieceKind::King && mv.from.file.abs_diff(mv.to.file) == 2 {
+                let (rook_from, rook_to) = if mv.to.file > mv.from.file {
+                    (7, 5)
+                } else {
+                    (0, 3)
+                };
+                let rook = self.get(Square::at(rook_from, mv.from.rank));
+                self.set(Square::at(rook_from, mv.from.rank), None);
+                self.set(Square::at(rook_to, mv.from.rank), rook);
+            }
+
+            let placed = match mv.promotion {
+                Some(kind) if piece.kind == PieceKind::Pawn => Piece::new(kind, piece.side),
+                _ => piece,
+            };
+            self.set(mv.to, Some(placed));
+
+            self.en_passant = (piece.kind == PieceKind::Pawn
+                && mv.from.rank.abs_diff(mv.to.rank) == 2)
+                .then(|| Square::at(mv.from.file, (mv.from.rank + mv.to.rank) / 2));
+
+            // Moving a king or rook, or capturing a rook, loses the castling rights.
+            for sq in [mv.from, mv.to] {
+                match (sq.file, sq.rank) {
+                    (4, 0) => {
+                        self.castling.white_king_side = false;
+                        self.castling.white_queen_side = false;
+                    }
+                    (4, 7) => {
+                        self.castling.black_king_side = false;
+                        self.castling.black_queen_side = false;
+                    }
+                    (7, 0) => self.castling.white_king_side = false,
+                    (0, 0) => self.castling.white_queen_side = false,
+                    (7, 7) => self.castling.black_king_side = false,
+                    (0, 7) => self.castling.black_queen_side = false,
+                    _ => {}
+                }
+            }
+
+            if piece.kind == PieceKind::Pawn || captured.is_some() {
+                self.halfmove_clock = 0;
+            } else {
+                self.halfmove_clock += 1;
+            }
+            if self.side_to_move == Side::Black {
+                self.fullmove_number += 1;
+            }
+            self.side_to_move = self.side_to_move.opponent();
+            captured
+        }
+    }
+
+    /// The move rules of a game played on a [`Board`]. Implement this for chess variants.
+    ///
+    /// Only `moves_from` is required. The provided methods build legal move lists, check
+    /// and mate detection on top of it, treating a move that leaves the mover's king
+    /// attacked as illegal. Games without kings get every move `moves_from` returns.
+    pub trait MoveRules {
+        /// Adds the moves of the piece on `from` to `moves`, without checking whether they
+        /// leave its own king in check.
+        fn moves_from(&self, board: &Board, from: Square, moves: &mut Vec<Move>);
+
+        /// Returns `true` if a piece of `by` could capture on `sq`.
+        fn is_attacked(&self, board: &Board, sq: Square, by: Side) -> bool {
+            let mut moves = Vec::new();
+            for (from, _) in board.pieces().filter(|(_, p)| p.side == by) {
+                self.moves_from(board, from, &mut moves);
+            }
+            moves.iter().any(|m| m.to == sq)
+        }
+
+        /// Returns every legal move for the side to move.
+        fn legal_moves(&self, board: &Board) -> Vec<Move> {
+            let side = board.side_to_move;
+            let mut moves = Vec::new();
+            for (from, _) in board.pieces().filter(|(_, p)| p.side == side) {
+                self.moves_from(board, from, &mut moves);
+            }
+            moves.retain(|&mv| {
+                let mut after = board.clone();
+                after.make_move(mv);
+                after
+                    .king(side)
+                    .is_none_or(|king| !self.is_attacked(&after, king, side.opponent()))
+            });
+            moves
+        }
+
+        /// Returns the legal moves of the piece on `from`, e.g. to highlight where a picked
+        /// up piece can go.
+        fn legal_moves_from(&self, board: &Board, from: Square) -> Vec<Move> {
+            let mut moves = self.legal_moves(board);
+            moves.retain(|m| m.from == from);
+            moves
+        }
+
+        /// Returns `true` if the side to move is in check.
+        fn in_check(&self, board: &Board) -> bool {
+            let side = board.side_to_move;
+            board
+                .king(side)
+                .is_some_and(|king| self.is_attacked(board, king, side.opponent()))
+        }
+
+        /// Returns `true` if the side to move is in check and has no legal moves.
+        fn is_checkmate(&self, board: &Board) -> bool {
+            self.in_check(board) && self.legal_moves(board).is_empty()
+        }
+
+        /// Returns `true` if the side to move is not in check but has no legal moves.
+        fn is_stalemate(&self, board: &Board) -> bool {
+            !self.in_check(board) && self.legal_moves(board).is_empty()
+        }
+    }
+
+    const KNIGHT_STEPS: [(i32, i32); 8] = [
+        (1, 2),
+        (2, 1),
+        (2, -1),
+        (1, -2),
+        (-1, -2),
+        (-2, -1),
+        (-2, 1),
+        (-1, 2),
+    ];
+    const KING_STEPS: [(i32, i32); 8] = [
+        (1, 0),
+        (1, 1),
+        (0, 1),
+        (-1, 1),
+        (-1, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+    ];
+    const ROOK_RAYS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+    const BISHOP_RAYS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+    /// The rules of standard chess.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct StandardRules;
+
+    impl StandardRules {
+        fn steps(
+            board: &Board,
+            from: Square,
+            side: Side,
+            steps: &[(i32, i32)],
+            moves: &mut Vec<Move>,
+        ) {
+            for &(df, dr) in steps {
+                if let Some(to) = from.offset(df, dr) {
+                    if board.get(to).is_none_or(|p| p.side != side) {
+                        moves.push(Move::new(from, to));
+                    }
+                }
+            }
+        }
+
+        fn rays(
+            board: &Board,
+            from: Square,
+            side: Side,
+            rays: &[(i32, i32)],
+            moves: &mut Vec<Move>,
+        ) {
+            for &(df, dr) in rays {
+                let mut at = from;
+                while let Some(to) = at.offset(df, dr) {
+                    match board.get(to) {
+                        None => moves.push(Move::new(from, to)),
+                        Some(p) => {
+                            if p.side != side {
+                                moves.push(Move::new(from, to));
+                            }
+                            break;
+                        }
+                    }
+                    at = to;
+                }
+            }
+        }
+
+        fn pawn(board: &Board, from: Square, side: Side, moves: &mut Vec<Move>) {
+            let (dir, start, last) = match side {
+                Side::White => (1, 1, 7),
+                Side::Black => (-1, 6, 0),
+            };
+            let mut push = |to: Square| {
+                if to.rank == last {
+                    for kind in [
+                        PieceKind::Queen,
+                        PieceKind::Rook,
+                        PieceKind::Bishop,
+                        PieceKind::Knight,
+                    ] {
+                        moves.push(Move {
+                            from,
+                            to,
+                            promotion: Some(kind),
+                        });
+                    }
+                } else {
+                    moves.push(Move::new(from, to));
+                }
+            };
+
+            if let Some(one) = from.offset(0, dir).filter(|&sq| board.get(sq).is_none()) {
+                push(one);
+                if from.rank == start {
+                    if let Some(two) = from
+                        .offset(0, dir * 2)
+                        .filter(|&sq| board.get(sq).is_none())
+                    {
+                        push(two);
+                    }
+                }
+            }
+            for df in [-1, 1] {
+                if let Some(to) = from.offset(df, dir) {
+                    let enemy = board.get(to).is_some_and(|p| p.side != side);
+                    if enemy || Some(to) == board.en_passant {
+                        push(to);
+                    }
+                }
+            }
+        }
+
+        fn castling(&self, board: &Board, from: Square, side: Side, moves: &mut Vec<Move>) {
+            let (rank, king_side, queen_side) = match side {
+                Side::White => (
+                    0,
+                    board.castling.white_king_side,
+                    board.castling.white_queen_side,
+                ),
+                Side::Black => (
+                    7,
+                    board.castling.black_king_side,
+                    board.castling.black_queen_side,
+                ),
+            };
+            if from != Square::at(4, rank) || self.is_attacked(board, from, side.opponent()) {
+                return;
+            }
+            let empty = |files: &[u8]| {
+                files
+                    .iter()
+                    .all(|&f| board.get(Square::at(f, rank)).is_none())
+            };
+            let safe = |files: &[u8]| {
+                files
+                    .iter()
+                    .all(|&f| !self.is_attacked(board, Square::at(f, rank), side.opponent()))
+            };
+            if king_side && empty(&[5, 6]) && safe(&[5, 6]) {
+                moves.push(Move::new(from, Square::at(6, rank)));
+            }
+            if queen_side && empty(&[1, 2, 3]) && safe(&[2, 3]) {
+                moves.push(Move::new(from, Square::at(2, rank)));
+            }
+        }
+    }
+
+    impl MoveRules for StandardRules {
+        fn moves_from(&self, board: &Board, from: Square, moves: &mut Vec<Move>) {
+            let Some(piece) = board.get(from) else {
+                return;
+            };
+            let side = piece.side;
+            match piece.kind {
+                PieceKind::King => {
+                    Self::steps(board, from, side, &KING_STEPS, moves);
+                    self.castling(board, from, side, moves);
+                }
+                PieceKind::Queen => {
+                    Self::rays(board, from, side, &ROOK_RAYS, moves);
+                    Self::rays(board, from, side, &BISHOP_RAYS, moves);
+                }
+                PieceKind::Rook => Self::rays(board, from, side, &ROOK_RAYS, moves),
+                PieceKind::Bishop => Self::rays(board, from, side, &BISHOP_RAYS, moves),
+                PieceKind::Knight => Self::steps(board, from, side, &KNIGHT_STEPS, moves),
+                PieceKind::Pawn => Self::pawn(board, from, side, moves),
+            }
+        }
+
+        fn is_attacked(&self, board: &Board, sq: Square, by: Side) -> bool {
+            let holds = |df: i32, dr: i32, kinds: &[PieceKind]| {
+                sq.offset(df, dr)
+                    .and_then(|at| board.get(at))
+                    .is_some_and(|p| p.side == by && kinds.contains(&p.kind))
+            };
+            let pawn_dir = match by {
+                Side::White => -1,
+                Side::Black => 1,
+            };
+            if holds(-1, pawn_dir, &[PieceKind::Pawn]) || holds(1, pawn_dir, &[PieceKind::Pawn]) {
+                return true;
+            }
+            if KNIGHT_STEPS
+                .iter()
+                .any(|&(df, dr)| holds(df, dr, &[PieceKind::Knight]))
+                || KING_STEPS
+                    .iter()
+                    .any(|&(df, dr)| holds(df, dr, &[PieceKind::King]))
+            {
+                return true;
+            }
+
+            let slides = |rays: &[(i32, i32)], kinds: &[PieceKind]| {
+                rays.iter().any(|&(df, dr)| {
+                    let mut at = sq;
+                    while let Some(next) = at.offset(df, dr) {
+                        if let Some(p) = board.get(next) {
+                            return p.side == by && kinds.contains(&p.kind);
+                        }
+                        at = next;
+                    }
+                    false
+                })
+            };
+            slides(&ROOK_RAYS, &[PieceKind::Rook, PieceKind::Queen])
+                || slides(&BISHOP_RAYS, &[PieceKind::Bishop, PieceKind::Queen])
+        }
+    }
+
+    /// How pieces are drawn by `draw_board`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PieceGlyphs<'a> {
+        /// FEN letters, like `K` and `p`, which every console font has.
+        Letters,
+        /// Chess symbols like `♔`, which need a font that has them.
+        Unicode,
+        /// One sprite per piece, indexed by [`Piece::index`], drawn at the top-left of
+        /// its square.
+        Sprites(&'a [Sprite; 12]),
+    }
+
+    /// How a board drawn with `draw_board` looks.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct BoardStyle<'a> {
+        /// Size of each square in cells. Defaults to 3 × 1, which looks roughly square in
+        /// most console fonts.
+        pub square_width: i32,
+        pub square_height: i32,
+        /// Background colors of the light and dark squares.
+        pub light: u16,
+        pub dark: u16,
+        /// Foreground colors of the white and black pieces.
+        pub white: u16,
+        pub black: u16,
+        pub pieces: PieceGlyphs<'a>,
+        /// Draws the board from black's side.
+        pub flipped: bool,
+        /// Draws file letters below the board and rank numbers to its left.
+        pub coordinates: bool,
+        /// Squares drawn with a different background color, e.g. the selected piece,
+        /// its moves or the last move played.
+        pub highlights: &'a [(Square, u16)],
+    }
+
+    impl Default for BoardStyle<'_> {
+        fn default() -> Self {
+            Self {
+                square_width: 3,
+                square_height: 1,
+                light: BG_GREY,
+                dark: BG_DARK_YELLOW,
+                white: FG_WHITE,
+                black: FG_BLACK,
+                pieces: PieceGlyphs::Letters,
+                flipped: false,
+                coordinates: true,
+                highlights: &[],
+            }
+        }
+    }
+
+    impl BoardStyle<'_> {
+        /// Returns the screen position of the top-left of `sq` on a board drawn at
+        /// `(x, y)`.
+        pub fn square_pos(&self, x: i32, y: i32, sq: Square) -> (i32, i32) {
+            let (col, row) = if self.flipped {
+                (7 - sq.file as i32, sq.rank as i32)
+            } else {
+                (sq.file as i32, 7 - sq.rank as i32)
+            };
+            let x = x + if self.coordinates { 2 } else { 0 };
+            (x + col * self.square_width, y + row * self.square_height)
+        }
+
+        /// Returns the square under the screen position `(sx, sy)` on a board drawn at
+        /// `(x, y)`, e.g. the one the mouse is over.
+        pub fn square_at(&self, x: i32, y: i32, sx: i32, sy: i32) -> Option<Square> {
+            let x = x + if self.coordinates { 2 } else { 0 };
+            let (w, h) = (self.square_width.max(1), self.square_height.max(1));
+            if sx < x || sy < y {
+                return None;
+            }
+            let (col, row) = ((sx - x) / w, (sy - y) / h);
+            if col > 7 || row > 7 {
+                return None;
+            }
+            let (file, rank) = if self.flipped {
+                (7 - col, row)
+            } else {
+                (col, 7 - row)
+            };
+            Some(Square::at(file as u8, rank as u8))
+        }
+    }
+
+    impl<G: ConsoleGame> ConsoleGameEngine<G> {
+        /// Draws `board` with its top-left corner at `(x, y)`.
+        pub fn draw_board(&mut self, x: i32, y: i32, board: &Board, style: &BoardStyle) {
+            let (w, h) = (style.square_width, style.square_height);
+            for rank in 0..8 {
+                for file in 0..8 {
+                    let sq = Square::at(file, rank);
+                    let (sx, sy) = style.square_pos(x, y, sq);
+                    let mut bg = if (file + rank) % 2 == 0 {
+                        style.dark
+                    } else {
+                        style.light
+                    };
+                    if let Some(&(_, col)) = style.highlights.iter().rev().find(|(s, _)| *s == sq) {
+                        bg = col;
+                    }
+                    let bg = bg & 0x00F0;
+                    self.fill_rect_with(sx, sy, sx + w, sy + h, EMPTY, bg);
+
+                    let Some(piece) = board.get(sq) else {
+                        continue;
+                    };
+                    let fg = match piece.side {
+                        Side::White => style.white,
+                        Side::Black => style.black,
+                    } & 0x000F;
+                    let (cx, cy) = (sx + (w - 1) / 2, sy + (h - 1) / 2);
+                    match style.pieces {
+                        PieceGlyphs::Letters => {
+                            self.draw_with(cx, cy, piece.fen_char() as u16, fg | bg)
+                        }
+                        PieceGlyphs::Unicode => self.draw_with(cx, cy, piece.unicode(), fg | bg),
+                        PieceGlyphs::Sprites(sprites) => {
+                            self.draw_sprite(sx, sy, &sprites[piece.index()])
+                        }
+                    }
+                }
+            }
+
+            if style.coordinates {
+                for i in 0..8u8 {
+                    let (fx, _) = style.square_pos(x, y, Square::at(i, 0));
+                    let (_, ry) = style.square_pos(x, y, Square::at(0, i));
+                    self.draw_with(fx + (w - 1) / 2, y + 8 * h, (b'a' + i) as u16, FG_GREY);
+                    self.draw_with(x, ry + (h - 1) / 2, (b'1' + i) as u16, FG_GREY);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Puzzle
+
+/// Building blocks for falling-block puzzle games: the seven tetrominoes with Super
+/// Rotation System rotation and wall kicks, a well with line clearing, and bag
+/// randomizers.
+///
+/// Coordinates are in cells with `y` growing downwards, like the screen. A piece's
+/// position is the top-left of its 3×3 (or 4×4 for the I piece) rotation box.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use rusty_console_game_engine::puzzle::*;
+///
+/// let mut well = Well::new(10, 20);
+/// let mut bag = Bag::tetrominoes(seed);
+/// let mut piece = Piece::spawn(bag.next(), &well).unwrap();
+///
+/// // In update:
+/// if engine.key_pressed(key::ARROW_UP) {
+///     piece.rotate(&well, Turn::Clockwise);
+/// }
+/// if fall_timer_elapsed && !piece.shift(&well, 0, 1) {
+///     well.lock(&piece);
+///     let lines = well.clear_lines();
+///     piece = match Piece::spawn(bag.next(), &well) {
+///         Some(piece) => piece,
+///         None => return self.game_over(),
+///     };
+/// }
+///
+/// engine.draw_well(2, 1, &well, 2);
+/// engine.draw_piece(2, 1, &piece.ghost(&well), 2, glyph::LIGHT_SHADE);
+/// engine.draw_piece(2, 1, &piece, 2, SOLID);
+/// ```
+pub mod puzzle {
+    use crate::color::*;
+    use crate::pixel::SOLID;
+    use crate::procgen::Rng;
+    use crate::{ConsoleGame, ConsoleGameEngine};
+
+    /// The seven pieces of four cells.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Tetromino {
+        I,
+        O,
+        T,
+        S,
+        Z,
+        J,
+        L,
+    }
+
+    impl Tetromino {
+        pub const ALL: [Tetromino; 7] = [
+            Tetromino::I,
+            Tetromino::O,
+            Tetromino::T,
+            Tetromino::S,
+            Tetromino::Z,
+            Tetromino::J,
+            Tetromino::L,
+        ];
+
+        /// Returns the guideline color of the piece.
+        pub fn color(self) -> u16 {
+            match self {
+                Tetromino::I => FG_CYAN,
+                Tetromino::O => FG_YELLOW,
+                Tetromino::T => FG_MAGENTA,
+                Tetromino::S => FG_GREEN,
+                Tetromino::Z => FG_RED,
+                Tetromino::J => FG_BLUE,
+                Tetromino::L => FG_DARK_YELLOW,
+            }
+        }
+
+        /// Returns the width and height of the rotation box.
+        pub fn box_size(self) -> i32 {
+            match self {
+                Tetromino::I | Tetromino::O => 4,
+                _ => 3,
+            }
+        }
+
+        /// Returns the cells of the piece within its rotation box, turned clockwise
+        /// `rotation` times from the spawn orientation.
+        pub fn cells(self, rotation: u8) -> [(i32, i32); 4] {
+            let mut cells = match self {
+                Tetromino::I => [(0, 1), (1, 1), (2, 1), (3, 1)],
+                Tetromino::O => return [(1, 0), (2, 0), (1, 1), (2, 1)],
+                Tetromino::T => [(1, 0), (0, 1), (1, 1), (2, 1)],
+                Tetromino::S => [(1, 0), (2, 0), (0, 1), (1, 1)],
+                Tetromino::Z => [(0, 0), (1, 0), (1, 1), (2, 1)],
+                Tetromino::J => [(0, 0), (0, 1), (1, 1), (2, 1)],
+                Tetromino::L => [(2, 0), (0, 1), (1, 1), (2, 1)],
+            };
+            let n = self.box_size();
+            for _ in 0..rotation % 4 {
+                for cell in &mut cells {
+                    *cell = (n - 1 - cell.1, cell.0);
+                }
+            }
+            cells
+        }
+
+        /// Returns the SRS wall kick offsets to try, in order, when turning from
+        /// `rotation` to `rotation + 1` (clockwise) or `rotation + 3` (counter-clockwise).
+        pub fn kicks(self, rotation: u8, turn: Turn) -> [(i32, i32); 5] {
+            // From the guideline tables for clockwise turns out of each rotation, with y
+            // flipped to grow downwards. Counter-clockwise turns undo the clockwise turn
+            // out of the rotation they end in, so they use its kicks negated.
+            const JLSTZ: [[(i32, i32); 5]; 4] = [
+                [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+                [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+                [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+            ];
+            const I: [[(i32, i32); 5]; 4] = [
+                [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+                [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+                [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+                [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+            ];
+            let table = match self {
+                Tetromino::O => return [(0, 0); 5],
+                Tetromino::I => &I,
+                _ => &JLSTZ,
+            };
+            match turn {
+                Turn::Clockwise => table[rotation as usize % 4],
+                Turn::CounterClockwise => table[(rotation as usize + 3) % 4].map(|(x, y)| (-x, -y)),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Turn {
+        Clockwise,
+        CounterClockwise,
+    }
+
+    /// A falling piece.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Piece {
+        pub kind: Tetromino,
+        /// Clockwise quarter turns from the spawn orientation, from 0 to 3.
+        pub rotation: u8,
+        pub x: i32,
+        pub y: i32,
+    }
+
+    impl Piece {
+        /// Places a new piece centered at the top of `well`, or returns `None` if it
+        /// doesn't fit there because the stack has reached the top.
+        pub fn spawn(kind: Tetromino, well: &Well) -> Option<Self> {
+            let piece = Self {
+                kind,
+                rotation: 0,
+                x: (well.width as i32 - kind.box_size()) / 2,
+                y: 0,
+            };
+            well.fits(&piece).then_some(piece)
+        }
+
+        /// Returns the cells the piece covers in the well.
+        pub fn cells(&self) -> [(i32, i32); 4] {
+            self.kind
+                .cells(self.rotation)
+                .map(|(cx, cy)| (self.x + cx, self.y + cy))
+        }
+
+        /// Moves by `(dx, dy)` if the piece fits there. Returns `false` if it is blocked.
+        pub fn shift(&mut self, well: &Well, dx: i32, dy: i32) -> bool {
+            let moved = Self {
+                x: self.x + dx,
+                y: self.y + dy,
+                ..*self
+            };
+            if well.fits(&moved) {
+                *self = moved;
+                true
+            } else {
+                false
+            }
+        }
+
+        /// Rotates a quarter turn, trying the SRS wall kicks in order. Returns `false` if
+        /// none of them fit.
+        pub fn rotate(&mut self, well: &Well, turn: Turn) -> bool {
+            let rotation = match turn {
+                Turn::Clockwise => (self.rotation + 1) % 4,
+                Turn::CounterClockwise => (self.rotation + 3) % 4,
+            };
+            for (dx, dy) in self.kind.kicks(self.rotation, turn) {
+                let turned = Self {
+                    rotation,
+                    x: self.x + dx,
+                    y: self.y + dy,
+                    ..*self
+                };
+                if well.fits(&turned) {
+                    *self = turned;
+                    return true;
+                }
+            }
+            false
+        }
+
+        /// Returns where the piece would land if dropped straight down, for drawing a
+        /// ghost piece.
+        pub fn ghost(&self, well: &Well) -> Self {
+            let mut ghost = *self;
+            while ghost.shift(well, 0, 1) {}
+            ghost
+        }
+
+        /// Drops the piece straight down and returns how many rows it fell.
+        pub fn hard_drop(&mut self, well: &Well) -> i32 {
+            let from = self.y;
+            *self = self.ghost(well);
+            self.y - from
+        }
+    }
+
+    /// The grid pieces fall into and stack up in.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Well {
+        pub width: usize,
+        pub height: usize,
+        cells: Vec<Option<u16>>,
+    }
+
+    impl Well {
+        /// Creates an empty well, e.g. 10 × 20 for the standard size.
+        pub fn new(width: usize, height: usize) -> Self {
+            Self {
+                width,
+                height,
+                cells: vec![None; width * height],
+            }
+        }
+
+        fn index(&self, x: i32, y: i32) -> Option<usize> {
+            if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+                Some(y as usize * self.width + x as usize)
+            } else {
+                None
+            }
+        }
+
+        /// Returns the color of the block at `(x, y)`, or `None` if it is empty.
+        pub fn get(&self, x: i32, y: i32) -> Option<u16> {
+            self.index(x, y).and_then(|i| self.cells[i])
+        }
+
+        /// Sets the block at `(x, y)`, e.g. to add garbage rows.
+        pub fn set(&mut self, x: i32, y: i32, block: Option<u16>) {
+            if let Some(i) = self.index(x, y) {
+                self.cells[i] = block;
+            }
+        }
+
+        /// Returns `true` if `(x, y)` is free. Cells above the well are free, the sides
+        /// and floor are not.
+        pub fn is_free(&self, x: i32, y: i32) -> bool {
+            if x < 0 || x >= self.width as i32 || y >= self.height as i32 {
+                return false;
+            }
+            y < 0 || self.get(x, y).is_none()
+        }
+
+        /// Returns `true` if every cell of `piece` is free.
+        pub fn fits(&self, piece: &Piece) -> bool {
+            piece.cells().iter().all(|&(x, y)| self.is_free(x, y))
+        }
+
+        /// Adds `piece` to the stack in its color.
+        pub fn lock(&mut self, piece: &Piece) {
+            for (x, y) in piece.cells() {
+                self.set(x, y, Some(piece.kind.color()));
+            }
+        }
+
+        /// Returns the rows that are completely filled, from top to bottom.
+        pub fn full_rows(&self) -> Vec<usize> {
+            (0..self.height)
+                .filter(|&y| {
+                    self.cells[y * self.width..(y + 1) * self.width]
+                        .iter()
+                        .all(Option::is_some)
+                })
+                .collect()
+        }
+
+        /// Removes every full row, dropping the rows above into the gap, and returns how
+        /// many were cleared.
+        pub fn clear_lines(&mut self) -> usize {
+            let full = self.full_rows();
+            let w = self.width;
+            let mut kept: Vec<Option<u16>> = Vec::with_capacity(self.cells.len());
+            kept.resize(full.len() * w, None);
+            for y in (0..self.height).filter(|y| !full.contains(y)) {
+                kept.extend_from_slice(&self.cells[y * w..(y + 1) * w]);
+            }
+            self.cells = kept;
+            full.len()
+        }
+
+        /// Empties the well.
+        pub fn clear(&mut self) {
+            self.cells.fill(None);
+        }
+    }
+
+    /// Deals items in a random order, a whole set at a time, so every item comes up
+    /// once per set and there are no long droughts or floods. The standard tetromino
+    /// randomizer is a bag of the seven pieces.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Bag<T> {
+        items: Vec<T>,
+        queue: std::collections::VecDeque<T>,
+        rng: Rng,
+    }
+
+    impl Bag<Tetromino> {
+        /// Creates a bag of the seven tetrominoes.
+        pub fn tetrominoes(seed: u64) -> Self {
+            Self::new(&Tetromino::ALL, seed)
+        }
+    }
+
+    impl<T: Clone> Bag<T> {
+        /// Creates a bag that deals `items`, shuffled with `seed`. Items can be repeated
+        /// to make them come up more often, e.g. a 14-bag of two sets of pieces.
+        pub fn new(items: &[T], seed: u64) -> Self {
+            Self {
+                items: items.to_vec(),
+                queue: std::collections::VecDeque::new(),
+                rng: Rng::new(seed),
+            }
+        }
+
+        fn refill(&mut self, count: usize) {
+            while self.queue.len() < count && !self.items.is_empty() {
+                let mut set = self.items.clone();
+                self.rng.shuffle(&mut set);
+                self.queue.extend(set);
+            }
+        }
+
+        /// Deals the next item. Panics if the bag was created empty.
+        #[allow(clippy::should_implement_trait)]
+        pub fn next(&mut self) -> T {
+            self.refill(1);
+            self.queue.pop_front().expect("bag has no items")
+        }
+
+        /// Returns the next `count` items without dealing them, for a preview queue.
+        pub fn peek(&mut self, count: usize) -> Vec<T> {
+            self.refill(count);
+            self.queue.iter().take(count).cloned().collect()
+        }
+    }
+
+    impl<G: ConsoleGame> ConsoleGameEngine<G> {
+        /// Draws the blocks of `well` with its top-left corner at `(x, y)`, each block
+        /// `cell_width` cells wide.
+        pub fn draw_well(&mut self, x: i32, y: i32, well: &Well, cell_width: i32) {
+            for wy in 0..well.height as i32 {
+                for wx in 0..well.width as i32 {
+                    if let Some(col) = well.get(wx, wy) {
+                        let sx = x + wx * cell_width;
+                        self.fill_rect_with(sx, y + wy, sx + cell_width, y + wy + 1, SOLID, col);
+                    }
+                }
+            }
+        }
+
+        /// Draws `piece` in its color with `glyph` into a well drawn at `(x, y)`, e.g.
+        /// `SOLID` for the falling piece and `glyph::LIGHT_SHADE` for its ghost. Cells
+        /// above the well are not drawn.
+        pub fn draw_piece(&mut self, x: i32, y: i32, piece: &Piece, cell_width: i32, glyph: u16) {
+            for (px, py) in piece.cells() {
+                if py >= 0 {
+                    let sx = x + px * cell_width;
+                    let col = piece.kind.color();
+                    self.fill_rect_with(sx, y + py, sx + cell_width, y + py + 1, glyph, col);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Snake
+
+/// Grid movement for snake games: a body stored as a ring buffer with an occupancy grid,
+/// so moving, growing and checking for self-collision stay cheap however long it gets.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use rusty_console_game_engine::snake::*;
+///
+/// let mut snake = Snake::new(20, 10, 4, Direction::East, 40, 25);
+///
+/// // In update:
+/// if engine.key_pressed(ARROW_UP) {
+///     snake.turn(Direction::North);
+/// }
+/// if step_timer_elapsed {
+///     match snake.step() {
+///         SnakeStep::Moved if snake.head() == food => {
+///             snake.grow(3);
+///             food = snake.random_free_cell(&mut rng).unwrap();
+///         }
+///         SnakeStep::Moved => {}
+///         SnakeStep::HitSelf | SnakeStep::HitWall => return self.game_over(),
+///     }
+/// }
+/// engine.draw_snake(0, 0, &snake, FG_GREEN);
+/// ```
+pub mod snake {
+    use std::collections::VecDeque;
+
+    use crate::glyph::DARK_SHADE;
+    use crate::pixel::SOLID;
+    pub use crate::procgen::Direction;
+    use crate::procgen::Rng;
+    use crate::{ConsoleGame, ConsoleGameEngine};
+
+    /// Most turns remembered between steps, so quick double turns aren't lost.
+    const MAX_QUEUED_TURNS: usize = 2;
+
+    /// Returns the cell one step from `(x, y)` in `dir` on a `width` × `height` grid,
+    /// wrapping around the edges.
+    pub fn wrap_step(x: i32, y: i32, dir: Direction, width: i32, height: i32) -> (i32, i32) {
+        let (dx, dy) = dir.offset();
+        (
+            (x + dx).rem_euclid(width.max(1)),
+            (y + dy).rem_euclid(height.max(1)),
+        )
+    }
+
+    /// What happened on a `Snake::step`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SnakeStep {
+        Moved,
+        /// The head ran into the body. The snake didn't move.
+        HitSelf,
+        /// The head left the grid, which only happens with wrapping turned off. The snake
+        /// didn't move.
+        HitWall,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Snake {
+        /// Whether leaving one edge of the grid comes back in the opposite one. Defaults
+        /// to `true`.
+        pub wrap: bool,
+        width: i32,
+        height: i32,
+        /// Head first.
+        body: VecDeque<(i32, i32)>,
+        /// How many body segments are on each cell.
+        occupied: Vec<u16>,
+        direction: Direction,
+        turns: VecDeque<Direction>,
+        growth: u32,
+    }
+
+    impl Snake {
+        /// Creates a snake `length` cells long with its head at `(x, y)`, facing `dir` on a
+        /// `width` × `height` grid. The body trails out behind the head.
+        pub fn new(x: i32, y: i32, length: usize, dir: Direction, width: i32, height: i32) -> Self {
+            let (width, height) = (width.max(1), height.max(1));
+            let mut snake = Self {
+                wrap: true,
+                width,
+                height,
+                body: VecDeque::with_capacity((width * height) as usize),
+                occupied: vec![0; (width * height) as usize],
+                direction: dir,
+                turns: VecDeque::new(),
+                growth: 0,
+            };
+            let mut at = (x.rem_euclid(width), y.rem_euclid(height));
+            for _ in 0..length.max(1) {
+                let i = snake.index(at);
+                snake.body.push_back(at);
+                snake.occupied[i] += 1;
+                at = wrap_step(at.0, at.1, dir.opposite(), width, height);
+            }
+            snake
+        }
+
+        fn index(&self, (x, y): (i32, i32)) -> usize {
+            (y * self.width + x) as usize
+        }
+
+        /// Asks the snake to turn towards `dir` on an upcoming step. Turning straight back
+        /// on itself is ignored.
+        pub fn turn(&mut self, dir: Direction) {
+            let last = self.turns.back().copied().unwrap_or(self.direction);
+            if dir != last && dir != last.opposite() && self.turns.len() < MAX_QUEUED_TURNS {
+                self.turns.push_back(dir);
+            }
+        }
+
+        /// Makes the snake `segments` longer, one segment per step.
+        pub fn grow(&mut self, segments: u32) {
+            self.growth += segments;
+        }
+
+        /// Moves the head one cell, taking the next queued turn first.
+        pub fn step(&mut self) -> SnakeStep {
+            let dir = self.turns.front().copied().unwrap_or(self.direction);
+            let (hx, hy) = self.head();
+            let (dx, dy) = dir.offset();
+            let next = if self.wrap {
+                wrap_step(hx, hy, dir, self.width, self.height)
+            } else {
+                (hx + dx, hy + dy)
+            };
+            if !self.in_bounds(next.0, next.1) {
+                return SnakeStep::HitWall;
+            }
+
+            // The tail moves out of the way this step unless the snake is growing.
+            let tail = *self.body.back().unwrap();
+            let blocked = self.occupied[self.index(next)];
+            let vacated = u16::from(self.growth == 0 && tail == next);
+            if blocked > vacated {
+                return SnakeStep::HitSelf;
+            }
+
+            self.turns.pop_front();
+            self.direction = dir;
+            if self.growth > 0 {
+                self.growth -= 1;
+            } else {
+                self.body.pop_back();
+                let i = self.index(tail);
+                self.occupied[i] -= 1;
+            }
+            self.body.push_front(next);
+            let i = self.index(next);
+            self.occupied[i] += 1;
+            SnakeStep::Moved
+        }
+
+        fn in_bounds(&self, x: i32, y: i32) -> bool {
+            x >= 0 && y >= 0 && x < self.width && y < self.height
+        }
+
+        pub fn head(&self) -> (i32, i32) {
+            self.body[0]
+        }
+
+        pub fn tail(&self) -> (i32, i32) {
+            self.body[self.body.len() - 1]
+        }
+
+        /// Returns the direction the snake last moved in.
+        pub fn direction(&self) -> Direction {
+            self.direction
+        }
+
+        /// Iterates over the cells of the body, head first.
+        pub fn body(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+            self.body.iter().copied()
+        }
+
+        pub fn len(&self) -> usize {
+            self.body.len()
+        }
+
+        /// Always `false`; a snake has at least its head.
+        pub fn is_empty(&self) -> bool {
+            self.body.is_empty()
+        }
+
+        /// Returns `true` if any part of the snake is on `(x, y)`.
+        pub fn occupies(&self, x: i32, y: i32) -> bool {
+            self.in_bounds(x, y) && self.occupied[self.index((x, y))] > 0
+        }
+
+        /// Returns a random cell the snake isn't on, e.g. to place food, or `None` if it
+        /// fills the grid.
+        pub fn random_free_cell(&self, rng: &mut Rng) -> Option<(i32, i32)> {
+            let free = self.occupied.iter().filter(|&&n| n == 0).count();
+            if free == 0 {
+                return None;
+            }
+            let pick = rng.range(0, free as i32) as usize;
+            let i = self
+                .occupied
+                .iter()
+                .enumerate()
+                .filter(|(_, &n)| n == 0)
+                .nth(pick)?
+                .0 as i32;
+            Some((i % self.width, i / self.width))
+        }
+    }
+
+    impl<G: ConsoleGame> ConsoleGameEngine<G> {
+        /// Draws `snake` in `col` on a grid whose top-left corner is at `(x, y)`, with a
+        /// solid head and a shaded body.
+        pub fn draw_snake(&mut self, x: i32, y: i32, snake: &Snake, col: u16) {
+            for (i, &(sx, sy)) in snake.body.iter().enumerate().rev() {
+                let glyph = if i == 0 { SOLID } else { DARK_SHADE };
+                self.draw_with(x + sx, y + sy, glyph, col);
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Audio
+
+const CHUNK_SIZE: usize = 512;
+const DEFAULT_MAX_VOICES: usize = 16;
+static NOTE_COUNTER: AtomicU64 = AtomicU64::new(0);
+static SOUND_COUNTER: AtomicU64 = AtomicU64::new(1);
+const MAX_FINISHED_SAMPLES: usize = 256;
+const TEMP_SAMPLE_PREFIX: &str = "__temp_notes_";
+
+#[derive(Clone)]
+enum AudioCommand {
+    LoadSample(String),
+    /// Plays a region of a sample, then optionally loops another region of it until stopped.
+    PlaySample(String, u64, SampleRegion, Option<SampleRegion>),
+    StopSample(u64),
+    SetCue(String, String, u32, u32),
+    LoadSampleFromBuffer(String, Vec<i16>),
+    NoteOn(String, f32),
+    NoteOff(String, f32),
+    AllNotesOff(String),
+    SetVolume(f32),
+    PlayMidi(Vec<MidiEvent>),
+    StopMidi,
+    Drum(Drum),
+    SetChannelInstrument(String, Instrument),
+    SetChannelVolume(String, f32),
+    SetChannelPan(String, f32),
+    SetMidiInstrument(u8, Instrument),
+    SetEffects(Bus, Vec<Effect>),
+    SetMaxVoices(usize),
+    /// Opens the device without doing anything else, so the beat clock starts ticking.
+    Wake,
+    Quit,
+}
+
+struct PlayingSound {
+    key: String,
+    id: u64,
+    data: Vec<i16>,
+    cursor: usize,
+    /// Where the current region ends.
+    end: usize,
+    /// The region to repeat once the current one ends.
+    looping: Option<(usize, usize)>,
+}
+
+/// Part of a sample to play.
+#[derive(Clone)]
+enum SampleRegion {
+    Whole,
+    /// From the start to the end time, in milliseconds.
+    Millis(u32, u32),
+    /// A cue point set with `AudioEngine::set_cue`.
+    Cue(String),
+}
+
+impl SampleRegion {
+    /// Returns the range of interleaved stereo samples covered by the region, or `None` if
+    /// it names a cue the sample doesn't have.
+    fn resolve(
+        &self,
+        key: &str,
+        len: usize,
+        cues: &HashMap<(String, String), (u32, u32)>,
+    ) -> Option<(usize, usize)> {
+        let index = |ms: u32| ((ms as u64 * 44100 / 1000) as usize * 2).min(len);
+        let (start, end) = match self {
+            SampleRegion::Whole => return Some((0, len)),
+            SampleRegion::Millis(start, end) => (*start, *end),
+            SampleRegion::Cue(name) => *cues.get(&(key.to_string(), name.clone()))?,
+        };
+        Some((index(start), index(end).max(index(start))))
+    }
+}
+
+/// Identifies one playback of a sample, as returned by `AudioEngine::play_sample`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SoundId(u64);
+
+/// Which sample playbacks are still going, shared with the audio thread.
+#[derive(Default)]
+struct SoundTracker {
+    playing: Mutex<std::collections::HashSet<u64>>,
+    finished: Mutex<Vec<SoundId>>,
+}
+
+impl SoundTracker {
+    fn finish(&self, id: u64) {
+        if self.playing.lock().unwrap().remove(&id) {
+            let mut finished = self.finished.lock().unwrap();
+            // Games that never call `finished_samples` only keep the most recent ids.
+            if finished.len() >= MAX_FINISHED_SAMPLES {
+                finished.remove(0);
+            }
+            finished.push(SoundId(id));
+        }
+    }
+}
+
+struct PlayingNote {
+    freq: f32,
+    /// Phase of each of the instrument's oscillators, in radians.
+    phases: Vec<f32>,
+    /// Seconds since the note started.
+    time: f32,
+    /// When the note was released and the envelope level at that moment.
+    released: Option<(f32, f32)>,
+    velocity: f32,
+    active: bool,
+    instrument: Arc<Instrument>,
+    midi: Option<(u8, u8)>,
+}
+
+impl PlayingNote {
+    fn new(freq: f32, velocity: f32, instrument: Arc<Instrument>, midi: Option<(u8, u8)>) -> Self {
+        Self {
+            freq,
+            phases: vec![0.0; instrument.oscillators.len()],
+            time: 0.0,
+            released: None,
+            velocity,
+            active: true,
+            instrument,
+            midi,
+        }
+    }
+
+    /// Starts the release stage of the envelope.
+    fn release(&mut self) {
+        if self.released.is_none() {
+            self.released = Some((self.time, self.instrument.held_level(self.time)));
+        }
+    }
+
+    fn is_released(&self) -> bool {
+        self.released.is_some()
+    }
+
+    /// Returns the next sample, between -1 and 1, and moves the note forward in time.
+    fn next_sample(&mut self, sample_rate: f32) -> f32 {
+        let instrument = &*self.instrument;
+        let level = match self.released {
+            None => instrument.held_level(self.time),
+            Some((at, level)) => level * (1.0 - (self.time - at) / instrument.release.max(1e-4)),
+        };
+        // A note that dies away on its own (like a bell) is done once it has.
+        let decayed =
+            instrument.sustain <= 0.0 && self.time >= instrument.attack + instrument.decay;
+        if level <= 0.0 && (self.released.is_some() || decayed) {
+            self.active = false;
+            return 0.0;
+        }
+
+        let vibrato = 1.0
+            + instrument.vibrato_depth * (2.0 * PI * instrument.vibrato_speed * self.time).sin();
+        let mut s = 0.0;
+        for (osc, phase) in instrument.oscillators.iter().zip(self.phases.iter_mut()) {
+            s += osc.wave.sample(*phase) * osc.amplitude;
+            *phase += 2.0 * PI * self.freq * osc.ratio * vibrato / sample_rate;
+            if *phase >= 2.0 * PI {
+                *phase %= 2.0 * PI;
+            }
+        }
+
+        self.time += 1.0 / sample_rate;
+        s * level.max(0.0) * self.velocity * instrument.volume
+    }
+}
+
+/// A named group of notes played with `note_on`, sharing an instrument, volume and pan.
+struct SynthChannel {
+    instrument: Arc<Instrument>,
+    volume: f32,
+    pan: f32,
+    notes: Vec<PlayingNote>,
+}
+
+impl Default for SynthChannel {
+    fn default() -> Self {
+        Self {
+            instrument: Arc::new(Instrument::default()),
+            volume: 1.0,
+            pan: 0.0,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl SynthChannel {
+    /// Returns the gain of the left and right speakers. Panning turns one side down
+    /// rather than the other up, so a centred channel plays at full volume on both.
+    fn gains(&self) -> (f32, f32) {
+        (
+            self.volume * (1.0 - self.pan).min(1.0),
+            self.volume * (1.0 + self.pan).min(1.0),
+        )
+    }
+}
+
+/// Shape of the tone produced by the note synthesizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Triangle,
+    Saw,
+}
+
+impl Waveform {
+    /// Returns the value of the wave, between -1 and 1, at `phase` radians.
+    fn sample(self, phase: f32) -> f32 {
+        let p = phase / (2.0 * PI);
+        match self {
+            Waveform::Sine => phase.sin(),
+            Waveform::Square => {
+                if p < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            Waveform::Saw => 2.0 * p - 1.0,
+        }
+    }
+}
+
+/// One waveform in an [`Instrument`]'s mix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Oscillator {
+    pub wave: Waveform,
+    /// Frequency as a multiple of the note's, e.g. 2 for an octave above.
+    pub ratio: f32,
+    /// Volume of this waveform in the mix.
+    pub amplitude: f32,
+}
+
+impl Oscillator {
+    pub fn new(wave: Waveform, ratio: f32, amplitude: f32) -> Self {
+        Self {
+            wave,
+            ratio,
+            amplitude,
+        }
+    }
+}
+
+/// How the note synthesizer sounds: a mix of waveforms, shaped by an attack-decay-sustain-
+/// release envelope, with optional vibrato. Times are in seconds.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// engine.audio.set_channel_instrument("melody", Instrument::bell());
+/// engine.audio.note_on("melody", C5);
+///
+/// // or build your own
+/// engine.audio.set_channel_instrument("pad", Instrument {
+///     oscillators: vec![
+///         Oscillator::new(Waveform::Saw, 1.0, 0.6),
+///         Oscillator::new(Waveform::Saw, 1.01, 0.6),
+///     ],
+///     release: 0.3,
+///     ..Instrument::default()
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    /// The waveforms mixed together for each note.
+    pub oscillators: Vec<Oscillator>,
+    /// Time to rise to full volume.
+    pub attack: f32,
+    /// Time to fall from full volume to the sustain level.
+    pub decay: f32,
+    /// Volume held while the note is on (0 to 1). At 0 the note dies away by itself.
+    pub sustain: f32,
+    /// Time to fade out after the note is released.
+    pub release: f32,
+    /// Vibrato strength as a fraction of the pitch.
+    pub vibrato_depth: f32,
+    /// Vibrato speed in Hz.
+    pub vibrato_speed: f32,
+    /// Overall volume (0 to 1).
+    pub volume: f32,
+}
+
+impl Default for Instrument {
+    /// A plain sine wave.
+    fn default() -> Self {
+        Self::new(Waveform::Sine)
+    }
+}
+
+impl Instrument {
+    /// Creates an instrument playing a single waveform, fading in and out over 50 ms.
+    pub fn new(wave: Waveform) -> Self {
+        Self {
+            oscillators: vec![Oscillator::new(wave, 1.0, 1.0)],
+            attack: 0.05,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.05,
+            vibrato_depth: 0.0,
+            vibrato_speed: 0.0,
+            volume: 1.0,
+        }
+    }
+
+    /// Reedy square waves with a gentle vibrato.
+    pub fn harmonica() -> Self {
+        Self {
+            oscillators: vec![
+                Oscillator::new(Waveform::Square, 1.0, 1.0),
+                Oscillator::new(Waveform::Square, 1.5, 0.5),
+                Oscillator::new(Waveform::Square, 2.0, 0.25),
+            ],
+            attack: 0.02,
+            decay: 1.0,
+            sustain: 0.9,
+            release: 0.1,
+            vibrato_depth: 0.004,
+            vibrato_speed: 5.0,
+            volume: 0.35,
+        }
+    }
+
+    /// Sine waves at inharmonic ratios that ring out and fade, even while held.
+    pub fn bell() -> Self {
+        Self {
+            oscillators: vec![
+                Oscillator::new(Waveform::Sine, 1.0, 1.0),
+                Oscillator::new(Waveform::Sine, 2.0, 0.6),
+                Oscillator::new(Waveform::Sine, 2.76, 0.4),
+                Oscillator::new(Waveform::Sine, 5.4, 0.25),
+            ],
+            attack: 0.005,
+            decay: 1.5,
+            sustain: 0.0,
+            release: 1.0,
+            vibrato_depth: 0.001,
+            vibrato_speed: 5.0,
+            volume: 0.45,
+        }
+    }
+
+    /// Two slightly detuned square waves, for chiptune melodies.
+    pub fn square_lead() -> Self {
+        Self {
+            oscillators: vec![
+                Oscillator::new(Waveform::Square, 1.0, 1.0),
+                Oscillator::new(Waveform::Square, 1.005, 0.5),
+            ],
+            attack: 0.01,
+            decay: 0.2,
+            sustain: 0.8,
+            release: 0.15,
+            vibrato_depth: 0.006,
+            vibrato_speed: 6.0,
+            volume: 0.4,
+        }
+    }
+
+    /// A saw wave over a sine an octave below, with a plucked envelope.
+    pub fn bass() -> Self {
+        Self {
+            oscillators: vec![
+                Oscillator::new(Waveform::Saw, 1.0, 0.6),
+                Oscillator::new(Waveform::Sine, 0.5, 1.0),
+            ],
+            attack: 0.005,
+            decay: 0.3,
+            sustain: 0.6,
+            release: 0.1,
+            vibrato_depth: 0.0,
+            vibrato_speed: 0.0,
+            volume: 0.6,
+        }
+    }
+
+    /// Returns the envelope level `time` seconds into a note that is still held.
+    fn held_level(&self, time: f32) -> f32 {
+        if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.decay {
+            1.0 - (time - self.attack) / self.decay * (1.0 - self.sustain)
+        } else {
+            self.sustain
+        }
+    }
+}
+
+impl From<Waveform> for Instrument {
+    fn from(wave: Waveform) -> Self {
+        Self::new(wave)
+    }
+}
+
+/// A note starting (or stopping, with a velocity of 0) `time` seconds into a MIDI song.
+#[derive(Clone, Copy)]
+struct MidiEvent {
+    time: f32,
+    channel: u8,
+    key: u8,
+    velocity: u8,
+}
+
+struct MidiPlayback {
+    events: Vec<MidiEvent>,
+    next: usize,
+    time: f32,
+}
+
+/// A group of sounds that share an effects chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    /// Samples, including sound effects and `play_note` / `play_notes`, and metronome
+    /// clicks.
+    Sfx,
+    /// Notes from `note_on` and MIDI songs.
+    Music,
+    /// Everything, after the other buses are mixed together.
+    Master,
+}
+
+/// An audio effect applied to a [`Bus`] with `AudioEngine::set_effect`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Muffles the sound above the cutoff frequency in Hz, e.g. for underwater scenes.
+    LowPass(f32),
+    /// Repeats the sound after `time` seconds, each echo `feedback` (0 to 1) times as loud
+    /// as the previous one. `mix` is how loud the echoes are compared to the original.
+    Delay { time: f32, feedback: f32, mix: f32 },
+    /// Reduces the sound to `bits` bits and holds every sample for `downsample` samples,
+    /// for a lo-fi crunch.
+    BitCrush { bits: u8, downsample: u32 },
+    /// Makes the sound ring out like in a room; `room` from 0 (small) to 1 (large hall).
+    Reverb { room: f32, mix: f32 },
+}
+
+const REVERB_COMBS: [usize; 4] = [1116, 1188, 1277, 1356];
+const REVERB_ALLPASSES: [usize; 2] = [556, 441];
+const REVERB_STEREO_SPREAD: usize = 23;
+
+/// Feedback delay line used by the reverb.
+struct DelayLine {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            pos: 0,
+        }
+    }
+
+    fn comb(&mut self, input: f32, feedback: f32) -> f32 {
+        let out = self.buffer[self.pos];
+        self.buffer[self.pos] = input + out * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        out
+    }
+
+    fn allpass(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        self.buffer[self.pos] = input + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// An [`Effect`] with its running state, processing interleaved stereo samples.
+enum EffectProcessor {
+    LowPass {
+        alpha: f32,
+        state: [f32; 2],
+    },
+    Delay {
+        buffer: Vec<f32>,
+        pos: usize,
+        feedback: f32,
+        mix: f32,
+    },
+    BitCrush {
+        step: f32,
+        downsample: u32,
+        counter: u32,
+        held: [f32; 2],
+    },
+    Reverb {
+        combs: Vec<[DelayLine; 2]>,
+        allpasses: Vec<[DelayLine; 2]>,
+        feedback: f32,
+        mix: f32,
+    },
+}
+
+impl EffectProcessor {
+    fn new(effect: Effect) -> Self {
+        let sample_rate = 44100.0;
+        match effect {
+            Effect::LowPass(cutoff) => EffectProcessor::LowPass {
+                alpha: 1.0 - (-2.0 * PI * cutoff.max(1.0) / sample_rate).exp(),
+                state: [0.0; 2],
+            },
+            Effect::Delay {
+                time,
+                feedback,
+                mix,
+            } => EffectProcessor::Delay {
+                buffer: vec![0.0; ((time.max(0.001) * sample_rate) as usize).max(1) * 2],
+                pos: 0,
+                feedback: feedback.clamp(0.0, 0.95),
+                mix,
+            },
+            Effect::BitCrush { bits, downsample } => EffectProcessor::BitCrush {
+                step: 2f32.powi(16 - bits.clamp(1, 16) as i32),
+                downsample: downsample.max(1),
+                counter: 0,
+                held: [0.0; 2],
+            },
+            Effect::Reverb { room, mix } => EffectProcessor::Reverb {
+                combs: REVERB_COMBS
+                    .iter()
+                    .map(|&n| [DelayLine::new(n), DelayLine::new(n + REVERB_STEREO_SPREAD)])
+                    .collect(),
+                allpasses: REVERB_ALLPASSES
+                    .iter()
+                    .map(|&n| [DelayLine::new(n), DelayLine::new(n + REVERB_STEREO_SPREAD)])
+                    .collect(),
+                feedback: 0.7 + 0.28 * room.clamp(0.0, 1.0),
+                mix,
+            },
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        match self {
+            EffectProcessor::LowPass { alpha, state } => {
+                for frame in samples.chunks_exact_mut(2) {
+                    for (s, y) in frame.iter_mut().zip(state.iter_mut()) {
+                        *y += *alpha * (*s - *y);
+                        *s = *y;
+                    }
+                }
+            }
+            EffectProcessor::Delay {
+                buffer,
+                pos,
+                feedback,
+                mix,
+            } => {
+                for s in samples.iter_mut() {
+                    let echo = buffer[*pos];
+                    buffer[*pos] = *s + echo * *feedback;
+                    *pos = (*pos + 1) % buffer.len();
+                    *s += echo * *mix;
+                }
+            }
+            EffectProcessor::BitCrush {
+                step,
+                downsample,
+                counter,
+                held,
+            } => {
+                for frame in samples.chunks_exact_mut(2) {
+                    if *counter == 0 {
+                        for (h, s) in held.iter_mut().zip(frame.iter()) {
+                            *h = (*s / *step).round() * *step;
+                        }
+                    }
+                    *counter = (*counter + 1) % *downsample;
+                    frame.copy_from_slice(held);
+                }
+            }
+            EffectProcessor::Reverb {
+                combs,
+                allpasses,
+                feedback,
+                mix,
+            } => {
+                for frame in samples.chunks_exact_mut(2) {
+                    let input = (frame[0] + frame[1]) * 0.015;
+                    for (c, s) in frame.iter_mut().enumerate() {
+                        let mut wet: f32 =
+                            combs.iter_mut().map(|l| l[c].comb(input, *feedback)).sum();
+                        for line in allpasses.iter_mut() {
+                            wet = line[c].allpass(wet);
+                        }
+                        *s += wet * *mix;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A sound from the Windows sound scheme, played with `AudioEngine::play_system_sound`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemSound {
+    /// The default beep.
+    Default,
+    /// Information.
+    Asterisk,
+    /// Warning.
+    Exclamation,
+    /// Critical stop.
+    Hand,
+    Question,
+}
+
+impl SystemSound {
+    /// Returns the name of the sound in the registry's sound scheme.
+    fn alias(self) -> &'static str {
+        match self {
+            SystemSound::Default => ".Default",
+            SystemSound::Asterisk => "SystemAsterisk",
+            SystemSound::Exclamation => "SystemExclamation",
+            SystemSound::Hand => "SystemHand",
+            SystemSound::Question => "SystemQuestion",
+        }
+    }
+
+    /// Returns the frequency (Hz) and duration (ms) of the beep played instead when the
+    /// system has no sound for it.
+    fn fallback(self) -> (f32, u32) {
+        match self {
+            SystemSound::Default => (880.0, 120),
+            SystemSound::Asterisk => (1320.0, 150),
+            SystemSound::Exclamation => (660.0, 200),
+            SystemSound::Hand => (220.0, 300),
+            SystemSound::Question => (990.0, 150),
+        }
+    }
+}
+
+/// MIDI channel 10 (9 counting from 0) is reserved for drums, which the synth can't play.
+const MIDI_DRUM_CHANNEL: u8 = 9;
+
+/// Audio engine used through  the `ConsoleGameEngine`.
+///
+/// Handles asynchronous playback of WAV files and synthesized notes.
+///
+/// Users can interact with it via the audio field in the `ConsoleGameEngine`:
+///
+/// ```rust
+/// engine.audio.load_sample("explosion.wav");
+/// engine.audio.play_sample("explosion.wav");
+/// engine.audio.play_note(A4, 500);
+/// engine.audio.play_notes(&[A4, C_SHARP5, E5], 1000);
+/// engine.audio.note_on("melody", A4);
+/// engine.audio.note_off("melody", A4);
+/// ```
+#[derive(Clone)]
+pub struct AudioEngine {
+    tx: Sender<AudioCommand>,
+    clock: Arc<BeatClock>,
+    sounds: Arc<SoundTracker>,
+    /// The last chunk sent to the speakers, mixed down to mono between -1 and 1.
+    last_chunk: Arc<Mutex<Vec<f32>>>,
+    #[cfg(feature = "speech")]
+    speech: Sender<SpeechCommand>,
+    _shutdown: Arc<AudioShutdown>,
+}
+
+/// Musical clock advanced by the audio thread as it mixes, so beats follow the audio
+/// rather than the frame rate.
+#[derive(Default)]
+struct BeatClock {
+    /// Beats per minute as `f32` bits. 0 means the clock is stopped.
+    bpm: AtomicU32,
+    /// Beats since the clock was reset, as `f64` bits.
+    position: AtomicU64,
+    /// Whole beats already reported by `beats_elapsed`.
+    seen: AtomicU64,
+    /// Beats per bar set by `start_metronome`. 0 means the default of 4.
+    beats_per_bar: AtomicU32,
+    /// Whether the audio thread plays a click on every beat.
+    metronome: AtomicBool,
+}
+
+impl BeatClock {
+    /// Moves the clock forward and returns its position before and after.
+    fn advance(&self, seconds: f32) -> (f64, f64) {
+        let bpm = f32::from_bits(self.bpm.load(Relaxed));
+        if bpm <= 0.0 {
+            let position = f64::from_bits(self.position.load(Relaxed));
+            return (position, position);
+        }
+        let step = seconds as f64 * bpm as f64 / 60.0;
+        // A compare-and-swap, so a reset from the game thread in between isn't overwritten
+        // with the old position.
+        let position = self
+            .position
+            .fetch_update(Relaxed, Relaxed, |bits| {
+                Some((f64::from_bits(bits) + step).to_bits())
+            })
+            .unwrap_or_else(|bits| bits);
+        let position = f64::from_bits(position);
+        (position, position + step)
+    }
+}
+
+/// Length of a metronome click in seconds.
+const CLICK_LENGTH: f32 = 0.03;
+
+/// A metronome click being mixed by the audio thread.
+struct MetronomeClick {
+    /// Samples left before the click starts, within the current chunk.
+    delay: usize,
+    /// Samples played so far.
+    age: usize,
+    freq: f32,
+    gain: f32,
+}
+
+impl MetronomeClick {
+    fn is_finished(&self) -> bool {
+        self.age as f32 >= CLICK_LENGTH * 44100.0
+    }
+}
+
+/// Stops the audio thread once the last clone of the `AudioEngine` is dropped.
+struct AudioShutdown(Sender<AudioCommand>);
+
+impl Drop for AudioShutdown {
+    fn drop(&mut self) {
+        let _ = self.0.send(AudioCommand::Quit);
+    }
+}
+
+/// The system API used to play audio, chosen with `AudioEngine::with_backend`.
+///
+/// `Wasapi` only exists with the `wasapi` feature, so matches outside the crate need a
+/// wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum AudioBackend {
+    /// The classic `waveOut` API. Works everywhere, but has around 100 ms of latency.
+    #[default]
+    WaveOut,
+    /// WASAPI in shared mode, with around 10-20 ms of latency.
+    #[cfg(feature = "wasapi")]
+    Wasapi,
+    /// Mixes everything but plays it nowhere. Used in headless mode, where there may be no
+    /// audio device.
+    Silent,
+}
+
+/// Length of the WASAPI buffer in 100 ns units.
+#[cfg(feature = "wasapi")]
+const WASAPI_BUFFER_DURATION: i64 = 300_000;
+
+#[cfg(feature = "wasapi")]
+struct WasapiOutput {
+    client: IAudioClient,
+    render: IAudioRenderClient,
+    buffer_frames: u32,
+}
+
+#[cfg(feature = "wasapi")]
+impl WasapiOutput {
+    fn open(format: &WAVEFORMATEX) -> windows::core::Result<Self> {
+        use windows::Win32::System::Com::{
+            CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED,
+        };
+
+        unsafe {
+            CoInitializeEx(None, COINIT_MULTITHREADED).ok()?;
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+            let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+            // Let Windows convert our 44.1 kHz 16-bit stereo to the device's mix format.
+            client.Initialize(
+                AUDCLNT_SHAREMODE_SHARED,
+                AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
+                WASAPI_BUFFER_DURATION,
+                0,
+                format,
+                None,
+            )?;
+            let buffer_frames = client.GetBufferSize()?;
+            let render: IAudioRenderClient = client.GetService()?;
+            client.Start()?;
+
+            Ok(Self {
+                client,
+                render,
+                buffer_frames,
+            })
+        }
+    }
+
+    /// Returns how many frames can be written, or an error if the device has gone away
+    /// (e.g. it was unplugged).
+    fn free_frames(&self) -> windows::core::Result<u32> {
+        let padding = unsafe { self.client.GetCurrentPadding() }?;
+        Ok(self.buffer_frames.saturating_sub(padding))
+    }
+}
+
+#[cfg(feature = "wasapi")]
+impl Drop for WasapiOutput {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.client.Stop();
+        }
+    }
+}
+
+#[cfg(feature = "speech")]
+enum SpeechCommand {
+    Speak(String),
+    Stop,
+    SetRate(i32),
+    SetVolume(u16),
+}
+
+/// Starts the thread that owns the SAPI voice. It ends once every `AudioEngine` clone,
+/// and with them the sender, is dropped.
+#[cfg(feature = "speech")]
+fn spawn_speech_thread() -> Sender<SpeechCommand> {
+    use windows::Win32::Media::Speech::{
+        ISpVoice, SpVoice, SPF_ASYNC, SPF_IS_NOT_XML, SPF_PURGEBEFORESPEAK,
+    };
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED,
+    };
+
+    let (tx, rx) = mpsc::channel::<SpeechCommand>();
+    thread::spawn(move || {
+        let voice: ISpVoice = unsafe {
+            match CoInitializeEx(None, COINIT_MULTITHREADED)
+                .ok()
+                .and_then(|_| CoCreateInstance(&SpVoice, None, CLSCTX_ALL))
+            {
+                Ok(voice) => voice,
+                Err(e) => {
+                    eprintln!("Failed to create speech voice: {}", e);
+                    return;
+                }
+            }
+        };
+
+        while let Ok(cmd) = rx.recv() {
+            unsafe {
+                let _ = match cmd {
+                    SpeechCommand::Speak(text) => voice.Speak(
+                        &HSTRING::from(text),
+                        (SPF_ASYNC.0 | SPF_IS_NOT_XML.0) as u32,
+                        None,
+                    ),
+                    SpeechCommand::Stop => voice.Speak(
+                        PCWSTR::null(),
+                        (SPF_ASYNC.0 | SPF_PURGEBEFORESPEAK.0) as u32,
+                        None,
+                    ),
+                    SpeechCommand::SetRate(rate) => voice.SetRate(rate),
+                    SpeechCommand::SetVolume(volume) => voice.SetVolume(volume),
+                };
+            }
+        }
+    });
+    tx
+}
+
+/// Where the audio thread sends mixed chunks.
+enum AudioOutput {
+    WaveOut(HWAVEOUT),
+    #[cfg(feature = "wasapi")]
+    Wasapi(WasapiOutput),
+    Silent,
+}
+
+impl AudioOutput {
+    fn open(backend: AudioBackend, format: &WAVEFORMATEX) -> Option<Self> {
+        if backend == AudioBackend::Silent {
+            return Some(AudioOutput::Silent);
+        }
+        #[cfg(feature = "wasapi")]
+        if backend == AudioBackend::Wasapi {
+            match WasapiOutput::open(format) {
+                Ok(output) => return Some(AudioOutput::Wasapi(output)),
+                Err(e) => eprintln!("Failed to open WASAPI, falling back to waveOut: {}", e),
+            }
+        }
+        #[cfg(not(feature = "wasapi"))]
+        let _ = backend;
+
+        let mut h_waveout = HWAVEOUT::default();
+        unsafe {
+            let res = waveOutOpen(
+                Some(&mut h_waveout),
+                WAVE_MAPPER,
+                format,
+                None,
+                Some(0),
+                CALLBACK_NULL,
+            );
+
+            if res != MMSYSERR_NOERROR {
+                eprintln!("Failed to open audio device: {}", res);
+                return None;
+            }
+        }
+        Some(AudioOutput::WaveOut(h_waveout))
+    }
+
+    /// Queues a chunk of interleaved stereo samples.
+    fn write(&mut self, data: Vec<i16>) {
+        match self {
+            AudioOutput::WaveOut(h_waveout) => AudioEngine::play_buffer(*h_waveout, data),
+            AudioOutput::Silent => {}
+            #[cfg(feature = "wasapi")]
+            AudioOutput::Wasapi(output) => {
+                let free = output.free_frames().unwrap_or(0);
+                let frames = (data.len() / 2).min(free as usize) as u32;
+                if frames == 0 {
+                    return;
+                }
+                unsafe {
+                    if let Ok(ptr) = output.render.GetBuffer(frames) {
+                        std::ptr::copy_nonoverlapping(
+                            data.as_ptr(),
+                            ptr as *mut i16,
+                            frames as usize * 2,
+                        );
+                        let _ = output.render.ReleaseBuffer(frames, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits until it is time to mix the next chunk. Returns `false` if the output has
+    /// failed and should be replaced.
+    fn wait(&self) -> bool {
+        match self {
+            AudioOutput::WaveOut(_) | AudioOutput::Silent => {
+                thread::sleep(Duration::from_millis(10))
+            }
+            #[cfg(feature = "wasapi")]
+            AudioOutput::Wasapi(output) => loop {
+                match output.free_frames() {
+                    Ok(free) if free >= CHUNK_SIZE as u32 => break,
+                    Ok(_) => thread::sleep(Duration::from_millis(1)),
+                    Err(e) => {
+                        eprintln!("WASAPI device lost, falling back to waveOut: {}", e);
+                        return false;
+                    }
+                }
+            },
+        }
+        true
+    }
+}
+
+impl AudioEngine {
+    #[allow(clippy::new_without_default)]
+    fn new() -> Self {
+        Self::with_backend(AudioBackend::default())
+    }
+
+    /// Creates an audio engine that plays through the given backend.
+    ///
+    /// The engine creates a `waveOut` one by default; replace it to lower the latency:
+    ///
+    /// ```rust,ignore
+    /// engine.audio = AudioEngine::with_backend(AudioBackend::Wasapi);
+    /// ```
+    ///
+    /// If the backend can't be opened, it falls back to `waveOut`.
+    pub fn with_backend(backend: AudioBackend) -> Self {
+        let (tx, rx) = mpsc::channel::<AudioCommand>();
+        let clock = Arc::new(BeatClock::default());
+        let thread_clock = clock.clone();
+        let sounds = Arc::new(SoundTracker::default());
+        let thread_sounds = sounds.clone();
+        let last_chunk = Arc::new(Mutex::new(vec![0.0; CHUNK_SIZE]));
+        let thread_last_chunk = last_chunk.clone();
+
+        thread::spawn(move || {
+            let format = WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_PCM as u16,
+                nChannels: 2,
+                nSamplesPerSec: 44100,
+                nAvgBytesPerSec: 44100 * 2 * 2,
+                nBlockAlign: 4,
+                wBitsPerSample: 16,
+                cbSize: 0,
+            };
+
+            // The device is only opened once the engine is first used, so an engine that is
+            // replaced before then, like the one `construct_headless` swaps out, never opens it.
+            let first = match rx.recv() {
+                Ok(AudioCommand::Quit) | Err(_) => return,
+                Ok(first) => first,
+            };
+            let Some(mut output) = AudioOutput::open(backend, &format) else {
+                return;
+            };
+            let mut pending = Some(first);
+
+            let mut samples = HashMap::new();
+            let mut active_sounds = Vec::new();
+            let mut cues = HashMap::new();
+            let mut channels: HashMap<String, SynthChannel> = HashMap::new();
+            let mut midi_notes = Vec::new();
+            let mut volume = 1.0f32;
+            let mut midi: Option<MidiPlayback> = None;
+            let mut midi_instruments: [Arc<Instrument>; 16] =
+                std::array::from_fn(|_| Arc::new(AudioEngine::midi_instrument(Waveform::Square)));
+            let mut effects: [Vec<EffectProcessor>; 3] = Default::default();
+            let mut max_voices = DEFAULT_MAX_VOICES;
+            let mut clicks: Vec<MetronomeClick> = Vec::new();
+            let mut drum_sounds: HashMap<Drum, Arc<[f32]>> = HashMap::new();
+            let mut drum_hits: Vec<(Arc<[f32]>, usize)> = Vec::new();
+
+            'audio_loop: loop {
+                while let Some(cmd) = pending.take().or_else(|| rx.try_recv().ok()) {
+                    match cmd {
+                        AudioCommand::LoadSample(path) => {
+                            if let Ok(data) = AudioEngine::load_wav(&path) {
+                                samples.insert(path, data);
+                            }
+                        }
+                        AudioCommand::LoadSampleFromBuffer(key, buffer) => {
+                            samples.insert(key, buffer);
+                        }
+                        AudioCommand::PlaySample(path, id, region, then) => {
+                            let sound = samples.get(&path).and_then(|data: &Vec<i16>| {
+                                let (cursor, end) = region.resolve(&path, data.len(), &cues)?;
+                                let looping = match then {
+                                    Some(then) => Some(then.resolve(&path, data.len(), &cues)?),
+                                    None => None,
+                                };
+                                Some(PlayingSound {
+                                    data: data.clone(),
+                                    cursor,
+                                    end,
+                                    // An empty loop would never finish.
+                                    looping: looping.filter(|&(start, end)| end > start + 1),
+                                    key: path.clone(),
+                                    id,
+                                })
+                            });
+                            match sound {
+                                Some(sound) => active_sounds.push(sound),
+                                None => thread_sounds.finish(id),
+                            }
+                        }
+                        AudioCommand::StopSample(id) => {
+                            active_sounds.retain(|s| {
+                                if s.id == id && s.key.starts_with(TEMP_SAMPLE_PREFIX) {
+                                    samples.remove(&s.key);
+                                }
+                                s.id != id
+                            });
+                            thread_sounds.finish(id);
+                        }
+                        AudioCommand::SetCue(path, name, start_ms, end_ms) => {
+                            cues.insert((path, name), (start_ms, end_ms));
+                        }
+                        AudioCommand::NoteOn(name, freq) => {
+                            let channel = channels.entry(name).or_default();
+                            let note =
+                                PlayingNote::new(freq, 1.0, channel.instrument.clone(), None);
+                            AudioEngine::add_voice(&mut channel.notes, note, max_voices);
+                        }
+                        AudioCommand::NoteOff(name, freq) => {
+                            if let Some(channel) = channels.get_mut(&name) {
+                                for note in channel.notes.iter_mut() {
+                                    if (note.freq - freq).abs() < f32::EPSILON && note.active {
+                                        note.release();
+                                    }
+                                }
+                            }
+                        }
+                        AudioCommand::AllNotesOff(name) => {
+                            if let Some(channel) = channels.get_mut(&name) {
+                                channel.notes.iter_mut().for_each(PlayingNote::release);
+                            }
+                        }
+                        AudioCommand::SetChannelInstrument(name, instrument) => {
+                            channels.entry(name).or_default().instrument = Arc::new(instrument);
+                        }
+                        AudioCommand::SetChannelVolume(name, volume) => {
+                            channels.entry(name).or_default().volume = volume;
+                        }
+                        AudioCommand::SetChannelPan(name, pan) => {
+                            channels.entry(name).or_default().pan = pan;
+                        }
+                        AudioCommand::SetVolume(v) => volume = v,
+                        AudioCommand::PlayMidi(events) => {
+                            AudioEngine::release_midi_notes(&mut midi_notes);
+                            midi = Some(MidiPlayback {
+                                events,
+                                next: 0,
+                                time: 0.0,
+                            });
+                        }
+                        AudioCommand::Drum(drum) => {
+                            let sound = drum_sounds
+                                .entry(drum)
+                                .or_insert_with(|| drum.render_mono().into());
+                            drum_hits.push((sound.clone(), 0));
+                        }
+                        AudioCommand::StopMidi => {
+                            AudioEngine::release_midi_notes(&mut midi_notes);
+                            midi = None;
+                        }
+                        AudioCommand::SetMaxVoices(max) => {
+                            max_voices = max.max(1);
+                            let lists = channels.values_mut().map(|c| &mut c.notes);
+                            for notes in lists.chain(std::iter::once(&mut midi_notes)) {
+                                while notes.len() > max_voices {
+                                    notes.remove(0);
+                                }
+                            }
+                        }
+                        AudioCommand::SetEffects(bus, chain) => {
+                            effects[bus as usize] =
+                                chain.into_iter().map(EffectProcessor::new).collect();
+                        }
+                        AudioCommand::SetMidiInstrument(channel, new) => {
+                            if let Some(i) = midi_instruments.get_mut(channel as usize) {
+                                *i = Arc::new(new);
+                            }
+                        }
+                        AudioCommand::Wake => {}
+                        AudioCommand::Quit => break 'audio_loop,
+                    }
+                }
+
+                let (before, after) = thread_clock.advance(CHUNK_SIZE as f32 / 44100.0);
+
+                let beats_per_bar = thread_clock.beats_per_bar.load(Relaxed).max(1) as u64;
+                if thread_clock.metronome.load(Relaxed) && after > before {
+                    // A freshly reset clock sits exactly on beat 0, which should click too.
+                    let mut beat = if before == 0.0 {
+                        0.0
+                    } else {
+                        before.floor() + 1.0
+                    };
+                    while beat <= after {
+                        let downbeat = beat as u64 % beats_per_bar == 0;
+                        clicks.push(MetronomeClick {
+                            delay: ((beat - before) / (after - before) * CHUNK_SIZE as f64)
+                                as usize,
+                            age: 0,
+                            freq: if downbeat { 1760.0 } else { 1320.0 },
+                            gain: if downbeat { 0.5 } else { 0.3 },
+                        });
+                        beat += 1.0;
+                    }
+                }
+
+                if let Some(song) = &mut midi {
+                    song.time += CHUNK_SIZE as f32 / 44100.0;
+                    while let Some(&event) = song.events.get(song.next) {
+                        if event.time > song.time {
+                            break;
+                        }
+                        song.next += 1;
+                        AudioEngine::apply_midi_event(
+                            &mut midi_notes,
+                            event,
+                            &midi_instruments,
+                            max_voices,
+                        );
+                    }
+                    if song.next >= song.events.len() {
+                        midi = None;
+                    }
+                }
+
+                let mut sfx_buffer = vec![0f32; CHUNK_SIZE * 2];
+                let mut music_buffer = vec![0f32; CHUNK_SIZE * 2];
+
+                for sound in active_sounds.iter_mut() {
+                    for i in 0..CHUNK_SIZE {
+                        let idx = i * 2;
+                        if sound.cursor + 1 >= sound.end {
+                            match sound.looping {
+                                Some((start, end)) => (sound.cursor, sound.end) = (start, end),
+                                None => break,
+                            }
+                        }
+                        sfx_buffer[idx] += sound.data[sound.cursor] as f32;
+                        sfx_buffer[idx + 1] += sound.data[sound.cursor + 1] as f32;
+                        sound.cursor += 2;
+                    }
+                }
+
+                for click in clicks.iter_mut() {
+                    for i in click.delay.min(CHUNK_SIZE)..CHUNK_SIZE {
+                        let t = click.age as f32 / 44100.0;
+                        let envelope = (1.0 - t / CLICK_LENGTH).max(0.0).powi(3);
+                        let s = (2.0 * PI * click.freq * t).sin() * envelope * click.gain;
+                        sfx_buffer[i * 2] += s * i16::MAX as f32;
+                        sfx_buffer[i * 2 + 1] += s * i16::MAX as f32;
+                        click.age += 1;
+                    }
+                    click.delay = click.delay.saturating_sub(CHUNK_SIZE);
+                }
+                clicks.retain(|c| !c.is_finished());
+
+                // Fixed gain per voice, so notes don't get quieter as others start.
+                let voice_gain = 0.6 / (max_voices as f32).sqrt();
+
+                for channel in channels.values_mut() {
+                    let (left, right) = channel.gains();
+                    AudioEngine::mix_notes(
+                        &mut channel.notes,
+                        &mut music_buffer,
+                        voice_gain * left,
+                        voice_gain * right,
+                    );
+                }
+                AudioEngine::mix_notes(&mut midi_notes, &mut music_buffer, voice_gain, voice_gain);
+
+                for (sound, cursor) in drum_hits.iter_mut() {
+                    let end = (*cursor + CHUNK_SIZE).min(sound.len());
+                    for (frame, &v) in music_buffer.chunks_exact_mut(2).zip(&sound[*cursor..end]) {
+                        frame[0] += v * i16::MAX as f32;
+                        frame[1] += v * i16::MAX as f32;
+                    }
+                    *cursor = end;
+                }
+                drum_hits.retain(|(sound, cursor)| *cursor < sound.len());
+
+                for effect in effects[Bus::Sfx as usize].iter_mut() {
+                    effect.process(&mut sfx_buffer);
+                }
+                // Soft-clip the notes so many voices at once saturate instead of clipping.
+                for s in music_buffer.iter_mut() {
+                    *s = (*s / i16::MAX as f32).tanh() * i16::MAX as f32;
+                }
+                for effect in effects[Bus::Music as usize].iter_mut() {
+                    effect.process(&mut music_buffer);
+                }
+                for (s, m) in sfx_buffer.iter_mut().zip(&music_buffer) {
+                    *s += m;
+                }
+                for effect in effects[Bus::Master as usize].iter_mut() {
+                    effect.process(&mut sfx_buffer);
+                }
+
+                let final_buffer: Vec<i16> = sfx_buffer
+                    .into_iter()
+                    .map(|s| (s * volume).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+                    .collect();
+
+                if let Ok(mut chunk) = thread_last_chunk.lock() {
+                    chunk.clear();
+                    chunk.extend(final_buffer.chunks_exact(2).map(|frame| {
+                        (frame[0] as f32 + frame[1] as f32) / (2.0 * i16::MAX as f32)
+                    }));
+                }
+
+                output.write(final_buffer);
+
+                active_sounds.retain(|s| {
+                    let playing = s.cursor + 1 < s.end || s.looping.is_some();
+                    if !playing {
+                        thread_sounds.finish(s.id);
+                        if s.key.starts_with(TEMP_SAMPLE_PREFIX) {
+                            samples.remove(&s.key);
+                        }
+                    }
+                    playing
+                });
+                for channel in channels.values_mut() {
+                    channel.notes.retain(|n| n.active);
+                }
+                midi_notes.retain(|n| n.active);
+
+                if !output.wait() {
+                    match AudioOutput::open(AudioBackend::WaveOut, &format) {
+                        Some(fallback) => output = fallback,
+                        None => break 'audio_loop,
+                    }
+                }
+            }
+        });
+
+        Self {
+            _shutdown: Arc::new(AudioShutdown(tx.clone())),
+            tx,
+            clock,
+            sounds,
+            last_chunk,
+            #[cfg(feature = "speech")]
+            speech: spawn_speech_thread(),
+        }
+    }
+
+    /// Loads a WAV file asynchronously.
+    ///
+    /// The sample can later be played using `play_sample`.
+    /// The path is used as the key to identify the sample.
+    /// Normally used in the `create` function when implementing the `ConsoleGame` trait.
+    pub fn load_sample<P: AsRef<Path>>(&self, path: P) {
+        let _ = self.tx.send(AudioCommand::LoadSample(
+            path.as_ref().to_string_lossy().into(),
+        ));
+    }
+
+    /// Plays a previously loaded sample asynchronously.
+    ///
+    /// Multiple instances of the same sample can play simultaneously. The returned id can be
+    /// checked with `is_playing`, and shows up in `finished_samples` once playback ends.
+    pub fn play_sample<P: AsRef<Path>>(&self, path: P) -> SoundId {
+        self.start_sample(path.as_ref().to_string_lossy().into())
+    }
+
+    /// Returns `true` while the sample playback `id` is still going.
+    pub fn is_playing(&self, id: SoundId) -> bool {
+        self.sounds.playing.lock().unwrap().contains(&id.0)
+    }
+
+    /// Returns the sample playbacks that have finished since the last call, e.g. to chain a
+    /// reload click after a shot or start the next music track.
+    ///
+    /// Playing a sample that was never loaded finishes immediately. Only the last 256
+    /// finished playbacks are kept between calls.
+    pub fn finished_samples(&self) -> Vec<SoundId> {
+        std::mem::take(&mut *self.sounds.finished.lock().unwrap())
+    }
+
+    /// Plays the part of a previously loaded sample between `start_ms` and `end_ms`, e.g.
+    /// one of several effects packed into a single WAV file.
+    pub fn play_sample_region<P: AsRef<Path>>(
+        &self,
+        path: P,
+        start_ms: u32,
+        end_ms: u32,
+    ) -> SoundId {
+        let region = SampleRegion::Millis(start_ms, end_ms);
+        self.start_region(path.as_ref().to_string_lossy().into(), region, None)
+    }
+
+    /// Names the part of a sample between `start_ms` and `end_ms`, so it can be played with
+    /// `play_cue`, `loop_cue` or `play_intro_then_loop`.
+    ///
+    /// Cues can be set before the sample has finished loading. Setting a cue again moves it.
+    pub fn set_cue<P: AsRef<Path>>(&self, path: P, name: &str, start_ms: u32, end_ms: u32) {
+        let _ = self.tx.send(AudioCommand::SetCue(
+            path.as_ref().to_string_lossy().into(),
+            name.to_string(),
+            start_ms,
+            end_ms,
+        ));
+    }
+
+    /// Plays a cue set with `set_cue` once. Playing a cue that was never set finishes
+    /// immediately.
+    pub fn play_cue<P: AsRef<Path>>(&self, path: P, cue: &str) -> SoundId {
+        let region = SampleRegion::Cue(cue.to_string());
+        self.start_region(path.as_ref().to_string_lossy().into(), region, None)
+    }
+
+    /// Plays a cue set with `set_cue` over and over until `stop_sample` is called.
+    pub fn loop_cue<P: AsRef<Path>>(&self, path: P, cue: &str) -> SoundId {
+        let region = SampleRegion::Cue(cue.to_string());
+        let key = path.as_ref().to_string_lossy().into();
+        self.start_region(key, region.clone(), Some(region))
+    }
+
+    /// Plays the `intro` cue once and then seamlessly repeats the `looped` cue until
+    /// `stop_sample` is called, for music tracks with an intro.
+    ///
+    /// ```rust,ignore
+    /// engine.audio.load_sample("theme.wav");
+    /// engine.audio.set_cue("theme.wav", "intro", 0, 6400);
+    /// engine.audio.set_cue("theme.wav", "loop", 6400, 38400);
+    /// self.music = engine.audio.play_intro_then_loop("theme.wav", "intro", "loop");
+    /// ```
+    pub fn play_intro_then_loop<P: AsRef<Path>>(
+        &self,
+        path: P,
+        intro: &str,
+        looped: &str,
+    ) -> SoundId {
+        self.start_region(
+            path.as_ref().to_string_lossy().into(),
+            SampleRegion::Cue(intro.to_string()),
+            Some(SampleRegion::Cue(looped.to_string())),
+        )
+    }
+
+    /// Stops the sample playback `id`, which then shows up in `finished_samples`.
+    pub fn stop_sample(&self, id: SoundId) {
+        let _ = self.tx.send(AudioCommand::StopSample(id.0));
+    }
+
+    fn start_sample(&self, key: String) -> SoundId {
+        self.start_region(key, SampleRegion::Whole, None)
+    }
+
+    fn start_region(
+        &self,
+        key: String,
+        region: SampleRegion,
+        then: Option<SampleRegion>,
+    ) -> SoundId {
+        let id = SOUND_COUNTER.fetch_add(1, Relaxed);
+        // Temporary samples (`play_note`, `beep`, ...) never hand their id to the caller,
+        // so they aren't tracked.
+        if !key.starts_with(TEMP_SAMPLE_PREFIX) {
+            self.sounds.playing.lock().unwrap().insert(id);
+        }
+        let _ = self
+            .tx
+            .send(AudioCommand::PlaySample(key, id, region, then));
+        SoundId(id)
+    }
+
+    /// Generates and plays a single note of the given frequency (Hz) and duration (ms).
+    ///
+    /// Useful for procedural audio or simple effects.
+    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
+    pub fn play_note(&self, frequency: f32, duration_ms: u32) {
+        let sample_rate = 44100;
+        let sample_count = ((duration_ms as f32 / 1000.0) * sample_rate as f32) as usize;
+        if sample_count == 0 {
+            return;
+        }
+
+        let mut mono = vec![0f32; sample_count];
+        for (n, v) in mono.iter_mut().enumerate() {
+            let t = n as f32 / sample_rate as f32;
+            *v = (t * frequency * 2.0 * PI).sin();
+        }
+
+        Self::apply_attack_release(&mut mono, sample_rate, duration_ms);
+
+        let mut stereo = vec![0i16; sample_count * 2];
+        let scale = 0.95;
+        for (n, &v) in mono.iter().enumerate() {
+            let s = (v * scale * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
+            let si = s as i16;
+            stereo[n * 2] = si;
+            stereo[n * 2 + 1] = si;
+        }
+
+        let key = Self::generate_unique_key();
+        let _ = self
+            .tx
+            .send(AudioCommand::LoadSampleFromBuffer(key.clone(), stereo));
+        self.start_sample(key);
+    }
+
+    /// Generates and plays multiple notes simultaneously (like a chord).
+    ///
+    /// Each frequency in `freqs` is mixed together, scaled to avoid clipping,
+    /// and played for the given duration (ms).
+    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
+    pub fn play_notes(&self, freqs: &[f32], duration_ms: u32) {
+        if freqs.is_empty() {
+            return;
+        }
+        let sample_rate = 44100u32;
+        let sample_count = ((duration_ms as f32 / 1000.0) * sample_rate as f32) as usize;
+        if sample_count == 0 {
+            return;
+        }
+
+        let mut mono = vec![0f32; sample_count];
+        for &freq in freqs {
+            for (n, v) in mono.iter_mut().enumerate() {
+                let t = n as f32 / sample_rate as f32;
+                *v += (t * freq * 2.0 * PI).sin();
+            }
+        }
+
+        let max_possible = freqs.len() as f32;
+        let scale = 0.9 / max_possible;
+        for v in mono.iter_mut() {
+            *v *= scale;
+        }
+
+        Self::apply_attack_release(&mut mono, sample_rate, duration_ms);
+
+        let mut stereo = vec![0i16; sample_count * 2];
+        for (n, &v) in mono.iter().enumerate() {
+            let s = (v * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
+            let si = s as i16;
+            stereo[n * 2] = si;
+            stereo[n * 2 + 1] = si;
+        }
+
+        let key = Self::generate_unique_key();
+        let _ = self
+            .tx
+            .send(AudioCommand::LoadSampleFromBuffer(key.clone(), stereo));
+        self.start_sample(key);
+    }
+
+    /// Plays a sound from the Windows sound scheme, e.g. `SystemSound::Asterisk`, for quick
+    /// feedback without shipping any WAV files.
+    ///
+    /// System sounds play outside the engine's mixer, so the volume and effects don't apply.
+    /// If the system has no sound set for it, a synthesized beep is played instead.
+    pub fn play_system_sound(&self, sound: SystemSound) {
+        let alias = HSTRING::from(sound.alias());
+        let flags = SND_ALIAS | SND_ASYNC | SND_NODEFAULT | SND_SYSTEM;
+        if !unsafe { PlaySoundW(&alias, None, flags) }.as_bool() {
+            let (freq, duration_ms) = sound.fallback();
+            self.play_note(freq, duration_ms);
+        }
+    }
+
+    /// Plays a short synthesized beep through the engine's mixer.
+    pub fn beep(&self) {
+        let (freq, duration_ms) = SystemSound::Default.fallback();
+        self.play_note(freq, duration_ms);
+    }
+
+    /// Reads `text` aloud with the system's text-to-speech voice, after anything already
+    /// being spoken, e.g. for narrated menus.
+    ///
+    /// ```rust,ignore
+    /// engine.audio.stop_speaking();
+    /// engine.audio.speak("Game over");
+    /// ```
+    ///
+    /// Speech plays outside the engine's mixer, so the volume and effects don't apply; use
+    /// `set_speech_volume` instead.
+    #[cfg(feature = "speech")]
+    pub fn speak(&self, text: &str) {
+        let _ = self.speech.send(SpeechCommand::Speak(text.to_string()));
+    }
+
+    /// Stops the current speech and drops any that is queued, e.g. before reading out the
+    /// newly selected menu item.
+    #[cfg(feature = "speech")]
+    pub fn stop_speaking(&self) {
+        let _ = self.speech.send(SpeechCommand::Stop);
+    }
+
+    /// Sets how fast `speak` talks, from -10 (slowest) to 10 (fastest). 0 is the normal
+    /// speed.
+    #[cfg(feature = "speech")]
+    pub fn set_speech_rate(&self, rate: i32) {
+        let _ = self
+            .speech
+            .send(SpeechCommand::SetRate(rate.clamp(-10, 10)));
+    }
+
+    /// Sets the volume of `speak`, from `0.0` (silent) to `1.0` (full volume, the default).
+    #[cfg(feature = "speech")]
+    pub fn set_speech_volume(&self, volume: f32) {
+        let volume = (volume.clamp(0.0, 1.0) * 100.0).round() as u16;
+        let _ = self.speech.send(SpeechCommand::SetVolume(volume));
+    }
+
+    /// Starts playing a note of the given frequency (Hz) immediately on the named synth
+    /// channel.
+    ///
+    /// Each channel has its own instrument, volume, pan and voices, so a melody, a bass line
+    /// and drums can play together without cutting each other off. Channels are created the
+    /// first time they're used, playing a sine wave at full volume in the centre.
+    ///
+    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
+    ///
+    /// ```rust,ignore
+    /// engine.audio.set_channel_instrument("bass", Instrument::bass());
+    /// engine.audio.set_channel_pan("bass", -0.3);
+    /// engine.audio.note_on("melody", E5);
+    /// engine.audio.note_on("bass", E3);
+    /// ```
+    pub fn note_on(&self, channel: &str, freq: f32) {
+        let _ = self
+            .tx
+            .send(AudioCommand::NoteOn(channel.to_string(), freq));
+    }
+
+    /// Stops a previously started note of the given frequency (Hz) on the named channel.
+    ///
+    /// Normally used in conjunction with the note constants (A4, C_SHARP5, E5)
+    /// and with `note_on` to control sustained notes.
+    pub fn note_off(&self, channel: &str, freq: f32) {
+        let _ = self
+            .tx
+            .send(AudioCommand::NoteOff(channel.to_string(), freq));
+    }
+
+    /// Stops every note playing on the named channel.
+    pub fn all_notes_off(&self, channel: &str) {
+        let _ = self.tx.send(AudioCommand::AllNotesOff(channel.to_string()));
+    }
+
+    /// Sets the instrument of the named channel. Notes already playing keep their
+    /// instrument.
+    pub fn set_channel_instrument(&self, channel: &str, instrument: Instrument) {
+        let _ = self.tx.send(AudioCommand::SetChannelInstrument(
+            channel.to_string(),
+            instrument,
+        ));
+    }
+
+    /// Sets the volume of the named channel, from `0.0` (silent) to `1.0` (the default).
+    pub fn set_channel_volume(&self, channel: &str, volume: f32) {
+        let _ = self.tx.send(AudioCommand::SetChannelVolume(
+            channel.to_string(),
+            volume.max(0.0),
+        ));
+    }
+
+    /// Sets where the named channel sits between the speakers, from `-1.0` (left) through
+    /// `0.0` (centre, the default) to `1.0` (right).
+    pub fn set_channel_pan(&self, channel: &str, pan: f32) {
+        let _ = self.tx.send(AudioCommand::SetChannelPan(
+            channel.to_string(),
+            pan.clamp(-1.0, 1.0),
+        ));
+    }
+
+    /// Sets how many notes can play at once on each synth channel, and in the MIDI song
+    /// (16 by default).
+    ///
+    /// Starting a note when all voices are busy stops the oldest one, preferring notes that
+    /// are already fading out. Every voice is mixed at the same fixed volume, so fewer
+    /// voices means louder notes.
+    pub fn set_max_voices(&self, max: usize) {
+        let _ = self.tx.send(AudioCommand::SetMaxVoices(max));
+    }
+
+    /// Sets the master volume applied to everything that is played.
+    ///
+    /// `1.0` is full volume (the default) and `0.0` is silent.
+    pub fn set_volume(&self, volume: f32) {
+        let _ = self.tx.send(AudioCommand::SetVolume(volume.max(0.0)));
+    }
+
+    /// Replaces the effects on `bus` with a single effect, e.g.
+    /// `audio.set_effect(Bus::Sfx, Effect::LowPass(800.0))` while underwater.
+    pub fn set_effect(&self, bus: Bus, effect: Effect) {
+        self.set_effects(bus, &[effect]);
+    }
+
+    /// Replaces the effects on `bus` with a chain of effects, applied in order.
+    pub fn set_effects(&self, bus: Bus, effects: &[Effect]) {
+        let _ = self
+            .tx
+            .send(AudioCommand::SetEffects(bus, effects.to_vec()));
+    }
+
+    /// Removes all effects from `bus`.
+    pub fn clear_effects(&self, bus: Bus) {
+        self.set_effects(bus, &[]);
+    }
+
+    /// Sets the tempo of the beat clock in beats per minute and starts it. A tempo of 0
+    /// stops the clock (the default).
+    ///
+    /// The clock is driven by the audio thread, so beats stay in time with the music
+    /// regardless of the frame rate. Changing the tempo keeps the current position.
+    pub fn set_bpm(&self, bpm: f32) {
+        self.clock.bpm.store(bpm.max(0.0).to_bits(), Relaxed);
+        // The clock is advanced by the audio thread, which waits for its first command.
+        let _ = self.tx.send(AudioCommand::Wake);
+    }
+
+    /// Returns the tempo of the beat clock in beats per minute.
+    pub fn bpm(&self) -> f32 {
+        f32::from_bits(self.clock.bpm.load(Relaxed))
+    }
+
+    /// Returns how many beats have started since the last call, usually 0 or 1 per frame.
+    ///
+    /// Call it once per `update` and react to beats when it is non-zero:
+    ///
+    /// ```rust,ignore
+    /// if engine.audio.beats_elapsed() > 0 {
+    ///     self.pulse = 1.0;
+    /// }
+    /// ```
+    pub fn beats_elapsed(&self) -> u32 {
+        let beat = self.beat_position().floor() as u64;
+        let seen = self.clock.seen.swap(beat, Relaxed);
+        beat.saturating_sub(seen) as u32
+    }
+
+    /// Returns the number of beats since the clock was reset, including the fraction of the
+    /// current beat, e.g. for animations that pulse with the music.
+    pub fn beat_position(&self) -> f64 {
+        f64::from_bits(self.clock.position.load(Relaxed))
+    }
+
+    /// Starts the beat clock at `bpm` from beat 0 with a metronome clicking every beat, the
+    /// first beat of each bar of `beats_per_bar` beats higher and louder.
+    ///
+    /// The clicks play on [`Bus::Sfx`]. Use `beat_in_bar` and `beat_phase` to follow along,
+    /// e.g. to grade a rhythm game's button presses by how close they are to a beat.
+    pub fn start_metronome(&self, bpm: f32, beats_per_bar: u32) {
+        self.clock
+            .beats_per_bar
+            .store(beats_per_bar.max(1), Relaxed);
+        self.clock.metronome.store(true, Relaxed);
+        self.set_bpm(bpm);
+        // Reset last, so the audio thread can't move past beat 0 before it knows to click.
+        self.reset_beat_clock();
+    }
+
+    /// Stops the metronome clicking. The beat clock keeps running; stop it with
+    /// `set_bpm(0.0)`.
+    pub fn stop_metronome(&self) {
+        self.clock.metronome.store(false, Relaxed);
+    }
+
+    /// Returns `true` while the metronome is clicking.
+    pub fn metronome_running(&self) -> bool {
+        self.clock.metronome.load(Relaxed)
+    }
+
+    /// Returns how far through the current beat the clock is, from 0 (on the beat) up to 1.
+    pub fn beat_phase(&self) -> f32 {
+        self.beat_position().fract() as f32
+    }
+
+    /// Returns which beat of the bar the clock is on, starting from 0 on the downbeat. Bars
+    /// are as long as set by `start_metronome`, or 4 beats if it hasn't been started.
+    pub fn beat_in_bar(&self) -> u32 {
+        let beats_per_bar = match self.clock.beats_per_bar.load(Relaxed) {
+            0 => 4,
+            n => n,
+        };
+        (self.beat_position().floor() as u64 % beats_per_bar as u64) as u32
+    }
+
+    /// Moves the beat clock back to beat 0, e.g. when a song starts.
+    pub fn reset_beat_clock(&self) {
+        self.clock.position.store(0f64.to_bits(), Relaxed);
+        self.clock.seen.store(0, Relaxed);
+    }
+
+    /// Returns the last chunk of audio sent to the speakers, mixed down to mono, with
+    /// samples between -1 and 1. Useful for drawing an oscilloscope.
+    pub fn waveform(&self) -> Vec<f32> {
+        self.last_chunk.lock().unwrap().clone()
+    }
+
+    /// Returns how loud the last chunk of audio sent to the speakers is across `bins`
+    /// frequency bands, from bass to treble, for music visualizers and rhythm effects.
+    ///
+    /// The bands are spaced logarithmically from 20 Hz up, like the notes of a piano, and
+    /// each value is roughly between 0 (silent) and 1 (a full-volume tone).
+    ///
+    /// ```rust,ignore
+    /// for (i, level) in engine.audio.spectrum(32).into_iter().enumerate() {
+    ///     let h = (level * 20.0) as i32;
+    ///     engine.fill_rect_with(i as i32 * 2, 20 - h, i as i32 * 2 + 1, 20, SOLID, FG_GREEN);
+    /// }
+    /// ```
+    pub fn spectrum(&self, bins: usize) -> Vec<f32> {
+        let samples = self.waveform();
+        let n = samples.len();
+        if bins == 0 || n < 2 {
+            return vec![0.0; bins];
+        }
+
+        // Hann window, so the edges of the chunk don't smear energy into every band.
+        let mut re: Vec<f32> = samples
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| s * (0.5 - 0.5 * (2.0 * PI * i as f32 / (n - 1) as f32).cos()))
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+
+        // The window halves the amplitude and the negative frequencies hold the other half.
+        let scale = 4.0 / n as f32;
+        let magnitudes: Vec<f32> = (0..n / 2)
+            .map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt() * scale)
+            .collect();
+
+        let sample_rate = 44100.0;
+        let (low, high) = (20.0f32, sample_rate / 2.0);
+        let bin_of = |freq: f32| ((freq * n as f32 / sample_rate) as usize).min(n / 2);
+        (0..bins)
+            .map(|b| {
+                let start = bin_of(low * (high / low).powf(b as f32 / bins as f32));
+                let end = bin_of(low * (high / low).powf((b + 1) as f32 / bins as f32));
+                let start = start.min(n / 2 - 1);
+                magnitudes[start..end.max(start + 1)]
+                    .iter()
+                    .fold(0.0, |max: f32, &m| max.max(m))
+            })
+            .collect()
+    }
+
+    /// Loads a buffer of samples asynchronously so it can be played with `play_sample(name)`.
+    ///
+    /// `samples` is 44100 Hz 16-bit stereo, left and right interleaved, such as the
+    /// buffers made by [`SfxGenerator`]. Loading again under the same name replaces it.
+    pub fn load_sample_from_buffer(&self, name: &str, samples: Vec<i16>) {
+        let _ = self.tx.send(AudioCommand::LoadSampleFromBuffer(
+            name.to_string(),
+            samples,
+        ));
+    }
+
+    fn apply_attack_release(buffer: &mut [f32], sample_rate: u32, duration_ms: u32) {
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+
+        let ramp_pct = 0.10;
+        let ramp_samps =
+            ((duration_ms as f32 / 1000.0) * sample_rate as f32 * ramp_pct).round() as usize;
+        let ramp_samps = ramp_samps.min(len / 2);
+
+        for (i, v) in buffer.iter_mut().take(ramp_samps).enumerate() {
+            *v *= i as f32 / ramp_samps.max(1) as f32;
+        }
+
+        for i in 0..ramp_samps {
+            let idx = len - ramp_samps + i;
+            buffer[idx] *= 1.0 - (i as f32 / ramp_samps as f32);
+        }
+    }
+
+    /// Plays a synthesized drum hit on [`Bus::Music`], e.g. `audio.drum(Drum::Kick)`.
+    ///
+    /// Together with `note_on` and the beat clock this is enough for fully procedural
+    /// music:
+    ///
+    /// ```rust,ignore
+    /// for _ in 0..engine.audio.beats_elapsed() {
+    ///     match engine.audio.beat_in_bar() {
+    ///         0 | 2 => engine.audio.drum(Drum::Kick),
+    ///         _ => engine.audio.drum(Drum::Snare),
+    ///     }
+    ///     engine.audio.drum(Drum::HiHat);
+    /// }
+    /// ```
+    pub fn drum(&self, drum: Drum) {
+        let _ = self.tx.send(AudioCommand::Drum(drum));
+    }
+
+    /// Parses a standard MIDI file (`.mid`) and plays it through the note synthesizer,
+    /// replacing any song that is already playing.
+    ///
+    /// Every channel is played with the instrument set by `set_midi_instrument` (a square
+    /// wave by default), at a volume following the note velocity. Tempo changes are honoured;
+    /// program changes and controllers are ignored, and the drum channel (channel 10) is
+    /// skipped.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a valid MIDI file. SMPTE time
+    /// division is not supported.
+    pub fn play_midi<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = std::fs::read(path)?;
+        let events = parse_midi(&bytes)?;
+        let _ = self.tx.send(AudioCommand::PlayMidi(events));
+        Ok(())
+    }
+
+    /// Stops the MIDI song started with `play_midi`, releasing its notes.
+    pub fn stop_midi(&self) {
+        let _ = self.tx.send(AudioCommand::StopMidi);
+    }
+
+    /// Sets the waveform used for notes on a MIDI channel (0 to 15).
+    pub fn set_midi_waveform(&self, channel: u8, wave: Waveform) {
+        self.set_midi_instrument(channel, Self::midi_instrument(wave));
+    }
+
+    /// Sets the instrument used for notes on a MIDI channel (0 to 15), e.g.
+    /// `Instrument::bass()` on the bass line's channel.
+    pub fn set_midi_instrument(&self, channel: u8, instrument: Instrument) {
+        let _ = self
+            .tx
+            .send(AudioCommand::SetMidiInstrument(channel, instrument));
+    }
+
+    /// Returns the instrument MIDI channels play with `set_midi_waveform`, which starts
+    /// notes quicker than `note_on` so fast passages stay crisp.
+    fn midi_instrument(wave: Waveform) -> Instrument {
+        Instrument {
+            attack: 0.005,
+            ..Instrument::new(wave)
+        }
+    }
+
+    /// Adds the next chunk of `notes` to the interleaved stereo `buffer`.
+    fn mix_notes(notes: &mut [PlayingNote], buffer: &mut [f32], left: f32, right: f32) {
+        for note in notes.iter_mut().filter(|n| n.active) {
+            for frame in buffer.chunks_exact_mut(2) {
+                let s = note.next_sample(44100.0) * i16::MAX as f32;
+                frame[0] += s * left;
+                frame[1] += s * right;
+            }
+        }
+    }
+
+    fn release_midi_notes(notes: &mut [PlayingNote]) {
+        for note in notes.iter_mut().filter(|n| n.midi.is_some()) {
+            note.release();
+        }
+    }
+
+    /// Adds a voice, stealing the oldest one if `max_voices` are already playing. Voices
+    /// that are already fading out are stolen first.
+    fn add_voice(notes: &mut Vec<PlayingNote>, note: PlayingNote, max_voices: usize) {
+        while notes.len() >= max_voices {
+            let victim = notes.iter().position(|n| n.is_released()).unwrap_or(0);
+            notes.remove(victim);
+        }
+        notes.push(note);
+    }
+
+    fn apply_midi_event(
+        notes: &mut Vec<PlayingNote>,
+        event: MidiEvent,
+        instruments: &[Arc<Instrument>; 16],
+        max_voices: usize,
+    ) {
+        let id = Some((event.channel, event.key));
+        if event.velocity == 0 {
+            for note in notes
+                .iter_mut()
+                .filter(|n| n.midi == id && !n.is_released())
+            {
+                note.release();
+            }
+            return;
+        }
+
+        let note = PlayingNote::new(
+            440.0 * 2f32.powf((event.key as f32 - 69.0) / 12.0),
+            event.velocity as f32 / 127.0,
+            instruments[event.channel as usize & 0x0F].clone(),
+            id,
+        );
+        Self::add_voice(notes, note, max_voices);
+    }
+
+    fn generate_unique_key() -> String {
+        let id = NOTE_COUNTER.fetch_add(1, Relaxed);
+        format!("{}{}", TEMP_SAMPLE_PREFIX, id)
+    }
+
+    fn load_wav(path: &str) -> std::io::Result<Vec<i16>> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let data_start = buf.windows(4).position(|w| w == b"data").unwrap() + 8;
+        let samples: Vec<i16> = buf[data_start..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        Ok(samples)
+    }
+
+    fn play_buffer(h_waveout: HWAVEOUT, data: Vec<i16>) {
+        let boxed_data = Box::new(data);
+        let raw_data = Box::into_raw(boxed_data);
+
+        let mut hdr = Box::new(WAVEHDR {
+            lpData: PSTR(unsafe { (*raw_data).as_ptr() as *mut u8 }),
+            dwBufferLength: (unsafe { (*raw_data).len() * 2 } as u32),
+            dwFlags: 0,
+            dwLoops: 0,
+            dwUser: raw_data as usize,
+            ..Default::default()
+        });
+
+        unsafe {
+            waveOutPrepareHeader(h_waveout, &mut *hdr, std::mem::size_of::<WAVEHDR>() as u32);
+            waveOutWrite(h_waveout, &mut *hdr, std::mem::size_of::<WAVEHDR>() as u32);
+        }
+
+        let _ = Box::into_raw(hdr);
+    }
+}
+
+/// In-place radix-2 FFT. The length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Reads a MIDI variable-length quantity.
+fn read_vlq(data: &[u8], pos: &mut usize) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let byte = *data.get(*pos).ok_or("unexpected end of MIDI track")?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("invalid variable-length value in MIDI track".into())
+}
+
+/// Returns the id and body of the MIDI chunk starting at `pos`.
+fn midi_chunk(bytes: &[u8], pos: usize) -> Option<(&[u8], &[u8])> {
+    let header = bytes.get(pos..pos + 8)?;
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    Some((&header[0..4], bytes.get(pos + 8..pos + 8 + len)?))
+}
+
+/// Parses a standard MIDI file into note events sorted by time, with tempo changes applied.
+fn parse_midi(bytes: &[u8]) -> Result<Vec<MidiEvent>, Box<dyn std::error::Error>> {
+    let chunk = |pos: usize| midi_chunk(bytes, pos).ok_or("unexpected end of MIDI file");
+
+    let (id, header) = chunk(0)?;
+    if id != b"MThd" || header.len() < 6 {
+        return Err("not a MIDI file".into());
+    }
+    let tracks = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 {
+        return Err("SMPTE time division is not supported".into());
+    }
+    let ticks_per_beat = division.max(1) as f64;
+
+    // (tick, order, Some(note) or None for a tempo change, tempo in µs per beat)
+    let mut raw: Vec<(u64, usize, Option<MidiEvent>, u32)> = Vec::new();
+    let mut pos = 8 + header.len();
+
+    for _ in 0..tracks {
+        let (id, track) = chunk(pos)?;
+        pos += 8 + track.len();
+        if id != b"MTrk" {
+            continue;
+        }
+
+        let mut p = 0;
+        let mut tick = 0u64;
+        let mut running = 0u8;
+        while p < track.len() {
+            tick += read_vlq(track, &mut p)? as u64;
+            let mut status = *track.get(p).ok_or("unexpected end of MIDI track")?;
+            if status & 0x80 != 0 {
+                p += 1;
+            } else {
+                status = running;
+            }
+
+            match status {
+                0xFF => {
+                    let kind = *track.get(p).ok_or("unexpected end of MIDI track")?;
+                    p += 1;
+                    let len = read_vlq(track, &mut p)? as usize;
+                    let data = track
+                        .get(p..p + len)
+                        .ok_or("unexpected end of MIDI track")?;
+                    p += len;
+                    if kind == 0x51 && len == 3 {
+                        let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                        raw.push((tick, raw.len(), None, tempo));
+                    } else if kind == 0x2F {
+                        break;
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    let len = read_vlq(track, &mut p)? as usize;
+                    p += len;
+                }
+                0x80..=0xEF => {
+                    running = status;
+                    let len = if matches!(status & 0xF0, 0xC0 | 0xD0) {
+                        1
+                    } else {
+                        2
+                    };
+                    let data = track
+                        .get(p..p + len)
+                        .ok_or("unexpected end of MIDI track")?;
+                    p += len;
+
+                    let channel = status & 0x0F;
+                    let kind = status & 0xF0;
+                    if (kind == 0x80 || kind == 0x90) && channel != MIDI_DRUM_CHANNEL {
+                        let velocity = if kind == 0x80 { 0 } else { data[1] };
+                        let event = MidiEvent {
+                            time: 0.0,
+                            channel,
+                            key: data[0],
+                            velocity,
+                        };
+                        raw.push((tick, raw.len(), Some(event), 0));
+                    }
+                }
+                _ => return Err(format!("invalid MIDI status byte {:#04x}", status).into()),
+            }
+        }
+    }
+
+    raw.sort_by_key(|&(tick, order, _, _)| (tick, order));
+
+    let mut events = Vec::new();
+    let mut tempo = 500_000.0; // µs per beat, i.e. 120 BPM
+    let (mut last_tick, mut seconds) = (0u64, 0.0f64);
+    for (tick, _, event, new_tempo) in raw {
+        seconds += (tick - last_tick) as f64 / ticks_per_beat * tempo / 1_000_000.0;
+        last_tick = tick;
+        match event {
+            Some(mut event) => {
+                event.time = seconds as f32;
+                events.push(event);
+            }
+            None => tempo = new_tempo as f64,
+        }
+    }
+
+    Ok(events)
+}
+
+/// A percussion sound played with `AudioEngine::drum`, synthesized from pitch sweeps and
+/// filtered noise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Drum {
+    /// A sine wave sweeping down from 150 Hz, with a click on top.
+    Kick,
+    /// A short low tone under a burst of high-passed noise.
+    Snare,
+    /// A very short tick of high-passed noise.
+    HiHat,
+    /// High-passed noise left to ring out.
+    OpenHiHat,
+}
+
+impl Drum {
+    /// Returns the length of the sound in seconds.
+    fn length(self) -> f32 {
+        match self {
+            Drum::Kick => 0.4,
+            Drum::Snare => 0.25,
+            Drum::HiHat => 0.08,
+            Drum::OpenHiHat => 0.4,
+        }
+    }
+
+    /// Renders the drum to 44100 Hz 16-bit stereo samples, ready for
+    /// `AudioEngine::load_sample_from_buffer`.
+    pub fn render(self) -> Vec<i16> {
+        self.render_mono()
+            .into_iter()
+            .flat_map(|v| {
+                let s = (v * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                [s, s]
+            })
+            .collect()
+    }
+
+    /// Renders the drum to mono samples between -1 and 1.
+    fn render_mono(self) -> Vec<f32> {
+        let sample_rate = 44100.0;
+        let length = self.length();
+        let count = (length * sample_rate) as usize;
+
+        let mut noise = SfxGenerator::new(self as u64);
+        // One-pole high-pass filter, so the noise hisses instead of rumbling.
+        let cutoff = match self {
+            Drum::Kick => 20.0,
+            Drum::Snare => 1500.0,
+            Drum::HiHat | Drum::OpenHiHat => 7000.0,
+        };
+        let alpha = 1.0 / (1.0 + 2.0 * PI * cutoff / sample_rate);
+        let (mut last_in, mut last_out) = (0.0f32, 0.0f32);
+
+        let mut phase = 0.0f32;
+        let mut out = Vec::with_capacity(count);
+        for n in 0..count {
+            let t = n as f32 / sample_rate;
+
+            let white = noise.range(-1.0, 1.0);
+            let hiss = alpha * (last_out + white - last_in);
+            (last_in, last_out) = (white, hiss);
+
+            let v = match self {
+                Drum::Kick => {
+                    let freq = 45.0 + 105.0 * (-t * 30.0).exp();
+                    phase += 2.0 * PI * freq / sample_rate;
+                    phase.sin() * (-t * 8.0).exp() + white * 0.3 * (-t * 300.0).exp()
+                }
+                Drum::Snare => {
+                    phase += 2.0 * PI * 180.0 / sample_rate;
+                    phase.sin() * 0.5 * (-t * 20.0).exp() + hiss * 1.2 * (-t * 12.0).exp()
+                }
+                Drum::HiHat => hiss * 1.5 * (-t * 60.0).exp(),
+                Drum::OpenHiHat => hiss * 1.2 * (-t * 8.0).exp(),
+            };
+
+            // Fade out over the last 5 ms so the cut doesn't click.
+            let fade = ((length - t) / 0.005).min(1.0);
+            out.push((v * fade * 0.8).clamp(-1.0, 1.0));
+        }
+        out
+    }
+}
+
+/// Waveform of a sound effect made by [`SfxGenerator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SfxWave {
+    Square,
+    Saw,
+    Sine,
+    Noise,
+}
+
+/// The kinds of sound effect [`SfxGenerator`] has presets for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SfxKind {
+    Coin,
+    Laser,
+    Explosion,
+    Jump,
+    Hit,
+}
+
+/// Parameters of a procedurally generated sound effect. Times are in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SfxParams {
+    pub wave: SfxWave,
+    /// Starting pitch in Hz. For noise, how often the noise changes value.
+    pub base_freq: f32,
+    /// Pitch change in octaves per second, negative to slide down.
+    pub freq_slide: f32,
+    /// Time to fade in.
+    pub attack: f32,
+    /// Time held at full volume after the attack.
+    pub sustain: f32,
+    /// Extra volume at the start of the sustain, fading out over it (0 to 1).
+    pub punch: f32,
+    /// Time to fade out after the sustain.
+    pub decay: f32,
+    /// Fraction of each square wave period that is high (0 to 1).
+    pub duty: f32,
+    /// Change of `duty` per second.
+    pub duty_slide: f32,
+    /// Vibrato strength as a fraction of the pitch.
+    pub vibrato_depth: f32,
+    /// Vibrato speed in Hz.
+    pub vibrato_speed: f32,
+    /// Pitch multiplier applied once `arp_time` has passed. 1 disables it.
+    pub arp_mult: f32,
+    /// Time of the pitch jump.
+    pub arp_time: f32,
+    /// Overall volume (0 to 1).
+    pub volume: f32,
+}
+
+impl Default for SfxParams {
+    fn default() -> Self {
+        Self {
+            wave: SfxWave::Square,
+            base_freq: 440.0,
+            freq_slide: 0.0,
+            attack: 0.0,
+            sustain: 0.1,
+            punch: 0.0,
+            decay: 0.2,
+            duty: 0.5,
+            duty_slide: 0.0,
+            vibrato_depth: 0.0,
+            vibrato_speed: 0.0,
+            arp_mult: 1.0,
+            arp_time: 0.0,
+            volume: 0.5,
+        }
+    }
+}
+
+/// Generates classic retro sound effects (coins, lasers, explosions, ...) without any
+/// WAV files, in the spirit of sfxr.
+///
+/// Every call to `params` or `generate` gives a new variation; the same seed always gives
+/// the same sequence of sounds.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut sfx = SfxGenerator::new(42);
+/// engine.audio.load_sample_from_buffer("coin", sfx.generate(SfxKind::Coin));
+/// // later
+/// engine.audio.play_sample("coin");
+/// ```
+#[derive(Debug, Clone)]
+pub struct SfxGenerator {
+    rng: procgen::Rng,
+}
+
+impl SfxGenerator {
+    /// Creates a generator whose variations are determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: procgen::Rng::new(seed),
+        }
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Returns the parameters for a new variation of the given kind of effect, to tweak
+    /// before passing them to `render`.
+    pub fn params(&mut self, kind: SfxKind) -> SfxParams {
+        let base = SfxParams::default();
+        match kind {
+            SfxKind::Coin => SfxParams {
+                base_freq: self.range(600.0, 1100.0),
+                sustain: self.range(0.03, 0.1),
+                punch: self.range(0.3, 0.6),
+                decay: self.range(0.1, 0.3),
+                arp_mult: self.range(1.3, 1.6),
+                arp_time: self.range(0.04, 0.08),
+                ..base
+            },
+            SfxKind::Laser => SfxParams {
+                wave: [SfxWave::Square, SfxWave::Saw, SfxWave::Sine]
+                    [(self.next_f32() * 3.0) as usize],
+                base_freq: self.range(500.0, 1500.0),
+                freq_slide: self.range(-10.0, -4.0),
+                sustain: self.range(0.05, 0.15),
+                decay: self.range(0.05, 0.2),
+                duty: self.range(0.2, 0.5),
+                duty_slide: self.range(-1.0, 1.0),
+                ..base
+            },
+            SfxKind::Explosion => SfxParams {
+                wave: SfxWave::Noise,
+                base_freq: self.range(2000.0, 6000.0),
+                freq_slide: self.range(-3.0, -1.0),
+                sustain: self.range(0.1, 0.3),
+                punch: self.range(0.2, 0.6),
+                decay: self.range(0.3, 0.6),
+                vibrato_depth: self.range(0.0, 0.3),
+                vibrato_speed: self.range(5.0, 20.0),
+                ..base
+            },
+            SfxKind::Jump => SfxParams {
+                base_freq: self.range(250.0, 500.0),
+                freq_slide: self.range(2.0, 5.0),
+                sustain: self.range(0.1, 0.2),
+                decay: self.range(0.1, 0.25),
+                duty: self.range(0.3, 0.6),
+                ..base
+            },
+            SfxKind::Hit => SfxParams {
+                wave: if self.next_f32() < 0.5 {
+                    SfxWave::Noise
+                } else {
+                    SfxWave::Square
+                },
+                base_freq: self.range(200.0, 600.0),
+                freq_slide: self.range(-8.0, -4.0),
+                sustain: self.range(0.02, 0.08),
+                decay: self.range(0.1, 0.2),
+                ..base
+            },
+        }
+    }
+
+    /// Generates a new variation of the given kind of effect, ready for
+    /// `AudioEngine::load_sample_from_buffer`.
+    pub fn generate(&mut self, kind: SfxKind) -> Vec<i16> {
+        let params = self.params(kind);
+        Self::render(&params)
+    }
+
+    /// Renders an effect to 44100 Hz 16-bit stereo samples.
+    pub fn render(params: &SfxParams) -> Vec<i16> {
+        let sample_rate = 44100.0;
+        let attack = params.attack.max(0.0);
+        let sustain = params.sustain.max(0.0);
+        let decay = params.decay.max(0.0);
+        let total = ((attack + sustain + decay) * sample_rate) as usize;
+
+        let mut noise = SfxGenerator::new(params.base_freq.to_bits() as u64);
+        let mut noise_value = noise.range(-1.0, 1.0);
+        let mut phase = 0.0f32;
+        let mut out = Vec::with_capacity(total * 2);
+
+        for n in 0..total {
+            let t = n as f32 / sample_rate;
+
+            let envelope = if t < attack {
+                t / attack
+            } else if t < attack + sustain {
+                let p = (t - attack) / sustain.max(f32::EPSILON);
+                1.0 + params.punch * (1.0 - p)
+            } else {
+                1.0 - (t - attack - sustain) / decay.max(f32::EPSILON)
+            };
+
+            let mut freq = params.base_freq * 2f32.powf(params.freq_slide * t);
+            if params.vibrato_depth > 0.0 {
+                freq *= 1.0 + params.vibrato_depth * (2.0 * PI * params.vibrato_speed * t).sin();
+            }
+            if params.arp_mult != 1.0 && t >= params.arp_time {
+                freq *= params.arp_mult;
+            }
+            let freq = freq.clamp(20.0, 20000.0);
+
+            phase += freq / sample_rate;
+            if phase >= 1.0 {
+                phase -= phase.floor();
+                noise_value = noise.range(-1.0, 1.0);
+            }
+
+            let duty = (params.duty + params.duty_slide * t).clamp(0.05, 0.95);
+            let v = match params.wave {
+                SfxWave::Square => {
+                    if phase < duty {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                SfxWave::Saw => 2.0 * phase - 1.0,
+                SfxWave::Sine => (2.0 * PI * phase).sin(),
+                SfxWave::Noise => noise_value,
+            };
+
+            let s = (v * envelope.max(0.0) * params.volume * i16::MAX as f32)
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            out.push(s);
+            out.push(s);
+        }
+
+        out
+    }
+}
+
+// endregion
+
+// region: Config
+
+/// Engine settings that can be loaded from and saved to a config file,
+/// so players can tweak them without recompiling the game.
+///
+/// The file uses a simple INI format:
+///
+/// ```ini
+/// [display]
+/// width = 160
+/// height = 100
+/// font_width = 8
+/// font_height = 8
+///
+/// [audio]
+/// volume = 0.8
+///
+/// [keys]
+/// jump = SPACE
+/// left = A
+/// right = D
+/// ```
+///
+/// Key bindings map action names to key names (see [`key::from_name`]).
+/// Missing settings keep their default values.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let config = EngineConfig::load("settings.ini").unwrap_or_default();
+/// engine.construct_console_from_config(&config)?;
+///
+/// if engine.key_pressed(config.key("jump").unwrap_or(key::SPACE)) {
+///     // ...
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    /// Console width in characters.
+    pub width: i16,
+    /// Console height in characters.
+    pub height: i16,
+    /// Font width in pixels.
+    pub font_width: i16,
+    /// Font height in pixels.
+    pub font_height: i16,
+    /// Font face name. See `construct_console_with_font`.
+    pub font_face: String,
+    /// Master audio volume, from `0.0` (silent) to `1.0` (full volume).
+    pub volume: f32,
+    /// Key bindings as `(action, key)` pairs.
+    pub key_bindings: Vec<(String, usize)>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            width: 160,
+            height: 100,
+            font_width: 8,
+            font_height: 8,
+            font_face: DEFAULT_FONT_FACE.to_string(),
+            volume: 1.0,
+            key_bindings: Vec::new(),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Loads a config from an INI file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, or if a line can't be parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Parses a config from the contents of an INI file. See `load`.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = Self::default();
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_ascii_lowercase();
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `name = value`", n + 1))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            let bad_value = || format!("line {}: invalid value `{}` for `{}`", n + 1, value, name);
+
+            match (section.as_str(), name) {
+                ("display", "width") => config.width = value.parse().map_err(|_| bad_value())?,
+                ("display", "height") => config.height = value.parse().map_err(|_| bad_value())?,
+                ("display", "font_width") => {
+                    config.font_width = value.parse().map_err(|_| bad_value())?
+                }
+                ("display", "font_height") => {
+                    config.font_height = value.parse().map_err(|_| bad_value())?
+                }
+                ("display", "font_face") => config.font_face = value.to_string(),
+                ("audio", "volume") => config.volume = value.parse().map_err(|_| bad_value())?,
+                ("keys", action) => {
+                    let key = key::from_name(value).ok_or_else(bad_value)?;
+                    config.bind(action, key);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Saves the config to an INI file that can be read back with `load`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_ini_string())?;
+        Ok(())
+    }
+
+    fn to_ini_string(&self) -> String {
+        let mut out = String::new();
+        out.push_str("[display]\n");
+        out.push_str(&format!("width = {}\n", self.width));
+        out.push_str(&format!("height = {}\n", self.height));
+        out.push_str(&format!("font_width = {}\n", self.font_width));
+        out.push_str(&format!("font_height = {}\n", self.font_height));
+        out.push_str(&format!("font_face = {}\n", self.font_face));
+        out.push_str("\n[audio]\n");
+        out.push_str(&format!("volume = {}\n", self.volume));
+        out.push_str("\n[keys]\n");
+        for (action, k) in &self.key_bindings {
+            match key::name(*k) {
+                Some(name) => out.push_str(&format!("{} = {}\n", action, name)),
+                None => out.push_str(&format!("{} = 0x{:02X}\n", action, k)),
+            }
+        }
+        out
+    }
+
+    /// Binds `action` to `key`, replacing any previous binding for that action.
+    pub fn bind(&mut self, action: &str, key: usize) {
+        match self.key_bindings.iter_mut().find(|(a, _)| a == action) {
+            Some(binding) => binding.1 = key,
+            None => self.key_bindings.push((action.to_string(), key)),
+        }
+    }
+
+    /// Returns the key bound to `action`, if any.
+    pub fn key(&self, action: &str) -> Option<usize> {
+        self.key_bindings
+            .iter()
+            .find(|(a, _)| a == action)
+            .map(|&(_, k)| k)
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Initializes the console using the display settings of `config`,
+    /// and applies its audio volume.
+    ///
+    /// See `construct_console` for details and errors.
+    pub fn construct_console_from_config(
+        &mut self,
+        config: &EngineConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.construct_console_with_font(
+            &config.font_face,
+            config.width,
+            config.height,
+            config.font_width,
+            config.font_height,
+        )?;
+        self.audio.set_volume(config.volume);
+        Ok(())
+    }
+}
+
+// endregion
+
+// region: Hot Reload
+
+/// Game logic that lives in a dynamic library and can be reloaded by [`HotReloadGame`].
+///
+/// Implement this in a separate crate built with `crate-type = ["cdylib"]`, and export it
+/// with [`hot_reload_entry!`]. Requires the `hot-reload` feature.
+#[cfg(feature = "hot-reload")]
+pub trait HotGame {
+    /// Creates the game. `saved` contains the bytes returned by `save_state`
+    /// of the previous version of the library when it is reloaded, and `None` on the first load.
+    fn create(engine: &mut ConsoleGameEngine<HotReloadGame>, saved: Option<&[u8]>) -> Self
+    where
+        Self: Sized;
+
+    /// Called once per frame, just like `ConsoleGame::update`.
+    fn update(&mut self, engine: &mut ConsoleGameEngine<HotReloadGame>, elapsed_time: f32) -> bool;
+
+    /// Serializes the game state so it survives a reload.
+    ///
+    /// The default implementation saves nothing, so the game restarts from scratch on every reload.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// Exports a [`HotGame`] implementation from a dynamic library so [`HotReloadGame`] can load it.
+///
+/// ```rust,ignore
+/// rusty_console_game_engine::hot_reload_entry!(MyGame);
+/// ```
+#[cfg(feature = "hot-reload")]
+#[macro_export]
+macro_rules! hot_reload_entry {
+    ($game:ty) => {
+        // The `unsafe(...)` form is required by edition 2024 and accepted by older ones.
+        #[unsafe(no_mangle)]
+        pub fn __rcge_hot_create(
+            engine: &mut $crate::ConsoleGameEngine<$crate::HotReloadGame>,
+            saved: Option<&[u8]>,
+        ) -> Box<dyn $crate::HotGame> {
+            Box::new(<$game as $crate::HotGame>::create(engine, saved))
+        }
+    };
+}
+
+#[cfg(feature = "hot-reload")]
+type HotCreateFn = fn(&mut ConsoleGameEngine<HotReloadGame>, Option<&[u8]>) -> Box<dyn HotGame>;
+
+#[cfg(feature = "hot-reload")]
+const HOT_RELOAD_CHECK_INTERVAL: f32 = 0.25;
+
+/// A `ConsoleGame` that runs game logic from a dynamic library and reloads it whenever
+/// the library is rebuilt, keeping the game running.
+///
+/// The game logic implements [`HotGame`] and is exported with [`hot_reload_entry!`].
+/// Before a reload, the old game's `save_state` is called and the bytes are passed
+/// to the new game's `create`, so state can be carried over.
+///
+/// The library is copied to a temporary file before being loaded, so `cargo build`
+/// can overwrite it while the game is running. Both the game and the library must be
+/// built with the same compiler and engine version, so this is meant for development only.
+///
+/// Requires the `hot-reload` feature.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let game = HotReloadGame::new("My Game", "target/debug/my_game_logic.dll");
+/// let mut engine = ConsoleGameEngine::new(game);
+/// engine.construct_console(160, 100, 8, 8)?;
+/// engine.start();
+/// ```
+#[cfg(feature = "hot-reload")]
+pub struct HotReloadGame {
+    name: String,
+    lib_path: PathBuf,
+    library: Option<(HMODULE, PathBuf)>,
+    game: Option<Box<dyn HotGame>>,
+    modified: Option<std::time::SystemTime>,
+    pending: Option<std::time::SystemTime>,
+    check_timer: f32,
+    loads: u32,
+}
+
+#[cfg(feature = "hot-reload")]
+impl HotReloadGame {
+    /// Creates a game that loads its logic from the dynamic library at `lib_path`.
+    pub fn new<P: AsRef<Path>>(app_name: &str, lib_path: P) -> Self {
+        Self {
+            name: app_name.to_string(),
+            lib_path: lib_path.as_ref().to_path_buf(),
+            library: None,
+            game: None,
+            modified: None,
+            pending: None,
+            check_timer: 0.0,
+            loads: 0,
+        }
+    }
+
+    /// Returns how many times the library has been loaded, including the first load.
+    pub fn load_count(&self) -> u32 {
+        self.loads
+    }
+
+    fn modified_time(&self) -> Option<std::time::SystemTime> {
+        std::fs::metadata(&self.lib_path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    fn load_library(
+        &mut self,
+    ) -> Result<(HMODULE, PathBuf, HotCreateFn), Box<dyn std::error::Error>> {
+        let stem = self
+            .lib_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let copy = std::env::temp_dir().join(format!(
+            "{}-hot-{}-{}.dll",
+            stem,
+            std::process::id(),
+            self.loads
+        ));
+        std::fs::copy(&self.lib_path, &copy)?;
+
+        let module = unsafe { LoadLibraryW(&HSTRING::from(copy.as_os_str()))? };
+        let symbol = unsafe { GetProcAddress(module, windows::core::s!("__rcge_hot_create")) };
+
+        let Some(symbol) = symbol else {
+            unsafe {
+                let _ = FreeLibrary(module);
+            }
+            let _ = std::fs::remove_file(&copy);
+            return Err("library does not export a game (missing hot_reload_entry!)".into());
+        };
+
+        let create = unsafe {
+            std::mem::transmute::<unsafe extern "system" fn() -> isize, HotCreateFn>(symbol)
+        };
+        self.loads += 1;
+
+        Ok((module, copy, create))
+    }
+
+    fn unload(&mut self) {
+        self.game = None;
+        if let Some((module, copy)) = self.library.take() {
+            unsafe {
+                let _ = FreeLibrary(module);
+            }
+            let _ = std::fs::remove_file(copy);
+        }
+    }
+
+    fn reload(
+        &mut self,
+        engine: &mut ConsoleGameEngine<Self>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (module, copy, create) = self.load_library()?;
+
+        let saved = self.game.as_ref().map(|g| g.save_state());
+        self.unload();
+
+        self.game = Some(create(engine, saved.as_deref()));
+        self.library = Some((module, copy));
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+impl ConsoleGame for HotReloadGame {
+    fn app_name(&self) -> &str {
+        &self.name
+    }
+
+    fn create(&mut self, engine: &mut ConsoleGameEngine<Self>) -> bool {
+        self.modified = self.modified_time();
+        match self.reload(engine) {
+            Ok(()) => true,
+            Err(e) => {
+                engine.log(
+                    LogLevel::Error,
+                    format!("failed to load game library: {}", e),
+                );
+                false
+            }
+        }
+    }
+
+    fn update(&mut self, engine: &mut ConsoleGameEngine<Self>, elapsed_time: f32) -> bool {
+        self.check_timer += elapsed_time;
+        if self.check_timer >= HOT_RELOAD_CHECK_INTERVAL {
+            self.check_timer = 0.0;
+
+            // wait until the modification time stops changing,
+            // so a library that is still being written isn't loaded
+            let modified = self.modified_time();
+            if modified != self.modified {
+                if modified.is_some() && modified == self.pending {
+                    match self.reload(engine) {
+                        Ok(()) => {
+                            self.modified = modified;
+                            engine.log(LogLevel::Info, "game library reloaded");
+                        }
+                        Err(e) => engine.log(
+                            LogLevel::Error,
+                            format!("failed to reload game library: {}", e),
+                        ),
+                    }
+                    self.pending = None;
+                } else {
+                    self.pending = modified;
+                }
+            }
+        }
+
+        match self.game.as_mut() {
+            Some(game) => game.update(engine, elapsed_time),
+            None => false,
+        }
+    }
+
+    fn destroy(&mut self, _engine: &mut ConsoleGameEngine<Self>) -> bool {
+        self.unload();
+        true
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+impl Drop for HotReloadGame {
+    fn drop(&mut self) {
+        self.unload();
+    }
+}
+
+// endregion
+
+// region: Engine
+
+static RUNNING: AtomicBool = AtomicBool::new(true);
+/// Set when the console is closing and the exit can no longer be cancelled by `destroy`.
+static FORCED_EXIT: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_DONE: Mutex<bool> = Mutex::new(false);
+static SHUTDOWN_CV: Condvar = Condvar::new();
+
+const HEADLESS_FRAME_TIME: f32 = 1.0 / 60.0;
+/// How long the ctrl handler waits for the game to shut down before Windows kills the
+/// process anyway (which happens after about 5 seconds).
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(4500);
+
+unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            RUNNING.store(false, SeqCst);
+            BOOL(1)
+        }
+        CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
+            FORCED_EXIT.store(true, SeqCst);
+            RUNNING.store(false, SeqCst);
+
+            // The process is terminated as soon as this handler returns, so wait for the
+            // game loop to run `destroy` and restore the console first.
+            let done = SHUTDOWN_DONE.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = SHUTDOWN_CV.wait_timeout_while(done, SHUTDOWN_TIMEOUT, |done| !*done);
+            BOOL(1)
+        }
+        _ => BOOL(0),
+    }
+}
+
+fn signal_shutdown_done() {
+    *SHUTDOWN_DONE.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    SHUTDOWN_CV.notify_all();
+}
+
+/// Trait that defines the behavior of a game to be run by the `ConsoleGameEngine`.
+///
+/// To create a game, define a struct containing your game state and implement this trait
+/// for it. The engine will call the provided methods during the game loop.
+pub trait ConsoleGame: Sized {
+    /// Sets the name of the game or application.
+    /// The default name is "Default"
+    ///
+    /// Override this method to set a custom title for your game.
+    fn app_name(&self) -> &str {
+        "Default"
+    }
+
+    /// Called once when the game starts.
+    ///
+    /// Use this method to initialize your game state, load sprites, set variables, etc.
+    ///
+    /// # Parameters
+    /// * `engine` - A mutable reference to the running `ConsoleGameEngine`. You can use
+    ///   this to query the screen, input, or draw anything immediately if needed.
+    ///
+    /// # Returns
+    /// Return `true` to continue running the game, or `false` to immediately exit.
+    fn create(&mut self, engine: &mut ConsoleGameEngine<Self>) -> bool;
+
+    /// Called once per frame to update the game state and render.
+    ///
+    /// This is where the main game logic should live: moving objects, handling input,
+    /// checking collisions, drawing, etc.
+    ///
+    /// # Parameters
+    /// * `engine` - A mutable reference to the `ConsoleGameEngine`. Use it to draw shapes,
+    ///   sprites, and query input.
+    /// * `elapsed_time` - Time (in seconds) since the last frame. Useful for smooth movement
+    ///   and animations.
+    ///
+    /// # Returns
+    /// Return `true` to continue running the game, or `false` to exit.
+    fn update(&mut self, engine: &mut ConsoleGameEngine<Self>, elapsed_time: f32) -> bool;
+
+    /// Called when the console window is resized by the user.
+    ///
+    /// By the time this is called the screen buffer has already been resized
+    /// (and cleared) to the new dimensions, so `screen_width` and `screen_height`
+    /// return the new values. Use it to recompute layouts or reallocate anything
+    /// that depends on the screen size.
+    ///
+    /// # Parameters
+    /// * `engine` - A mutable reference to the `ConsoleGameEngine`.
+    /// * `width` - New width of the console in characters.
+    /// * `height` - New height of the console in characters.
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing.
+    #[allow(unused_variables)]
+    fn on_resize(&mut self, engine: &mut ConsoleGameEngine<Self>, width: i32, height: i32) {}
+
+    /// Called when the game is paused with `ConsoleGameEngine::pause`, or when the console
+    /// loses focus and `set_pause_on_focus_loss` is enabled.
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing.
+    #[allow(unused_variables)]
+    fn on_pause(&mut self, engine: &mut ConsoleGameEngine<Self>) {}
+
+    /// Called when the game is resumed after `on_pause`.
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing.
+    #[allow(unused_variables)]
+    fn on_resume(&mut self, engine: &mut ConsoleGameEngine<Self>) {}
+
+    /// Called when the console window gains or loses focus.
+    ///
+    /// # Parameters
+    /// * `engine` - A mutable reference to the `ConsoleGameEngine`.
+    /// * `focused` - `true` if the console now has focus.
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing.
+    #[allow(unused_variables)]
+    fn on_focus_changed(&mut self, engine: &mut ConsoleGameEngine<Self>, focused: bool) {}
+
+    /// Called for every key going down (including repeats) or up, in order, before `update`.
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing.
+    #[allow(unused_variables)]
+    fn on_key_event(&mut self, engine: &mut ConsoleGameEngine<Self>, event: KeyEvent) {}
+
+    /// Called once when the game exits or the engine is shutting down.
+    ///
+    /// Use this method to clean up resources, save game state, or free memory.
+    ///
+    /// # Parameters
+    /// * `engine` - A mutable reference to the `ConsoleGameEngine`.
+    ///
+    /// # Returns
+    /// Return `true` to allow normal shutdown, or `false` to prevent shutdown (rarely used).
+    ///
+    /// # Default Implementation
+    /// The default implementation does nothing and returns `true`.
+    #[allow(unused_variables)]
+    fn destroy(&mut self, engine: &mut ConsoleGameEngine<Self>) -> bool {
+        true
+    }
+}
+
+/// Which modifier keys are held, as returned by `ConsoleGameEngine::modifiers`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+/// A single input event, as returned by `ConsoleGameEngine::events`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    /// A key went down, or repeated while held. `ch` is the typed character, if any.
+    KeyDown { key: usize, ch: Option<char> },
+    /// A key was released.
+    KeyUp { key: usize },
+    /// The mouse moved to a new cell.
+    MouseMove { x: i32, y: i32 },
+    /// A mouse button was pressed.
+    MouseDown { button: usize },
+    /// A mouse button was released.
+    MouseUp { button: usize },
+    /// The mouse wheel was turned. Positive is away from the user, one notch is 120.
+    MouseWheel { delta: i32 },
+    /// The console gained (`true`) or lost (`false`) focus.
+    Focus(bool),
+    /// The screen buffer was resized to the given size in characters.
+    Resize { width: i32, height: i32 },
+}
+
+/// A key going down or up, as passed to `ConsoleGame::on_key_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Virtual key code, comparable with the constants in [`key`].
+    pub key: usize,
+    /// The character typed by the key, if it typed one.
+    pub ch: Option<char>,
+    /// `true` when the key went down or repeated, `false` when it was released.
+    pub down: bool,
+    /// The modifier keys held when the event happened.
+    pub modifiers: Modifiers,
+}
+
+/// Game hooks waiting to be called at the start of the next frame.
+#[derive(Clone, Copy)]
+enum PendingHook {
+    Pause,
+    Resume,
+    Focus(bool),
+    Key(KeyEvent),
+}
+
+/// The console font face used unless another one is chosen.
+pub const DEFAULT_FONT_FACE: &str = "Consolas";
+
+/// The legacy raster (bitmap) console font.
+pub const RASTER_FONT_FACE: &str = "Terminal";
+
+/// The `(width, height)` sizes in pixels the raster font comes in.
+pub const RASTER_FONT_SIZES: [(i16, i16); 10] = [
+    (4, 6),
+    (6, 8),
+    (8, 8),
+    (16, 8),
+    (5, 12),
+    (7, 12),
+    (8, 12),
+    (16, 12),
+    (12, 16),
+    (10, 18),
+];
+
+/// Checks that the console can use the font face `face` at `fontw` x `fonth` pixels.
+fn check_font(face: &str, fontw: i16, fonth: i16) -> Result<(), String> {
+    if face.is_empty() || face.encode_utf16().count() > 31 {
+        return Err(format!("Invalid font face `{}`", face));
+    }
+    if face.eq_ignore_ascii_case(RASTER_FONT_FACE) && !RASTER_FONT_SIZES.contains(&(fontw, fonth)) {
+        return Err(format!(
+            "The raster font doesn't come in {}x{}, see RASTER_FONT_SIZES",
+            fontw, fonth
+        ));
+    }
+    Ok(())
+}
+
+/// The main engine that runs a game implementing `ConsoleGame`.
+///
+/// Handles console creation, input, rendering, and the main game loop.
+#[derive(Clone)]
+pub struct ConsoleGameEngine<G: ConsoleGame> {
+    app_name: String,
+
+    output_handle: HANDLE,
+    input_handle: HANDLE,
+
+    original_state: Option<ConsoleState>,
+    /// Whether this engine installed the console-control handler, which waits for it to
+    /// finish shutting down.
+    ctrl_handler: bool,
+    headless: bool,
+    game_created: bool,
+    game_finished: bool,
+
+    key_new_state: [u16; 256],
+    key_old_state: [u16; 256],
+    key_pressed: [bool; 256],
+    key_released: [bool; 256],
+    key_held: [bool; 256],
+
+    mouse_new_state: [bool; 5],
+    mouse_old_state: [bool; 5],
+    mouse_pressed: [bool; 5],
+    mouse_released: [bool; 5],
+    mouse_held: [bool; 5],
+
+    mouse_x: i32,
+    mouse_y: i32,
+
+    console_in_focus: bool,
+    typed_keys: Vec<(usize, Option<char>)>,
+    event_queue: bool,
+    events: Vec<InputEvent>,
+    text_prompt: Option<TextPrompt>,
+
+    pending_resize: Option<(i16, i16)>,
+    pending_hooks: Vec<PendingHook>,
+    paused: bool,
+    pause_on_focus_loss: bool,
+    /// The console window's thread, while the cursor is hidden by attaching to its input.
+    cursor_thread: Option<u32>,
+    cursor_visible: bool,
+    mouse_captured: bool,
+    capture_paused: bool,
+    mouse_delta: (i32, i32),
+    mouse_gestures: MouseGestures,
+    windowed: Option<WindowedState>,
+
+    rect: SMALL_RECT,
+
+    screen_width: i16,
+    screen_height: i16,
+
+    font_width: i16,
+    font_height: i16,
+    font_face: String,
+
+    window_buffer: Vec<CHAR_INFO>,
+    depth_buffer: Vec<f32>,
+
+    glyph_fallback: GlyphFallback,
+    glyph_support: Vec<u8>,
+
+    palette: Option<Palette>,
+    color_remap: Option<fn(u16) -> u16>,
+    remapped_buffer: Vec<CHAR_INFO>,
+
+    input_recording: Option<InputRecording>,
+    input_playback: Option<InputPlayback>,
+
+    debug_overlay: DebugOverlay,
+    draw_calls: u32,
+
+    transition: Option<ActiveTransition>,
+    transition_midpoint: bool,
+    atmosphere: Option<Atmosphere>,
+    post_fx: PostFx,
+    presenter: Option<Presenter>,
+    screens: Vec<Screen>,
+    active_screen: usize,
+    title: TitleState,
+    render_queue: RenderQueueState,
+    frame_timing: FrameTiming,
+    announcer: Announcer,
+    render_threads: usize,
+
+    log: Log,
+
+    #[cfg(feature = "scripting")]
+    scripts: Option<std::rc::Rc<std::cell::RefCell<ScriptHost>>>,
+
+    pub audio: AudioEngine,
+
+    game: Option<G>,
+}
+
+// region: Core
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Creates a new `ConsoleGameEngine` with the given game.
+    ///
+    /// # Parameters
+    /// * `game` - The user-defined struct implementing `ConsoleGame`.
+    pub fn new(game: G) -> Self {
+        let app_name = game.app_name().to_string();
+        let mouse_x = 0;
+        let mouse_y = 0;
+        // The console handles are fetched by `construct_console`, so headless engines never
+        // touch the console.
+        let output_handle = INVALID_HANDLE_VALUE;
+        let input_handle = INVALID_HANDLE_VALUE;
+        let rect = SMALL_RECT::default();
+        let window_buffer = Vec::new();
+
+        Self {
+            app_name,
+            output_handle,
+            input_handle,
+            original_state: None,
+            ctrl_handler: false,
+            headless: false,
+            game_created: false,
+            game_finished: false,
+            key_new_state: [0; 256],
+            key_old_state: [0; 256],
+            key_pressed: [false; 256],
+            key_released: [false; 256],
+            key_held: [false; 256],
+            mouse_new_state: [false; 5],
+            mouse_old_state: [false; 5],
+            mouse_pressed: [false; 5],
+            mouse_released: [false; 5],
+            mouse_held: [false; 5],
+            mouse_x,
+            mouse_y,
+            console_in_focus: true,
+            typed_keys: Vec::new(),
+            event_queue: false,
+            events: Vec::new(),
+            text_prompt: None,
+            pending_resize: None,
+            pending_hooks: Vec::new(),
+            paused: false,
+            pause_on_focus_loss: false,
+            cursor_thread: None,
+            cursor_visible: true,
+            mouse_captured: false,
+            capture_paused: false,
+            mouse_delta: (0, 0),
+            mouse_gestures: MouseGestures::default(),
+            windowed: None,
+            rect,
+            screen_width: 80,
+            screen_height: 80,
+            font_width: 8,
+            font_height: 8,
+            font_face: DEFAULT_FONT_FACE.to_string(),
+            window_buffer,
+            depth_buffer: Vec::new(),
+            glyph_fallback: GlyphFallback::Off,
+            glyph_support: Vec::new(),
+            palette: None,
+            color_remap: None,
+            remapped_buffer: Vec::new(),
+            input_recording: None,
+            input_playback: None,
+            debug_overlay: DebugOverlay::default(),
+            draw_calls: 0,
+            transition: None,
+            transition_midpoint: false,
+            atmosphere: None,
+            post_fx: PostFx::default(),
+            presenter: None,
+            screens: Vec::new(),
+            active_screen: 0,
+            title: TitleState::default(),
+            render_queue: RenderQueueState::default(),
+            frame_timing: FrameTiming::default(),
+            announcer: Announcer::default(),
+            render_threads: 1,
+            log: Log::new(),
+            #[cfg(feature = "scripting")]
+            scripts: None,
+            audio: AudioEngine::new(),
+            game: Some(game),
+        }
+    }
+
+    /// Returns the width of the console in characters.
+    pub fn screen_width(&self) -> i32 {
+        self.screen_width as i32
+    }
+
+    /// Returns the height of the console in characters.
+    pub fn screen_height(&self) -> i32 {
+        self.screen_height as i32
+    }
+
+    /// Returns `true` if the specified key was pressed this frame.
+    ///
+    /// Normally used in conjection with key constants such as
+    /// `K_W`, `K_0`, `K_UP`, etc.
+    pub fn key_pressed(&self, key: usize) -> bool {
+        self.key_pressed[key]
+    }
+
+    /// Returns `true` if the specified key was released this frame.
+    ///
+    /// Normally used in conjection with key constants such as
+    /// `K_W`, `K_0`, `K_UP`, etc.
+    pub fn key_released(&self, key: usize) -> bool {
+        self.key_released[key]
+    }
+
+    /// Returns `true` if the specified key is currently held down.
+    ///
+    /// Normally used in conjection with key constants such as
+    /// `K_W`, `K_0`, `K_UP`, etc.
+    pub fn key_held(&self, key: usize) -> bool {
+        self.key_held[key]
+    }
+
+    /// Returns which of Ctrl, Alt and Shift are held this frame.
+    ///
+    /// Either the left or the right key counts.
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            ctrl: self.key_held[key::CONTROL],
+            alt: self.key_held[key::ALT],
+            shift: self.key_held[key::SHIFT],
+        }
+    }
+
+    /// Returns `true` on the frame a key combination such as `&[key::CONTROL, key::S]`
+    /// is completed.
+    ///
+    /// All keys have to be held and at least one of them pressed this frame, so holding
+    /// the combination only fires once. Modifiers that aren't part of the chord must be
+    /// up, so `Ctrl+S` doesn't also fire for `Ctrl+Shift+S`.
+    pub fn key_chord(&self, keys: &[usize]) -> bool {
+        if keys.is_empty() || !keys.iter().all(|&k| self.key_held[k]) {
+            return false;
+        }
+        if !keys.iter().any(|&k| self.key_pressed[k]) {
+            return false;
+        }
+        [key::CONTROL, key::ALT, key::SHIFT]
+            .iter()
+            .all(|m| keys.contains(m) || !self.key_held[*m])
+    }
+
+    /// Returns `true` if the specified mouse button was pressed this frame.
+    ///
+    /// Normally used in conjection with mouse button constants
+    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
+    pub fn mouse_pressed(&self, button: usize) -> bool {
+        self.mouse_pressed[button]
+    }
+
+    /// Returns `true` if the specified mouse button was released this frame.
+    ///
+    /// Normally used in conjection with mouse button constants
+    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
+    pub fn mouse_released(&self, button: usize) -> bool {
+        self.mouse_released[button]
+    }
+
+    /// Returns `true` if the specified mouse button is currently held down.
+    ///
+    /// Normally used in conjection with mouse button constants
+    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
+    pub fn mouse_held(&self, button: usize) -> bool {
+        self.mouse_held[button]
+    }
+
+    /// Returns the current X position of the mouse in console coordinates.
+    pub fn mouse_x(&self) -> i32 {
+        self.mouse_x
+    }
+
+    /// Returns the current Y position of the mouse in console coordinates.
+    pub fn mouse_y(&self) -> i32 {
+        self.mouse_y
+    }
+
+    /// Returns the current (X, Y) position of the mouse.
+    pub fn mouse_pos(&self) -> (i32, i32) {
+        (self.mouse_x, self.mouse_y)
+    }
+
+    /// Returns `true` if the console currently has focus.
+    pub fn console_focused(&self) -> bool {
+        self.console_in_focus
+    }
+
+    /// Returns the printable characters typed since the last frame, including key repeats
+    /// and the effect of Shift and the keyboard layout.
+    pub fn typed_text(&self) -> String {
+        self.typed_keys.iter().filter_map(|&(_, ch)| ch).collect()
+    }
+
+    /// Turns recording of `events` on or off. Off by default.
+    pub fn set_event_queue(&mut self, enabled: bool) {
+        self.event_queue = enabled;
+        self.events.clear();
+    }
+
+    /// Returns every input event received since the last frame, in the order it happened.
+    ///
+    /// Unlike `key_pressed` and friends, which sample the state once per frame, this shows
+    /// everything in between, e.g. two clicks within one frame. Empty unless enabled with
+    /// `set_event_queue`.
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    fn push_event(&mut self, event: InputEvent) {
+        if self.event_queue {
+            self.events.push(event);
+        }
+    }
+
+    /// Initializes the console with the given dimensions and font size.
+    ///
+    /// This function sets up the console window, screen buffer, font, and other
+    /// properties. It now returns a `Result` to indicate success or failure.
+    /// The font face is `DEFAULT_FONT_FACE` unless another one was chosen with
+    /// `construct_console_with_font`.
+    ///
+    /// # Parameters
+    /// - `width` - Console width in characters.
+    /// - `height` - Console height in characters.
+    /// - `fontw` - Font width in pixels.
+    /// - `fonth` - Font height in pixels.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The console handle is invalid.
+    /// - The requested console size exceeds the maximum allowed for the current display/font.
+    /// - Any Windows API call fails (setting buffer size, window info, font, etc.)
+    pub fn construct_console(
+        &mut self,
+        width: i16,
+        height: i16,
+        fontw: i16,
+        fonth: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.original_state.is_none() {
+            unsafe {
+                self.output_handle = GetStdHandle(STD_OUTPUT_HANDLE)?;
+                self.input_handle = GetStdHandle(STD_INPUT_HANDLE)?;
+            }
+        }
+        if self.output_handle == INVALID_HANDLE_VALUE {
+            return Err("Bad Handle".into());
+        }
+
+        if self.original_state.is_none() {
+            let state = ConsoleState::save(self.output_handle, self.input_handle);
+            install_crash_guard(self.output_handle, self.input_handle, state.clone());
+            self.original_state = Some(state);
+        }
+
+        self.setup_window(width, height, fontw, fonth)?;
+
+        self.set_ctrl_handler(Some(console_handler), true)?;
+        self.ctrl_handler = true;
+
+        self.set_console_mode()?;
+
+        self.set_console_cursor_info()?;
+
+        Ok(())
+    }
+
+    /// Initializes the console like `construct_console`, using the font face `face`.
+    ///
+    /// Any font installed for console use works, e.g. `"Lucida Console"`. `RASTER_FONT_FACE`
+    /// selects the legacy bitmap font for a chunky, square-pixel look; it only comes in the
+    /// sizes listed in `RASTER_FONT_SIZES`, and legacy consoles draw it using the OEM code
+    /// page, so characters outside it may show up wrong.
+    ///
+    /// # Errors
+    /// Returns an error if `face` is longer than 31 characters, if the raster font is asked
+    /// for in a size it doesn't come in, or for any of the reasons `construct_console` can fail.
+    pub fn construct_console_with_font(
+        &mut self,
+        face: &str,
+        width: i16,
+        height: i16,
+        fontw: i16,
+        fonth: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        check_font(face, fontw, fonth)?;
+        self.font_face = face.to_string();
+        self.construct_console(width, height, fontw, fonth)
+    }
+
+    /// Initializes the engine without a console.
+    ///
+    /// No console APIs are called: drawing goes into the in-memory screen buffer,
+    /// which can be inspected with `get_cell`, and frames are stepped manually
+    /// with `run_frames`. Useful for unit tests and CI, where there is no console.
+    ///
+    /// `audio` is replaced by a silent engine that mixes sounds without opening an audio
+    /// device, so samples loaded before this call have to be loaded again.
+    ///
+    /// # Parameters
+    /// - `width` - Screen buffer width in characters.
+    /// - `height` - Screen buffer height in characters.
+    pub fn construct_headless(&mut self, width: i16, height: i16) {
+        self.headless = true;
+        self.audio = AudioEngine::with_backend(AudioBackend::Silent);
+
+        self.screen_width = width;
+        self.screen_height = height;
+
+        self.rect = SMALL_RECT {
+            Left: 0,
+            Top: 0,
+            Right: self.screen_width - 1,
+            Bottom: self.screen_height - 1,
+        };
+
+        self.window_buffer = vec![
+            CHAR_INFO::default();
+            (self.screen_width as i32 * self.screen_height as i32) as usize
+        ];
+    }
+
+    /// Returns `true` if the engine was set up with `construct_headless`.
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    /// Runs `n` frames of the game without presenting them.
+    ///
+    /// `create()` is called before the first frame if it hasn't been yet.
+    /// Every frame is given a fixed elapsed time of 1/60th of a second, unless
+    /// a recording is being replayed with `replay_inputs`. If the game exits,
+    /// either by returning `false` from `update` or through `request_exit`,
+    /// `destroy()` is called, and unless it cancels the exit, no further frames
+    /// are run, now or in later calls.
+    ///
+    /// # Returns
+    /// `true` if the game is still running after the last frame.
+    pub fn run_frames(&mut self, n: usize) -> bool {
+        if self.game_finished {
+            return false;
+        }
+        let Some(mut game) = self.game.take() else {
+            return false;
+        };
+
+        if !self.game_created {
+            self.game_created = true;
+            if !game.create(self) {
+                RUNNING.store(false, SeqCst);
+            }
+        }
+
+        for _ in 0..n {
+            if !RUNNING.load(SeqCst) {
+                break;
+            }
+
+            let elapsed_time = self.begin_frame(HEADLESS_FRAME_TIME);
+            let elapsed_time = self.run_hooks(&mut game, elapsed_time);
+            if !game.update(self, elapsed_time) {
+                RUNNING.store(false, SeqCst);
+            }
+            self.end_frame(elapsed_time);
+        }
+
+        if !RUNNING.load(SeqCst) {
+            self.game_finished = game.destroy(self) || FORCED_EXIT.load(SeqCst);
+            // Either the exit was cancelled, or this game is over and the flag, which every
+            // engine shares, has to be ready for the next one; tests often run several.
+            RUNNING.store(true, SeqCst);
+        }
+
+        self.game = Some(game);
+
+        !self.game_finished
+    }
+
+    /// Pauses the game. `ConsoleGame::on_pause` is called at the start of the next frame.
+    ///
+    /// `update` keeps being called while paused, so the game can draw a pause screen and
+    /// call `resume`, but with an `elapsed_time` of 0 so timers and physics stand still.
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.paused = true;
+            self.pending_hooks.push(PendingHook::Pause);
+        }
+    }
+
+    /// Resumes the game after `pause`. `ConsoleGame::on_resume` is called at the start of
+    /// the next frame.
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.pending_hooks.push(PendingHook::Resume);
+        }
+    }
+
+    /// Returns `true` while the game is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses the game automatically while the console is out of focus. Off by default.
+    pub fn set_pause_on_focus_loss(&mut self, enabled: bool) {
+        self.pause_on_focus_loss = enabled;
+    }
+
+    /// Calls the game hooks queued up since the last frame, and returns the elapsed time
+    /// `update` should see.
+    fn run_hooks(&mut self, game: &mut G, elapsed_time: f32) -> f32 {
+        if let Some((width, height)) = self.pending_resize.take() {
+            self.resize_buffer(width, height);
+            game.on_resize(self, width as i32, height as i32);
+        }
+
+        // Hooks can queue more hooks (e.g. pausing from `on_focus_changed`), so drain by index.
+        let mut i = 0;
+        while i < self.pending_hooks.len() {
+            match self.pending_hooks[i] {
+                PendingHook::Pause => game.on_pause(self),
+                PendingHook::Resume => game.on_resume(self),
+                PendingHook::Focus(focused) => {
+                    game.on_focus_changed(self, focused);
+                    if self.pause_on_focus_loss {
+                        if focused {
+                            self.resume();
+                        } else {
+                            self.pause();
+                        }
+                    }
+                }
+                PendingHook::Key(event) => game.on_key_event(self, event),
+            }
+            i += 1;
+        }
+        self.pending_hooks.clear();
+
+        if self.paused {
+            0.0
+        } else {
+            elapsed_time
+        }
+    }
+
+    /// Asks the game loop to stop after the current frame, as if `update` had returned `false`.
+    ///
+    /// `destroy` is still called and can cancel the exit by returning `false`.
+    pub fn request_exit(&self) {
+        RUNNING.store(false, SeqCst);
+    }
+
+    /// Returns `true` if the game loop is going to stop after the current frame, because of
+    /// `request_exit`, Ctrl+C, Ctrl+Break, or the console being closed.
+    ///
+    /// Games can check this in `update` to save their progress early.
+    pub fn exit_requested(&self) -> bool {
+        !RUNNING.load(SeqCst)
+    }
+
+    /// Returns the glyph and color at `(x, y)` in the screen buffer,
+    /// or `None` if the position is off-screen.
+    pub fn get_cell(&self, x: i32, y: i32) -> Option<(u16, u16)> {
+        if x >= 0 && x < self.screen_width as i32 && y >= 0 && y < self.screen_height as i32 {
+            let cell = self.window_buffer[(y * self.screen_width as i32 + x) as usize];
+            Some((unsafe { cell.Char.UnicodeChar }, cell.Attributes))
+        } else {
+            None
+        }
+    }
+
+    fn update_keys(&mut self) {
+        for i in 0..256 {
+            self.key_pressed[i] = false;
+            self.key_released[i] = false;
+
+            self.key_new_state[i] = unsafe { GetAsyncKeyState(i as i32) as u16 };
+
+            if self.key_new_state[i] != self.key_old_state[i] {
+                if (self.key_new_state[i] & 0x8000) != 0 {
+                    self.key_pressed[i] = !self.key_held[i];
+                    self.key_held[i] = true;
+                } else {
+                    self.key_released[i] = true;
+                    self.key_held[i] = false;
+                }
+            }
+
+            self.key_old_state[i] = self.key_new_state[i];
+        }
+    }
+
+    fn update_mouse(&mut self) {
+        self.typed_keys.clear();
+        self.events.clear();
+
+        let mut events: u32 = 0;
+        self.get_number_of_console_input_events(&mut events);
+
+        let count = events.min(32);
+        let mut in_buf = [INPUT_RECORD::default(); 32];
+        let mut read = 0;
+        if count > 0 {
+            self.read_console_input_w(count as usize, &mut in_buf, &mut read);
+        }
+
+        for record in &in_buf[..read as usize] {
+            match record.EventType as u32 {
+                FOCUS_EVENT => {
+                    let focused = unsafe { record.Event.FocusEvent.bSetFocus.as_bool() };
+                    if focused != self.console_in_focus {
+                        self.pending_hooks.push(PendingHook::Focus(focused));
+                    }
+                    self.console_in_focus = focused;
+                    self.push_event(InputEvent::Focus(focused));
+                }
+                WINDOW_BUFFER_SIZE_EVENT => {
+                    let size = unsafe { record.Event.WindowBufferSizeEvent.dwSize };
+                    if size.X != self.screen_width || size.Y != self.screen_height {
+                        self.pending_resize = Some((size.X, size.Y));
+                    }
+                    self.push_event(InputEvent::Resize {
+                        width: size.X as i32,
+                        height: size.Y as i32,
+                    });
+                }
+                KEY_EVENT => {
+                    let ke = unsafe { record.Event.KeyEvent };
+                    let key = ke.wVirtualKeyCode as usize;
+                    let ch = char::from_u32(unsafe { ke.uChar.UnicodeChar } as u32)
+                        .filter(|c| !c.is_control());
+                    let state = ke.dwControlKeyState;
+                    let mut event = KeyEvent {
+                        key,
+                        ch: None,
+                        down: ke.bKeyDown.as_bool(),
+                        modifiers: Modifiers {
+                            ctrl: state & (LEFT_CTRL_PRESSED | RIGHT_CTRL_PRESSED) != 0,
+                            alt: state & (LEFT_ALT_PRESSED | RIGHT_ALT_PRESSED) != 0,
+                            shift: state & SHIFT_PRESSED != 0,
+                        },
+                    };
+                    if event.down {
+                        event.ch = ch;
+                        for _ in 0..ke.wRepeatCount.max(1) {
+                            self.typed_keys.push((key, ch));
+                            self.pending_hooks.push(PendingHook::Key(event));
+                            self.push_event(InputEvent::KeyDown { key, ch });
+                        }
+                    } else {
+                        self.pending_hooks.push(PendingHook::Key(event));
+                        self.push_event(InputEvent::KeyUp { key });
+                    }
+                }
+                MOUSE_EVENT => {
+                    let me = unsafe { record.Event.MouseEvent };
+                    match me.dwEventFlags {
+                        0 | DOUBLE_CLICK => {
+                            for m in 0..5 {
+                                let down = (me.dwButtonState & (1 << m)) != 0;
+                                if down != self.mouse_new_state[m] {
+                                    self.push_event(if down {
+                                        InputEvent::MouseDown { button: m }
+                                    } else {
+                                        InputEvent::MouseUp { button: m }
+                                    });
+                                }
+                                self.mouse_new_state[m] = down;
+                            }
+                        }
+                        MOUSE_MOVED => {
+                            self.mouse_x = me.dwMousePosition.X as i32;
+                            self.mouse_y = me.dwMousePosition.Y as i32;
+                            self.push_event(InputEvent::MouseMove {
+                                x: self.mouse_x,
+                                y: self.mouse_y,
+                            });
+                        }
+                        MOUSE_WHEELED => {
+                            let delta = (me.dwButtonState >> 16) as i16 as i32;
+                            self.push_event(InputEvent::MouseWheel { delta });
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for m in 0..5 {
+            self.mouse_pressed[m] = false;
+            self.mouse_released[m] = false;
+
+            if self.mouse_new_state[m] != self.mouse_old_state[m] {
+                if self.mouse_new_state[m] {
+                    self.mouse_pressed[m] = true;
+                    self.mouse_held[m] = true;
+                } else {
+                    self.mouse_released[m] = true;
+                    self.mouse_held[m] = false;
+                }
+            }
+
+            self.mouse_old_state[m] = self.mouse_new_state[m];
+        }
+    }
+
+    fn setup_window(
+        &mut self,
+        width: i16,
+        height: i16,
+        fontw: i16,
+        fonth: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        check_font(&self.font_face, fontw, fonth)?;
+
+        self.screen_width = width;
+        self.screen_height = height;
+
+        self.rect = SMALL_RECT {
+            Left: 0,
+            Top: 0,
+            Right: 1,
+            Bottom: 1,
+        };
+
+        self.set_console_window_info(self.output_handle, true, &self.rect)?;
+
+        let coord = COORD {
+            X: self.screen_width,
+            Y: self.screen_height,
+        };
+
+        self.set_console_screen_buffer_size(self.output_handle, coord)?;
+
+        self.set_console_active_screen_buffer(self.output_handle)?;
+
+        let mut font_cfi = CONSOLE_FONT_INFOEX {
+            cbSize: size_of::<CONSOLE_FONT_INFOEX>().try_into().unwrap(),
+            nFont: 0,
+            dwFontSize: COORD { X: fontw, Y: fonth },
+            FontFamily: FF_DONTCARE.0 as u32,
+            FontWeight: FW_NORMAL.0,
+            ..Default::default()
+        };
+
+        let face = self.font_face.clone();
+        self.set_face_name(&mut font_cfi.FaceName, &face);
+
+        self.set_current_console_font_ex(self.output_handle, false, &font_cfi)?;
+        self.clear_glyph_support();
+
+        let max_size = unsafe { GetLargestConsoleWindowSize(self.output_handle) };
+
+        if width > max_size.X || height > max_size.Y {
+            return Err(format!(
+                "Requested console size {}x{} exceeds maximum {}x{} for this display/font.",
+                width, height, max_size.X, max_size.Y
+            )
+            .into());
+        }
+
+        let mut screen_buffer_csbi = CONSOLE_SCREEN_BUFFER_INFO::default();
+        self.get_console_screen_buffer_info(self.output_handle, &mut screen_buffer_csbi)?;
+
+        self.validate_window_size(&screen_buffer_csbi)?;
+
+        self.rect = SMALL_RECT {
+            Left: 0,
+            Top: 0,
+            Right: self.screen_width - 1,
+            Bottom: self.screen_height - 1,
+        };
+
+        self.set_console_window_info(self.output_handle, true, &self.rect)?;
+
+        self.window_buffer = vec![
+            CHAR_INFO::default();
+            (self.screen_width as i32 * self.screen_height as i32) as usize
+        ];
+
+        self.font_width = fontw;
+        self.font_height = fonth;
+
+        Ok(())
+    }
+
+    /// Changes the console font size while the game is running.
+    ///
+    /// The screen keeps its width and height in characters, so the window
+    /// grows or shrinks with the font. Can be called from `update`.
+    ///
+    /// # Errors
+    /// Returns an error if the console can't fit the current screen size with the new font.
+    /// In that case the previous font size is restored.
+    pub fn set_font_size(
+        &mut self,
+        fontw: i16,
+        fonth: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.reconfigure(self.screen_width, self.screen_height, fontw, fonth)
+    }
+
+    /// Changes the screen size (in characters) while the game is running.
+    ///
+    /// The screen buffer is reallocated and cleared, so everything has to be redrawn
+    /// afterwards. Can be called from `update`, e.g. to switch between a menu and a
+    /// gameplay resolution.
+    ///
+    /// # Errors
+    /// Returns an error if the requested size doesn't fit the display with the current font.
+    /// In that case the previous screen size is restored.
+    pub fn resize(&mut self, width: i16, height: i16) -> Result<(), Box<dyn std::error::Error>> {
+        self.reconfigure(width, height, self.font_width, self.font_height)
+    }
+
+    /// Changes the console font face while the game is running, keeping the font size.
+    ///
+    /// See `construct_console_with_font` for which faces can be used.
+    ///
+    /// # Errors
+    /// Returns an error if the face can't be used at the current font size, or if the
+    /// console can't fit the current screen size with it. In that case the previous font
+    /// face is restored.
+    pub fn set_font_face(&mut self, face: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_font(face, self.font_width, self.font_height)?;
+        let old_face = std::mem::replace(&mut self.font_face, face.to_string());
+        let result = self.set_font_size(self.font_width, self.font_height);
+        if result.is_err() {
+            self.font_face = old_face;
+            let _ = self.set_font_size(self.font_width, self.font_height);
+        }
+        result
+    }
+
+    /// Returns the name of the console font face.
+    pub fn font_face(&self) -> &str {
+        &self.font_face
+    }
+
+    /// Returns the width of the console font in pixels.
+    pub fn font_width(&self) -> i32 {
+        self.font_width as i32
+    }
+
+    /// Returns the height of the console font in pixels.
+    pub fn font_height(&self) -> i32 {
+        self.font_height as i32
+    }
+
+    fn reconfigure(
+        &mut self,
+        width: i16,
+        height: i16,
+        fontw: i16,
+        fonth: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if width <= 0 || height <= 0 {
+            return Err(format!("Invalid screen size {}x{}", width, height).into());
+        }
+        if let Some(presenter) = &mut self.presenter {
+            presenter.sync();
+        }
+
+        if self.headless {
+            self.font_width = fontw;
+            self.font_height = fonth;
+            self.resize_buffer(width, height);
+            return Ok(());
+        }
+
+        let (old_width, old_height) = (self.screen_width, self.screen_height);
+        let (old_fontw, old_fonth) = (self.font_width, self.font_height);
+
+        if let Err(e) = self.setup_window(width, height, fontw, fonth) {
+            let _ = self.setup_window(old_width, old_height, old_fontw, old_fonth);
+            return Err(e);
+        }
+
+        self.set_console_cursor_info()?;
+
+        Ok(())
+    }
+
+    fn resize_buffer(&mut self, width: i16, height: i16) {
+        self.screen_width = width.max(1);
+        self.screen_height = height.max(1);
+
+        self.rect = SMALL_RECT {
+            Left: 0,
+            Top: 0,
+            Right: self.screen_width - 1,
+            Bottom: self.screen_height - 1,
+        };
+
+        self.window_buffer = vec![
+            CHAR_INFO::default();
+            (self.screen_width as i32 * self.screen_height as i32) as usize
+        ];
+
+        if !self.headless {
+            let _ = self.set_console_cursor_info();
+        }
+    }
+
+    fn begin_frame(&mut self, elapsed_time: f32) -> f32 {
+        if !self.headless {
+            self.update_keys();
+            self.update_mouse();
+            self.update_mouse_capture();
+        }
+
+        self.announcer.recent.clear();
+
+        let elapsed_time = match self.step_input_playback() {
+            Some(elapsed_time) => elapsed_time,
+            None => self.smooth_frame_time(elapsed_time),
+        };
+        self.step_input_recording(elapsed_time);
+        self.step_mouse_gestures(elapsed_time);
+
+        if self.key_pressed(key::F3) {
+            self.debug_overlay.enabled = !self.debug_overlay.enabled;
+        }
+        if let Some(k) = self.log.console_key {
+            if self.key_pressed(k) {
+                self.log.console_visible = !self.log.console_visible;
+            }
+        }
+        self.draw_calls = 0;
+        self.step_transition(elapsed_time);
+        self.step_atmosphere(elapsed_time);
+
+        elapsed_time
+    }
+
+    fn end_frame(&mut self, elapsed_time: f32) {
+        #[cfg(feature = "scripting")]
+        self.run_script_hooks(elapsed_time);
+
+        self.drain_render_queue();
+        self.draw_atmosphere();
+        self.draw_transition();
+        self.apply_post_fx();
+        self.record_frame_time(elapsed_time);
+        if self.debug_overlay.enabled {
+            self.draw_debug_overlay();
+        }
+        if self.log.console_visible {
+            self.draw_log_console();
+        }
+    }
+
+    /// Starts the game loop and runs the game until it exits.
+    ///
+    /// Calls `create()`, `update()`, and `destroy()` on the user's game struct.
+    pub fn start(mut self) {
+        let mut game = self.game.take().unwrap();
+        *SHUTDOWN_DONE.lock().unwrap_or_else(|e| e.into_inner()) = false;
+
+        if !self.game_created {
+            self.game_created = true;
+            if !game.create(&mut self) {
+                RUNNING.store(false, SeqCst);
+            }
+        }
+
+        let mut tp_1 = Instant::now();
+
+        while RUNNING.load(SeqCst) {
+            while RUNNING.load(SeqCst) {
+                let tp_2 = Instant::now();
+                let elapsed = tp_2.duration_since(tp_1);
+                tp_1 = tp_2;
+
+                let elapsed_time = self.begin_frame(elapsed.as_secs_f32());
+
+                let fps = if elapsed_time > 0.0 {
+                    1.0 / elapsed_time
+                } else {
+                    0.0
+                };
+
+                let elapsed_time = self.run_hooks(&mut game, elapsed_time);
+
+                if !game.update(&mut self, elapsed_time) {
+                    RUNNING.store(false, SeqCst);
+                }
+
+                self.end_frame(elapsed_time);
+
+                if self.headless {
+                    continue;
+                }
+
+                self.update_title(elapsed_time, fps);
+                self.present();
+            }
+
+            if !game.destroy(&mut self) && !FORCED_EXIT.load(SeqCst) {
+                RUNNING.store(true, SeqCst);
+            }
+        }
+    }
+}
+
+impl<G: ConsoleGame> Drop for ConsoleGameEngine<G> {
+    fn drop(&mut self) {
+        self.presenter = None;
+        if std::thread::panicking() && self.original_state.is_some() {
+            // The panic hook has already restored the console, and doing it again would
+            // clear the panic message.
+            if self.ctrl_handler {
+                signal_shutdown_done();
+            }
+            return;
+        }
+        if self.active_screen != 0 {
+            let _ = self.set_active_screen(0);
+        }
+        let _ = self.stop_recording();
+        let _ = self.set_mouse_captured(false);
+        self.restore_mouse_cursor();
+        if let Some(state) = &self.original_state {
+            state.restore(self.output_handle, self.input_handle);
+            remove_crash_guard();
+        }
+        if self.ctrl_handler {
+            signal_shutdown_done();
+        }
+    }
+}
+
+// endregion
+
+// region: Frame Timing
+
+#[derive(Clone, Default)]
+struct FrameTiming {
+    max_delta: Option<f32>,
+    smoothing: usize,
+    history: VecDeque<f32>,
+    raw: f32,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Limits the elapsed time passed to `update` to at most `max` seconds, or `None` for
+    /// no limit (the default).
+    ///
+    /// A single long frame, e.g. while the window is dragged, otherwise moves everything
+    /// so far in one step that fast objects pass straight through walls. Something like
+    /// `Some(0.1)` slows the game down during hitches instead.
+    pub fn set_max_frame_time(&mut self, max: Option<f32>) {
+        self.frame_timing.max_delta = max.map(|max| max.max(0.0));
+    }
+
+    /// Passes the average elapsed time of the last `frames` frames to `update`, evening
+    /// out jitter in the frame rate. `0` or `1` turns smoothing off (the default).
+    ///
+    /// Frame times are limited by `set_max_frame_time` before they are averaged, so a
+    /// single hitch doesn't linger in the average.
+    pub fn set_frame_smoothing(&mut self, frames: usize) {
+        self.frame_timing.smoothing = frames;
+        self.frame_timing.history.clear();
+    }
+
+    /// Returns the measured time of the last frame in seconds, before it was limited or
+    /// smoothed.
+    pub fn raw_elapsed_time(&self) -> f32 {
+        self.frame_timing.raw
+    }
+
+    fn smooth_frame_time(&mut self, elapsed_time: f32) -> f32 {
+        let timing = &mut self.frame_timing;
+        timing.raw = elapsed_time;
+
+        let elapsed_time = match timing.max_delta {
+            Some(max) => elapsed_time.min(max),
+            None => elapsed_time,
+        };
+        if timing.smoothing <= 1 {
+            return elapsed_time;
+        }
+
+        timing.history.push_back(elapsed_time);
+        while timing.history.len() > timing.smoothing {
+            timing.history.pop_front();
+        }
+        timing.history.iter().sum::<f32>() / timing.history.len() as f32
+    }
+}
+
+// endregion
+
+// region: Present
+
+struct PresentFrame {
+    buffer: Vec<CHAR_INFO>,
+    size: COORD,
+    rect: SMALL_RECT,
+}
+
+/// Writes frames to the console on a separate thread.
+///
+/// Cloning a presenter starts a new present thread for the same console.
+struct Presenter {
+    output_handle: usize,
+    tx: Option<Sender<PresentFrame>>,
+    /// Written buffers coming back, with the result of writing them.
+    done_rx: Receiver<(Vec<CHAR_INFO>, windows::core::Result<()>)>,
+    spare: Option<Vec<CHAR_INFO>>,
+    in_flight: bool,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Presenter {
+    fn new(output_handle: HANDLE) -> Self {
+        let (tx, rx) = mpsc::channel::<PresentFrame>();
+        let (done_tx, done_rx) = mpsc::channel();
+        let handle = output_handle.0 as usize;
+
+        let thread = thread::spawn(move || {
+            for mut frame in rx {
+                let result = unsafe {
+                    WriteConsoleOutputW(
+                        HANDLE(handle as *mut _),
+                        frame.buffer.as_ptr(),
+                        frame.size,
+                        COORD { X: 0, Y: 0 },
+                        &mut frame.rect,
+                    )
+                };
+                if done_tx.send((frame.buffer, result)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            output_handle: handle,
+            tx: Some(tx),
+            done_rx,
+            spare: None,
+            in_flight: false,
+            thread: Some(thread),
+        }
+    }
+
+    /// Waits for the frame being written, if any, and returns a buffer that is free to reuse.
+    fn take_buffer(&mut self) -> Vec<CHAR_INFO> {
+        if self.in_flight {
+            self.in_flight = false;
+            match self.done_rx.recv() {
+                Ok((buffer, Ok(()))) => buffer,
+                // Failures are reported here, on the game thread, like `write_console_output`
+                // reports them when presenting synchronously.
+                Ok((_, Err(e))) => {
+                    eprintln!("WriteConsoleOutputW Failed: {:?}", e);
+                    exit(1);
+                }
+                Err(_) => Vec::new(),
+            }
+        } else {
+            self.spare.take().unwrap_or_default()
+        }
+    }
+
+    /// Blocks until the frame being written, if any, has reached the console.
+    fn sync(&mut self) {
+        let buffer = self.take_buffer();
+        self.spare = Some(buffer);
+    }
+
+    fn present(&mut self, buffer: &[CHAR_INFO], size: COORD, rect: SMALL_RECT) {
+        let mut front = self.take_buffer();
+        front.clear();
+        front.extend_from_slice(buffer);
+
+        if let Some(tx) = &self.tx {
+            self.in_flight = tx
+                .send(PresentFrame {
+                    buffer: front,
+                    size,
+                    rect,
+                })
+                .is_ok();
+        }
+    }
+}
+
+impl Clone for Presenter {
+    fn clone(&self) -> Self {
+        Presenter::new(HANDLE(self.output_handle as *mut _))
+    }
+}
+
+impl Drop for Presenter {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Enables or disables writing frames to the console on a separate thread.
+    ///
+    /// When enabled, `present` copies the finished frame into a second buffer and hands it to
+    /// a present thread, so the next `update` runs while the slow console write is still in
+    /// progress. Only one frame is ever in flight: presenting waits for the previous frame
+    /// to be written first, so frames are never torn or skipped.
+    pub fn set_threaded_present(&mut self, enabled: bool) {
+        if enabled && self.presenter.is_none() {
+            self.presenter = Some(Presenter::new(self.output_handle));
+        } else if !enabled {
+            self.presenter = None;
+        }
+    }
+
+    /// Returns `true` if frames are written to the console on a separate thread.
+    pub fn threaded_present(&self) -> bool {
+        self.presenter.is_some()
+    }
+
+    /// Writes the current frame to the console.
+    ///
+    /// This is called automatically after every `update`. With threaded present enabled, it
+    /// first waits until the previous frame has been written, then hands over a copy of this
+    /// one and returns immediately. Does nothing in headless mode.
+    pub fn present(&mut self) {
+        if self.headless {
+            return;
+        }
+
+        if self.glyph_fallback == GlyphFallback::Substitute {
+            self.substitute_glyphs();
+        }
+
+        let size = COORD {
+            X: self.screen_width,
+            Y: self.screen_height,
+        };
+
+        let buffer = if self.remap_colors() {
+            &self.remapped_buffer
+        } else {
+            &self.window_buffer
+        };
+
+        match &mut self.presenter {
+            Some(presenter) => presenter.present(buffer, size, self.rect),
+            None => {
+                let mut rect = self.rect;
+                self.write_console_output(
+                    self.output_handle,
+                    buffer.as_ptr(),
+                    size,
+                    COORD { X: 0, Y: 0 },
+                    &mut rect,
+                );
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Glyph Fallback
+
+/// How characters the console font can't draw are handled when a frame is presented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GlyphFallback {
+    /// Characters are written as-is. Legacy consoles draw missing glyphs as empty boxes or
+    /// question marks.
+    #[default]
+    Off,
+    /// Characters the font doesn't have are swapped for the nearest one it does: shades
+    /// for lighter shades or `#`, box drawing for `+`, `-` and `|`, arrows and triangles
+    /// for `<`, `>`, `^` and `v`, and anything else for `?`. Characters outside the Basic
+    /// Multilingual Plane can't be probed and are always replaced.
+    Substitute,
+}
+
+/// Cached results of probing the console font, one entry per UTF-16 unit.
+const GLYPH_UNKNOWN: u8 = 0;
+const GLYPH_PRESENT: u8 = 1;
+const GLYPH_MISSING: u8 = 2;
+
+/// Returns replacements for `ch` to try in order, best first. `?` is used when none of
+/// them are supported either.
+fn glyph_substitutes(ch: u16) -> &'static [u16] {
+    const HASH: u16 = b'#' as u16;
+    match ch {
+        pixel::SOLID => &[glyph::DARK_SHADE, HASH],
+        glyph::DARK_SHADE => &[glyph::MEDIUM_SHADE, HASH],
+        glyph::MEDIUM_SHADE => &[glyph::LIGHT_SHADE, b'%' as u16],
+        glyph::LIGHT_SHADE => &[b':' as u16],
+        glyph::UPPER_HALF => &[b'"' as u16],
+        glyph::LOWER_HALF => &[b'_' as u16],
+        glyph::LEFT_HALF | glyph::RIGHT_HALF => &[b'|' as u16],
+        0x2589..=0x258B | 0x258D..=0x258F => &[glyph::LEFT_HALF, b'|' as u16],
+        0x2500 | 0x2501 | 0x2504 | 0x2505 | 0x2508 | 0x2509 | 0x254C | 0x254D => &[b'-' as u16],
+        glyph::DOUBLE_HORIZONTAL => &[glyph::SINGLE_HORIZONTAL, b'=' as u16],
+        0x2502 | 0x2503 | 0x2506 | 0x2507 | 0x250A | 0x250B | 0x254E | 0x254F => &[b'|' as u16],
+        glyph::DOUBLE_VERTICAL => &[glyph::SINGLE_VERTICAL, b'|' as u16],
+        0x2500..=0x257F => &[b'+' as u16],
+        glyph::ARROW_LEFT | glyph::TRIANGLE_LEFT => &[b'<' as u16],
+        glyph::ARROW_RIGHT | glyph::TRIANGLE_RIGHT => &[b'>' as u16],
+        glyph::ARROW_UP | glyph::TRIANGLE_UP => &[b'^' as u16],
+        glyph::ARROW_DOWN | glyph::TRIANGLE_DOWN => &[b'v' as u16],
+        glyph::BULLET => &[0x00B7, b'*' as u16],
+        glyph::SMILEY => &[b'@' as u16],
+        glyph::NOTE => &[b'&' as u16],
+        glyph::SPADE | glyph::CLUB | glyph::HEART | glyph::DIAMOND => &[b'*' as u16],
+        0x2654 | 0x265A => &[b'K' as u16],
+        0x2655 | 0x265B => &[b'Q' as u16],
+        0x2656 | 0x265C => &[b'R' as u16],
+        0x2657 | 0x265D => &[b'B' as u16],
+        0x2658 | 0x265E => &[b'N' as u16],
+        0x2659 | 0x265F => &[b'P' as u16],
+        _ => &[],
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sets how characters the console font can't draw are handled. Defaults to
+    /// `GlyphFallback::Off`.
+    ///
+    /// With `GlyphFallback::Substitute`, every frame is checked just before it is written to
+    /// the console. Each character is probed against the current console font the first
+    /// time it appears and the result is cached, so only new characters cost anything.
+    /// Substitutes are written back into the screen buffer.
+    pub fn set_glyph_fallback(&mut self, mode: GlyphFallback) {
+        self.glyph_fallback = mode;
+    }
+
+    /// Returns how characters the console font can't draw are handled.
+    pub fn glyph_fallback(&self) -> GlyphFallback {
+        self.glyph_fallback
+    }
+
+    /// Returns `true` if the current console font has a glyph for `ch`.
+    ///
+    /// ASCII is always assumed to be supported, as is everything in headless mode or when
+    /// the font can't be probed. Surrogate halves are reported as unsupported.
+    pub fn glyph_supported(&mut self, ch: u16) -> bool {
+        self.probe_glyphs(&[ch]);
+        self.glyph_support[ch as usize] != GLYPH_MISSING
+    }
+
+    /// Forgets every probed glyph, so they are checked again against the current font.
+    fn clear_glyph_support(&mut self) {
+        self.glyph_support.clear();
+    }
+
+    /// Probes every character in `chars` that hasn't been probed yet in one batch.
+    fn probe_glyphs(&mut self, chars: &[u16]) {
+        if self.glyph_support.is_empty() {
+            self.glyph_support = vec![GLYPH_UNKNOWN; 0x10000];
+            for ch in 0x20..0x7F {
+                self.glyph_support[ch] = GLYPH_PRESENT;
+            }
+            for ch in 0xD800..=0xDFFF {
+                self.glyph_support[ch] = GLYPH_MISSING;
+            }
+        }
+
+        let mut unknown: Vec<u16> = chars
+            .iter()
+            .copied()
+            .filter(|&ch| self.glyph_support[ch as usize] == GLYPH_UNKNOWN)
+            .collect();
+        if unknown.is_empty() {
+            return;
+        }
+        unknown.sort_unstable();
+        unknown.dedup();
+
+        let indices = self.font_glyph_indices(&unknown);
+        for (i, &ch) in unknown.iter().enumerate() {
+            let missing = indices.as_ref().is_some_and(|indices| indices[i] == 0xFFFF);
+            self.glyph_support[ch as usize] = if missing {
+                GLYPH_MISSING
+            } else {
+                GLYPH_PRESENT
+            };
+        }
+    }
+
+    /// Looks `chars` up in the current console font, returning their glyph indices with
+    /// `0xFFFF` for missing glyphs, or `None` if the font couldn't be queried.
+    fn font_glyph_indices(&self, chars: &[u16]) -> Option<Vec<u16>> {
+        let hwnd = self.console_window().ok()?;
+
+        let mut font_cfi = CONSOLE_FONT_INFOEX {
+            cbSize: std::mem::size_of::<CONSOLE_FONT_INFOEX>() as u32,
+            ..Default::default()
+        };
+        unsafe { GetCurrentConsoleFontEx(self.output_handle, false, &mut font_cfi) }.ok()?;
+
+        let logfont = LOGFONTW {
+            lfHeight: font_cfi.dwFontSize.Y as i32,
+            lfWidth: font_cfi.dwFontSize.X as i32,
+            lfWeight: font_cfi.FontWeight as i32,
+            lfFaceName: font_cfi.FaceName,
+            ..Default::default()
+        };
+
+        let mut indices = vec![0u16; chars.len()];
+        unsafe {
+            let font = CreateFontIndirectW(&logfont);
+            if font.is_invalid() {
+                return None;
+            }
+            let hdc = GetDC(Some(hwnd));
+            if hdc.is_invalid() {
+                let _ = DeleteObject(font.into());
+                return None;
+            }
+            let previous = SelectObject(hdc, font.into());
+            let found = GetGlyphIndicesW(
+                hdc,
+                PCWSTR(chars.as_ptr()),
+                chars.len() as i32,
+                indices.as_mut_ptr(),
+                GGI_MARK_NONEXISTING_GLYPHS,
+            );
+            SelectObject(hdc, previous);
+            ReleaseDC(Some(hwnd), hdc);
+            let _ = DeleteObject(font.into());
+            if found == u32::MAX {
+                return None;
+            }
+        }
+        Some(indices)
+    }
+
+    /// Replaces every character in the frame the console font can't draw with its nearest
+    /// supported substitute.
+    fn substitute_glyphs(&mut self) {
+        let chars: Vec<u16> = self
+            .window_buffer
+            .iter()
+            .map(|cell| unsafe { cell.Char.UnicodeChar })
+            .collect();
+        self.probe_glyphs(&chars);
+
+        let candidates: Vec<u16> = chars
+            .iter()
+            .filter(|&&ch| self.glyph_support[ch as usize] == GLYPH_MISSING)
+            .flat_map(|&ch| glyph_substitutes(ch).iter().copied())
+            .collect();
+        self.probe_glyphs(&candidates);
+
+        for (cell, &ch) in self.window_buffer.iter_mut().zip(&chars) {
+            if self.glyph_support[ch as usize] != GLYPH_MISSING {
+                continue;
+            }
+            let trailing = cell.Attributes & TRAILING_HALF != 0;
+            let substitute = glyph_substitutes(ch)
+                .iter()
+                .copied()
+                .find(|&sub| self.glyph_support[sub as usize] == GLYPH_PRESENT)
+                .unwrap_or(b'?' as u16);
+            // A wide character shrinks to one cell, so its right half is blanked.
+            cell.Char.UnicodeChar = if trailing { b' ' as u16 } else { substitute };
+            cell.Attributes &= !(LEADING_HALF | TRAILING_HALF);
+        }
+    }
+}
+
+// endregion
+
+// region: Palette
+
+/// The RGB values of the 16 console colors, in the order of the `color` constants
+/// (`FG_BLACK`, `FG_DARK_BLUE`, ..., `FG_WHITE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [(u8, u8, u8); 16],
+}
+
+impl Palette {
+    /// The default Windows console colors.
+    pub const DEFAULT: Palette = Palette {
+        colors: [
+            (12, 12, 12),
+            (0, 55, 218),
+            (19, 161, 14),
+            (58, 150, 221),
+            (197, 15, 31),
+            (136, 23, 152),
+            (193, 156, 0),
+            (204, 204, 204),
+            (118, 118, 118),
+            (59, 120, 255),
+            (22, 198, 12),
+            (97, 214, 214),
+            (231, 72, 86),
+            (180, 0, 158),
+            (249, 241, 165),
+            (242, 242, 242),
+        ],
+    };
+
+    /// Colors that stay apart for players with deuteranopia (green-blind). Greens lean
+    /// towards blue and reds towards orange, so they differ in hue and brightness.
+    pub const DEUTERANOPIA: Palette = Palette {
+        colors: [
+            (12, 12, 12),
+            (0, 60, 150),
+            (0, 120, 110),
+            (60, 140, 200),
+            (170, 80, 0),
+            (140, 70, 130),
+            (180, 160, 0),
+            (204, 204, 204),
+            (118, 118, 118),
+            (40, 130, 255),
+            (0, 170, 130),
+            (86, 190, 240),
+            (230, 110, 0),
+            (204, 121, 167),
+            (240, 228, 66),
+            (242, 242, 242),
+        ],
+    };
+
+    /// Colors that stay apart for players with protanopia (red-blind). Like `DEUTERANOPIA`,
+    /// but with brighter reds, which protanopes see as darker.
+    pub const PROTANOPIA: Palette = Palette {
+        colors: [
+            (12, 12, 12),
+            (0, 60, 150),
+            (0, 120, 110),
+            (60, 140, 200),
+            (200, 100, 0),
+            (150, 80, 150),
+            (190, 170, 0),
+            (204, 204, 204),
+            (118, 118, 118),
+            (40, 130, 255),
+            (0, 170, 130),
+            (86, 190, 240),
+            (255, 140, 20),
+            (220, 140, 190),
+            (245, 235, 90),
+            (242, 242, 242),
+        ],
+    };
+
+    /// Colors that stay apart for players with tritanopia (blue-blind). Blues are darker,
+    /// cyans lighter and yellows lean towards orange, keeping them apart from greens and
+    /// magentas.
+    pub const TRITANOPIA: Palette = Palette {
+        colors: [
+            (12, 12, 12),
+            (0, 45, 140),
+            (20, 140, 40),
+            (0, 150, 170),
+            (200, 20, 40),
+            (150, 30, 90),
+            (200, 120, 0),
+            (204, 204, 204),
+            (118, 118, 118),
+            (60, 110, 230),
+            (60, 200, 60),
+            (110, 220, 230),
+            (240, 70, 70),
+            (220, 60, 140),
+            (255, 210, 120),
+            (242, 242, 242),
+        ],
+    };
+
+    fn to_color_table(self) -> [COLORREF; 16] {
+        self.colors
+            .map(|(r, g, b)| COLORREF(r as u32 | (g as u32) << 8 | (b as u32) << 16))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::DEFAULT
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Changes the RGB values of the 16 console colors, e.g. to
+    /// `set_palette(&Palette::DEUTERANOPIA)` for color-blind players.
+    ///
+    /// Everything on screen changes color at once, without redrawing. The palette stays
+    /// in effect when switching screens, and the original colors are restored on exit.
+    ///
+    /// # Errors
+    /// Returns an error if the console rejects the new colors.
+    pub fn set_palette(&mut self, palette: &Palette) -> Result<(), Box<dyn std::error::Error>> {
+        self.palette = Some(*palette);
+        self.apply_palette()
+    }
+
+    /// Returns the palette set with `set_palette`, or `None` if the console colors haven't
+    /// been changed.
+    pub fn palette(&self) -> Option<Palette> {
+        self.palette
+    }
+
+    /// Sets a function every cell's color goes through when a frame is presented, or `None`
+    /// to turn it off.
+    ///
+    /// The screen buffer keeps the colors the game drew, so this can remap colors the
+    /// palette can't, e.g. swapping red and green entirely, without touching drawing code.
+    /// Leading and trailing half flags of wide characters are kept as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// // Show everything red as blue instead.
+    /// engine.set_color_remap(Some(|col| match col & 0x0F {
+    ///     FG_RED => (col & !0x0F) | FG_BLUE,
+    ///     _ => col,
+    /// }));
+    /// ```
+    pub fn set_color_remap(&mut self, remap: Option<fn(u16) -> u16>) {
+        self.color_remap = remap;
+        if remap.is_none() {
+            self.remapped_buffer = Vec::new();
+        }
+    }
+
+    fn apply_palette(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(palette) = self.palette else {
+            return Ok(());
+        };
+        if self.headless {
+            return Ok(());
+        }
+        set_color_table(self.output_handle, palette.to_color_table())?;
+        Ok(())
+    }
+
+    /// Fills `remapped_buffer` with the frame passed through the color remap, returning
+    /// `false` if there is none.
+    fn remap_colors(&mut self) -> bool {
+        let Some(remap) = self.color_remap else {
+            return false;
+        };
+        const HALVES: u16 = LEADING_HALF | TRAILING_HALF;
+        self.remapped_buffer.clear();
+        self.remapped_buffer
+            .extend(self.window_buffer.iter().map(|cell| CHAR_INFO {
+                Attributes: remap(cell.Attributes & !HALVES) | (cell.Attributes & HALVES),
+                ..*cell
+            }));
+        true
+    }
+}
+
+/// Reads the 16 console colors of the screen buffer `handle`.
+fn color_table(handle: HANDLE) -> windows::core::Result<[COLORREF; 16]> {
+    let mut info = CONSOLE_SCREEN_BUFFER_INFOEX {
+        cbSize: std::mem::size_of::<CONSOLE_SCREEN_BUFFER_INFOEX>() as u32,
+        ..Default::default()
+    };
+    unsafe { GetConsoleScreenBufferInfoEx(handle, &mut info)? };
+    Ok(info.ColorTable)
+}
+
+/// Changes the 16 console colors of the screen buffer `handle`, leaving everything else.
+fn set_color_table(handle: HANDLE, table: [COLORREF; 16]) -> windows::core::Result<()> {
+    let mut info = CONSOLE_SCREEN_BUFFER_INFOEX {
+        cbSize: std::mem::size_of::<CONSOLE_SCREEN_BUFFER_INFOEX>() as u32,
+        ..Default::default()
+    };
+    unsafe {
+        GetConsoleScreenBufferInfoEx(handle, &mut info)?;
+        info.ColorTable = table;
+        // The window rectangle reads back one cell smaller than it is set, so setting it
+        // unchanged would shrink the window.
+        info.srWindow.Right += 1;
+        info.srWindow.Bottom += 1;
+        SetConsoleScreenBufferInfoEx(handle, &info)?;
+    }
+    Ok(())
+}
+
+// endregion
+
+// region: Screens
+
+/// Both read and write sharing for `CreateConsoleScreenBuffer`.
+const FILE_SHARE_READ_WRITE: u32 = 0x1 | 0x2;
+
+/// A console screen buffer. Ones created by the engine are closed when the last engine
+/// clone using them is dropped.
+struct ScreenHandle {
+    handle: HANDLE,
+    owned: bool,
+}
+
+impl Drop for ScreenHandle {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                let _ = CloseHandle(self.handle);
+            }
+        }
+    }
+}
+
+/// A screen that isn't active, with the contents it had when it was switched away from.
+#[derive(Clone)]
+struct Screen {
+    handle: std::rc::Rc<ScreenHandle>,
+    buffer: Vec<CHAR_INFO>,
+    width: i16,
+    height: i16,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Creates an additional screen and returns its index, for use with `set_active_screen`.
+    ///
+    /// The screen the console was constructed with is screen 0. Each screen is a separate
+    /// console screen buffer with its own contents, so switching between them is instant
+    /// and the game screen doesn't have to be redrawn after showing e.g. a debug view or
+    /// an editor. New screens start out blank.
+    ///
+    /// # Errors
+    /// Returns an error if the console can't create another screen buffer.
+    pub fn create_screen(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        if self.screens.is_empty() {
+            self.screens.push(Screen {
+                handle: std::rc::Rc::new(ScreenHandle {
+                    handle: self.output_handle,
+                    owned: false,
+                }),
+                buffer: Vec::new(),
+                width: self.screen_width,
+                height: self.screen_height,
+            });
+        }
+
+        let handle = if self.headless {
+            ScreenHandle {
+                handle: self.output_handle,
+                owned: false,
+            }
+        } else {
+            let handle = unsafe {
+                CreateConsoleScreenBuffer(
+                    (GENERIC_READ | GENERIC_WRITE).0,
+                    FILE_SHARE_READ_WRITE,
+                    None,
+                    CONSOLE_TEXTMODE_BUFFER,
+                    None,
+                )?
+            };
+            ScreenHandle {
+                handle,
+                owned: true,
+            }
+        };
+
+        self.screens.push(Screen {
+            handle: std::rc::Rc::new(handle),
+            buffer: Vec::new(),
+            width: 0,
+            height: 0,
+        });
+        Ok(self.screens.len() - 1)
+    }
+
+    /// Makes `screen` the screen that is shown, drawn to and presented.
+    ///
+    /// The contents of the previously active screen are kept, so switching back shows
+    /// them again without redrawing. A screen that was last shown at a different size
+    /// (because of `resize` or fullscreen) is resized and cleared when activated.
+    ///
+    /// # Errors
+    /// Returns an error if `screen` doesn't exist or the console rejects the switch.
+    pub fn set_active_screen(&mut self, screen: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if screen == self.active_screen && screen < self.screens.len().max(1) {
+            return Ok(());
+        }
+        if screen >= self.screens.len() {
+            return Err(format!("screen {} doesn't exist", screen).into());
+        }
+        if let Some(presenter) = &mut self.presenter {
+            presenter.sync();
+        }
+
+        let (width, height) = (self.screen_width, self.screen_height);
+        let target = &mut self.screens[screen];
+        let mut buffer = std::mem::take(&mut target.buffer);
+        let handle = target.handle.handle;
+        let resized = target.width != width || target.height != height;
+        if resized {
+            buffer = vec![CHAR_INFO::default(); (width as i32 * height as i32) as usize];
+            target.width = width;
+            target.height = height;
+        }
+
+        if !self.headless {
+            if resized {
+                self.set_console_screen_buffer_size(
+                    handle,
+                    COORD {
+                        X: width,
+                        Y: height,
+                    },
+                )?;
+                self.set_console_window_info(handle, true, &self.rect)?;
+            }
+            self.set_console_active_screen_buffer(handle)?;
+        }
+
+        let previous = &mut self.screens[self.active_screen];
+        previous.buffer = std::mem::replace(&mut self.window_buffer, buffer);
+        previous.width = width;
+        previous.height = height;
+
+        self.output_handle = handle;
+        self.active_screen = screen;
+
+        if !self.headless {
+            self.set_console_cursor_info()?;
+            self.enable_attribute_flags();
+            self.apply_palette()?;
+        }
+        if self.presenter.is_some() {
+            self.presenter = Some(Presenter::new(handle));
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the active screen. 0 is the screen the console was constructed with.
+    pub fn active_screen(&self) -> usize {
+        self.active_screen
+    }
+}
+
+// endregion
+
+// region: Title
+
+const DEFAULT_TITLE_FORMAT: &str = "Console Game Engine - {app} - FPS: {fps}";
+const TITLE_UPDATE_INTERVAL: f32 = 0.25;
+
+#[derive(Clone)]
+struct TitleState {
+    format: String,
+    suffix: String,
+    fields: HashMap<String, String>,
+    auto: bool,
+    dirty: bool,
+    timer: f32,
+    last: String,
+}
+
+impl Default for TitleState {
+    fn default() -> Self {
+        Self {
+            format: DEFAULT_TITLE_FORMAT.to_string(),
+            suffix: String::new(),
+            fields: HashMap::new(),
+            auto: true,
+            dirty: true,
+            timer: 0.0,
+            last: String::new(),
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sets the format of the console title the engine keeps up to date.
+    ///
+    /// `{app}` is replaced with the app name, `{fps}` with the current frame rate and
+    /// `{name}` with the value set by `set_title_field("name", ...)`. The default is
+    /// `"Console Game Engine - {app} - FPS: {fps}"`.
+    ///
+    /// The title is refreshed a few times per second, and only when its text changes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.set_title_format("{app} - Level {level} - Score {score}");
+    /// engine.set_title_field("level", 3);
+    /// engine.set_title_field("score", self.score);
+    /// ```
+    pub fn set_title_format(&mut self, format: &str) {
+        self.title.format = format.to_string();
+        self.title.dirty = true;
+    }
+
+    /// Sets the value of the `{key}` placeholder in the title format.
+    pub fn set_title_field<T: std::fmt::Display>(&mut self, key: &str, value: T) {
+        let value = value.to_string();
+        if self.title.fields.get(key) != Some(&value) {
+            self.title.fields.insert(key.to_string(), value);
+            self.title.dirty = true;
+        }
+    }
+
+    /// Sets text appended to the formatted title, separated by ` - `.
+    /// An empty string removes the suffix.
+    pub fn set_title_suffix(&mut self, suffix: &str) {
+        self.title.suffix = suffix.to_string();
+        self.title.dirty = true;
+    }
+
+    /// Enables or disables the automatic title updates.
+    ///
+    /// When disabled, the engine never touches the console title, so the game can set it
+    /// with `set_title` whenever it likes.
+    pub fn set_auto_title(&mut self, enabled: bool) {
+        self.title.auto = enabled;
+        self.title.dirty = true;
+    }
+
+    /// Sets the console title right away. Unless automatic title updates are disabled with
+    /// `set_auto_title(false)`, it will be replaced on the next title refresh.
+    pub fn set_title(&mut self, title: &str) {
+        if self.headless {
+            return;
+        }
+        let title = HSTRING::from(title);
+        self.set_console_title(PCWSTR(title.as_ptr()));
+    }
+
+    fn update_title(&mut self, elapsed_time: f32, fps: f32) {
+        if !self.title.auto {
+            return;
+        }
+
+        if self.announcer.title_timer > 0.0 {
+            self.announcer.title_timer -= elapsed_time;
+            return;
+        }
+
+        self.title.timer -= elapsed_time;
+        if self.title.timer > 0.0 && !self.title.dirty {
+            return;
+        }
+        self.title.timer = TITLE_UPDATE_INTERVAL;
+        self.title.dirty = false;
+
+        let mut text = self
+            .title
+            .format
+            .replace("{app}", &self.app_name)
+            .replace("{fps}", &format!("{:.2}", fps));
+        for (key, value) in &self.title.fields {
+            text = text.replace(&format!("{{{}}}", key), value);
+        }
+        if !self.title.suffix.is_empty() {
+            text = format!("{} - {}", text, self.title.suffix);
+        }
+
+        if text != self.title.last {
+            self.set_title(&text);
+            self.title.last = text;
+        }
+    }
+}
+
+// endregion
+
+// region: Accessibility
+
+/// How long an announcement stays in the console title before the normal title returns.
+const ANNOUNCE_TITLE_TIME: f32 = 3.0;
+
+#[derive(Clone)]
+struct Announcer {
+    in_title: bool,
+    title_timer: f32,
+    recent: Vec<String>,
+}
+
+impl Default for Announcer {
+    fn default() -> Self {
+        Self {
+            in_title: true,
+            title_timer: 0.0,
+            recent: Vec::new(),
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sends `text` to screen readers, e.g. the selected menu item or a line of dialogue.
+    ///
+    /// The engine draws straight into the screen buffer, which screen readers don't follow,
+    /// so important text has to be announced separately. The text is:
+    /// - shown in the console title for a few seconds, which screen readers read out when it
+    ///   changes (see `set_announce_in_title`). This is skipped when the game manages the
+    ///   title itself with `set_auto_title(false)`,
+    /// - written as a line to standard error if it has been redirected, e.g. to a file or a
+    ///   speech program,
+    /// - kept until the next frame in `announcements`, so games can route it elsewhere.
+    pub fn announce(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        self.announcer.recent.push(text.to_string());
+
+        if stderr_redirected() {
+            eprintln!("{}", text);
+        }
+
+        // Only borrow the title while the engine owns it, so it gets put back afterwards.
+        if self.announcer.in_title && self.title.auto && !self.headless {
+            self.set_title(text);
+            self.announcer.title_timer = ANNOUNCE_TITLE_TIME;
+            self.title.last.clear();
+        }
+    }
+
+    /// Enables or disables showing announcements in the console title. On by default.
+    pub fn set_announce_in_title(&mut self, enabled: bool) {
+        self.announcer.in_title = enabled;
+    }
+
+    /// Returns the text announced with `announce` since the start of this frame.
+    pub fn announcements(&self) -> &[String] {
+        &self.announcer.recent
+    }
+}
+
+/// Returns `true` if standard error goes somewhere other than the console.
+fn stderr_redirected() -> bool {
+    unsafe {
+        let Ok(handle) = GetStdHandle(STD_ERROR_HANDLE) else {
+            return false;
+        };
+        let mut mode = CONSOLE_MODE(0);
+        !handle.is_invalid() && GetConsoleMode(handle, &mut mode).is_err()
+    }
+}
+
+// endregion
+
+// region: Window
+
+/// What the window looked like before going fullscreen, so it can be restored.
+#[derive(Clone, Copy)]
+struct WindowedState {
+    width: i16,
+    height: i16,
+    style: i32,
+    rect: RECT,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    fn console_window(&self) -> Result<HWND, Box<dyn std::error::Error>> {
+        if self.headless {
+            return Err("no console window in headless mode".into());
+        }
+        let hwnd = unsafe { GetConsoleWindow() };
+        if hwnd.is_invalid() {
+            return Err("console has no window".into());
+        }
+        Ok(hwnd)
+    }
+
+    /// Moves the console window so its top-left corner is at `(x, y)` in screen pixels.
+    ///
+    /// Coordinates are relative to the primary monitor, so other monitors can be reached
+    /// with negative coordinates or ones past its edge.
+    pub fn set_window_position(
+        &mut self,
+        x: i32,
+        y: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                None,
+                x,
+                y,
+                0,
+                0,
+                SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the position of the console window's top-left corner in screen pixels.
+    pub fn window_position(&self) -> Result<(i32, i32), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(hwnd, &mut rect)? };
+        Ok((rect.left, rect.top))
+    }
+
+    /// Centers the console window on the work area (the screen minus the taskbar) of the
+    /// primary monitor.
+    pub fn center_window(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+
+        let mut rect = RECT::default();
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+        unsafe {
+            GetWindowRect(hwnd, &mut rect)?;
+            let monitor = MonitorFromPoint(POINT { x: 0, y: 0 }, MONITOR_DEFAULTTOPRIMARY);
+            if !GetMonitorInfoW(monitor, &mut info).as_bool() {
+                return Err("failed to query the primary monitor".into());
+            }
+        }
+
+        let work = info.rcWork;
+        let (w, h) = (rect.right - rect.left, rect.bottom - rect.top);
+        let x = work.left + ((work.right - work.left) - w) / 2;
+        let y = work.top + ((work.bottom - work.top) - h) / 2;
+        self.set_window_position(x, y)
+    }
+
+    /// Keeps the console window above all other windows, or stops doing so.
+    pub fn set_always_on_top(&mut self, on_top: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+        let insert_after = if on_top { HWND_TOPMOST } else { HWND_NOTOPMOST };
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                Some(insert_after),
+                0,
+                0,
+                0,
+                0,
+                SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sets the opacity of the whole console window, from `0.0` (invisible) to `1.0` (opaque).
+    pub fn set_window_opacity(&mut self, opacity: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+        let alpha = (opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        unsafe {
+            let style = GetWindowLongW(hwnd, GWL_EXSTYLE);
+            if style & WS_EX_LAYERED.0 as i32 == 0 {
+                SetWindowLongW(hwnd, GWL_EXSTYLE, style | WS_EX_LAYERED.0 as i32);
+            }
+            SetLayeredWindowAttributes(hwnd, COLORREF(0), alpha, LWA_ALPHA)?;
+        }
+        Ok(())
+    }
+
+    /// Shows or hides the mouse cursor while it is over the console window.
+    ///
+    /// Windows only hides the cursor over windows whose input the calling thread shares,
+    /// and the console window belongs to conhost, not to the game. So while the cursor is
+    /// hidden, the game thread's input is attached to the console window's thread. This
+    /// works in the classic console host, but terminals that draw the console in their own
+    /// window, like Windows Terminal, keep showing the cursor. Treat hiding as best effort.
+    ///
+    /// # Errors
+    /// Returns an error if there is no console window or its input can't be attached to.
+    pub fn set_mouse_cursor_visible(
+        &mut self,
+        visible: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if visible == self.cursor_visible {
+            return Ok(());
+        }
+        if visible {
+            self.restore_mouse_cursor();
+            return Ok(());
+        }
+        if !self.headless {
+            let hwnd = self.console_window()?;
+            unsafe {
+                let thread = GetWindowThreadProcessId(hwnd, None);
+                if thread == 0 || !AttachThreadInput(GetCurrentThreadId(), thread, true).as_bool() {
+                    return Err("can't attach to the console window's input".into());
+                }
+                self.cursor_thread = Some(thread);
+            }
+            step_cursor_count(false);
+            CURSOR_HIDDEN.store(true, SeqCst);
+        }
+        self.cursor_visible = false;
+        Ok(())
+    }
+
+    /// Returns `true` unless the mouse cursor was hidden with `set_mouse_cursor_visible`.
+    pub fn mouse_cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    fn restore_mouse_cursor(&mut self) {
+        if let Some(thread) = self.cursor_thread.take() {
+            step_cursor_count(true);
+            CURSOR_HIDDEN.store(false, SeqCst);
+            unsafe {
+                let _ = AttachThreadInput(GetCurrentThreadId(), thread, false);
+            }
+        }
+        self.cursor_visible = true;
+    }
+
+    /// Turns relative mouse mode on or off.
+    ///
+    /// While captured, the OS cursor is confined to the console window and pulled back to
+    /// its center every frame, and the movement since the previous frame is reported in
+    /// pixels by `mouse_delta`. This gives much finer control than the cell coordinates of
+    /// `mouse_pos`, e.g. for mouse-look. The capture is paused while the console is out of
+    /// focus. The cursor stays visible unless it is hidden with `set_mouse_cursor_visible`.
+    pub fn set_mouse_captured(&mut self, captured: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if self.headless {
+            self.mouse_captured = captured;
+            return Ok(());
+        }
+        if captured {
+            self.console_window()?;
+            self.mouse_captured = true;
+            self.recenter_cursor();
+        } else {
+            if self.mouse_captured {
+                unsafe { ClipCursor(None)? };
+            }
+            self.mouse_captured = false;
+        }
+        self.mouse_delta = (0, 0);
+        Ok(())
+    }
+
+    /// Returns `true` if relative mouse mode is on.
+    pub fn mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+
+    /// Returns how far the mouse moved since the last frame, in screen pixels.
+    ///
+    /// Always `(0, 0)` unless the mouse is captured with `set_mouse_captured`.
+    pub fn mouse_delta(&self) -> (i32, i32) {
+        self.mouse_delta
+    }
+
+    fn window_center(&self) -> Option<(RECT, POINT)> {
+        let hwnd = self.console_window().ok()?;
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(hwnd, &mut rect).ok()? };
+        let center = POINT {
+            x: (rect.left + rect.right) / 2,
+            y: (rect.top + rect.bottom) / 2,
+        };
+        Some((rect, center))
+    }
+
+    fn recenter_cursor(&self) -> Option<POINT> {
+        let (rect, center) = self.window_center()?;
+        unsafe {
+            let _ = ClipCursor(Some(&rect));
+            let _ = SetCursorPos(center.x, center.y);
+        }
+        Some(center)
+    }
+
+    fn update_mouse_capture(&mut self) {
+        self.mouse_delta = (0, 0);
+        if !self.mouse_captured {
+            return;
+        }
+        if !self.console_in_focus {
+            if !self.capture_paused {
+                unsafe {
+                    let _ = ClipCursor(None);
+                }
+                self.capture_paused = true;
+            }
+            return;
+        }
+        if self.capture_paused {
+            // Don't report the jump from wherever the cursor was while unfocused.
+            self.capture_paused = false;
+            self.recenter_cursor();
+            return;
+        }
+
+        let mut pos = POINT::default();
+        if unsafe { GetCursorPos(&mut pos) }.is_err() {
+            return;
+        }
+        if let Some((_, center)) = self.window_center() {
+            self.mouse_delta = (pos.x - center.x, pos.y - center.y);
+        }
+        self.recenter_cursor();
+    }
+
+    /// Switches between the normal window and a borderless window covering the whole monitor.
+    ///
+    /// The font size is kept, so going fullscreen makes the screen as many characters wide
+    /// and tall as fit on the monitor. Leaving fullscreen restores the previous size and
+    /// position. Either way the game is notified through `ConsoleGame::on_resize` at the
+    /// start of the next frame, and the screen has to be redrawn.
+    pub fn toggle_fullscreen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.windowed.is_some() {
+            self.exit_fullscreen()
+        } else {
+            self.enter_fullscreen()
+        }
+    }
+
+    /// Returns `true` if the console is currently fullscreen.
+    pub fn is_fullscreen(&self) -> bool {
+        self.windowed.is_some()
+    }
+
+    fn enter_fullscreen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+
+        let mut rect = RECT::default();
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+        let style = unsafe {
+            GetWindowRect(hwnd, &mut rect)?;
+            let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+            if !GetMonitorInfoW(monitor, &mut info).as_bool() {
+                return Err("failed to query the monitor".into());
+            }
+            GetWindowLongW(hwnd, GWL_STYLE)
+        };
+        let state = WindowedState {
+            width: self.screen_width,
+            height: self.screen_height,
+            style,
+            rect,
+        };
+
+        let borderless = style & !(WS_CAPTION.0 | WS_THICKFRAME.0) as i32;
+        unsafe { SetWindowLongW(hwnd, GWL_STYLE, borderless) };
+
+        let monitor = info.rcMonitor;
+        let max_size = unsafe { GetLargestConsoleWindowSize(self.output_handle) };
+        let width =
+            ((monitor.right - monitor.left) / self.font_width as i32).min(max_size.X as i32) as i16;
+        let height = ((monitor.bottom - monitor.top) / self.font_height as i32)
+            .min(max_size.Y as i32) as i16;
+
+        if let Err(e) = self.reconfigure(width, height, self.font_width, self.font_height) {
+            unsafe { SetWindowLongW(hwnd, GWL_STYLE, style) };
+            return Err(e);
+        }
+
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                None,
+                monitor.left,
+                monitor.top,
+                monitor.right - monitor.left,
+                monitor.bottom - monitor.top,
+                SWP_NOZORDER | SWP_FRAMECHANGED,
+            )?;
+        }
+
+        self.windowed = Some(state);
+        self.pending_resize = Some((self.screen_width, self.screen_height));
+        Ok(())
+    }
+
+    fn exit_fullscreen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let hwnd = self.console_window()?;
+        let Some(state) = self.windowed.take() else {
+            return Ok(());
+        };
+
+        unsafe { SetWindowLongW(hwnd, GWL_STYLE, state.style) };
+        self.reconfigure(state.width, state.height, self.font_width, self.font_height)?;
+
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                None,
+                state.rect.left,
+                state.rect.top,
+                0,
+                0,
+                SWP_NOSIZE | SWP_NOZORDER | SWP_FRAMECHANGED,
+            )?;
+        }
+
+        self.pending_resize = Some((self.screen_width, self.screen_height));
+        Ok(())
+    }
+}
+
+// endregion
+
+// region: Mouse Gestures
+
+const DEFAULT_DOUBLE_CLICK_TIME: f32 = 0.5;
+
+#[derive(Clone)]
+struct MouseGestures {
+    drag_threshold: i32,
+    double_click_time: f32,
+    press_pos: [Option<(i32, i32)>; 5],
+    dragging: [bool; 5],
+    since_click: [f32; 5],
+    click_pos: [(i32, i32); 5],
+    double_clicked: [bool; 5],
+}
+
+impl Default for MouseGestures {
+    fn default() -> Self {
+        Self {
+            drag_threshold: 1,
+            double_click_time: DEFAULT_DOUBLE_CLICK_TIME,
+            press_pos: [None; 5],
+            dragging: [false; 5],
+            since_click: [f32::INFINITY; 5],
+            click_pos: [(0, 0); 5],
+            double_clicked: [false; 5],
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Returns the start and current position of a drag with the given mouse button.
+    ///
+    /// A drag starts once the mouse has moved at least the drag threshold away from where
+    /// the button was pressed, and is still reported on the frame the button is released,
+    /// so a box selection can be committed in the same frame that `mouse_released` fires.
+    pub fn mouse_drag(&self, button: usize) -> Option<((i32, i32), (i32, i32))> {
+        let g = &self.mouse_gestures;
+        if !g.dragging[button] {
+            return None;
+        }
+        g.press_pos[button].map(|start| (start, (self.mouse_x, self.mouse_y)))
+    }
+
+    /// Returns `true` if the given mouse button was double-clicked this frame.
+    ///
+    /// The second press has to come within the double-click time of the first one and
+    /// without moving further than the drag threshold.
+    pub fn mouse_double_clicked(&self, button: usize) -> bool {
+        self.mouse_gestures.double_clicked[button]
+    }
+
+    /// Sets how far (in cells) the mouse has to move while held before it counts as a drag.
+    ///
+    /// Defaults to 1.
+    pub fn set_drag_threshold(&mut self, cells: i32) {
+        self.mouse_gestures.drag_threshold = cells.max(0);
+    }
+
+    /// Sets the maximum time in seconds between two clicks of a double-click.
+    ///
+    /// Defaults to 0.5, the same as Windows.
+    pub fn set_double_click_time(&mut self, seconds: f32) {
+        self.mouse_gestures.double_click_time = seconds.max(0.0);
+    }
+
+    fn step_mouse_gestures(&mut self, elapsed_time: f32) {
+        let pos = (self.mouse_x, self.mouse_y);
+        let g = &mut self.mouse_gestures;
+        let moved = |from: (i32, i32)| (pos.0 - from.0).abs().max((pos.1 - from.1).abs());
+
+        for m in 0..5 {
+            g.double_clicked[m] = false;
+            g.since_click[m] += elapsed_time;
+
+            if self.mouse_pressed[m] {
+                if g.since_click[m] <= g.double_click_time
+                    && moved(g.click_pos[m]) <= g.drag_threshold
+                {
+                    g.double_clicked[m] = true;
+                    // A third click starts over instead of being another double-click.
+                    g.since_click[m] = f32::INFINITY;
+                } else {
+                    g.since_click[m] = 0.0;
+                }
+                g.click_pos[m] = pos;
+                g.press_pos[m] = Some(pos);
+                g.dragging[m] = false;
+            }
+
+            if self.mouse_held[m] {
+                if let Some(start) = g.press_pos[m] {
+                    if !g.dragging[m] && moved(start) >= g.drag_threshold.max(1) {
+                        g.dragging[m] = true;
+                    }
+                }
+            } else if !self.mouse_released[m] {
+                g.press_pos[m] = None;
+                g.dragging[m] = false;
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Input Replay
+
+const INPUT_FRAME_SIZE: usize = 8 + 4 + 32 + 1 + 4 + 4;
+
+/// Snapshot of the keyboard and mouse state for a single frame.
+#[derive(Clone, Copy)]
+struct InputFrame {
+    timestamp: f64,
+    elapsed_time: f32,
+    keys: [u8; 32],
+    mouse_buttons: u8,
+    mouse_x: i32,
+    mouse_y: i32,
+}
+
+impl InputFrame {
+    fn to_bytes(self) -> [u8; INPUT_FRAME_SIZE] {
+        let mut buf = [0u8; INPUT_FRAME_SIZE];
+        buf[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.elapsed_time.to_le_bytes());
+        buf[12..44].copy_from_slice(&self.keys);
+        buf[44] = self.mouse_buttons;
+        buf[45..49].copy_from_slice(&self.mouse_x.to_le_bytes());
+        buf[49..53].copy_from_slice(&self.mouse_y.to_le_bytes());
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Self {
+        Self {
+            timestamp: f64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            elapsed_time: f32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            keys: buf[12..44].try_into().unwrap(),
+            mouse_buttons: buf[44],
+            mouse_x: i32::from_le_bytes(buf[45..49].try_into().unwrap()),
+            mouse_y: i32::from_le_bytes(buf[49..53].try_into().unwrap()),
+        }
+    }
+
+    fn key_held(&self, key: usize) -> bool {
+        self.keys[key / 8] & (1 << (key % 8)) != 0
+    }
+
+    fn mouse_held(&self, button: usize) -> bool {
+        self.mouse_buttons & (1 << button) != 0
+    }
+}
+
+#[derive(Clone)]
+struct InputRecording {
+    path: String,
+    timestamp: f64,
+    frames: Vec<InputFrame>,
+}
+
+#[derive(Clone)]
+struct InputPlayback {
+    frames: Vec<InputFrame>,
+    cursor: usize,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Starts recording the keyboard and mouse state of every frame.
+    ///
+    /// Each frame is stored together with its timestamp and elapsed time, and the
+    /// recording is written to `path` when `stop_recording` is called or the engine shuts down.
+    /// A recording can later be fed back into the engine with `replay_inputs`.
+    pub fn record_inputs<P: AsRef<Path>>(&mut self, path: P) {
+        self.input_recording = Some(InputRecording {
+            path: path.as_ref().to_string_lossy().into(),
+            timestamp: 0.0,
+            frames: Vec::new(),
+        });
+    }
+
+    /// Stops the current input recording and writes it to disk.
+    ///
+    /// Does nothing if no recording is in progress.
+    pub fn stop_recording(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(recording) = self.input_recording.take() else {
+            return Ok(());
+        };
+
+        let mut file = File::create(&recording.path)?;
+        for frame in &recording.frames {
+            file.write_all(&frame.to_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if inputs are currently being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.input_recording.is_some()
+    }
+
+    /// Loads a recording made with `record_inputs` and replays it.
+    ///
+    /// While replaying, the recorded key and mouse state replaces the real input,
+    /// and `update` receives the recorded elapsed time for each frame, so a game
+    /// using a seeded RNG will play out exactly as it did when it was recorded.
+    /// Live input resumes once the recording runs out.
+    pub fn replay_inputs<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        if buf.len() % INPUT_FRAME_SIZE != 0 {
+            return Err("input recording truncated".into());
+        }
+
+        let frames = buf
+            .chunks_exact(INPUT_FRAME_SIZE)
+            .map(InputFrame::from_bytes)
+            .collect();
+
+        self.input_playback = Some(InputPlayback { frames, cursor: 0 });
+
+        Ok(())
+    }
+
+    /// Stops replaying inputs and returns control to the keyboard and mouse.
+    pub fn stop_replay(&mut self) {
+        if self.input_playback.take().is_some() {
+            self.key_held = [false; 256];
+            self.mouse_held = [false; 5];
+        }
+    }
+
+    /// Returns `true` if a recording is currently being replayed.
+    pub fn is_replaying(&self) -> bool {
+        self.input_playback.is_some()
+    }
+
+    fn step_input_playback(&mut self) -> Option<f32> {
+        let playback = self.input_playback.as_mut()?;
+
+        let Some(&frame) = playback.frames.get(playback.cursor) else {
+            self.stop_replay();
+            return None;
+        };
+        playback.cursor += 1;
+
+        for i in 0..256 {
+            let held = frame.key_held(i);
+            self.key_pressed[i] = held && !self.key_held[i];
+            self.key_released[i] = !held && self.key_held[i];
+            self.key_held[i] = held;
+        }
+
+        for m in 0..5 {
+            let held = frame.mouse_held(m);
+            self.mouse_pressed[m] = held && !self.mouse_held[m];
+            self.mouse_released[m] = !held && self.mouse_held[m];
+            self.mouse_held[m] = held;
+        }
+
+        self.mouse_x = frame.mouse_x;
+        self.mouse_y = frame.mouse_y;
+
+        Some(frame.elapsed_time)
+    }
+
+    fn step_input_recording(&mut self, elapsed_time: f32) {
+        let Some(recording) = self.input_recording.as_mut() else {
+            return;
+        };
+
+        recording.timestamp += elapsed_time as f64;
+
+        let mut keys = [0u8; 32];
+        for (i, &held) in self.key_held.iter().enumerate() {
+            if held {
+                keys[i / 8] |= 1 << (i % 8);
+            }
+        }
+
+        let mut mouse_buttons = 0u8;
+        for (m, &held) in self.mouse_held.iter().enumerate() {
+            if held {
+                mouse_buttons |= 1 << m;
+            }
+        }
+
+        recording.frames.push(InputFrame {
+            timestamp: recording.timestamp,
+            elapsed_time,
+            keys,
+            mouse_buttons,
+            mouse_x: self.mouse_x,
+            mouse_y: self.mouse_y,
+        });
+    }
+}
+
+// endregion
+
+// region: Debug Overlay
+
+const FRAME_TIME_HISTORY: usize = 64;
+const DEBUG_GRAPH_HEIGHT: i32 = 4;
+
+#[derive(Clone, Default)]
+struct DebugOverlay {
+    enabled: bool,
+    frame_times: VecDeque<f32>,
+    watches: Vec<(String, String)>,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Shows or hides the debug overlay.
+    ///
+    /// The overlay is drawn on top of the game after every `update` and shows the FPS,
+    /// a graph of recent frame times, the number of draw calls made during the frame,
+    /// and any values registered with `debug_watch`.
+    /// It can also be toggled at any time by pressing F3.
+    pub fn set_debug_overlay(&mut self, enabled: bool) {
+        self.debug_overlay.enabled = enabled;
+    }
+
+    /// Returns `true` if the debug overlay is currently shown.
+    pub fn debug_overlay_enabled(&self) -> bool {
+        self.debug_overlay.enabled
+    }
+
+    /// Shows `value` in the debug overlay, labeled with `key`.
+    ///
+    /// Calling this again with the same `key` replaces the previous value,
+    /// so it can be called every frame for things like entity counts or player positions.
+    pub fn debug_watch<T: std::fmt::Display>(&mut self, key: &str, value: T) {
+        let value = value.to_string();
+        match self
+            .debug_overlay
+            .watches
+            .iter_mut()
+            .find(|(k, _)| k == key)
+        {
+            Some(watch) => watch.1 = value,
+            None => self.debug_overlay.watches.push((key.to_string(), value)),
+        }
+    }
+
+    /// Removes all values added with `debug_watch`.
+    pub fn clear_debug_watches(&mut self) {
+        self.debug_overlay.watches.clear();
+    }
+
+    /// Returns the number of draw calls made so far this frame.
+    ///
+    /// Every line, triangle, rectangle, circle, string, model, and sprite counts as one call.
+    /// Outlined shapes count the lines they're made of.
+    pub fn draw_calls(&self) -> u32 {
+        self.draw_calls
+    }
+
+    fn record_frame_time(&mut self, elapsed_time: f32) {
+        let frame_times = &mut self.debug_overlay.frame_times;
+        frame_times.push_back(elapsed_time);
+        if frame_times.len() > FRAME_TIME_HISTORY {
+            frame_times.pop_front();
+        }
+    }
+
+    fn draw_debug_overlay(&mut self) {
+        let draw_calls = self.draw_calls;
+        let frame_times = &self.debug_overlay.frame_times;
+
+        let count = frame_times.len().max(1) as f32;
+        let average = frame_times.iter().sum::<f32>() / count;
+        let worst = frame_times.iter().fold(0.0f32, |a, &b| a.max(b));
+        let fps = if average > 0.0 { 1.0 / average } else { 0.0 };
+
+        let mut lines = vec![
+            format!("FPS: {:.1}", fps),
+            format!(
+                "Frame: {:.2} ms (max {:.2} ms)",
+                average * 1000.0,
+                worst * 1000.0
+            ),
+            format!("Draw calls: {}", draw_calls),
+        ];
+        for (key, value) in &self.debug_overlay.watches {
+            lines.push(format!("{}: {}", key, value));
+        }
+
+        let text_width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let width = (text_width.max(FRAME_TIME_HISTORY) as i32 + 2).min(self.screen_width());
+        let height = lines.len() as i32 + DEBUG_GRAPH_HEIGHT + 3;
+
+        for y in 0..height {
+            for x in 0..width {
+                self.draw_with(x, y, EMPTY, BG_BLACK);
+            }
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_overlay_string(1, i as i32 + 1, line, FG_WHITE | BG_BLACK);
+        }
+
+        let graph_bottom = lines.len() as i32 + DEBUG_GRAPH_HEIGHT + 1;
+        let scale = worst.max(1.0 / 30.0);
+        let samples: Vec<f32> = self.debug_overlay.frame_times.iter().copied().collect();
+
+        for (i, &t) in samples.iter().enumerate() {
+            let x = 1 + (FRAME_TIME_HISTORY - samples.len() + i) as i32;
+            let col = if t <= 1.0 / 55.0 {
+                FG_GREEN
+            } else if t <= 1.0 / 30.0 {
+                FG_YELLOW
+            } else {
+                FG_RED
+            };
+
+            let level = t / scale * DEBUG_GRAPH_HEIGHT as f32;
+            for row in 0..DEBUG_GRAPH_HEIGHT {
+                let fill = level - row as f32;
+                let glyph = if fill >= 1.0 {
+                    SOLID
+                } else if fill >= 0.75 {
+                    THREE_QUARTERS
+                } else if fill >= 0.5 {
+                    HALF
+                } else if fill >= 0.25 {
+                    QUARTER
+                } else {
+                    continue;
+                };
+                self.draw_with(x, graph_bottom - row, glyph, col | BG_BLACK);
+            }
+        }
+    }
+
+    fn draw_overlay_string(&mut self, x: i32, y: i32, text: &str, col: u16) {
+        for (i, ch, flags) in layout_text(text) {
+            self.draw_with(x + i, y, ch, col | flags);
+        }
+    }
+}
+
+// endregion
+
+// region: Transitions
+
+/// A full-screen effect used to hide a scene change, started with `start_transition`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// Fades the screen to the transition color through the shade glyphs.
+    Fade,
+    /// Sweeps the transition color across the screen from left to right.
+    Wipe,
+    /// Covers the screen with the transition color in random-looking cells.
+    Dissolve,
+    /// Makes the screen increasingly blocky: each block takes the cell in its top-left
+    /// corner, and blocks grow from 1 cell to 16 × 16 cells at the midpoint. The transition
+    /// color isn't used, so the screen is never fully covered and the scene change shows as
+    /// a jump between two blocky pictures.
+    Pixelate,
+}
+
+#[derive(Clone, Copy)]
+struct ActiveTransition {
+    kind: Transition,
+    duration: f32,
+    time: f32,
+    col: u16,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Starts a transition that covers the screen with black and uncovers it again over
+    /// `duration` seconds.
+    ///
+    /// The transition is drawn on top of every frame until it finishes. The screen is fully
+    /// covered halfway through, which is when `transition_midpoint` returns `true` and the
+    /// game should switch scenes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// if engine.key_pressed(key::ENTER) {
+    ///     engine.start_transition(Transition::Fade, 0.5);
+    /// }
+    /// if engine.transition_midpoint() {
+    ///     self.scene = Scene::Level;
+    /// }
+    /// ```
+    pub fn start_transition(&mut self, transition: Transition, duration: f32) {
+        self.start_transition_with(transition, duration, FG_BLACK);
+    }
+
+    /// Starts a transition like `start_transition`, covering the screen with `col` instead of
+    /// black. `Transition::Pixelate` ignores `col`.
+    pub fn start_transition_with(&mut self, transition: Transition, duration: f32, col: u16) {
+        self.transition = Some(ActiveTransition {
+            kind: transition,
+            duration: duration.max(f32::EPSILON),
+            time: 0.0,
+            col,
+        });
+        self.transition_midpoint = false;
+    }
+
+    /// Returns `true` while a transition is playing.
+    pub fn transition_active(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Returns `true` during the one frame in which the current transition covers the screen.
+    pub fn transition_midpoint(&self) -> bool {
+        self.transition_midpoint
+    }
+
+    fn step_transition(&mut self, elapsed_time: f32) {
+        self.transition_midpoint = false;
+
+        if let Some(t) = &mut self.transition {
+            let half = t.duration / 2.0;
+            let before = t.time;
+            t.time += elapsed_time;
+            self.transition_midpoint = before < half && t.time >= half;
+            if before >= t.duration {
+                self.transition = None;
+            }
+        }
+    }
+
+    fn draw_transition(&mut self) {
+        let Some(t) = self.transition else {
+            return;
+        };
+
+        let progress = (t.time / t.duration).min(1.0);
+        let cover = 1.0 - (2.0 * progress - 1.0).abs();
+        let col = t.col & 0x0F;
+        let (w, h) = (self.screen_width(), self.screen_height());
+
+        match t.kind {
+            Transition::Fade => {
+                for y in 0..h {
+                    for x in 0..w {
+                        let level = (cover * 4.0 + dither_threshold(x, y) - 0.5).round() as i32;
+                        let glyph = match level {
+                            i32::MIN..=0 => continue,
+                            1 => QUARTER,
+                            2 => HALF,
+                            3 => THREE_QUARTERS,
+                            _ => SOLID,
+                        };
+                        let idx = (y * w + x) as usize;
+                        let bg = self.window_buffer[idx].Attributes & 0x00F0;
+                        self.window_buffer[idx].Char.UnicodeChar = glyph;
+                        self.window_buffer[idx].Attributes = col | bg;
+                    }
+                }
+            }
+            Transition::Wipe => {
+                let edge = (cover * w as f32).ceil() as i32;
+                self.fill_rect_with(0, 0, edge, h, SOLID, col);
+            }
+            Transition::Dissolve => {
+                for y in 0..h {
+                    for x in 0..w {
+                        let hash = (x as u32).wrapping_mul(374_761_393)
+                            ^ (y as u32).wrapping_mul(668_265_263);
+                        let hash = (hash ^ (hash >> 13)).wrapping_mul(1_274_126_177);
+                        if ((hash >> 16) as f32 / 65536.0) < cover {
+                            self.draw_with(x, y, SOLID, col);
+                        }
+                    }
+                }
+            }
+            Transition::Pixelate => {
+                let size = 1 + (cover * 15.0) as i32;
+                if size > 1 {
+                    for y in 0..h {
+                        for x in 0..w {
+                            let src = ((y - y % size) * w + (x - x % size)) as usize;
+                            self.window_buffer[(y * w + x) as usize] = self.window_buffer[src];
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Atmosphere
+
+/// Rain, snow or storms drawn by an [`Atmosphere`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Weather {
+    #[default]
+    Clear,
+    Rain,
+    Snow,
+    /// Rain with lightning flashes every few seconds.
+    Storm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct WeatherParticle {
+    x: f32,
+    y: f32,
+    speed: f32,
+    phase: f32,
+}
+
+/// Seconds a lightning flash lasts.
+const LIGHTNING_TIME: f32 = 0.25;
+/// Average seconds between lightning strikes in a storm.
+const LIGHTNING_INTERVAL: f32 = 8.0;
+
+/// A world clock with day/night darkening and weather, drawn over every frame once set
+/// with `set_atmosphere`.
+///
+/// The atmosphere is composited after `update` and the render queue but before
+/// transitions, so transitions and the debug overlay aren't darkened.
+/// Night darkens flat cells by swapping them for lighter shade glyphs over the night
+/// color, so nothing the game drew is lost, just dimmed.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// engine.set_atmosphere(Atmosphere {
+///     hour: 18.0,
+///     hours_per_second: 0.05,
+///     weather: Weather::Storm,
+///     wind: -6.0,
+///     ..Default::default()
+/// });
+///
+/// // In update:
+/// if engine.atmosphere().is_some_and(|a| a.lightning_struck()) {
+///     engine.audio.play_sample("thunder.wav");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atmosphere {
+    /// Time of day in hours, from `0.0` up to `24.0`. Noon is `12.0`.
+    pub hour: f32,
+    /// In-game hours that pass every real second, or `0.0` to stop the clock.
+    pub hours_per_second: f32,
+    /// How dark midnight gets, from `0.0` for not at all to `1.0` for the lightest shade.
+    /// Defaults to `0.75`.
+    pub night_darkness: f32,
+    /// Background color showing through darkened cells. Defaults to `FG_BLACK`.
+    pub night_color: u16,
+    pub weather: Weather,
+    /// How heavy rain or snow is, from `0.0` to `1.0`. Defaults to `0.5`.
+    pub intensity: f32,
+    /// Sideways drift of rain and snow in cells per second.
+    pub wind: f32,
+    particles: Vec<WeatherParticle>,
+    flash: f32,
+    struck: bool,
+    rng: procgen::Rng,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            hour: 12.0,
+            hours_per_second: 0.0,
+            night_darkness: 0.75,
+            night_color: FG_BLACK,
+            weather: Weather::Clear,
+            intensity: 0.5,
+            wind: 0.0,
+            particles: Vec::new(),
+            flash: 0.0,
+            struck: false,
+            rng: procgen::Rng::new(0x5EA7_4E55),
+        }
+    }
+}
+
+impl Atmosphere {
+    /// Creates a clear atmosphere at `hour` with the clock stopped.
+    pub fn new(hour: f32) -> Self {
+        Self {
+            hour: hour.rem_euclid(24.0),
+            ..Default::default()
+        }
+    }
+
+    /// Returns how much light there is, from `1.0` at noon to `0.0` at midnight.
+    pub fn daylight(&self) -> f32 {
+        ((self.hour - 12.0) / 12.0 * PI).cos() * 0.5 + 0.5
+    }
+
+    /// Returns how dark the screen is made, from `0.0` in the day up to `night_darkness`
+    /// in the middle of the night. Dusk and dawn fade between the two.
+    pub fn darkness(&self) -> f32 {
+        let night = ((0.6 - self.daylight()) / 0.5).clamp(0.0, 1.0);
+        night * self.night_darkness.clamp(0.0, 1.0)
+    }
+
+    /// Returns `true` between dusk and dawn.
+    pub fn is_night(&self) -> bool {
+        self.daylight() < 0.35
+    }
+
+    /// Flashes lightning now, whatever the weather.
+    pub fn strike_lightning(&mut self) {
+        self.flash = LIGHTNING_TIME;
+        self.struck = true;
+    }
+
+    /// Returns `true` on the frame lightning struck, e.g. to play thunder.
+    pub fn lightning_struck(&self) -> bool {
+        self.struck
+    }
+
+    /// Advances the clock, weather and lightning by `elapsed` seconds on a `w` × `h`
+    /// screen.
+    fn step(&mut self, elapsed: f32, w: i32, h: i32) {
+        self.hour = (self.hour + self.hours_per_second * elapsed).rem_euclid(24.0);
+
+        self.struck = false;
+        self.flash = (self.flash - elapsed).max(0.0);
+        if self.weather == Weather::Storm && self.rng.chance(elapsed / LIGHTNING_INTERVAL) {
+            self.strike_lightning();
+        }
+
+        let (w, h) = (w as f32, h as f32);
+        let density = match self.weather {
+            Weather::Clear => 0.0,
+            Weather::Rain | Weather::Storm => 0.03,
+            Weather::Snow => 0.015,
+        };
+        let count = (w * h * density * self.intensity.clamp(0.0, 1.0)) as usize;
+        self.particles.truncate(count);
+        while self.particles.len() < count {
+            let snow = self.weather == Weather::Snow;
+            self.particles.push(WeatherParticle {
+                x: self.rng.next_f32() * w,
+                y: self.rng.next_f32() * h,
+                speed: if snow { 3.0 } else { 30.0 } * (0.6 + self.rng.next_f32() * 0.8),
+                phase: self.rng.next_f32() * 2.0 * PI,
+            });
+        }
+
+        let snow = self.weather == Weather::Snow;
+        for p in &mut self.particles {
+            p.phase += elapsed * 2.0;
+            // Snowflakes sway as they fall.
+            let sway = if snow { p.phase.sin() * 2.0 } else { 0.0 };
+            p.x = (p.x + (self.wind + sway) * elapsed).rem_euclid(w.max(1.0));
+            p.y += p.speed * elapsed;
+            if p.y >= h {
+                p.y -= h;
+                p.x = self.rng.next_f32() * w;
+            }
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sets the day/night cycle and weather drawn over every frame.
+    pub fn set_atmosphere(&mut self, atmosphere: Atmosphere) {
+        self.atmosphere = Some(atmosphere);
+    }
+
+    /// Stops drawing the day/night cycle and weather.
+    pub fn clear_atmosphere(&mut self) {
+        self.atmosphere = None;
+    }
+
+    /// Returns the current atmosphere, if one is set.
+    pub fn atmosphere(&self) -> Option<&Atmosphere> {
+        self.atmosphere.as_ref()
+    }
+
+    /// Returns the current atmosphere mutably, e.g. to change the weather.
+    pub fn atmosphere_mut(&mut self) -> Option<&mut Atmosphere> {
+        self.atmosphere.as_mut()
+    }
+
+    fn step_atmosphere(&mut self, elapsed_time: f32) {
+        let (w, h) = (self.screen_width(), self.screen_height());
+        if let Some(atmosphere) = &mut self.atmosphere {
+            atmosphere.step(elapsed_time, w, h);
+        }
+    }
+
+    fn draw_atmosphere(&mut self) {
+        let Some(atmosphere) = &self.atmosphere else {
+            return;
+        };
+        let (w, h) = (self.screen_width(), self.screen_height());
+        let lit = atmosphere.flash > 0.0;
+
+        let darkness = atmosphere.darkness();
+        if darkness > 0.0 && !lit {
+            let night_bg = (atmosphere.night_color & 0x0F) << 4;
+            for y in 0..h {
+                for x in 0..w {
+                    let level = (darkness * 3.0 + dither_threshold(x, y) - 0.5).round() as i32;
+                    if level <= 0 {
+                        continue;
+                    }
+                    let idx = (y * w + x) as usize;
+                    let cell = &mut self.window_buffer[idx];
+                    let glyph = unsafe { cell.Char.UnicodeChar };
+                    let attr = cell.Attributes;
+                    // How much of the cell is covered by its foreground color, in quarters.
+                    let (coverage, fg) = match glyph {
+                        SOLID => (4, attr & 0x0F),
+                        EMPTY => (4, (attr >> 4) & 0x0F),
+                        THREE_QUARTERS => (3, attr & 0x0F),
+                        HALF => (2, attr & 0x0F),
+                        QUARTER => (1, attr & 0x0F),
+                        _ => {
+                            cell.Attributes = dim_color(attr);
+                            continue;
+                        }
+                    };
+                    cell.Char.UnicodeChar = match coverage - level {
+                        i32::MIN..=0 => EMPTY,
+                        1 => QUARTER,
+                        2 => HALF,
+                        3 => THREE_QUARTERS,
+                        _ => SOLID,
+                    };
+                    cell.Attributes = (attr & 0xFF00) | night_bg | fg;
+                }
+            }
+        }
+
+        let (glyph, col) = match atmosphere.weather {
+            Weather::Clear => (EMPTY, 0),
+            Weather::Snow => ('*' as u16, FG_WHITE),
+            Weather::Rain | Weather::Storm if atmosphere.wind > 4.0 => ('\\' as u16, FG_CYAN),
+            Weather::Rain | Weather::Storm if atmosphere.wind < -4.0 => ('/' as u16, FG_CYAN),
+            Weather::Rain | Weather::Storm => ('|' as u16, FG_CYAN),
+        };
+        for p in &atmosphere.particles {
+            let (x, y) = (p.x as i32, p.y as i32);
+            if x < 0 || y < 0 || x >= w || y >= h {
+                continue;
+            }
+            // Slow flakes look further away.
+            let glyph = if glyph == '*' as u16 && p.speed < 3.0 {
+                '.' as u16
+            } else {
+                glyph
+            };
+            let cell = &mut self.window_buffer[(y * w + x) as usize];
+            cell.Char.UnicodeChar = glyph;
+            cell.Attributes = (cell.Attributes & 0xFFF0) | col;
+        }
+
+        if lit {
+            for cell in &mut self.window_buffer {
+                cell.Attributes |= 0x0008;
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Post Processing
+
+/// Post-processing passes applied to the final frame, set with `set_post_fx`.
+///
+/// The passes run after `update` and transitions, but before the debug overlay and
+/// log console so those stay readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PostFx {
+    /// Darkens every other row, like the scanlines of a CRT.
+    pub scanlines: bool,
+    /// Lets flat colors bleed into the cell to their right using a shade glyph.
+    /// Only cells drawn with `SOLID` or `EMPTY` are affected, so text stays intact.
+    pub color_bleed: bool,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sets which post-processing passes are applied to every frame.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.set_post_fx(PostFx {
+    ///     scanlines: true,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn set_post_fx(&mut self, fx: PostFx) {
+        self.post_fx = fx;
+    }
+
+    /// Returns the post-processing passes currently applied to every frame.
+    pub fn post_fx(&self) -> PostFx {
+        self.post_fx
+    }
+
+    fn apply_post_fx(&mut self) {
+        let (w, h) = (self.screen_width(), self.screen_height());
+
+        if self.post_fx.color_bleed {
+            let flat_color = |cell: &CHAR_INFO| {
+                let glyph = unsafe { cell.Char.UnicodeChar };
+                match glyph {
+                    SOLID => Some(cell.Attributes & 0x0F),
+                    EMPTY => Some((cell.Attributes >> 4) & 0x0F),
+                    _ => None,
+                }
+            };
+
+            for y in 0..h {
+                for x in (1..w).rev() {
+                    let idx = (y * w + x) as usize;
+                    let left = flat_color(&self.window_buffer[idx - 1]);
+                    let this = flat_color(&self.window_buffer[idx]);
+                    if let (Some(left), Some(this)) = (left, this) {
+                        if left != this {
+                            self.window_buffer[idx].Char.UnicodeChar = QUARTER;
+                            self.window_buffer[idx].Attributes = left | (this << 4);
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.post_fx.scanlines {
+            for y in (1..h).step_by(2) {
+                for x in 0..w {
+                    let idx = (y * w + x) as usize;
+                    let col = self.window_buffer[idx].Attributes;
+                    self.window_buffer[idx].Attributes = dim_color(col);
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Text Prompt
+
+/// The state of a text prompt started with `prompt_text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptStatus {
+    /// The player is still typing.
+    Editing,
+    /// The player pressed Enter. Contains the entered text.
+    Done(String),
+    /// The player pressed Escape.
+    Cancelled,
+}
+
+#[derive(Clone)]
+struct TextPrompt {
+    label: String,
+    text: Vec<char>,
+    cursor: usize,
+    max_len: usize,
+    started: Instant,
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Shows a modal text box labelled `label` and lets the player type up to `max_len`
+    /// characters into it.
+    ///
+    /// Call this every frame, after drawing the rest of the scene, until it returns
+    /// something other than `PromptStatus::Editing`. The arrow keys, Home, End,
+    /// Backspace and Delete edit the text, Enter accepts it and Escape cancels.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// if self.entering_name {
+    ///     match engine.prompt_text("Enter name:", 12) {
+    ///         PromptStatus::Editing => {}
+    ///         PromptStatus::Done(name) => self.add_high_score(name),
+    ///         PromptStatus::Cancelled => self.entering_name = false,
+    ///     }
+    /// }
+    /// ```
+    pub fn prompt_text(&mut self, label: &str, max_len: usize) -> PromptStatus {
+        let mut prompt = match self.text_prompt.take() {
+            Some(p) if p.label == label => p,
+            _ => TextPrompt {
+                label: label.to_string(),
+                text: Vec::new(),
+                cursor: 0,
+                max_len,
+                started: Instant::now(),
+            },
+        };
+
+        for &(vk, ch) in &self.typed_keys {
+            match vk {
+                key::ENTER => return PromptStatus::Done(prompt.text.iter().collect()),
+                key::ESCAPE => return PromptStatus::Cancelled,
+                key::BACKSPACE if prompt.cursor > 0 => {
+                    prompt.cursor -= 1;
+                    prompt.text.remove(prompt.cursor);
+                }
+                key::DELETE if prompt.cursor < prompt.text.len() => {
+                    prompt.text.remove(prompt.cursor);
+                }
+                key::ARROW_LEFT => prompt.cursor = prompt.cursor.saturating_sub(1),
+                key::ARROW_RIGHT => prompt.cursor = (prompt.cursor + 1).min(prompt.text.len()),
+                key::HOME => prompt.cursor = 0,
+                key::END => prompt.cursor = prompt.text.len(),
+                _ => {
+                    if let Some(ch) = ch {
+                        if prompt.text.len() < prompt.max_len {
+                            prompt.text.insert(prompt.cursor, ch);
+                            prompt.cursor += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.draw_text_prompt(&prompt);
+        self.text_prompt = Some(prompt);
+        PromptStatus::Editing
+    }
+
+    /// Returns `true` if a text prompt is currently open.
+    pub fn prompt_active(&self) -> bool {
+        self.text_prompt.is_some()
+    }
+
+    /// Closes the current text prompt, discarding what was typed.
+    pub fn cancel_prompt(&mut self) {
+        self.text_prompt = None;
+    }
+
+    fn draw_text_prompt(&mut self, prompt: &TextPrompt) {
+        // The prompt gives every character one cell, which has no room for the two halves
+        // of a character outside the Basic Multilingual Plane.
+        fn prompt_glyph(ch: char) -> u16 {
+            u16::try_from(ch as u32).unwrap_or(b'?' as u16)
+        }
+
+        let label_len = text_width(&prompt.label) as i32;
+        let w = (label_len + 6)
+            .max(prompt.max_len as i32 + 5)
+            .min(self.screen_width());
+        let h = 3;
+        let x = (self.screen_width() - w) / 2;
+        let y = (self.screen_height() - h) / 2;
+
+        self.draw_panel(
+            x,
+            y,
+            w,
+            h,
+            &PanelStyle {
+                border: BorderStyle::Double,
+                fill: Some(BG_BLACK),
+                title: Some(&prompt.label),
+                ..Default::default()
+            },
+        );
+
+        let visible = (w - 4).max(1) as usize;
+        let scroll = (prompt.cursor + 1).saturating_sub(visible);
+        for (i, &ch) in prompt.text.iter().skip(scroll).take(visible).enumerate() {
+            self.draw_with(x + 2 + i as i32, y + 1, prompt_glyph(ch), FG_WHITE);
+        }
+
+        if prompt.started.elapsed().as_millis() % 1000 < 500 {
+            let cx = x + 2 + (prompt.cursor - scroll) as i32;
+            let ch = prompt
+                .text
+                .get(prompt.cursor)
+                .map_or(EMPTY, |&c| prompt_glyph(c));
+            self.draw_with(cx, y + 1, ch, FG_BLACK | BG_WHITE);
+        }
+    }
+}
+
+// endregion
+
+// region: Menu
+
+/// An item of a menu, which may itself contain more items forming a submenu.
+///
+/// Menus are built with chained calls and driven by a [`MenuManager`].
+///
+/// # Examples
+///
+/// ```rust
+/// use rusty_console_game_engine::*;
+///
+/// let menu = Menu::new("Main")
+///     .item(Menu::new("Continue").id(1).enabled(false))
+///     .item(Menu::new("New Game").id(2))
+///     .item(
+///         Menu::new("Options")
+///             .item(Menu::new("Sound").id(10))
+///             .item(Menu::new("Controls").id(11)),
+///     )
+///     .item(Menu::new("Quit").id(3));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Menu {
+    name: String,
+    id: Option<u32>,
+    enabled: bool,
+    columns: usize,
+    rows: usize,
+    items: Vec<Menu>,
+    cursor: usize,
+    top: usize,
+}
+
+impl Menu {
+    /// Creates an enabled menu item called `name` with no command id and no children.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            id: None,
+            enabled: true,
+            columns: 1,
+            rows: 0,
+            items: Vec::new(),
+            cursor: 0,
+            top: 0,
+        }
+    }
+
+    /// Sets the command id returned when this item is confirmed.
+    pub fn id(mut self, id: u32) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets whether this item can be selected.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Lays the children of this item out in a grid of `columns` columns, showing at most
+    /// `rows` rows at once and scrolling the rest. `rows == 0` shows every row.
+    pub fn table(mut self, columns: usize, rows: usize) -> Self {
+        self.columns = columns.max(1);
+        self.rows = rows;
+        self
+    }
+
+    /// Adds a child item.
+    pub fn item(mut self, item: Menu) -> Self {
+        self.items.push(item);
+        self
+    }
+
+    /// Returns the name of this item.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the children of this item.
+    pub fn items(&self) -> &[Menu] {
+        &self.items
+    }
+
+    /// Returns `true` if this item can be selected.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables or disables this item.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Finds the item called `name` among this item and all its descendants.
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Menu> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.items.iter_mut().find_map(|item| item.find_mut(name))
+    }
+
+    fn total_rows(&self) -> usize {
+        self.items.len().div_ceil(self.columns)
+    }
+
+    fn visible_rows(&self) -> usize {
+        if self.rows == 0 {
+            self.total_rows()
+        } else {
+            self.rows.min(self.total_rows())
+        }
+    }
+
+    fn cell_width(&self) -> usize {
+        self.items
+            .iter()
+            .map(|i| text_width(&i.name))
+            .max()
+            .unwrap_or(0)
+            + 3
+    }
+
+    /// Keeps the cursor of this item and all its descendants on an existing child.
+    fn clamp_cursors(&mut self) {
+        self.cursor = self.cursor.min(self.items.len().saturating_sub(1));
+        let row = self.cursor / self.columns;
+        let visible = self.visible_rows().max(1);
+        self.top = self
+            .top
+            .min(self.total_rows().saturating_sub(visible))
+            .min(row);
+        if row >= self.top + visible {
+            self.top = row + 1 - visible;
+        }
+        for item in &mut self.items {
+            item.clamp_cursors();
+        }
+    }
+
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        if self.items.is_empty() {
+            return;
+        }
+
+        let cols = self.columns as i32;
+        let (col, row) = (
+            (self.cursor % self.columns) as i32,
+            (self.cursor / self.columns) as i32,
+        );
+        let (col, row) = ((col + dx).clamp(0, cols - 1), (row + dy).max(0));
+        let next = (row * cols + col) as usize;
+        if next < self.items.len() {
+            self.cursor = next;
+        }
+
+        let row = self.cursor / self.columns;
+        let visible = self.visible_rows().max(1);
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + visible {
+            self.top = row + 1 - visible;
+        }
+    }
+}
+
+/// Keeps track of which menus are open and turns navigation into command ids.
+///
+/// Navigation can be driven by `update`, which reads the keyboard, or by calling
+/// `up`, `down`, `left`, `right`, `confirm` and `back` from any other input source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuManager {
+    root: Menu,
+    path: Vec<usize>,
+    open: bool,
+}
+
+impl MenuManager {
+    /// Creates a closed manager for the menu `root`.
+    pub fn new(root: Menu) -> Self {
+        Self {
+            root,
+            path: Vec::new(),
+            open: false,
+        }
+    }
+
+    /// Opens the root menu.
+    pub fn open(&mut self) {
+        self.open = true;
+        self.path.clear();
+    }
+
+    /// Closes all menus.
+    pub fn close(&mut self) {
+        self.open = false;
+        self.path.clear();
+    }
+
+    /// Returns `true` if the menu is open.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Calls `f` with the root menu, for example to enable or disable items, then moves any
+    /// cursor left past the end of its menu back onto the last item and closes submenus
+    /// that no longer exist.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// manager.edit_menu(|menu| {
+    ///     if let Some(item) = menu.find_mut("Continue") {
+    ///         item.set_enabled(has_save);
+    ///     }
+    /// });
+    /// ```
+    pub fn edit_menu<R>(&mut self, f: impl FnOnce(&mut Menu) -> R) -> R {
+        let result = f(&mut self.root);
+        self.root.clamp_cursors();
+
+        let mut menu = &self.root;
+        let mut depth = 0;
+        while let Some(&i) = self.path.get(depth) {
+            match menu.items.get(i).filter(|item| !item.items.is_empty()) {
+                Some(item) => menu = item,
+                None => break,
+            }
+            depth += 1;
+        }
+        self.path.truncate(depth);
+
+        result
+    }
+
+    fn current_mut(&mut self) -> &mut Menu {
+        let mut menu = &mut self.root;
+        for &i in &self.path {
+            menu = &mut menu.items[i];
+        }
+        menu
+    }
+
+    /// Moves the cursor of the innermost open menu up.
+    pub fn up(&mut self) {
+        self.current_mut().move_cursor(0, -1);
+    }
+
+    /// Moves the cursor of the innermost open menu down.
+    pub fn down(&mut self) {
+        self.current_mut().move_cursor(0, 1);
+    }
+
+    /// Moves the cursor of the innermost open menu left.
+    pub fn left(&mut self) {
+        self.current_mut().move_cursor(-1, 0);
+    }
+
+    /// Moves the cursor of the innermost open menu right.
+    pub fn right(&mut self) {
+        self.current_mut().move_cursor(1, 0);
+    }
+
+    /// Selects the item under the cursor.
+    ///
+    /// Opens its submenu if it has children, otherwise returns its command id.
+    /// Disabled items do nothing.
+    pub fn confirm(&mut self) -> Option<u32> {
+        if !self.open {
+            return None;
+        }
+
+        let menu = self.current_mut();
+        let cursor = menu.cursor;
+        let item = menu.items.get(cursor)?;
+        if !item.enabled {
+            return None;
+        }
+        if item.items.is_empty() {
+            return item.id;
+        }
+
+        self.path.push(cursor);
+        None
+    }
+
+    /// Closes the innermost submenu, or the whole menu if only the root is open.
+    pub fn back(&mut self) {
+        if self.path.pop().is_none() {
+            self.open = false;
+        }
+    }
+
+    /// Handles the arrow keys, Enter or Space to confirm and Escape or Backspace to go back.
+    /// Returns the command id of the confirmed item, if any.
+    pub fn update<G: ConsoleGame>(&mut self, engine: &ConsoleGameEngine<G>) -> Option<u32> {
+        if !self.open {
+            return None;
+        }
+
+        if engine.key_pressed(key::ARROW_UP) {
+            self.up();
+        }
+        if engine.key_pressed(key::ARROW_DOWN) {
+            self.down();
+        }
+        if engine.key_pressed(key::ARROW_LEFT) {
+            self.left();
+        }
+        if engine.key_pressed(key::ARROW_RIGHT) {
+            self.right();
+        }
+        if engine.key_pressed(key::ESCAPE) || engine.key_pressed(key::BACKSPACE) {
+            self.back();
+        }
+        if engine.key_pressed(key::ENTER) || engine.key_pressed(key::SPACE) {
+            return self.confirm();
+        }
+
+        None
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the open menus of `manager` with their top-left corner at `(x, y)`.
+    ///
+    /// Each open submenu is drawn next to the item that opened it.
+    pub fn draw_menu(&mut self, x: i32, y: i32, manager: &MenuManager) {
+        if !manager.open {
+            return;
+        }
+
+        let (mut x, mut y) = (x, y);
+        let mut menu = &manager.root;
+        let mut depth = 0;
+        loop {
+            let (cx, cy) = self.draw_menu_level(x, y, menu);
+            match manager.path.get(depth) {
+                Some(&i) => {
+                    menu = &menu.items[i];
+                    (x, y) = (cx, cy);
+                    depth += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Draws a single menu level and returns where a submenu of the selected item goes.
+    fn draw_menu_level(&mut self, x: i32, y: i32, menu: &Menu) -> (i32, i32) {
+        let cell_w = menu.cell_width() as i32;
+        let cols = menu.columns as i32;
+        let rows = menu.visible_rows() as i32;
+
+        self.draw_panel(
+            x,
+            y,
+            cell_w * cols + 3,
+            rows + 2,
+            &PanelStyle {
+                fill: Some(BG_BLACK),
+                ..Default::default()
+            },
+        );
+
+        let mut submenu_at = (x + 2, y + 1);
+        for (i, item) in menu.items.iter().enumerate() {
+            let (col, row) = ((i % menu.columns) as i32, (i / menu.columns) as i32);
+            let row = row - menu.top as i32;
+            if row < 0 || row >= rows {
+                continue;
+            }
+
+            let (ix, iy) = (x + 2 + col * cell_w, y + 1 + row);
+            let col = if item.enabled { FG_WHITE } else { FG_DARK_GREY };
+            if i == menu.cursor {
+                self.draw_with(ix, iy, glyph::TRIANGLE_RIGHT, FG_YELLOW);
+                submenu_at = (ix + cell_w, iy);
+            }
+            self.draw_overlay_string(ix + 1, iy, &item.name, col);
+            if !item.items.is_empty() {
+                self.draw_with(ix + cell_w - 1, iy, glyph::ARROW_RIGHT, col);
+            }
+        }
+
+        submenu_at
+    }
+}
+
+// endregion
+
+// region: Dialogue
+
+#[derive(Debug, Clone, PartialEq)]
+struct DialogueLine {
+    speaker: Option<String>,
+    text: String,
+    portrait: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DialogueNode {
+    lines: Vec<DialogueLine>,
+    choices: Vec<(String, String)>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DialogueState {
+    node: String,
+    line: usize,
+    revealed: f32,
+    waited: f32,
+    choice: usize,
+}
+
+/// Plays branching conversations as text boxes with a typewriter reveal.
+///
+/// Conversations are written in a small text format made of labelled nodes:
+///
+/// ```text
+/// [start]
+/// @portrait old_man
+/// Old Man: It's dangerous to go alone!
+/// Old Man: Take this.
+/// > Take the sword -> sword
+/// > Leave -> leave
+///
+/// [sword]
+/// @portrait none
+/// You got the sword.
+///
+/// [leave]
+/// Old Man: Suit yourself.
+/// -> start
+/// ```
+///
+/// - `[label]` starts a node.
+/// - `Speaker: text` or plain `text` is a line shown in its own text box.
+/// - `@portrait name` shows the portrait added with `add_portrait` for the following lines,
+///   `@portrait none` hides it.
+/// - `> text -> label` adds a choice shown after the last line of the node.
+/// - `-> label` continues with another node once the node is finished.
+///   Without choices or a jump, the dialogue ends after the last line.
+/// - Lines starting with `#` are comments.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut dialogue = Dialogue::from_file("dialogue/intro.txt")?;
+/// dialogue.add_portrait("old_man", Sprite::from_file("sprites/old_man.spr")?);
+/// dialogue.start("start");
+///
+/// // in update:
+/// dialogue.update(engine, elapsed_time);
+/// engine.draw_dialogue(&dialogue);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialogue {
+    nodes: HashMap<String, DialogueNode>,
+    portraits: HashMap<String, Sprite>,
+    state: Option<DialogueState>,
+    chars_per_second: f32,
+    auto_advance: Option<f32>,
+}
+
+impl Dialogue {
+    /// Parses a dialogue from the text format described on [`Dialogue`].
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut nodes: HashMap<String, DialogueNode> = HashMap::new();
+        let mut current: Option<String> = None;
+        let mut portrait: Option<String> = None;
+
+        for (n, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(label) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let label = label.trim().to_string();
+                if nodes.contains_key(&label) {
+                    return Err(format!("line {}: duplicate node '{}'", n + 1, label));
+                }
+                nodes.insert(label.clone(), DialogueNode::default());
+                current = Some(label);
+                portrait = None;
+                continue;
+            }
+
+            let Some(node) = current.as_ref().and_then(|c| nodes.get_mut(c)) else {
+                return Err(format!("line {}: text outside of a [node]", n + 1));
+            };
+
+            if let Some(name) = line.strip_prefix("@portrait") {
+                let name = name.trim();
+                portrait = (!name.is_empty() && name != "none").then(|| name.to_string());
+            } else if let Some(choice) = line.strip_prefix('>') {
+                let (text, target) = choice
+                    .rsplit_once("->")
+                    .ok_or_else(|| format!("line {}: choice without '-> label'", n + 1))?;
+                node.choices
+                    .push((text.trim().to_string(), target.trim().to_string()));
+            } else if let Some(target) = line.strip_prefix("->") {
+                node.next = Some(target.trim().to_string());
+            } else {
+                let (speaker, text) = match line.split_once(':') {
+                    Some((speaker, text)) if !speaker.trim().is_empty() => {
+                        (Some(speaker.trim().to_string()), text.trim().to_string())
+                    }
+                    _ => (None, line.to_string()),
+                };
+                node.lines.push(DialogueLine {
+                    speaker,
+                    text,
+                    portrait: portrait.clone(),
+                });
+            }
+        }
+
+        for (label, node) in &nodes {
+            let targets = node.choices.iter().map(|(_, t)| t).chain(&node.next);
+            for target in targets {
+                if !nodes.contains_key(target) {
+                    return Err(format!(
+                        "node '{}' refers to unknown node '{}'",
+                        label, target
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            nodes,
+            portraits: HashMap::new(),
+            state: None,
+            chars_per_second: 30.0,
+            auto_advance: None,
+        })
+    }
+
+    /// Loads a dialogue from a file in the format described on [`Dialogue`].
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&text)?)
+    }
+
+    /// Registers a sprite that lines can show with `@portrait name`.
+    pub fn add_portrait(&mut self, name: &str, sprite: Sprite) {
+        self.portraits.insert(name.to_string(), sprite);
+    }
+
+    /// Sets how many characters are revealed per second. Defaults to 30.
+    pub fn set_speed(&mut self, chars_per_second: f32) {
+        self.chars_per_second = chars_per_second.max(0.0);
+    }
+
+    /// Makes fully revealed lines advance by themselves after `seconds`,
+    /// or only on key presses if `None` (the default). Choices always wait for the player.
+    pub fn set_auto_advance(&mut self, seconds: Option<f32>) {
+        self.auto_advance = seconds;
+    }
+
+    /// Starts the dialogue at the node called `label`.
+    /// Returns `false` if there is no such node.
+    pub fn start(&mut self, label: &str) -> bool {
+        if !self.nodes.contains_key(label) {
+            return false;
+        }
+        self.state = Some(DialogueState {
+            node: label.to_string(),
+            line: 0,
+            revealed: 0.0,
+            waited: 0.0,
+            choice: 0,
+        });
+        self.skip_empty_nodes();
+        true
+    }
+
+    /// Stops the dialogue.
+    pub fn stop(&mut self) {
+        self.state = None;
+    }
+
+    /// Returns `true` while the dialogue is playing.
+    pub fn is_active(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Returns the label of the node currently playing.
+    pub fn current_node(&self) -> Option<&str> {
+        self.state.as_ref().map(|s| s.node.as_str())
+    }
+
+    fn node(&self) -> Option<(&DialogueState, &DialogueNode)> {
+        let state = self.state.as_ref()?;
+        Some((state, self.nodes.get(&state.node)?))
+    }
+
+    fn current_line(&self) -> Option<&DialogueLine> {
+        let (state, node) = self.node()?;
+        node.lines.get(state.line)
+    }
+
+    fn line_finished(&self) -> bool {
+        match (self.node(), self.current_line()) {
+            (Some((state, _)), Some(line)) => state.revealed >= line.text.chars().count() as f32,
+            _ => true,
+        }
+    }
+
+    fn showing_choices(&self) -> bool {
+        match self.node() {
+            Some((state, node)) => {
+                !node.choices.is_empty()
+                    && state.line + 1 >= node.lines.len()
+                    && self.line_finished()
+            }
+            None => false,
+        }
+    }
+
+    fn goto(&mut self, label: Option<String>) {
+        match label {
+            Some(label) => {
+                self.start(&label);
+            }
+            None => self.state = None,
+        }
+    }
+
+    /// Moves on from nodes that have no lines to show.
+    fn skip_empty_nodes(&mut self) {
+        for _ in 0..self.nodes.len() {
+            match self.node() {
+                Some((_, node)) if node.lines.is_empty() && node.choices.is_empty() => {
+                    let next = node.next.clone();
+                    if next.is_none() {
+                        self.state = None;
+                        return;
+                    }
+                    let label = next.unwrap();
+                    if let Some(state) = &mut self.state {
+                        state.node = label;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Reveals the rest of the current line, or moves on to the next line if it is fully shown.
+    pub fn advance(&mut self) {
+        if !self.line_finished() {
+            if let (Some(len), Some(state)) = (
+                self.current_line().map(|l| l.text.chars().count()),
+                &mut self.state,
+            ) {
+                state.revealed = len as f32;
+            }
+            return;
+        }
+        if self.showing_choices() {
+            return;
+        }
+
+        let Some((state, node)) = self.node() else {
+            return;
+        };
+        if state.line + 1 < node.lines.len() {
+            let state = self.state.as_mut().unwrap();
+            state.line += 1;
+            state.revealed = 0.0;
+            state.waited = 0.0;
+        } else {
+            let next = node.next.clone();
+            self.goto(next);
+        }
+    }
+
+    /// Moves the choice cursor by `delta` entries.
+    pub fn move_choice(&mut self, delta: i32) {
+        let Some(count) = self.node().map(|(_, n)| n.choices.len()) else {
+            return;
+        };
+        if let Some(state) = &mut self.state {
+            if count > 0 {
+                state.choice = (state.choice as i32 + delta).rem_euclid(count as i32) as usize;
+            }
+        }
+    }
+
+    /// Picks the choice under the cursor, jumping to its node.
+    /// Returns the label of that node, or `None` if no choices are shown.
+    pub fn choose(&mut self) -> Option<String> {
+        if !self.showing_choices() {
+            return None;
+        }
+        let (state, node) = self.node()?;
+        let target = node.choices.get(state.choice)?.1.clone();
+        self.goto(Some(target.clone()));
+        Some(target)
+    }
+
+    /// Advances the typewriter reveal and handles input: Enter or Space reveals or advances
+    /// the text and picks choices, and the up and down arrows move the choice cursor.
+    pub fn update<G: ConsoleGame>(&mut self, engine: &ConsoleGameEngine<G>, elapsed_time: f32) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        state.revealed += elapsed_time * self.chars_per_second;
+        if self.chars_per_second == 0.0 {
+            state.revealed = f32::MAX;
+        }
+
+        let confirm = engine.key_pressed(key::ENTER) || engine.key_pressed(key::SPACE);
+        if self.showing_choices() {
+            if engine.key_pressed(key::ARROW_UP) {
+                self.move_choice(-1);
+            }
+            if engine.key_pressed(key::ARROW_DOWN) {
+                self.move_choice(1);
+            }
+            if confirm {
+                self.choose();
+            }
+            return;
+        }
+
+        if self.line_finished() {
+            if let (Some(delay), Some(state)) = (self.auto_advance, &mut self.state) {
+                state.waited += elapsed_time;
+                if state.waited >= delay {
+                    self.advance();
+                    return;
+                }
+            }
+        }
+        if confirm {
+            self.advance();
+        }
+    }
+}
+
+/// Splits `text` into lines at most `width` cells wide, breaking between words.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let len = text_width(&line);
+        if len > 0 && len + 1 + text_width(word) > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the current text box of `dialogue` along the bottom of the screen.
+    pub fn draw_dialogue(&mut self, dialogue: &Dialogue) {
+        let Some((state, node)) = dialogue.node() else {
+            return;
+        };
+        let line = dialogue.current_line();
+
+        let portrait = line
+            .and_then(|l| l.portrait.as_ref())
+            .and_then(|p| dialogue.portraits.get(p));
+        let portrait_h = portrait.map_or(0, |p| p.height as i32);
+        let choices = if dialogue.showing_choices() {
+            node.choices.len() as i32
+        } else {
+            0
+        };
+
+        let w = self.screen_width();
+        let h = (portrait_h + 2).max(6 + choices).min(self.screen_height());
+        let y = self.screen_height() - h;
+
+        self.draw_panel(
+            0,
+            y,
+            w,
+            h,
+            &PanelStyle {
+                border: BorderStyle::Double,
+                fill: Some(BG_BLACK),
+                title: line.and_then(|l| l.speaker.as_deref()),
+                ..Default::default()
+            },
+        );
+
+        let mut text_x = 2;
+        if let Some(sprite) = portrait {
+            self.draw_sprite(1, y + 1, sprite);
+            text_x = sprite.width as i32 + 2;
+        }
+
+        let shown: String = line
+            .map(|l| l.text.chars().take(state.revealed as usize).collect())
+            .unwrap_or_default();
+        let wrap_width = (w - text_x - 2).max(1) as usize;
+        let last_row = y + h - 1 - choices;
+        for (row, text) in (y + 1..last_row).zip(wrap_text(&shown, wrap_width)) {
+            self.draw_overlay_string(text_x, row, &text, FG_WHITE);
+        }
+
+        if choices > 0 {
+            let first = y + h - 1 - choices;
+            for (i, (text, _)) in node.choices.iter().enumerate() {
+                let cy = first + i as i32;
+                let selected = i == state.choice;
+                if selected {
+                    self.draw_with(text_x, cy, glyph::TRIANGLE_RIGHT, FG_YELLOW);
+                }
+                let col = if selected { FG_YELLOW } else { FG_GREY };
+                self.draw_overlay_string(text_x + 2, cy, text, col);
+            }
+        } else if dialogue.line_finished() {
+            self.draw_with(w - 3, y + h - 2, glyph::TRIANGLE_DOWN, FG_WHITE);
+        }
+    }
+}
+
+// endregion
+
+// region: Score Widgets
+
+/// A score that counts up (or down) to its value over a short time instead of jumping,
+/// like the score display of an arcade game.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut score = RollingCounter::new(0);
+///
+/// // When an enemy dies:
+/// score.add(250);
+///
+/// // In update:
+/// score.update(elapsed_time);
+/// engine.draw_counter(1, 0, &score, 8, FG_WHITE);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollingCounter {
+    /// Seconds a change takes to finish rolling. Defaults to `0.5`.
+    pub roll_time: f32,
+    value: i64,
+    shown: f64,
+    speed: f64,
+}
+
+impl RollingCounter {
+    /// Creates a counter showing `value`.
+    pub fn new(value: i64) -> Self {
+        Self {
+            roll_time: 0.5,
+            value,
+            shown: value as f64,
+            speed: 0.0,
+        }
+    }
+
+    /// Returns the value the counter is rolling towards.
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+
+    /// Returns the value currently shown.
+    pub fn shown(&self) -> i64 {
+        self.shown.round() as i64
+    }
+
+    /// Starts rolling towards `value`.
+    pub fn set(&mut self, value: i64) {
+        self.value = value;
+        let gap = (value as f64 - self.shown).abs();
+        self.speed = gap / self.roll_time.max(f32::EPSILON) as f64;
+    }
+
+    /// Starts rolling towards the current value plus `amount`.
+    pub fn add(&mut self, amount: i64) {
+        self.set(self.value + amount);
+    }
+
+    /// Jumps straight to `value` without rolling.
+    pub fn snap(&mut self, value: i64) {
+        self.value = value;
+        self.shown = value as f64;
+    }
+
+    /// Returns `true` while the shown value hasn't caught up yet.
+    pub fn is_rolling(&self) -> bool {
+        self.shown() != self.value
+    }
+
+    /// Rolls the shown value towards the real one by `elapsed` seconds.
+    pub fn update(&mut self, elapsed: f32) {
+        let gap = self.value as f64 - self.shown;
+        let step = self.speed * elapsed as f64;
+        if gap.abs() <= step {
+            self.shown = self.value as f64;
+        } else {
+            self.shown += step.copysign(gap);
+        }
+    }
+}
+
+impl Default for RollingCounter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FloatingNumber {
+    x: f32,
+    y: f32,
+    text: String,
+    col: u16,
+    age: f32,
+}
+
+/// Numbers that pop up, rise and fade away, such as damage dealt or points scored.
+///
+/// Each number rises by `rise` cells over its `lifetime`, slowing down as it goes. For the
+/// last half of its life it dissolves through the dark, medium and light shade glyphs.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut popups = FloatingNumbers::new();
+///
+/// // When the player hits an enemy:
+/// popups.spawn(enemy.x, enemy.y - 1.0, damage, FG_RED);
+///
+/// // In update:
+/// popups.update(elapsed_time);
+/// engine.draw_floating_numbers(&popups);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatingNumbers {
+    /// Seconds each number stays on screen. Defaults to `1.0`.
+    pub lifetime: f32,
+    /// Cells each number rises over its lifetime. Defaults to `3.0`.
+    pub rise: f32,
+    numbers: Vec<FloatingNumber>,
+}
+
+impl Default for FloatingNumbers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FloatingNumbers {
+    pub fn new() -> Self {
+        Self {
+            lifetime: 1.0,
+            rise: 3.0,
+            numbers: Vec::new(),
+        }
+    }
+
+    /// Pops up `value` centered on `(x, y)`.
+    pub fn spawn(&mut self, x: f32, y: f32, value: i64, col: u16) {
+        self.spawn_text(x, y, &value.to_string(), col);
+    }
+
+    /// Pops up `text` centered on `(x, y)`, e.g. `"+100"` or `"CRIT!"`.
+    pub fn spawn_text(&mut self, x: f32, y: f32, text: &str, col: u16) {
+        self.numbers.push(FloatingNumber {
+            x: x - text_width(text) as f32 / 2.0,
+            y,
+            text: text.to_string(),
+            col,
+            age: 0.0,
+        });
+    }
+
+    /// Moves every number along by `elapsed` seconds and removes the ones that have faded.
+    pub fn update(&mut self, elapsed: f32) {
+        let lifetime = self.lifetime;
+        self.numbers.retain_mut(|n| {
+            n.age += elapsed;
+            n.age < lifetime
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.numbers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.numbers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.numbers.clear();
+    }
+}
+
+/// Counts hits landed in quick succession. Each hit restarts a timer, and the combo ends
+/// when the timer runs out.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut combo = ComboMeter::new(2.0);
+///
+/// // When the player lands a hit:
+/// combo.hit();
+/// score.add(10 * combo.count() as i64);
+///
+/// // In update:
+/// if let Some(total) = combo.update(elapsed_time) {
+///     popups.spawn_text(40.0, 10.0, &format!("{} HIT COMBO", total), FG_YELLOW);
+/// }
+/// engine.draw_combo_meter(1, 2, 12, &combo, FG_YELLOW);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComboMeter {
+    /// Seconds allowed between hits before the combo ends.
+    pub window: f32,
+    count: u32,
+    best: u32,
+    timer: f32,
+    pulse: f32,
+}
+
+/// Seconds the combo count stays highlighted after a hit.
+const COMBO_PULSE: f32 = 0.15;
+
+impl ComboMeter {
+    /// Creates a meter where hits must land within `window` seconds of each other.
+    pub fn new(window: f32) -> Self {
+        Self {
+            window,
+            count: 0,
+            best: 0,
+            timer: 0.0,
+            pulse: 0.0,
+        }
+    }
+
+    /// Adds a hit to the combo and returns the new count.
+    pub fn hit(&mut self) -> u32 {
+        self.count += 1;
+        self.best = self.best.max(self.count);
+        self.timer = self.window;
+        self.pulse = COMBO_PULSE;
+        self.count
+    }
+
+    /// Ends the combo straight away, e.g. when the player takes damage.
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.timer = 0.0;
+        self.pulse = 0.0;
+    }
+
+    /// Runs the timer down by `elapsed` seconds. Returns the final count of a combo that
+    /// ended this update.
+    pub fn update(&mut self, elapsed: f32) -> Option<u32> {
+        self.pulse = (self.pulse - elapsed).max(0.0);
+        if self.count == 0 {
+            return None;
+        }
+        self.timer -= elapsed;
+        if self.timer > 0.0 {
+            return None;
+        }
+        let total = self.count;
+        self.reset();
+        Some(total)
+    }
+
+    /// Returns the number of hits in the current combo, or `0` if there is none.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the longest combo so far.
+    pub fn best(&self) -> u32 {
+        self.best
+    }
+
+    /// Returns `true` while a combo is going.
+    pub fn is_active(&self) -> bool {
+        self.count > 0
+    }
+
+    /// Returns how much of the window is left before the combo ends, from `1.0` right
+    /// after a hit down to `0.0`.
+    pub fn remaining(&self) -> f32 {
+        if self.window > 0.0 {
+            (self.timer / self.window).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws the shown value of `counter` at `(x, y)`, padded with zeros to `digits` wide.
+    pub fn draw_counter(
+        &mut self,
+        x: i32,
+        y: i32,
+        counter: &RollingCounter,
+        digits: usize,
+        col: u16,
+    ) {
+        self.draw_string_with(x, y, &format!("{:0digits$}", counter.shown()), col);
+    }
+
+    /// Draws every number in `numbers`, dissolving the older ones through the shade glyphs.
+    pub fn draw_floating_numbers(&mut self, numbers: &FloatingNumbers) {
+        for n in &numbers.numbers {
+            let t = (n.age / numbers.lifetime.max(f32::EPSILON)).min(1.0);
+            let risen = numbers.rise * (1.0 - (1.0 - t) * (1.0 - t));
+            let (x, y) = (n.x.round() as i32, (n.y - risen).round() as i32);
+            let shade = match t {
+                t if t < 0.5 => None,
+                t if t < 2.0 / 3.0 => Some(glyph::DARK_SHADE),
+                t if t < 5.0 / 6.0 => Some(glyph::MEDIUM_SHADE),
+                _ => Some(glyph::LIGHT_SHADE),
+            };
+            for (i, ch, flags) in layout_text(&n.text) {
+                if ch != ' ' as u16 {
+                    self.draw_with(x + i, y, shade.unwrap_or(ch), n.col | flags);
+                }
+            }
+        }
+    }
 
-    /// Called once when the game exits or the engine is shutting down.
-    ///
-    /// Use this method to clean up resources, save game state, or free memory.
+    /// Draws the combo count of `combo` at `(x, y)` as e.g. `x12`, with a bar of `w` cells
+    /// below it showing the time left to keep it going. Draws nothing without a combo.
     ///
-    /// # Parameters
-    /// * `engine` - A mutable reference to the `ConsoleGameEngine`.
+    /// The count flashes in reverse for a moment after every hit.
+    pub fn draw_combo_meter(&mut self, x: i32, y: i32, w: i32, combo: &ComboMeter, col: u16) {
+        if !combo.is_active() {
+            return;
+        }
+        let text_col = if combo.pulse > 0.0 {
+            col | color::REVERSE
+        } else {
+            col
+        };
+        self.draw_string_with(x, y, &format!("x{}", combo.count), text_col);
+
+        // Full cells, then one shade glyph for the partly drained cell.
+        let fill = combo.remaining() * w as f32;
+        for i in 0..w {
+            let glyph = match fill - i as f32 {
+                f if f >= 1.0 => SOLID,
+                f if f >= 0.75 => THREE_QUARTERS,
+                f if f >= 0.5 => HALF,
+                f if f > 0.0 => QUARTER,
+                _ => break,
+            };
+            self.draw_with(x + i, y + 1, glyph, col);
+        }
+    }
+}
+
+// endregion
+
+// region: Gauges
+
+/// How a gauge drawn with `draw_gauge` shows its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GaugeKind {
+    /// A bar that fills to an eighth of a cell with the partial block glyphs.
+    Smooth,
+    /// A bar split into this many segments with a gap between them, like hearts or
+    /// battery cells. A partly filled segment is drawn with a shade glyph.
+    Segmented(u32),
+    /// A ring as wide and tall as the gauge, filled clockwise from the top.
+    Radial,
+}
+
+/// How a gauge drawn with `draw_gauge` looks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaugeStyle<'a> {
+    pub kind: GaugeKind,
+    /// Color of the filled part.
+    pub color: u16,
+    /// Colors that replace `color` while the gauge is at or below a fraction of its max,
+    /// e.g. `&[(0.25, FG_RED), (0.5, FG_YELLOW)]`. The lowest matching fraction wins.
+    pub thresholds: &'a [(f32, u16)],
+    /// Color of the empty part, drawn with the light shade glyph, or `None` to leave it
+    /// untouched.
+    pub empty: Option<u16>,
+}
+
+impl Default for GaugeStyle<'_> {
+    fn default() -> Self {
+        Self {
+            kind: GaugeKind::Smooth,
+            color: FG_GREEN,
+            thresholds: &[],
+            empty: Some(FG_DARK_GREY),
+        }
+    }
+}
+
+impl GaugeStyle<'_> {
+    /// Returns the fill color for a gauge that is `fraction` full.
+    pub fn color_at(&self, fraction: f32) -> u16 {
+        self.thresholds
+            .iter()
+            .filter(|&&(at, _)| fraction <= at)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(self.color, |&(_, col)| col)
+    }
+}
+
+/// `▏` to `█`, filled by one to eight eighths from the left.
+const LEFT_EIGHTHS: [u16; 8] = [
+    0x258F, 0x258E, 0x258D, 0x258C, 0x258B, 0x258A, 0x2589, SOLID,
+];
+
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Draws a health bar or similar gauge `w` cells wide at `(x, y)`, filled to `value`
+    /// out of `max`. Radial gauges are `w` cells tall as well.
     ///
-    /// # Returns
-    /// Return `true` to allow normal shutdown, or `false` to prevent shutdown (rarely used).
+    /// # Examples
     ///
-    /// # Default Implementation
-    /// The default implementation does nothing and returns `true`.
-    #[allow(unused_variables)]
-    fn destroy(&mut self, engine: &mut ConsoleGameEngine<Self>) -> bool {
-        true
+    /// ```rust,ignore
+    /// engine.draw_gauge(1, 1, 20, self.hp, 100.0, &GaugeStyle {
+    ///     thresholds: &[(0.25, FG_RED), (0.5, FG_YELLOW)],
+    ///     ..Default::default()
+    /// });
+    /// engine.draw_gauge(1, 3, 10, self.lives as f32, 5.0, &GaugeStyle {
+    ///     kind: GaugeKind::Segmented(5),
+    ///     color: FG_RED,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn draw_gauge(&mut self, x: i32, y: i32, w: i32, value: f32, max: f32, style: &GaugeStyle) {
+        let fraction = if max > 0.0 {
+            (value / max).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let col = style.color_at(fraction);
+        let empty = |engine: &mut Self, cx, cy| {
+            if let Some(empty) = style.empty {
+                engine.draw_with(cx, cy, glyph::LIGHT_SHADE, empty);
+            }
+        };
+
+        match style.kind {
+            GaugeKind::Smooth => {
+                let eighths = (fraction * w as f32 * 8.0).round() as i32;
+                for i in 0..w {
+                    match eighths - i * 8 {
+                        e if e >= 8 => self.draw_with(x + i, y, SOLID, col),
+                        e if e > 0 => {
+                            // The rest of a partial cell stays empty-colored.
+                            let bg = style.empty.map_or(0, |c| (c & 0x0F) << 4);
+                            self.draw_with(x + i, y, LEFT_EIGHTHS[e as usize - 1], col | bg);
+                        }
+                        _ => empty(self, x + i, y),
+                    }
+                }
+            }
+            GaugeKind::Segmented(segments) => {
+                let segments = segments.max(1) as i32;
+                let filled = fraction * segments as f32;
+                for s in 0..segments {
+                    let (start, end) = (s * w / segments, (s + 1) * w / segments);
+                    // Leave a gap after every segment but the last, if there is room.
+                    let end = if s < segments - 1 && end - start > 1 {
+                        end - 1
+                    } else {
+                        end
+                    };
+                    let amount = (filled - s as f32).clamp(0.0, 1.0);
+                    for cx in x + start..x + end {
+                        match amount {
+                            a if a >= 1.0 => self.draw_with(cx, y, SOLID, col),
+                            a if a > 0.0 => self.draw_with(cx, y, glyph::MEDIUM_SHADE, col),
+                            _ => empty(self, cx, y),
+                        }
+                    }
+                }
+            }
+            GaugeKind::Radial => {
+                let r = w as f32 / 2.0;
+                for cy in 0..w {
+                    for cx in 0..w {
+                        let dx = cx as f32 + 0.5 - r;
+                        let dy = cy as f32 + 0.5 - r;
+                        let dist = (dx * dx + dy * dy).sqrt();
+                        if dist > r || dist < r - 1.5 {
+                            continue;
+                        }
+                        // Clockwise from the top, from 0.0 to 1.0.
+                        let angle = dx.atan2(-dy).rem_euclid(2.0 * PI) / (2.0 * PI);
+                        if angle < fraction {
+                            self.draw_with(x + cx, y + cy, SOLID, col);
+                        } else {
+                            empty(self, x + cx, y + cy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// endregion
+
+// region: Logging
+
+const LOG_CAPACITY: usize = 256;
+const LOG_CONSOLE_LINES: usize = 12;
+
+/// Severity of a message passed to `ConsoleGameEngine::log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// Verbose information, only useful while debugging.
+    Debug,
+    /// General information about what the game is doing.
+    Info,
+    /// Something unexpected happened, but the game can carry on.
+    Warn,
+    /// Something went wrong.
+    Error,
+}
+
+impl LogLevel {
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+
+    fn color(self) -> u16 {
+        match self {
+            LogLevel::Debug => FG_GREY,
+            LogLevel::Info => FG_WHITE,
+            LogLevel::Warn => FG_YELLOW,
+            LogLevel::Error => FG_RED,
+        }
     }
 }
 
-/// The main engine that runs a game implementing `ConsoleGame`.
-///
-/// Handles console creation, input, rendering, and the main game loop.
 #[derive(Clone)]
-pub struct ConsoleGameEngine<G: ConsoleGame> {
-    app_name: String,
+struct Log {
+    start: Instant,
+    min_level: LogLevel,
+    entries: VecDeque<(LogLevel, f32, String)>,
+    file: Option<Arc<File>>,
+    console_visible: bool,
+    console_key: Option<usize>,
+}
 
-    output_handle: HANDLE,
-    input_handle: HANDLE,
+impl Log {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            min_level: LogLevel::Debug,
+            entries: VecDeque::with_capacity(LOG_CAPACITY),
+            file: None,
+            console_visible: false,
+            console_key: None,
+        }
+    }
+}
 
-    original_state: ConsoleState,
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Logs a message without writing to the console.
+    ///
+    /// `println!` and `eprintln!` write straight into the console and corrupt the game screen,
+    /// so use this instead. Messages are kept in memory (the most recent 256),
+    /// can be viewed in an overlay console shown with `set_log_console` or a key chosen with
+    /// `set_log_console_key`,
+    /// and are also appended to the log file if one was set with `set_log_file`.
+    pub fn log<T: std::fmt::Display>(&mut self, level: LogLevel, msg: T) {
+        if level < self.log.min_level {
+            return;
+        }
 
-    key_new_state: [u16; 256],
-    key_old_state: [u16; 256],
-    key_pressed: [bool; 256],
-    key_released: [bool; 256],
-    key_held: [bool; 256],
+        let time = self.log.start.elapsed().as_secs_f32();
+        let msg = msg.to_string();
 
-    mouse_new_state: [bool; 5],
-    mouse_old_state: [bool; 5],
-    mouse_pressed: [bool; 5],
-    mouse_released: [bool; 5],
-    mouse_held: [bool; 5],
+        if let Some(file) = &self.log.file {
+            let _ = writeln!(
+                file.as_ref(),
+                "[{:>9.3}] {:<5} {}",
+                time,
+                level.label(),
+                msg
+            );
+        }
 
-    mouse_x: i32,
-    mouse_y: i32,
+        if self.log.entries.len() == LOG_CAPACITY {
+            self.log.entries.pop_front();
+        }
+        self.log.entries.push_back((level, time, msg));
+    }
 
-    console_in_focus: bool,
+    /// Sets the minimum level of messages that are logged.
+    /// Messages below this level are discarded. The default is `LogLevel::Debug`.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log.min_level = level;
+    }
 
-    rect: SMALL_RECT,
+    /// Appends all subsequent log messages to the file at `path`, creating it if needed.
+    pub fn set_log_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        self.log.file = Some(Arc::new(file));
+        Ok(())
+    }
 
-    screen_width: i16,
-    screen_height: i16,
+    /// Shows or hides the log console overlay at the bottom of the screen.
+    pub fn set_log_console(&mut self, visible: bool) {
+        self.log.console_visible = visible;
+    }
 
-    window_buffer: Vec<CHAR_INFO>,
+    /// Sets a key that toggles the log console whenever it is pressed, e.g. `Some(key::F4)`.
+    /// `None`, the default, leaves every key to the game.
+    pub fn set_log_console_key(&mut self, key: Option<usize>) {
+        self.log.console_key = key;
+    }
 
-    pub audio: AudioEngine,
+    /// Returns the messages currently kept in memory, oldest first,
+    /// as `(level, seconds since the engine was created, message)`.
+    pub fn log_messages(&self) -> impl Iterator<Item = (LogLevel, f32, &str)> {
+        self.log
+            .entries
+            .iter()
+            .map(|(level, time, msg)| (*level, *time, msg.as_str()))
+    }
 
-    game: Option<G>,
+    fn draw_log_console(&mut self) {
+        let lines = LOG_CONSOLE_LINES.min(self.screen_height() as usize);
+        let top = self.screen_height() - lines as i32;
+        let width = self.screen_width();
+
+        for y in top..self.screen_height() {
+            for x in 0..width {
+                self.draw_with(x, y, EMPTY, BG_BLACK);
+            }
+        }
+
+        let skip = self.log.entries.len().saturating_sub(lines);
+        let entries: Vec<(LogLevel, String)> = self
+            .log
+            .entries
+            .iter()
+            .skip(skip)
+            .map(|(level, time, msg)| (*level, format!("[{:>8.2}] {}", time, msg)))
+            .collect();
+
+        for (i, (level, line)) in entries.iter().enumerate() {
+            self.draw_overlay_string(0, top + i as i32, line, level.color() | BG_BLACK);
+        }
+    }
 }
 
-// region: Core
+// endregion
+
+// region: Save Data
 
 impl<G: ConsoleGame> ConsoleGameEngine<G> {
-    /// Creates a new `ConsoleGameEngine` with the given game.
+    /// Returns the directory save data is stored in.
     ///
-    /// # Parameters
-    /// * `game` - The user-defined struct implementing `ConsoleGame`.
-    pub fn new(game: G) -> Self {
-        let app_name = game.app_name().to_string();
-        let mouse_x = 0;
-        let mouse_y = 0;
-        let output_handle = unsafe {
-            GetStdHandle(STD_OUTPUT_HANDLE).unwrap_or_else(|e| {
-                eprintln!("Error getting stdout handle: {:?}", e);
-                exit(1);
+    /// This is `%APPDATA%\<app name>` (the name returned by `ConsoleGame::app_name`),
+    /// or a folder with the app name in the current directory if `%APPDATA%` isn't set.
+    pub fn save_dir(&self) -> PathBuf {
+        let base = std::env::var_os("APPDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let name: String = self
+            .app_name
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+                c => c,
             })
-        };
-        let input_handle = unsafe {
-            GetStdHandle(STD_INPUT_HANDLE).unwrap_or_else(|e| {
-                eprintln!("Error getting stin handle: {:?}", e);
-                exit(1);
-            })
-        };
-        let original_state = ConsoleState::save(output_handle, input_handle);
-        let rect = SMALL_RECT::default();
-        let window_buffer = Vec::new();
+            .collect();
 
-        Self {
-            app_name,
-            output_handle,
-            input_handle,
-            original_state,
-            key_new_state: [0; 256],
-            key_old_state: [0; 256],
-            key_pressed: [false; 256],
-            key_released: [false; 256],
-            key_held: [false; 256],
-            mouse_new_state: [false; 5],
-            mouse_old_state: [false; 5],
-            mouse_pressed: [false; 5],
-            mouse_released: [false; 5],
-            mouse_held: [false; 5],
-            mouse_x,
-            mouse_y,
-            console_in_focus: true,
-            rect,
-            screen_width: 80,
-            screen_height: 80,
-            window_buffer,
-            audio: AudioEngine::new(),
-            game: Some(game),
+        base.join(name)
+    }
+
+    fn save_slot_path(
+        &self,
+        slot: &str,
+        extension: &str,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if slot.is_empty()
+            || slot
+                .chars()
+                .any(|c| !(c.is_alphanumeric() || c == '_' || c == '-' || c == ' '))
+        {
+            return Err(format!("invalid save slot name \"{}\"", slot).into());
         }
+
+        Ok(self.save_dir().join(format!("{}.{}", slot, extension)))
     }
 
-    /// Returns the width of the console in characters.
-    pub fn screen_width(&self) -> i32 {
-        self.screen_width as i32
+    /// Saves raw bytes into the save slot named `slot`, replacing anything saved there before.
+    ///
+    /// Slot names may only contain letters, digits, spaces, `-`, and `_`.
+    /// The data is written to a temporary file first, so a crash while saving
+    /// never leaves a half-written save behind.
+    pub fn save_state_bytes(
+        &self,
+        slot: &str,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.save_slot_path(slot, "sav")?;
+        Self::write_atomic(&path, data)
     }
 
-    /// Returns the height of the console in characters.
-    pub fn screen_height(&self) -> i32 {
-        self.screen_height as i32
+    /// Loads the raw bytes saved into the slot named `slot` with `save_state_bytes`.
+    ///
+    /// Returns `Ok(None)` if nothing has been saved into the slot yet.
+    pub fn load_state_bytes(
+        &self,
+        slot: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let path = self.save_slot_path(slot, "sav")?;
+        Self::read_if_exists(&path)
     }
 
-    /// Returns `true` if the specified key was pressed this frame.
+    /// Serializes `state` as JSON into the save slot named `slot`.
     ///
-    /// Normally used in conjection with key constants such as
-    /// `K_W`, `K_0`, `K_UP`, etc.
-    pub fn key_pressed(&self, key: usize) -> bool {
-        self.key_pressed[key]
+    /// Works with any type deriving `serde::Serialize`, e.g. high score tables or save games.
+    /// Slot names may only contain letters, digits, spaces, `-`, and `_`.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn save_state<T: serde::Serialize>(
+        &self,
+        slot: &str,
+        state: &T,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.save_slot_path(slot, "json")?;
+        let data = serde_json::to_vec_pretty(state)?;
+        Self::write_atomic(&path, &data)
     }
 
-    /// Returns `true` if the specified key was released this frame.
+    /// Loads a value saved into the slot named `slot` with `save_state`.
     ///
-    /// Normally used in conjection with key constants such as
-    /// `K_W`, `K_0`, `K_UP`, etc.
-    pub fn key_released(&self, key: usize) -> bool {
-        self.key_released[key]
+    /// Returns `Ok(None)` if nothing has been saved into the slot yet.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn load_state<T: serde::de::DeserializeOwned>(
+        &self,
+        slot: &str,
+    ) -> Result<Option<T>, Box<dyn std::error::Error>> {
+        let path = self.save_slot_path(slot, "json")?;
+        match Self::read_if_exists(&path)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the save slot named `slot`, if it exists.
+    pub fn delete_state(&self, slot: &str) -> Result<(), Box<dyn std::error::Error>> {
+        for extension in ["sav", "json"] {
+            let path = self.save_slot_path(slot, extension)?;
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Appended rather than replacing the extension, so `slot.sav` and `slot.json` don't
+        // share a temporary file.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
+}
+
+// endregion
+
+// region: Render Queue
+
+/// A drawing operation sent through a `RenderQueue`. Each one does the same as the engine
+/// method of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    Draw {
+        x: i32,
+        y: i32,
+        glyph: u16,
+        col: u16,
+    },
+    Line {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        glyph: u16,
+        col: u16,
+    },
+    Rectangle {
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+        glyph: u16,
+        col: u16,
+    },
+    FillRect {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        glyph: u16,
+        col: u16,
+    },
+    Circle {
+        x: i32,
+        y: i32,
+        r: i32,
+        glyph: u16,
+        col: u16,
+    },
+    FillCircle {
+        x: i32,
+        y: i32,
+        r: i32,
+        glyph: u16,
+        col: u16,
+    },
+    String {
+        x: i32,
+        y: i32,
+        text: String,
+        col: u16,
+    },
+    Sprite {
+        x: i32,
+        y: i32,
+        sprite: Sprite,
+    },
+}
+
+enum RenderMessage {
+    Draw(DrawCommand),
+    Layer(Vec<DrawCommand>),
+}
+
+/// A handle other threads can send drawing to, drawn on the main thread every frame.
+///
+/// Get one with `engine.render_queue()`, then clone it and move the clones into worker
+/// threads, which can build expensive visuals (pathfinding debug views, generated chunks)
+/// without holding up `update`. Queued drawing is applied after `update` returns, on top
+/// of whatever the game drew.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let queue = engine.render_queue();
+/// std::thread::spawn(move || {
+///     let path = find_path_slowly();
+///     queue.set_layer(path.iter().map(|&(x, y)| DrawCommand::Draw {
+///         x, y, glyph: SOLID, col: FG_GREEN,
+///     }).collect());
+/// });
+/// ```
+#[derive(Clone)]
+pub struct RenderQueue {
+    sender: Sender<RenderMessage>,
+}
 
-    /// Returns `true` if the specified key is currently held down.
+impl RenderQueue {
+    /// Queues `command` to be drawn once, on the next frame.
     ///
-    /// Normally used in conjection with key constants such as
-    /// `K_W`, `K_0`, `K_UP`, etc.
-    pub fn key_held(&self, key: usize) -> bool {
-        self.key_held[key]
+    /// Returns `false` if the engine has been dropped.
+    pub fn push(&self, command: DrawCommand) -> bool {
+        self.sender.send(RenderMessage::Draw(command)).is_ok()
     }
 
-    /// Returns `true` if the specified mouse button was pressed this frame.
+    /// Replaces the layer of commands that is drawn again every frame, so the result stays
+    /// on screen even though the game clears it. An empty list removes the layer. All
+    /// clones of a queue share the same layer.
     ///
-    /// Normally used in conjection with mouse button constants
-    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
-    pub fn mouse_pressed(&self, button: usize) -> bool {
-        self.mouse_pressed[button]
+    /// Returns `false` if the engine has been dropped.
+    pub fn set_layer(&self, commands: Vec<DrawCommand>) -> bool {
+        self.sender.send(RenderMessage::Layer(commands)).is_ok()
     }
+}
 
-    /// Returns `true` if the specified mouse button was released this frame.
-    ///
-    /// Normally used in conjection with mouse button constants
-    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
-    pub fn mouse_released(&self, button: usize) -> bool {
-        self.mouse_released[button]
-    }
+/// The receiving end of the render queue, kept by the engine.
+#[derive(Clone, Default)]
+struct RenderQueueState {
+    sender: Option<Sender<RenderMessage>>,
+    receiver: Option<Arc<Mutex<Receiver<RenderMessage>>>>,
+    layer: Vec<DrawCommand>,
+}
 
-    /// Returns `true` if the specified mouse button is currently held down.
-    ///
-    /// Normally used in conjection with mouse button constants
-    /// such as `M_LEFT`, `M_MIDDLE`, `M_RIGHT`, etc.
-    pub fn mouse_held(&self, button: usize) -> bool {
-        self.mouse_held[button]
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Returns a handle worker threads can send drawing to. See `RenderQueue`.
+    pub fn render_queue(&mut self) -> RenderQueue {
+        let queue = &mut self.render_queue;
+        let sender = queue.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            queue.receiver = Some(Arc::new(Mutex::new(receiver)));
+            sender
+        });
+        RenderQueue {
+            sender: sender.clone(),
+        }
     }
 
-    /// Returns the current X position of the mouse in console coordinates.
-    pub fn mouse_x(&self) -> i32 {
-        self.mouse_x
-    }
+    /// Draws the layer and every command queued since the last frame.
+    fn drain_render_queue(&mut self) {
+        let Some(receiver) = self.render_queue.receiver.clone() else {
+            return;
+        };
+        let mut once = Vec::new();
+        for message in receiver
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_iter()
+        {
+            match message {
+                RenderMessage::Draw(command) => once.push(command),
+                RenderMessage::Layer(commands) => self.render_queue.layer = commands,
+            }
+        }
 
-    /// Returns the current Y position of the mouse in console coordinates.
-    pub fn mouse_y(&self) -> i32 {
-        self.mouse_y
+        let layer = std::mem::take(&mut self.render_queue.layer);
+        for command in layer.iter().chain(&once) {
+            self.draw_command(command);
+        }
+        self.render_queue.layer = layer;
     }
 
-    /// Returns the current (X, Y) position of the mouse.
-    pub fn mouse_pos(&self) -> (i32, i32) {
-        (self.mouse_x, self.mouse_y)
+    /// Applies a single `DrawCommand` to the screen.
+    pub fn draw_command(&mut self, command: &DrawCommand) {
+        match *command {
+            DrawCommand::Draw { x, y, glyph, col } => self.draw_with(x, y, glyph, col),
+            DrawCommand::Line {
+                x1,
+                y1,
+                x2,
+                y2,
+                glyph,
+                col,
+            } => self.draw_line_with(x1, y1, x2, y2, glyph, col),
+            DrawCommand::Rectangle {
+                x,
+                y,
+                w,
+                h,
+                glyph,
+                col,
+            } => self.draw_rectangle_with(x, y, w, h, glyph, col),
+            DrawCommand::FillRect {
+                x1,
+                y1,
+                x2,
+                y2,
+                glyph,
+                col,
+            } => self.fill_rect_with(x1, y1, x2, y2, glyph, col),
+            DrawCommand::Circle {
+                x,
+                y,
+                r,
+                glyph,
+                col,
+            } => self.draw_circle_with(x, y, r, glyph, col),
+            DrawCommand::FillCircle {
+                x,
+                y,
+                r,
+                glyph,
+                col,
+            } => self.fill_circle_with(x, y, r, glyph, col),
+            DrawCommand::String {
+                x,
+                y,
+                ref text,
+                col,
+            } => self.draw_string_with(x, y, text, col),
+            DrawCommand::Sprite { x, y, ref sprite } => self.draw_sprite(x, y, sprite),
+        }
     }
+}
 
-    /// Returns `true` if the console currently has focus.
-    pub fn console_focused(&self) -> bool {
-        self.console_in_focus
-    }
+// endregion
 
-    /// Initializes the console with the given dimensions and font size.
-    ///
-    /// This function sets up the console window, screen buffer, font, and other
-    /// properties. It now returns a `Result` to indicate success or failure.
-    ///
-    /// # Parameters
-    /// - `width` - Console width in characters.
-    /// - `height` - Console height in characters.
-    /// - `fontw` - Font width in pixels.
-    /// - `fonth` - Font height in pixels.
-    ///
-    /// # Errors
-    /// Returns an error if:
-    /// - The console handle is invalid.
-    /// - The requested console size exceeds the maximum allowed for the current display/font.
-    /// - Any Windows API call fails (setting buffer size, window info, font, etc.)
-    pub fn construct_console(
-        &mut self,
-        width: i16,
-        height: i16,
-        fontw: i16,
-        fonth: i16,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if self.output_handle == INVALID_HANDLE_VALUE {
-            return Err("Bad Handle".into());
-        }
+// region: Snapshots
 
-        self.screen_width = width;
-        self.screen_height = height;
+/// A saved moment of a game, from `ConsoleGameEngine::snapshot`: the screen plus whatever
+/// bytes the game stored to describe its own state.
+#[derive(Clone)]
+pub struct Snapshot {
+    width: i16,
+    height: i16,
+    screen: Vec<CHAR_INFO>,
+    /// The game's state, serialized however the game likes.
+    pub data: Vec<u8>,
+}
 
-        self.rect = SMALL_RECT {
-            Left: 0,
-            Top: 0,
-            Right: 1,
-            Bottom: 1,
-        };
+impl Snapshot {
+    /// Returns the screen size the snapshot was taken at.
+    pub fn size(&self) -> (i32, i32) {
+        (self.width as i32, self.height as i32)
+    }
 
-        self.set_console_window_info(self.output_handle, true, &self.rect)?;
+    /// Deserializes a game state stored with `ConsoleGameEngine::snapshot_state`.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn state<T: serde::de::DeserializeOwned>(&self) -> Result<T, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_slice(&self.data)?)
+    }
+}
 
-        let coord = COORD {
-            X: self.screen_width,
-            Y: self.screen_height,
-        };
+/// A fixed-size history of snapshots for rewinding time or retrying instantly.
+///
+/// Once full, pushing a snapshot drops the oldest, so the buffer always holds the most
+/// recent `capacity` moments.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// // Keep the last 5 seconds at 60 frames per second.
+/// let mut history = RewindBuffer::new(300);
+///
+/// // Each frame:
+/// if engine.key_held(key::BACKSPACE) {
+///     if let Some(snapshot) = history.pop() {
+///         engine.restore(&snapshot);
+///         self.state = decode(&snapshot.data);
+///     }
+/// } else {
+///     self.step(engine, elapsed_time);
+///     history.push(engine.snapshot(encode(&self.state)));
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct RewindBuffer {
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+}
 
-        self.set_console_screen_buffer_size(self.output_handle, coord)?;
+impl RewindBuffer {
+    /// Creates a buffer holding up to `capacity` snapshots.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
 
-        self.set_console_active_screen_buffer(self.output_handle)?;
+    /// Adds a snapshot as the newest, dropping the oldest if the buffer is full.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
 
-        let mut font_cfi = CONSOLE_FONT_INFOEX {
-            cbSize: size_of::<CONSOLE_FONT_INFOEX>().try_into().unwrap(),
-            nFont: 0,
-            dwFontSize: COORD { X: fontw, Y: fonth },
-            FontFamily: FF_DONTCARE.0 as u32,
-            FontWeight: FW_NORMAL.0,
-            ..Default::default()
-        };
+    /// Removes and returns the newest snapshot, stepping one moment back in time.
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        self.snapshots.pop_back()
+    }
 
-        self.set_face_name(&mut font_cfi.FaceName, "Consolas");
+    /// Returns the newest snapshot without removing it.
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
 
-        self.set_current_console_font_ex(self.output_handle, false, &font_cfi)?;
+    /// Returns the oldest snapshot still held, for restarting from as far back as possible.
+    pub fn oldest(&self) -> Option<&Snapshot> {
+        self.snapshots.front()
+    }
 
-        let max_size = unsafe { GetLargestConsoleWindowSize(self.output_handle) };
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
 
-        if width > max_size.X || height > max_size.Y {
-            return Err(format!(
-                "Requested console size {}x{} exceeds maximum {}x{} for this display/font.",
-                width, height, max_size.X, max_size.Y
-            )
-            .into());
-        }
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
 
-        let mut screen_buffer_csbi = CONSOLE_SCREEN_BUFFER_INFO::default();
-        self.get_console_screen_buffer_info(self.output_handle, &mut screen_buffer_csbi)?;
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
 
-        self.validate_window_size(&screen_buffer_csbi)?;
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
 
-        self.rect = SMALL_RECT {
-            Left: 0,
-            Top: 0,
-            Right: self.screen_width - 1,
-            Bottom: self.screen_height - 1,
-        };
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Captures the screen buffer together with `data`, the game's own state serialized
+    /// however it likes.
+    pub fn snapshot(&self, data: Vec<u8>) -> Snapshot {
+        Snapshot {
+            width: self.screen_width,
+            height: self.screen_height,
+            screen: self.window_buffer.clone(),
+            data,
+        }
+    }
 
-        self.set_console_window_info(self.output_handle, true, &self.rect)?;
+    /// Captures the screen buffer together with `state` serialized as JSON. Read it back
+    /// with `Snapshot::state`.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn snapshot_state<T: serde::Serialize>(
+        &self,
+        state: &T,
+    ) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        Ok(self.snapshot(serde_json::to_vec(state)?))
+    }
 
-        self.window_buffer = vec![
-            CHAR_INFO::default();
-            (self.screen_width as i32 * self.screen_height as i32) as usize
-        ];
+    /// Puts the screen back the way it was when `snapshot` was taken. The game restores its
+    /// own state from `snapshot.data`.
+    ///
+    /// Returns `false`, leaving the screen alone, if the console has been resized since.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> bool {
+        if (snapshot.width, snapshot.height) != (self.screen_width, self.screen_height)
+            || snapshot.screen.len() != self.window_buffer.len()
+        {
+            return false;
+        }
+        self.window_buffer.copy_from_slice(&snapshot.screen);
+        true
+    }
+}
 
-        self.set_ctrl_handler(Some(console_handler), true)?;
+// endregion
 
-        self.set_console_mode()?;
+// region: Scripting
+
+#[cfg(feature = "scripting")]
+enum ScriptDrawCommand {
+    Clear(u16),
+    Draw(i32, i32, u16, u16),
+    String(i32, i32, String, u16),
+    Line(i32, i32, i32, i32, u16, u16),
+    Rectangle(i32, i32, i32, i32, u16, u16),
+    FillRect(i32, i32, i32, i32, u16, u16),
+    Circle(i32, i32, i32, u16, u16),
+    FillCircle(i32, i32, i32, u16, u16),
+    Triangle([i32; 6], u16, u16),
+    FillTriangle([i32; 6], u16, u16),
+}
 
-        self.set_console_cursor_info()?;
+/// Copy of the engine state that script functions read from and queue draw calls into.
+#[cfg(feature = "scripting")]
+#[derive(Default)]
+struct ScriptFrame {
+    screen_width: i64,
+    screen_height: i64,
+    key_pressed: Vec<bool>,
+    key_released: Vec<bool>,
+    key_held: Vec<bool>,
+    mouse_pressed: [bool; 5],
+    mouse_released: [bool; 5],
+    mouse_held: [bool; 5],
+    mouse_x: i64,
+    mouse_y: i64,
+    commands: Vec<ScriptDrawCommand>,
+    logs: Vec<String>,
+}
 
-        Ok(())
-    }
+#[cfg(feature = "scripting")]
+struct Script {
+    path: String,
+    ast: rhai::AST,
+    scope: rhai::Scope<'static>,
+    state: rhai::Dynamic,
+    has_update: bool,
+}
 
-    fn update_keys(&mut self) {
-        for i in 0..256 {
-            self.key_pressed[i] = false;
-            self.key_released[i] = false;
+#[cfg(feature = "scripting")]
+struct ScriptHost {
+    engine: rhai::Engine,
+    frame: std::rc::Rc<std::cell::RefCell<ScriptFrame>>,
+    scripts: Vec<Script>,
+}
 
-            self.key_new_state[i] = unsafe { GetAsyncKeyState(i as i32) as u16 };
+#[cfg(feature = "scripting")]
+impl ScriptHost {
+    fn new(audio: AudioEngine) -> Self {
+        use std::{cell::RefCell, rc::Rc};
 
-            if self.key_new_state[i] != self.key_old_state[i] {
-                if (self.key_new_state[i] & 0x8000) != 0 {
-                    self.key_pressed[i] = !self.key_held[i];
-                    self.key_held[i] = true;
-                } else {
-                    self.key_released[i] = true;
-                    self.key_held[i] = false;
-                }
-            }
+        let frame = Rc::new(RefCell::new(ScriptFrame::default()));
+        let mut engine = rhai::Engine::new();
 
-            self.key_old_state[i] = self.key_new_state[i];
+        let mut colors = rhai::Module::new();
+        for (name, value) in SCRIPT_COLORS {
+            colors.set_var(*name, *value as i64);
         }
-    }
+        engine.register_static_module("color", colors.into());
+
+        let mut pixels = rhai::Module::new();
+        for (name, value) in [
+            ("SOLID", SOLID),
+            ("THREE_QUARTERS", THREE_QUARTERS),
+            ("HALF", HALF),
+            ("QUARTER", QUARTER),
+            ("EMPTY", EMPTY),
+        ] {
+            pixels.set_var(name, value as i64);
+        }
+        engine.register_static_module("pixel", pixels.into());
 
-    fn update_mouse(&mut self) {
-        let mut events: u32 = 0;
-        self.get_number_of_console_input_events(&mut events);
-        if events == 0 {
-            return;
+        let mut keys = rhai::Module::new();
+        for (name, value) in key::NAMES {
+            keys.set_var(*name, *value as i64);
+        }
+        engine.register_static_module("key", keys.into());
+
+        let mut buttons = rhai::Module::new();
+        for (name, value) in [
+            ("LEFT", mouse_button::LEFT),
+            ("RIGHT", mouse_button::RIGHT),
+            ("MIDDLE", mouse_button::MIDDLE),
+            ("X1", mouse_button::X1),
+            ("X2", mouse_button::X2),
+        ] {
+            buttons.set_var(name, value as i64);
         }
+        engine.register_static_module("mouse_button", buttons.into());
 
-        let count = events.min(32);
-        let mut in_buf = [INPUT_RECORD::default(); 32];
-        let mut read = 0;
-        self.read_console_input_w(count as usize, &mut in_buf, &mut read);
+        let push = |frame: &Rc<RefCell<ScriptFrame>>, cmd: ScriptDrawCommand| {
+            frame.borrow_mut().commands.push(cmd);
+        };
 
-        for record in &in_buf[..read as usize] {
-            match record.EventType as u32 {
-                FOCUS_EVENT => unsafe {
-                    self.console_in_focus = record.Event.FocusEvent.bSetFocus.as_bool();
-                },
-                MOUSE_EVENT => {
-                    let me = unsafe { record.Event.MouseEvent };
-                    match me.dwEventFlags {
-                        0 => {
-                            for m in 0..5 {
-                                self.mouse_new_state[m] = (me.dwButtonState & (1 << m)) != 0;
-                            }
-                        }
-                        MOUSE_MOVED => {
-                            self.mouse_x = me.dwMousePosition.X as i32;
-                            self.mouse_y = me.dwMousePosition.Y as i32;
-                        }
-                        _ => {}
-                    }
+        let f = frame.clone();
+        engine.register_fn("clear", move |col: i64| {
+            push(&f, ScriptDrawCommand::Clear(col as u16))
+        });
+        let f = frame.clone();
+        engine.register_fn("draw", move |x: i64, y: i64, c: i64, col: i64| {
+            push(
+                &f,
+                ScriptDrawCommand::Draw(x as i32, y as i32, c as u16, col as u16),
+            )
+        });
+        let f = frame.clone();
+        engine.register_fn(
+            "draw_string",
+            move |x: i64, y: i64, text: rhai::ImmutableString, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::String(x as i32, y as i32, text.to_string(), col as u16),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "draw_line",
+            move |x1: i64, y1: i64, x2: i64, y2: i64, c: i64, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::Line(
+                        x1 as i32, y1 as i32, x2 as i32, y2 as i32, c as u16, col as u16,
+                    ),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "draw_rectangle",
+            move |x: i64, y: i64, w: i64, h: i64, c: i64, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::Rectangle(
+                        x as i32, y as i32, w as i32, h as i32, c as u16, col as u16,
+                    ),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "fill_rect",
+            move |x1: i64, y1: i64, x2: i64, y2: i64, c: i64, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::FillRect(
+                        x1 as i32, y1 as i32, x2 as i32, y2 as i32, c as u16, col as u16,
+                    ),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "draw_circle",
+            move |x: i64, y: i64, r: i64, c: i64, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::Circle(x as i32, y as i32, r as i32, c as u16, col as u16),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "fill_circle",
+            move |x: i64, y: i64, r: i64, c: i64, col: i64| {
+                push(
+                    &f,
+                    ScriptDrawCommand::FillCircle(
+                        x as i32, y as i32, r as i32, c as u16, col as u16,
+                    ),
+                )
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "draw_triangle",
+            move |pts: rhai::Array, c: i64, col: i64| {
+                if let Some(pts) = Self::triangle_points(&pts) {
+                    push(&f, ScriptDrawCommand::Triangle(pts, c as u16, col as u16))
                 }
-                _ => {}
-            }
-        }
+            },
+        );
+        let f = frame.clone();
+        engine.register_fn(
+            "fill_triangle",
+            move |pts: rhai::Array, c: i64, col: i64| {
+                if let Some(pts) = Self::triangle_points(&pts) {
+                    push(
+                        &f,
+                        ScriptDrawCommand::FillTriangle(pts, c as u16, col as u16),
+                    )
+                }
+            },
+        );
+
+        let f = frame.clone();
+        engine.register_fn("screen_width", move || f.borrow().screen_width);
+        let f = frame.clone();
+        engine.register_fn("screen_height", move || f.borrow().screen_height);
+
+        let f = frame.clone();
+        engine.register_fn("key_pressed", move |k: i64| {
+            f.borrow()
+                .key_pressed
+                .get(k as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("key_released", move |k: i64| {
+            f.borrow()
+                .key_released
+                .get(k as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("key_held", move |k: i64| {
+            f.borrow()
+                .key_held
+                .get(k as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("mouse_pressed", move |b: i64| {
+            f.borrow()
+                .mouse_pressed
+                .get(b as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("mouse_released", move |b: i64| {
+            f.borrow()
+                .mouse_released
+                .get(b as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("mouse_held", move |b: i64| {
+            f.borrow()
+                .mouse_held
+                .get(b as usize)
+                .copied()
+                .unwrap_or(false)
+        });
+        let f = frame.clone();
+        engine.register_fn("mouse_x", move || f.borrow().mouse_x);
+        let f = frame.clone();
+        engine.register_fn("mouse_y", move || f.borrow().mouse_y);
+
+        let a = audio.clone();
+        engine.register_fn("play_note", move |freq: f64, duration_ms: i64| {
+            a.play_note(freq as f32, duration_ms.max(0) as u32)
+        });
+        let a = audio.clone();
+        engine.register_fn(
+            "note_on",
+            move |channel: rhai::ImmutableString, freq: f64| a.note_on(&channel, freq as f32),
+        );
+        let a = audio.clone();
+        engine.register_fn(
+            "note_off",
+            move |channel: rhai::ImmutableString, freq: f64| a.note_off(&channel, freq as f32),
+        );
+        let a = audio.clone();
+        engine.register_fn("load_sample", move |path: rhai::ImmutableString| {
+            a.load_sample(path.as_str())
+        });
+        let a = audio;
+        engine.register_fn("play_sample", move |path: rhai::ImmutableString| {
+            a.play_sample(path.as_str())
+        });
 
-        for m in 0..5 {
-            self.mouse_pressed[m] = false;
-            self.mouse_released[m] = false;
+        let f = frame.clone();
+        engine.register_fn("log", move |msg: rhai::ImmutableString| {
+            f.borrow_mut().logs.push(msg.to_string())
+        });
 
-            if self.mouse_new_state[m] != self.mouse_old_state[m] {
-                if self.mouse_new_state[m] {
-                    self.mouse_pressed[m] = true;
-                    self.mouse_held[m] = true;
-                } else {
-                    self.mouse_released[m] = true;
-                    self.mouse_held[m] = false;
-                }
-            }
+        Self {
+            engine,
+            frame,
+            scripts: Vec::new(),
+        }
+    }
 
-            self.mouse_old_state[m] = self.mouse_new_state[m];
+    fn triangle_points(pts: &rhai::Array) -> Option<[i32; 6]> {
+        if pts.len() != 6 {
+            return None;
         }
+        let mut out = [0; 6];
+        for (o, p) in out.iter_mut().zip(pts) {
+            *o = p.as_int().ok()? as i32;
+        }
+        Some(out)
     }
+}
 
-    /// Starts the game loop and runs the game until it exits.
+#[cfg(feature = "scripting")]
+const SCRIPT_COLORS: &[(&str, u16)] = &[
+    ("FG_BLACK", FG_BLACK),
+    ("FG_DARK_BLUE", FG_DARK_BLUE),
+    ("FG_DARK_GREEN", FG_DARK_GREEN),
+    ("FG_DARK_CYAN", FG_DARK_CYAN),
+    ("FG_DARK_RED", FG_DARK_RED),
+    ("FG_DARK_MAGENTA", FG_DARK_MAGENTA),
+    ("FG_DARK_YELLOW", FG_DARK_YELLOW),
+    ("FG_GREY", FG_GREY),
+    ("FG_DARK_GREY", FG_DARK_GREY),
+    ("FG_BLUE", FG_BLUE),
+    ("FG_GREEN", FG_GREEN),
+    ("FG_CYAN", FG_CYAN),
+    ("FG_RED", FG_RED),
+    ("FG_MAGENTA", FG_MAGENTA),
+    ("FG_YELLOW", FG_YELLOW),
+    ("FG_WHITE", FG_WHITE),
+    ("BG_BLACK", BG_BLACK),
+    ("BG_DARK_BLUE", BG_DARK_BLUE),
+    ("BG_DARK_GREEN", BG_DARK_GREEN),
+    ("BG_DARK_CYAN", BG_DARK_CYAN),
+    ("BG_DARK_RED", BG_DARK_RED),
+    ("BG_DARK_MAGENTA", BG_DARK_MAGENTA),
+    ("BG_DARK_YELLOW", BG_DARK_YELLOW),
+    ("BG_GREY", BG_GREY),
+    ("BG_DARK_GREY", BG_DARK_GREY),
+    ("BG_BLUE", BG_BLUE),
+    ("BG_GREEN", BG_GREEN),
+    ("BG_CYAN", BG_CYAN),
+    ("BG_RED", BG_RED),
+    ("BG_MAGENTA", BG_MAGENTA),
+    ("BG_YELLOW", BG_YELLOW),
+    ("BG_WHITE", BG_WHITE),
+];
+
+#[cfg(feature = "scripting")]
+impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Loads and runs a [Rhai](https://rhai.rs) script.
     ///
-    /// Calls `create()`, `update()`, and `destroy()` on the user's game struct.
-    pub fn start(mut self) {
-        let mut game = self.game.take().unwrap();
+    /// The script's top-level code runs once. If it defines an `init()` function, that is
+    /// called next, and if it defines an `update(elapsed_time)` function, that is called
+    /// every frame after the game's own `update`, so anything it draws ends up on top.
+    /// Both functions can keep state between frames in the object map bound to `this`.
+    ///
+    /// Scripts can use the same drawing (`draw`, `draw_string`, `draw_line`, `fill_rect`,
+    /// `fill_circle`, `fill_triangle`, `clear`, ...), input (`key_pressed`, `mouse_x`, ...),
+    /// and audio (`play_note`, `note_on`, `play_sample`, ...) functions as the engine,
+    /// plus `log(msg)`, and the `color::`, `pixel::`, `key::` and `mouse_button::` constants.
+    ///
+    /// ```rhai
+    /// fn init() {
+    ///     this.x = 0;
+    /// }
+    ///
+    /// fn update(elapsed_time) {
+    ///     if key_held(key::D) { this.x += 20.0 * elapsed_time; }
+    ///     fill_circle(this.x.to_int(), 10, 3, pixel::SOLID, color::FG_RED);
+    /// }
+    /// ```
+    ///
+    /// If a script's `update` fails, the error is logged and the script is unloaded.
+    ///
+    /// Requires the `scripting` feature.
+    pub fn run_script<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let host = self
+            .scripts
+            .get_or_insert_with(|| {
+                std::rc::Rc::new(std::cell::RefCell::new(ScriptHost::new(self.audio.clone())))
+            })
+            .clone();
+
+        self.sync_script_frame(&host.borrow());
+
+        let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+            let mut host = host.borrow_mut();
+            let ast = host.engine.compile_file(path.as_ref().to_path_buf())?;
+            let mut scope = rhai::Scope::new();
+            host.engine.run_ast_with_scope(&mut scope, &ast)?;
+
+            let mut state = rhai::Dynamic::from_map(rhai::Map::new());
+            if ast
+                .iter_functions()
+                .any(|f| f.name == "init" && f.params.is_empty())
+            {
+                let options = rhai::CallFnOptions::new()
+                    .eval_ast(false)
+                    .rewind_scope(false)
+                    .bind_this_ptr(&mut state);
+                let _ = host.engine.call_fn_with_options::<rhai::Dynamic>(
+                    options,
+                    &mut scope,
+                    &ast,
+                    "init",
+                    (),
+                )?;
+            }
 
-        if !game.create(&mut self) {
-            RUNNING.store(false, SeqCst);
-        }
+            let has_update = ast
+                .iter_functions()
+                .any(|f| f.name == "update" && f.params.len() == 1);
 
-        let mut s: [u16; 256] = [0; 256];
-        let s_ptr = s.as_mut_ptr();
+            host.scripts.push(Script {
+                path: path.as_ref().to_string_lossy().into(),
+                ast,
+                scope,
+                state,
+                has_update,
+            });
+            Ok(())
+        })();
 
-        let mut tp_1 = Instant::now();
+        self.apply_script_frame(&host.borrow());
+        result
+    }
 
-        while RUNNING.load(SeqCst) {
-            while RUNNING.load(SeqCst) {
-                let tp_2 = Instant::now();
-                let elapsed = tp_2.duration_since(tp_1);
-                tp_1 = tp_2;
+    /// Unloads all scripts started with `run_script`.
+    ///
+    /// Requires the `scripting` feature.
+    pub fn unload_scripts(&mut self) {
+        if let Some(host) = &self.scripts {
+            host.borrow_mut().scripts.clear();
+        }
+    }
 
-                let elapsed_time = elapsed.as_secs_f32();
+    fn run_script_hooks(&mut self, elapsed_time: f32) {
+        let Some(host) = self.scripts.clone() else {
+            return;
+        };
 
-                let fps = if elapsed_time > 0.0 {
-                    1.0 / elapsed_time
-                } else {
-                    0.0
-                };
+        self.sync_script_frame(&host.borrow());
 
-                self.update_keys();
-                self.update_mouse();
+        let mut errors = Vec::new();
+        {
+            let host = &mut *host.borrow_mut();
+            let engine = &host.engine;
+            host.scripts.retain_mut(|script| {
+                if !script.has_update {
+                    return true;
+                }
 
-                if !game.update(&mut self, elapsed_time) {
-                    RUNNING.store(false, SeqCst);
+                let options = rhai::CallFnOptions::new()
+                    .eval_ast(false)
+                    .rewind_scope(false)
+                    .bind_this_ptr(&mut script.state);
+                match engine.call_fn_with_options::<rhai::Dynamic>(
+                    options,
+                    &mut script.scope,
+                    &script.ast,
+                    "update",
+                    (elapsed_time as f64,),
+                ) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        errors.push(format!("script {} failed: {}", script.path, e));
+                        false
+                    }
                 }
+            });
+        }
 
-                unsafe {
-                    let mut rect = self.rect;
+        self.apply_script_frame(&host.borrow());
 
-                    let w_char =
-                        format!("Console Game Engine - {} - FPS: {:.2}", self.app_name, fps);
-                    let w_string = HSTRING::from(w_char);
+        for error in errors {
+            self.log(LogLevel::Error, error);
+        }
+    }
 
-                    wsprintfW(PWSTR(s_ptr), PCWSTR(w_string.as_ptr()));
+    fn sync_script_frame(&self, host: &ScriptHost) {
+        let mut frame = host.frame.borrow_mut();
+        frame.screen_width = self.screen_width as i64;
+        frame.screen_height = self.screen_height as i64;
+        frame.key_pressed = self.key_pressed.to_vec();
+        frame.key_released = self.key_released.to_vec();
+        frame.key_held = self.key_held.to_vec();
+        frame.mouse_pressed = self.mouse_pressed;
+        frame.mouse_released = self.mouse_released;
+        frame.mouse_held = self.mouse_held;
+        frame.mouse_x = self.mouse_x as i64;
+        frame.mouse_y = self.mouse_y as i64;
+    }
 
-                    self.set_console_title(PCWSTR(s.as_ptr()));
+    fn apply_script_frame(&mut self, host: &ScriptHost) {
+        let (commands, logs) = {
+            let mut frame = host.frame.borrow_mut();
+            (
+                std::mem::take(&mut frame.commands),
+                std::mem::take(&mut frame.logs),
+            )
+        };
 
-                    self.write_console_output(
-                        self.output_handle,
-                        self.window_buffer.as_ptr(),
-                        COORD {
-                            X: self.screen_width,
-                            Y: self.screen_height,
-                        },
-                        COORD { X: 0, Y: 0 },
-                        &mut rect,
-                    );
+        for cmd in commands {
+            match cmd {
+                ScriptDrawCommand::Clear(col) => self.clear(col),
+                ScriptDrawCommand::Draw(x, y, c, col) => self.draw_with(x, y, c, col),
+                ScriptDrawCommand::String(x, y, text, col) => {
+                    for (i, ch, flags) in layout_text(&text) {
+                        self.draw_with(x + i, y, ch, col | flags);
+                    }
+                }
+                ScriptDrawCommand::Line(x1, y1, x2, y2, c, col) => {
+                    self.draw_line_with(x1, y1, x2, y2, c, col)
+                }
+                ScriptDrawCommand::Rectangle(x, y, w, h, c, col) => {
+                    self.draw_rectangle_with(x, y, w, h, c, col)
+                }
+                ScriptDrawCommand::FillRect(x1, y1, x2, y2, c, col) => {
+                    self.fill_rect_with(x1, y1, x2, y2, c, col)
+                }
+                ScriptDrawCommand::Circle(x, y, r, c, col) => {
+                    self.draw_circle_with(x, y, r, c, col)
+                }
+                ScriptDrawCommand::FillCircle(x, y, r, c, col) => {
+                    self.fill_circle_with(x, y, r, c, col)
+                }
+                ScriptDrawCommand::Triangle([x1, y1, x2, y2, x3, y3], c, col) => {
+                    self.draw_triangle_with(x1, y1, x2, y2, x3, y3, c, col)
+                }
+                ScriptDrawCommand::FillTriangle([x1, y1, x2, y2, x3, y3], c, col) => {
+                    self.fill_triangle_with(x1, y1, x2, y2, x3, y3, c, col)
                 }
-            }
-
-            if !game.destroy(&mut self) {
-                RUNNING.store(true, SeqCst);
             }
         }
-    }
-}
 
-impl<G: ConsoleGame> Drop for ConsoleGameEngine<G> {
-    fn drop(&mut self) {
-        self.original_state
-            .restore(self.output_handle, self.input_handle);
+        for msg in logs {
+            self.log(LogLevel::Info, msg);
+        }
     }
 }
 
@@ -1877,9 +20217,21 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
 
             SetConsoleMode(self.input_handle, mode)?;
         }
+        self.enable_attribute_flags();
         Ok(())
     }
 
+    /// Lets the console draw the grid and underline attribute flags, which it otherwise only
+    /// does for East Asian code pages. Older consoles don't support this, so failure is ignored.
+    fn enable_attribute_flags(&self) {
+        unsafe {
+            let mut mode = CONSOLE_MODE(0);
+            if GetConsoleMode(self.output_handle, &mut mode).is_ok() {
+                SetConsoleMode(self.output_handle, mode | ENABLE_LVB_GRID_WORLDWIDE).ok();
+            }
+        }
+    }
+
     fn set_console_cursor_info(&self) -> windows::core::Result<()> {
         unsafe {
             let info = CONSOLE_CURSOR_INFO {
@@ -1919,12 +20271,233 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
 
 // endregion
 
+// region: Text Layout
+
+/// Attribute bits marking the left and right halves of a double-width character, so the
+/// console draws it once across both cells.
+const LEADING_HALF: u16 = 0x0100;
+const TRAILING_HALF: u16 = 0x0200;
+
+/// Returns how many console cells `ch` takes up: 2 for wide characters such as CJK
+/// ideographs, fullwidth forms and most emoji, 0 for combining marks and other zero-width
+/// characters, and 1 for everything else.
+pub fn char_width(ch: char) -> usize {
+    let c = ch as u32;
+    match c {
+        0x0300..=0x036F
+        | 0x0483..=0x0489
+        | 0x0591..=0x05BD
+        | 0x0610..=0x061A
+        | 0x064B..=0x065F
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x200B..=0x200F
+        | 0x2028..=0x202E
+        | 0x2060..=0x2064
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F
+        | 0xFEFF
+        | 0xE0100..=0xE01EF => 0,
+        0x1100..=0x115F
+        | 0x231A..=0x231B
+        | 0x2329..=0x232A
+        | 0x23E9..=0x23EC
+        | 0x2614..=0x2615
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xA960..=0xA97F
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE10..=0xFE19
+        | 0xFE30..=0xFE6F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        // Anything else outside the Basic Multilingual Plane needs a surrogate pair, which
+        // can't fit in a single cell.
+        0x10000.. => 2,
+        _ => 1,
+    }
+}
+
+/// Returns how many console cells `text` takes up when drawn with `draw_string`.
+pub fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Lays `text` out into cells as `(column, UTF-16 unit, attribute flags)`.
+///
+/// Wide characters fill two cells marked as leading and trailing halves. Characters outside
+/// the Basic Multilingual Plane are split into their surrogate pair across the two cells,
+/// which terminals that support them put back together. Zero-width characters are dropped,
+/// since a cell can only hold one.
+fn layout_text(text: &str) -> impl Iterator<Item = (i32, u16, u16)> + '_ {
+    let mut column = 0;
+    text.chars().flat_map(move |ch| {
+        let width = char_width(ch);
+        let start = column;
+        column += width as i32;
+
+        let mut units = [0u16; 2];
+        let units = ch.encode_utf16(&mut units);
+        let cells: [Option<(i32, u16, u16)>; 2] = match (width, units.len()) {
+            (0, _) => [None, None],
+            (_, 2) => [
+                Some((start, units[0], LEADING_HALF)),
+                Some((start + 1, units[1], TRAILING_HALF)),
+            ],
+            (2, _) => [
+                Some((start, units[0], LEADING_HALF)),
+                Some((start + 1, units[0], TRAILING_HALF)),
+            ],
+            _ => [Some((start, units[0], 0)), None],
+        };
+        cells.into_iter().flatten()
+    })
+}
+
+// endregion
+
 // region: Drawing
 
 use color::*;
 use pixel::*;
 
+/// 4×4 Bayer matrix used for ordered dithering, with thresholds in `[0, 16)`.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Returns the ordered dithering threshold for `(x, y)`, in `[0.0, 1.0)`.
+fn dither_threshold(x: i32, y: i32) -> f32 {
+    (BAYER_4X4[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize] as f32 + 0.5) / 16.0
+}
+
+/// Returns `col` with bright foreground and background colors made dark and dark ones black.
+fn dim_color(col: u16) -> u16 {
+    let dim = |c: u16| if c & 0x8 != 0 { c & 0x7 } else { 0 };
+    (col & 0xFF00) | (dim((col >> 4) & 0xF) << 4) | dim(col & 0xF)
+}
+
+/// The line style used for the border of a panel drawn with `draw_panel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BorderStyle {
+    /// `┌─┐` single-line borders.
+    #[default]
+    Single,
+    /// `╔═╗` double-line borders.
+    Double,
+}
+
+impl BorderStyle {
+    /// Returns the corner and edge glyphs as
+    /// `[top_left, top_right, bottom_left, bottom_right, horizontal, vertical]`.
+    pub fn glyphs(self) -> [u16; 6] {
+        match self {
+            BorderStyle::Single => [
+                glyph::SINGLE_TOP_LEFT,
+                glyph::SINGLE_TOP_RIGHT,
+                glyph::SINGLE_BOTTOM_LEFT,
+                glyph::SINGLE_BOTTOM_RIGHT,
+                glyph::SINGLE_HORIZONTAL,
+                glyph::SINGLE_VERTICAL,
+            ],
+            BorderStyle::Double => [
+                glyph::DOUBLE_TOP_LEFT,
+                glyph::DOUBLE_TOP_RIGHT,
+                glyph::DOUBLE_BOTTOM_LEFT,
+                glyph::DOUBLE_BOTTOM_RIGHT,
+                glyph::DOUBLE_HORIZONTAL,
+                glyph::DOUBLE_VERTICAL,
+            ],
+        }
+    }
+}
+
+/// How a panel drawn with `draw_panel` looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelStyle<'a> {
+    /// Line style of the border.
+    pub border: BorderStyle,
+    /// Color of the border and title.
+    pub border_color: u16,
+    /// Color used to fill the interior, or `None` to leave it untouched.
+    pub fill: Option<u16>,
+    /// Text drawn into the top border, or `None` for no title.
+    pub title: Option<&'a str>,
+}
+
+impl Default for PanelStyle<'_> {
+    fn default() -> Self {
+        Self {
+            border: BorderStyle::Single,
+            border_color: FG_WHITE,
+            fill: None,
+            title: None,
+        }
+    }
+}
+
+/// Draw calls covering fewer cells than this are never split across threads,
+/// since starting the threads would cost more than it saves.
+const PARALLEL_MIN_CELLS: usize = 16 * 1024;
+
 impl<G: ConsoleGame> ConsoleGameEngine<G> {
+    /// Sets how many threads the heavy fill routines (`fill_rect_with`, `fill_triangle_with`
+    /// and sprite drawing) may split their work across. Each thread fills its own horizontal
+    /// band of the screen. Defaults to `1`.
+    ///
+    /// Small draw calls always run on the calling thread.
+    pub fn set_render_threads(&mut self, threads: usize) {
+        self.render_threads = threads.max(1);
+    }
+
+    /// Returns how many threads the heavy fill routines may use.
+    pub fn render_threads(&self) -> usize {
+        self.render_threads
+    }
+
+    /// Calls `f` with every screen row in `y1..y2` (clipped to the screen), splitting the rows
+    /// into bands across the render threads if the draw covers at least `cells` cells.
+    fn raster_rows<F>(&mut self, y1: i32, y2: i32, cells: usize, f: F)
+    where
+        F: Fn(i32, &mut [CHAR_INFO]) + Sync,
+    {
+        let width = self.screen_width as usize;
+        let y1 = y1.max(0);
+        let y2 = y2.min(self.screen_height());
+        if y1 >= y2 || width == 0 {
+            return;
+        }
+
+        let rows = &mut self.window_buffer[y1 as usize * width..y2 as usize * width];
+        let threads = self.render_threads.min((y2 - y1) as usize);
+
+        if threads <= 1 || cells < PARALLEL_MIN_CELLS {
+            for (i, row) in rows.chunks_mut(width).enumerate() {
+                f(y1 + i as i32, row);
+            }
+            return;
+        }
+
+        let band_rows = ((y2 - y1) as usize).div_ceil(threads);
+        let f = &f;
+        thread::scope(|scope| {
+            for (b, band) in rows.chunks_mut(band_rows * width).enumerate() {
+                scope.spawn(move || {
+                    let band_y = y1 + (b * band_rows) as i32;
+                    for (i, row) in band.chunks_mut(width).enumerate() {
+                        f(band_y + i as i32, row);
+                    }
+                });
+            }
+        });
+    }
+
     /// Clamps `x` and `y` to be within the screen boundaries.
     pub fn clip(&self, x: &mut i32, y: &mut i32) {
         if *x < 0 {
@@ -1966,11 +20539,13 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
     }
 
     /// Draws a string starting at `(x, y)` with the specified color.
+    ///
+    /// Wide characters such as CJK ideographs take up two cells; see [`text_width`].
     pub fn draw_string_with(&mut self, x: i32, y: i32, text: &str, col: u16) {
-        for (i, ch) in text.encode_utf16().enumerate() {
-            let idx = (y as usize) * self.screen_width as usize + (x as usize + i);
-            self.window_buffer[idx].Char.UnicodeChar = ch;
-            self.window_buffer[idx].Attributes = col;
+        self.draw_calls += 1;
+
+        for (i, ch, flags) in layout_text(text) {
+            self.draw_with(x + i, y, ch, col | flags);
         }
     }
 
@@ -1981,68 +20556,92 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
 
     /// Draws a string at `(x, y)` ignoring spaces (transparent spaces), using the specified color.
     pub fn draw_string_alpha_with(&mut self, x: i32, y: i32, text: &str, col: u16) {
-        for (i, ch) in text.encode_utf16().enumerate() {
+        self.draw_calls += 1;
+
+        for (i, ch, flags) in layout_text(text) {
             if ch != ' ' as u16 {
-                let idx = (y as usize) * self.screen_width as usize + (x as usize + i);
-                self.window_buffer[idx].Char.UnicodeChar = ch;
-                self.window_buffer[idx].Attributes = col;
+                self.draw_with(x + i, y, ch, col | flags);
             }
         }
     }
 
-    /// Draws a white line from `(x1, y1)` to `(x2, y2)`.
-    pub fn draw_line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
-        self.draw_line_with(x1, y1, x2, y2, SOLID, FG_WHITE);
+    /// Draws several differently colored pieces of text one after another, starting at `(x, y)`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.draw_rich_string(0, 0, &[("HP ", FG_WHITE), ("42", FG_RED)]);
+    /// ```
+    pub fn draw_rich_string(&mut self, x: i32, y: i32, parts: &[(&str, u16)]) {
+        let mut cx = x;
+        for &(text, col) in parts {
+            self.draw_string_with(cx, y, text, col);
+            cx += text_width(text) as i32;
+        }
     }
 
-    /// Draws a line from `(x1, y1)` to `(x2, y2)` with the specified glyph and color.
-    pub fn draw_line_with(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, c: u16, col: u16) {
-        let dx = x2 - x1;
-        let dy = y2 - y1;
-        let dx1 = dx.abs();
-        let dy1 = dy.abs();
-        let mut px = 2 * dy1 - dx1;
-        let mut py = 2 * dx1 - dy1;
-
-        if dy1 <= dx1 {
-            let (mut x, mut y, xe) = if dx >= 0 { (x1, y1, x2) } else { (x2, y2, x1) };
-            self.draw_with(x, y, c, col);
-
-            while x < xe {
-                x += 1;
-                if px < 0 {
-                    px += 2 * dy1;
-                } else {
-                    if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
-                        y += 1;
-                    } else {
-                        y -= 1;
+    /// Draws a string starting at `(x, y)` that may contain inline color codes.
+    ///
+    /// `§` followed by a hex digit (`0`-`f`) switches the foreground to that console color,
+    /// keeping the background of `col`, and `§r` resets to `col`. Any other character after
+    /// `§` is drawn as-is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.draw_string_coded(0, 0, "§cRed§r normal", FG_WHITE);
+    /// ```
+    pub fn draw_string_coded(&mut self, x: i32, y: i32, text: &str, col: u16) {
+        self.draw_calls += 1;
+
+        let mut current = col;
+        let mut cx = x;
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch == '§' {
+                match chars.peek().copied() {
+                    Some('r') | Some('R') => {
+                        chars.next();
+                        current = col;
+                        continue;
                     }
-                    px += 2 * (dy1 - dx1);
+                    Some(c) if c.is_ascii_hexdigit() => {
+                        chars.next();
+                        current = (col & 0xFFF0) | c.to_digit(16).unwrap() as u16;
+                        continue;
+                    }
+                    _ => {}
                 }
-                self.draw_with(x, y, c, col);
             }
-        } else {
-            let (mut x, mut y, ye) = if dy >= 0 { (x1, y1, y2) } else { (x2, y2, y1) };
-            self.draw_with(x, y, c, col);
 
-            while y < ye {
-                y += 1;
-                if py <= 0 {
-                    py += 2 * dx1;
-                } else {
-                    if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
-                        x += 1;
-                    } else {
-                        x -= 1;
-                    }
-                    py += 2 * (dx1 - dy1);
-                }
-                self.draw_with(x, y, c, col);
+            let mut buf = [0u8; 4];
+            for (i, unit, flags) in layout_text(ch.encode_utf8(&mut buf)) {
+                self.draw_with(cx + i, y, unit, current | flags);
             }
+            cx += char_width(ch) as i32;
         }
     }
 
+    /// Draws a string starting at `(x, y)` with the specified color ANDed with `attr_mask`.
+    ///
+    /// Useful for dimming or recoloring text without changing the color it was given,
+    /// e.g. `FG_RED | BG_RED` keeps only the red channel of both foreground and background.
+    pub fn draw_string_tinted(&mut self, x: i32, y: i32, text: &str, col: u16, attr_mask: u16) {
+        self.draw_string_with(x, y, text, col & attr_mask);
+    }
+
+    /// Draws a white line from `(x1, y1)` to `(x2, y2)`.
+    pub fn draw_line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
+        self.draw_line_with(x1, y1, x2, y2, SOLID, FG_WHITE);
+    }
+
+    /// Draws a line from `(x1, y1)` to `(x2, y2)` with the specified glyph and color.
+    pub fn draw_line_with(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, c: u16, col: u16) {
+        self.draw_calls += 1;
+
+        plot_line(x1, y1, x2, y2, |x, y| self.draw_with(x, y, c, col));
+    }
+
     /// Draws a white triangle connecting three points.
     pub fn draw_triangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, x3: i32, y3: i32) {
         self.draw_triangle_with(x1, y1, x2, y2, x3, y3, SOLID, FG_WHITE);
@@ -2086,11 +20685,11 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
     ) {
         use std::mem::swap;
 
-        let draw_line = |engine: &mut Self, sx: i32, ex: i32, y: i32| {
-            for i in sx..=ex {
-                engine.draw_with(i, y, c, col);
-            }
-        };
+        self.draw_calls += 1;
+
+        // Spans are collected first and filled afterwards so rows can be filled in parallel.
+        let mut spans: Vec<(i32, i32, i32)> = Vec::new();
+        let mut draw_line = |sx: i32, ex: i32, y: i32| spans.push((y, sx, ex));
 
         if y1 > y2 {
             swap(&mut y1, &mut y2);
@@ -2127,7 +20726,7 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
             while i < dx1 {
                 let minx = t1x.min(t2x);
                 let maxx = t1x.max(t2x);
-                draw_line(self, minx, maxx, y);
+                draw_line(minx, maxx, y);
 
                 y += 1;
                 i += 1;
@@ -2149,7 +20748,7 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
         while y <= y3 {
             let minx = t1x.min(t2x);
             let maxx = t1x.max(t2x);
-            draw_line(self, minx, maxx, y);
+            draw_line(minx, maxx, y);
 
             y += 1;
             if changed1 {
@@ -2163,6 +20762,30 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
                 break;
             }
         }
+
+        let Some(&(first_y, _, _)) = spans.first() else {
+            return;
+        };
+        let last_y = spans.last().unwrap().0;
+        let cells = spans
+            .iter()
+            .map(|&(_, sx, ex)| (ex - sx + 1) as usize)
+            .sum();
+        let width = self.screen_width();
+
+        let mut cell = CHAR_INFO::default();
+        cell.Char.UnicodeChar = c;
+        cell.Attributes = col;
+
+        self.raster_rows(first_y, last_y + 1, cells, |row_y, row| {
+            let Some(&(_, sx, ex)) = spans.get((row_y - first_y) as usize) else {
+                return;
+            };
+            let (sx, ex) = (sx.max(0), ex.min(width - 1));
+            if sx <= ex {
+                row[sx as usize..=ex as usize].fill(cell);
+            }
+        });
     }
 
     /// Draws a white rectangle at `(x, y)` with width `w` and height `h`.
@@ -2182,6 +20805,51 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
         self.draw_line_with(x + w - 1, y, x + w - 1, y + h - 1, c, col);
     }
 
+    /// Draws a panel with a box-drawing border at `(x, y)` with width `w` and height `h`.
+    ///
+    /// The title, if any, is drawn into the top border and cut off if it does not fit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// engine.draw_panel(2, 2, 30, 8, &PanelStyle {
+    ///     border: BorderStyle::Double,
+    ///     fill: Some(BG_BLUE),
+    ///     title: Some("Inventory"),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn draw_panel(&mut self, x: i32, y: i32, w: i32, h: i32, style: &PanelStyle) {
+        if w < 2 || h < 2 {
+            return;
+        }
+
+        if let Some(fill) = style.fill {
+            self.fill_rect_with(x + 1, y + 1, x + w - 1, y + h - 1, EMPTY, fill);
+        }
+
+        let [tl, tr, bl, br, hz, vt] = style.border.glyphs();
+        let col = style.border_color;
+        self.draw_line_with(x + 1, y, x + w - 2, y, hz, col);
+        self.draw_line_with(x + 1, y + h - 1, x + w - 2, y + h - 1, hz, col);
+        self.draw_line_with(x, y + 1, x, y + h - 2, vt, col);
+        self.draw_line_with(x + w - 1, y + 1, x + w - 1, y + h - 2, vt, col);
+        self.draw_with(x, y, tl, col);
+        self.draw_with(x + w - 1, y, tr, col);
+        self.draw_with(x, y + h - 1, bl, col);
+        self.draw_with(x + w - 1, y + h - 1, br, col);
+
+        if let Some(title) = style.title {
+            let max = (w - 4).max(0);
+            for (i, ch, flags) in layout_text(&format!(" {} ", title)) {
+                if i + (flags == LEADING_HALF) as i32 >= max {
+                    break;
+                }
+                self.draw_with(x + 2 + i, y, ch, col | flags);
+            }
+        }
+    }
+
     /// Fills a rectangle from `(x1, y1)` to `(x2, y2)` with white pixels.
     pub fn fill_rect(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
         self.fill_rect_with(x1, y1, x2, y2, SOLID, FG_WHITE);
@@ -2197,14 +20865,22 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
         c: u16,
         col: u16,
     ) {
+        self.draw_calls += 1;
+
         self.clip(&mut x1, &mut y1);
         self.clip(&mut x2, &mut y2);
-
-        for x in x1..x2 {
-            for y in y1..y2 {
-                self.draw_with(x, y, c, col);
-            }
+        if x1 >= x2 || y1 >= y2 {
+            return;
         }
+
+        let mut cell = CHAR_INFO::default();
+        cell.Char.UnicodeChar = c;
+        cell.Attributes = col;
+
+        let cells = ((x2 - x1) * (y2 - y1)) as usize;
+        self.raster_rows(y1, y2, cells, |_, row| {
+            row[x1 as usize..x2 as usize].fill(cell);
+        });
     }
 
     /// Draws a white circle centered at `(xc, yc)` with radius `r`.
@@ -2214,6 +20890,8 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
 
     /// Draws a circle centered at `(xc, yc)` with radius `r` using the specified glyph and color.
     pub fn draw_circle_with(&mut self, xc: i32, yc: i32, r: i32, c: u16, col: u16) {
+        self.draw_calls += 1;
+
         if r == 0 {
             return;
         }
@@ -2248,6 +20926,8 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
 
     /// Fills a circle centered at `(xc, yc)` with radius `r` using the specified glyph and color.
     pub fn fill_circle_with(&mut self, xc: i32, yc: i32, r: i32, c: u16, col: u16) {
+        self.draw_calls += 1;
+
         if r == 0 {
             return;
         }
@@ -2355,6 +21035,8 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
             return;
         }
 
+        self.draw_calls += 1;
+
         let cos_r = r.cos();
         let sin_r = r.sin();
         let mut transformed: Vec<(f32, f32)> = Vec::with_capacity(verts);
@@ -2425,19 +21107,93 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
         }
     }
 
+    /// Draws a buffer of intensities in `[0.0, 1.0]` at `(x, y)` using ordered dithering.
+    ///
+    /// `values` holds `width` values per row. `ramp` lists foreground colors from dark to
+    /// bright; between each pair of neighbouring colors the shade glyphs are used to blend
+    /// them, giving `(ramp.len() - 1) * 4 + 1` levels in total.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let greys = [FG_BLACK, FG_DARK_GREY, FG_GREY, FG_WHITE];
+    /// engine.draw_dithered(0, 0, width, &noise, &greys);
+    /// ```
+    pub fn draw_dithered(&mut self, x: i32, y: i32, width: usize, values: &[f32], ramp: &[u16]) {
+        if width == 0 || ramp.is_empty() {
+            return;
+        }
+        self.draw_calls += 1;
+
+        let levels = (ramp.len() - 1) * 4;
+        for (i, &v) in values.iter().enumerate() {
+            let (px, py) = (x + (i % width) as i32, y + (i / width) as i32);
+            let l = v.clamp(0.0, 1.0) * levels as f32 + dither_threshold(px, py) - 0.5;
+            let l = (l.round().max(0.0) as usize).min(levels);
+
+            let (lo, step) = (l / 4, l % 4);
+            let (glyph, col) = if step == 0 {
+                (SOLID, ramp[lo] & 0x0F)
+            } else {
+                let glyph = [SOLID, QUARTER, HALF, THREE_QUARTERS][step];
+                (glyph, (ramp[lo + 1] & 0x0F) | ((ramp[lo] & 0x0F) << 4))
+            };
+            self.draw_with(px, py, glyph, col);
+        }
+    }
+
+    /// Darkens every screen cell that belongs to an unlit tile of `mask`.
+    ///
+    /// `(x, y)` is the screen position of tile `(0, 0)` and every tile covers
+    /// `tile_w`×`tile_h` cells. Bright colors become their dark variants and dark colors
+    /// become black. Cells outside the mask are left untouched.
+    pub fn apply_light_mask(&mut self, mask: &LightMask, x: i32, y: i32, tile_w: i32, tile_h: i32) {
+        if tile_w <= 0 || tile_h <= 0 {
+            return;
+        }
+
+        for sy in 0..self.screen_height() {
+            for sx in 0..self.screen_width() {
+                let (tx, ty) = ((sx - x).div_euclid(tile_w), (sy - y).div_euclid(tile_h));
+                let inside =
+                    tx >= 0 && ty >= 0 && (tx as usize) < mask.width && (ty as usize) < mask.height;
+                if inside && !mask.is_lit(tx, ty) {
+                    let idx = (sy * self.screen_width() + sx) as usize;
+                    let col = self.window_buffer[idx].Attributes;
+                    self.window_buffer[idx].Attributes = dim_color(col);
+                }
+            }
+        }
+    }
+
     /// Draws a sprite at position `(x, y)`.
     pub fn draw_sprite(&mut self, x: i32, y: i32, sprite: &Sprite) {
+        self.draw_sprite_blend(x, y, sprite, BlendMode::Mask);
+    }
+
+    /// Draws a sprite at position `(x, y)` with every color attribute ANDed with `attr_mask`.
+    ///
+    /// This lets one asset be drawn in several colors, e.g. a white unit drawn with
+    /// `FG_RED | BG_RED` for the red team or a damage flash.
+    pub fn draw_sprite_tinted(&mut self, x: i32, y: i32, sprite: &Sprite, attr_mask: u16) {
+        self.draw_calls += 1;
+
         for i in 0..sprite.width {
             for j in 0..sprite.height {
                 let glyph = sprite.get_glyph(i, j);
                 if glyph != EMPTY {
-                    let color = sprite.get_color(i, j);
+                    let color = sprite.get_color(i, j) & attr_mask;
                     self.draw_with(x + i as i32, y + j as i32, glyph, color);
                 }
             }
         }
     }
 
+    /// Draws a sprite at position `(x, y)`, combining it with the screen using `mode`.
+    pub fn draw_sprite_blend(&mut self, x: i32, y: i32, sprite: &Sprite, mode: BlendMode) {
+        self.draw_partial_sprite_blend(x, y, sprite, 0, 0, sprite.width, sprite.height, mode);
+    }
+
     /// Draws a portion of a sprite at position `(x, y)` on the screen.
     ///
     /// # Parameters
@@ -2456,14 +21212,67 @@ impl<G: ConsoleGame> ConsoleGameEngine<G> {
         w: usize,
         h: usize,
     ) {
-        for i in 0..w {
-            for j in 0..h {
+        self.draw_partial_sprite_blend(x, y, sprite, ox, oy, w, h, BlendMode::Mask);
+    }
+
+    /// Draws a portion of a sprite at position `(x, y)` on the screen, combining it with the
+    /// screen using `mode`. See `draw_partial_sprite` for the other parameters.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_partial_sprite_blend(
+        &mut self,
+        x: i32,
+        y: i32,
+        sprite: &Sprite,
+        ox: usize,
+        oy: usize,
+        w: usize,
+        h: usize,
+        mode: BlendMode,
+    ) {
+        self.draw_calls += 1;
+
+        let width = self.screen_width();
+        self.raster_rows(y, y + h as i32, w * h, |sy, row| {
+            let j = (sy - y) as usize;
+            for i in 0..w {
+                let sx = x + i as i32;
+                if sx < 0 || sx >= width {
+                    continue;
+                }
+                let dst = &mut row[sx as usize];
                 let glyph = sprite.get_glyph(i + ox, j + oy);
-                if glyph != EMPTY {
-                    let color = sprite.get_color(i + ox, j + oy);
-                    self.draw_with(x + i as i32, y + j as i32, glyph, color);
+                let color = sprite.get_color(i + ox, j + oy);
+                if let Some((g, c)) = mode.blend(glyph, color, dst.Attributes) {
+                    dst.Char.UnicodeChar = g;
+                    dst.Attributes = c;
                 }
             }
+        });
+    }
+
+    /// Draws the frame called `name` from a sprite sheet at position `(x, y)`.
+    ///
+    /// Nothing is drawn if the sheet has no frame with that name.
+    pub fn draw_sheet_frame(&mut self, x: i32, y: i32, sheet: &SpriteSheet, name: &str) {
+        if let Some(r) = sheet.frame(name) {
+            self.draw_partial_sprite(x, y, &sheet.sprite, r.x, r.y, r.w, r.h);
+        }
+    }
+
+    /// Draws the frame of the animation called `name` that is shown `time` seconds
+    /// after the animation started, at position `(x, y)`. The animation loops forever.
+    ///
+    /// Nothing is drawn if the sheet has no animation with that name.
+    pub fn draw_sheet_animation(
+        &mut self,
+        x: i32,
+        y: i32,
+        sheet: &SpriteSheet,
+        name: &str,
+        time: f32,
+    ) {
+        if let Some(r) = sheet.animation(name).and_then(|a| a.frame_at(time)) {
+            self.draw_partial_sprite(x, y, &sheet.sprite, r.x, r.y, r.w, r.h);
         }
     }
 }